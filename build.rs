@@ -0,0 +1,15 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    let protoc =
+        protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+    std::env::set_var("PROTOC", protoc);
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/vrum.proto"], &["proto"])
+        .expect("failed to compile proto/vrum.proto");
+}
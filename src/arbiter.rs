@@ -0,0 +1,396 @@
+//! Arbitration between clients sharing the robot, e.g. a phone on the web
+//! dashboard, a safety operator's gamepad and an autonomy script. Each gets
+//! an `ArbiterClient` with a `ControlPriority`, and only the one holding
+//! control drives the motors.
+//!
+//! Control is explicit: a client calls `take_control()`, which is granted if
+//! nobody holds it or the holder has a lower priority, and `release()` when
+//! done. A holder of the same or a higher priority has to release first, so
+//! an operator taking over is never overridden by the autonomy stack. The
+//! motors are stopped whenever control changes hands. Sources that can't ask
+//! for control, like an RC transmitter, drive through a `ClaimingClient`.
+//!
+//! Drive commands from clients not in control fail, and their stops do
+//! nothing, so they can't disturb the holder. The emergency stop is not
+//! arbitrated.
+//!
+//! ```
+//! # use vrum::arbiter::{Arbiter, ControlPriority};
+//! # use vrum::motor_driver::MotorDriver;
+//! # use vrum::shared::{SharedController, SharedControllerConfig};
+//! # use vrum::simulator::SimulatedBoard;
+//! # use vrum::thunder_borg::ControllerBuilder;
+//! # let board = SimulatedBoard::new();
+//! # let controller = ControllerBuilder::new().build_with_bus(board.bus())?;
+//! # let controller = SharedController::spawn(SharedControllerConfig::default(), controller)?;
+//! let arbiter = Arbiter::new(controller);
+//! let mut autonomy = arbiter.client("planner", ControlPriority::Autonomy);
+//! let mut gamepad = arbiter.client("gamepad", ControlPriority::Operator);
+//!
+//! autonomy.take_control()?;
+//! autonomy.set_sides(0.5, 0.5)?;
+//! gamepad.take_control()?;
+//! assert!(autonomy.set_sides(0.5, 0.5).is_err());
+//! assert!(autonomy.take_control().is_err());
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::fmt::{self, Display};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::borg::CommStats;
+use crate::clock::{self, Clock};
+use crate::color::Color;
+use crate::error::Error;
+use crate::motor_driver::MotorDriver;
+use crate::shared::SharedController;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArbitrationError {
+    #[error("{holder} ({priority} priority) is in control")]
+    Held {
+        holder: String,
+        priority: ControlPriority,
+    },
+    #[error("not in control, take control before driving")]
+    NotInControl,
+}
+
+/// Who wins when clients compete for control, lowest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlPriority {
+    /// Scripts, missions and other autonomous control.
+    Autonomy,
+    /// Remote driving, e.g. from the web dashboard.
+    Teleop,
+    /// A safety operator, overriding everything else.
+    Operator,
+}
+
+impl Display for ControlPriority {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            ControlPriority::Autonomy => "autonomy",
+            ControlPriority::Teleop => "teleop",
+            ControlPriority::Operator => "operator",
+        })
+    }
+}
+
+/// The client in control.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Holder {
+    pub name: String,
+    pub priority: ControlPriority,
+}
+
+/// Hands out control of a `SharedController` to one client at a time.
+#[derive(Clone)]
+pub struct Arbiter {
+    controller: SharedController,
+    state: Arc<Mutex<ArbiterState>>,
+}
+
+#[derive(Default)]
+struct ArbiterState {
+    next_id: u64,
+    holder: Option<(u64, Holder)>,
+}
+
+impl Arbiter {
+    pub fn new(controller: SharedController) -> Self {
+        Arbiter {
+            controller,
+            state: Arc::new(Mutex::new(ArbiterState::default())),
+        }
+    }
+
+    /// A new client, not in control. `name` identifies it in the logs and
+    /// to the other clients.
+    pub fn client<S: Into<String>>(&self, name: S, priority: ControlPriority) -> ArbiterClient {
+        let mut state = self.lock();
+        state.next_id += 1;
+        ArbiterClient {
+            arbiter: self.clone(),
+            id: state.next_id,
+            name: name.into(),
+            priority,
+        }
+    }
+
+    pub fn holder(&self) -> Option<Holder> {
+        self.lock()
+            .holder
+            .as_ref()
+            .map(|(_, holder)| holder.clone())
+    }
+
+    /// The controller, for readings. Driving through it bypasses the
+    /// arbitration.
+    pub fn controller(&self) -> &SharedController {
+        &self.controller
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ArbiterState> {
+        // The holder is replaced whole, a poisoned state is still consistent.
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// One client of an `Arbiter`, driving the motors while in control. Control
+/// is released when it is dropped.
+pub struct ArbiterClient {
+    arbiter: Arbiter,
+    id: u64,
+    name: String,
+    priority: ControlPriority,
+}
+
+impl ArbiterClient {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn priority(&self) -> ControlPriority {
+        self.priority
+    }
+
+    /// Takes control unless a client of the same or a higher priority holds
+    /// it, stopping the motors if another client did.
+    pub fn take_control(&mut self) -> Result<(), Error> {
+        let mut state = self.arbiter.lock();
+        match state.holder {
+            Some((id, _)) if id == self.id => return Ok(()),
+            Some((_, ref holder)) if holder.priority >= self.priority => {
+                return Err(ArbitrationError::Held {
+                    holder: holder.name.clone(),
+                    priority: holder.priority,
+                }
+                .into());
+            }
+            Some((_, ref holder)) => {
+                warn!(
+                    "{} ({} priority) took control from {} ({} priority)",
+                    self.name, self.priority, holder.name, holder.priority
+                );
+                self.arbiter.controller.stop_motors()?;
+            }
+            None => info!("{} ({} priority) took control", self.name, self.priority),
+        }
+        state.holder = Some((
+            self.id,
+            Holder {
+                name: self.name.clone(),
+                priority: self.priority,
+            },
+        ));
+        Ok(())
+    }
+
+    /// Gives up control, stopping the motors, if this client holds it.
+    pub fn release(&mut self) -> Result<(), Error> {
+        let mut state = self.arbiter.lock();
+        if !matches!(state.holder, Some((id, _)) if id == self.id) {
+            return Ok(());
+        }
+        state.holder = None;
+        info!("{} released control", self.name);
+        self.arbiter.controller.stop_motors()
+    }
+
+    pub fn has_control(&self) -> bool {
+        matches!(self.arbiter.lock().holder, Some((id, _)) if id == self.id)
+    }
+
+    /// Stops the motors if this client is in control, does nothing
+    /// otherwise.
+    pub fn stop_motors(&self) -> Result<(), Error> {
+        let state = self.arbiter.lock();
+        match state.holder {
+            Some((id, _)) if id == self.id => self.arbiter.controller.stop_motors(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs `drive` on the controller if this client is in control, holding
+    /// the arbitration until it is done so it can't land after a handover.
+    fn drive<F>(&self, drive: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut SharedController) -> Result<(), Error>,
+    {
+        let state = self.arbiter.lock();
+        match state.holder {
+            Some((id, _)) if id == self.id => drive(&mut self.arbiter.controller.clone()),
+            _ => Err(ArbitrationError::NotInControl.into()),
+        }
+    }
+}
+
+impl Drop for ArbiterClient {
+    fn drop(&mut self) {
+        if let Err(error) = self.release() {
+            warn!(
+                "{} failed to stop the motors on release: {}",
+                self.name, error
+            );
+        }
+    }
+}
+
+/// Drive commands fail, and stops do nothing, unless in control.
+impl MotorDriver for ArbiterClient {
+    fn num_motors(&self) -> usize {
+        self.arbiter.controller.num_motors()
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        self.drive(|controller| controller.set_motor(index, power))
+    }
+
+    fn motor_power(&self, index: usize) -> f32 {
+        self.arbiter.controller.motor_power(index)
+    }
+
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        self.drive(|controller| controller.set_sides(left, right))
+    }
+
+    fn stop_all(&mut self) -> Result<(), Error> {
+        let state = self.arbiter.lock();
+        match state.holder {
+            Some((id, _)) if id == self.id => self.arbiter.controller.stop(),
+            _ => Ok(()),
+        }
+    }
+
+    fn fault(&mut self, index: usize) -> Result<bool, Error> {
+        self.arbiter.controller.clone().fault(index)
+    }
+
+    fn battery_voltage(&mut self) -> Result<f32, Error> {
+        self.arbiter.controller.get_battery_voltage()
+    }
+
+    fn battery_percent(&mut self) -> Result<f32, Error> {
+        self.arbiter.controller.clone().battery_percent()
+    }
+
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        self.arbiter.controller.comm_stats()
+    }
+
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        self.arbiter.controller.set_led(color)
+    }
+}
+
+/// An `ArbiterClient` for drive sources that can't ask for control, like an
+/// RC transmitter: it takes control with its first command other than a
+/// stop and releases it once they have been stopped for `release_after`.
+/// Commands it can't get control for are dropped, so the loop feeding it
+/// carries on and takes over when it can.
+pub struct ClaimingClient {
+    client: ArbiterClient,
+    release_after: Duration,
+    clock: Arc<dyn Clock>,
+    stopped_since: Option<Instant>,
+}
+
+impl ClaimingClient {
+    pub fn new(client: ArbiterClient, release_after: Duration) -> Self {
+        ClaimingClient::with_clock(client, release_after, clock::system())
+    }
+
+    pub fn with_clock(
+        client: ArbiterClient,
+        release_after: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        ClaimingClient {
+            client,
+            release_after,
+            clock,
+            stopped_since: None,
+        }
+    }
+
+    pub fn client(&self) -> &ArbiterClient {
+        &self.client
+    }
+
+    /// Takes control and runs `drive`, dropping it if control is held.
+    fn claim<F>(&mut self, drive: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut ArbiterClient) -> Result<(), Error>,
+    {
+        self.stopped_since = None;
+        match self
+            .client
+            .take_control()
+            .and_then(|()| drive(&mut self.client))
+        {
+            Err(Error::Arbitration(error)) => {
+                debug!("Dropping the command of {}: {}", self.client.name, error);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+impl MotorDriver for ClaimingClient {
+    fn num_motors(&self) -> usize {
+        self.client.num_motors()
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        self.claim(|client| client.set_motor(index, power))
+    }
+
+    fn motor_power(&self, index: usize) -> f32 {
+        self.client.motor_power(index)
+    }
+
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        if left == 0.0 && right == 0.0 {
+            return self.stop_all();
+        }
+        self.claim(|client| client.set_sides(left, right))
+    }
+
+    fn stop_all(&mut self) -> Result<(), Error> {
+        if !self.client.has_control() {
+            return Ok(());
+        }
+        let clock = &self.clock;
+        let stopped_since = *self.stopped_since.get_or_insert_with(|| clock.now());
+        if self.clock.elapsed(stopped_since) >= self.release_after {
+            self.stopped_since = None;
+            self.client.release()
+        } else {
+            self.client.stop_all()
+        }
+    }
+
+    fn fault(&mut self, index: usize) -> Result<bool, Error> {
+        self.client.fault(index)
+    }
+
+    fn battery_voltage(&mut self) -> Result<f32, Error> {
+        self.client.battery_voltage()
+    }
+
+    fn battery_percent(&mut self) -> Result<f32, Error> {
+        self.client.battery_percent()
+    }
+
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        self.client.comm_stats()
+    }
+
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        self.client.set_led_color(color)
+    }
+}
@@ -0,0 +1,176 @@
+//! Explicit arming of the motors. With `required = true` in the `[arming]`
+//! section of the configuration file the controller starts disarmed, and
+//! motor commands other than stops are rejected until it is armed, from the
+//! command line with `--arm`, an RC transmitter's arm switch or the web
+//! dashboard.
+//!
+//! Arming is refused unless the last motor command was a stop, so the robot
+//! doesn't lurch off with a stick held over. The emergency stop and drive
+//! faults disarm the motors, which then have to be armed again once they
+//! clear.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::Error;
+use crate::estop::EStopLatch;
+use crate::faults::FaultGuard;
+use crate::journal;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArmingError {
+    #[error("motor command rejected, the motors are disarmed")]
+    Disarmed,
+    #[error("cannot arm while the motors are commanded to move, centre the sticks first")]
+    NotIdle,
+    #[error("cannot arm while the emergency stop is latched")]
+    EStopped,
+    #[error("cannot arm with a drive fault raised")]
+    Faulted,
+}
+
+/// The `[arming]` section of the configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArmingConfig {
+    /// Start disarmed and reject motor commands until armed.
+    pub required: bool,
+}
+
+/// Shared arming state, checked by the `ArmingCheck` stage of the
+/// controller's pipeline.
+#[derive(Clone)]
+pub struct Arming {
+    state: Arc<ArmingState>,
+}
+
+struct ArmingState {
+    armed: AtomicBool,
+    /// Whether the last motor command was a stop.
+    idle: AtomicBool,
+    interlocks: Mutex<Interlocks>,
+}
+
+/// What disarms the motors.
+#[derive(Default)]
+struct Interlocks {
+    estop: Option<EStopLatch>,
+    /// E-stop triggers when last armed, so one reset before anyone looked
+    /// still disarms.
+    estop_triggers: u64,
+    faults: Option<FaultGuard>,
+}
+
+impl Default for Arming {
+    fn default() -> Self {
+        Arming {
+            state: Arc::new(ArmingState {
+                armed: AtomicBool::new(false),
+                idle: AtomicBool::new(true),
+                interlocks: Mutex::default(),
+            }),
+        }
+    }
+}
+
+impl Arming {
+    /// Disarmed.
+    pub fn new() -> Self {
+        Arming::default()
+    }
+
+    /// Disarms whenever `estop` is latched.
+    pub fn watch_estop(&self, estop: EStopLatch) {
+        let mut interlocks = self.interlocks();
+        interlocks.estop_triggers = estop.triggers();
+        interlocks.estop = Some(estop);
+    }
+
+    /// Disarms whenever `faults` reports a drive fault.
+    pub fn watch_faults(&self, faults: FaultGuard) {
+        self.interlocks().faults = Some(faults);
+    }
+
+    pub fn arm(&self) -> Result<(), Error> {
+        let mut interlocks = self.interlocks();
+        if let Some(error) = interlocks.tripped() {
+            if !matches!(error, ArmingError::EStopped) || interlocks.estop_latched() {
+                return Err(error.into());
+            }
+        }
+        if !self.state.idle.load(Ordering::SeqCst) {
+            return Err(ArmingError::NotIdle.into());
+        }
+        if let Some(ref estop) = interlocks.estop {
+            interlocks.estop_triggers = estop.triggers();
+        }
+        if !self.state.armed.swap(true, Ordering::SeqCst) {
+            info!(message_id = journal::MOTORS_ARMED, "Motors armed");
+        }
+        Ok(())
+    }
+
+    pub fn disarm(&self) {
+        if self.state.armed.swap(false, Ordering::SeqCst) {
+            info!(message_id = journal::MOTORS_DISARMED, "Motors disarmed");
+        }
+    }
+
+    /// Whether armed, disarming first if the e-stop is latched or a drive
+    /// fault raised.
+    pub fn is_armed(&self) -> bool {
+        if !self.state.armed.load(Ordering::SeqCst) {
+            return false;
+        }
+        match self.interlocks().tripped() {
+            Some(cause) => {
+                if self.state.armed.swap(false, Ordering::SeqCst) {
+                    warn!(
+                        message_id = journal::MOTORS_DISARMED,
+                        "Motors disarmed by {}",
+                        match cause {
+                            ArmingError::Faulted => "a drive fault",
+                            _ => "the emergency stop",
+                        }
+                    );
+                }
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Notes whether the latest motor command was a stop.
+    pub(crate) fn record_command(&self, is_stop: bool) {
+        self.state.idle.store(is_stop, Ordering::SeqCst);
+    }
+
+    fn interlocks(&self) -> MutexGuard<'_, Interlocks> {
+        // The interlocks are replaced whole, a poisoned lock is still consistent.
+        self.state
+            .interlocks
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl Interlocks {
+    fn estop_latched(&self) -> bool {
+        self.estop.as_ref().is_some_and(EStopLatch::is_latched)
+    }
+
+    /// What disarms the motors, if anything.
+    fn tripped(&self) -> Option<ArmingError> {
+        let estop_triggered = self
+            .estop
+            .as_ref()
+            .is_some_and(|estop| estop.triggers() != self.estop_triggers);
+        if self.estop_latched() || estop_triggered {
+            Some(ArmingError::EStopped)
+        } else if self.faults.as_ref().is_some_and(FaultGuard::is_faulted) {
+            Some(ArmingError::Faulted)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Shared-secret authentication of the remote control frontends. With a
+//! token set in the `[auth]` section of the configuration file, commands
+//! sent over the web dashboard, UDP or MQTT have to carry it, and clients
+//! without it only get the telemetry:
+//!
+//! ```toml
+//! [auth]
+//! token = "a long random string"
+//! ```
+//!
+//! The token travels in the clear, so this keeps the neighbours from driving
+//! the robot off the bench rather than stopping a determined attacker on the
+//! network. Keep the configuration file readable by vrum only.
+
+use std::fmt::{self, Debug};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("not authenticated, commands need the token")]
+    Unauthenticated,
+    #[error("wrong token")]
+    InvalidToken,
+}
+
+/// The `[auth]` section of the configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Without one every client can send commands.
+    pub token: Option<Token>,
+}
+
+/// A shared secret, kept out of the logs.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Token(String);
+
+impl Token {
+    pub fn new<S: Into<String>>(token: S) -> Self {
+        Token(token.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    /// Compares in constant time, so the comparison doesn't leak how much of
+    /// `candidate` is right.
+    pub fn matches(&self, candidate: &[u8]) -> bool {
+        let expected = self.as_bytes();
+        expected.len() == candidate.len()
+            && expected
+                .iter()
+                .zip(candidate)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}
+
+impl Debug for Token {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("Token(..)")
+    }
+}
+
+/// Whether a client presenting `candidate` may send commands: always when
+/// no token is required.
+pub fn is_authorized(required: Option<&Token>, candidate: Option<&[u8]>) -> bool {
+    match (required, candidate) {
+        (None, _) => true,
+        (Some(token), Some(candidate)) => token.matches(candidate),
+        (Some(_), None) => false,
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::color::Color;
+use crate::config::Config;
+use crate::error::Error;
+use crate::faults::FaultLatch;
+use crate::journal;
+use crate::motor_driver::MotorDriver;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatteryConfig {
+    /// Below this voltage the state changes to `BatteryState::Low` and the LED
+    /// flashes red.
+    pub warn_voltage: f32,
+    /// Below this voltage motor drive is stopped and refused, protecting the
+    /// pack from over-discharge.
+    pub cutoff_voltage: f32,
+    /// How far above a threshold the voltage has to recover before the state
+    /// is cleared, so readings sagging under load don't flap between states.
+    pub hysteresis: f32,
+    pub sample_interval_ms: u64,
+    pub flash_led: bool,
+}
+
+impl Default for BatteryConfig {
+    /// Thresholds for a 3S LiPo pack.
+    fn default() -> Self {
+        BatteryConfig {
+            warn_voltage: 10.5,
+            cutoff_voltage: 9.9,
+            hysteresis: 0.3,
+            sample_interval_ms: 500,
+            flash_led: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatteryState {
+    Ok,
+    Low,
+    Cutoff,
+}
+
+impl BatteryState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => BatteryState::Low,
+            2 => BatteryState::Cutoff,
+            _ => BatteryState::Ok,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            BatteryState::Ok => 0,
+            BatteryState::Low => 1,
+            BatteryState::Cutoff => 2,
+        }
+    }
+
+    fn next(self, voltage: f32, config: &BatteryConfig) -> Self {
+        if voltage < config.cutoff_voltage {
+            return BatteryState::Cutoff;
+        }
+        let recovered_from_cutoff = voltage >= config.cutoff_voltage + config.hysteresis;
+        let recovered_from_low = voltage >= config.warn_voltage + config.hysteresis;
+        match self {
+            BatteryState::Cutoff if !recovered_from_cutoff => BatteryState::Cutoff,
+            _ if voltage < config.warn_voltage => BatteryState::Low,
+            BatteryState::Cutoff | BatteryState::Low if !recovered_from_low => BatteryState::Low,
+            _ => BatteryState::Ok,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatteryEvent {
+    /// The battery state changed, `voltage` is the reading that caused it.
+    StateChanged { state: BatteryState, voltage: f32 },
+    /// The voltage could not be read.
+    ReadFailed,
+}
+
+/// Shared view of the battery state, attached to a `Controller` with
+/// `Controller::set_battery_guard` so motor commands are refused below the
+/// cutoff voltage.
+#[derive(Clone)]
+pub struct BatteryGuard {
+    state: Arc<AtomicU8>,
+}
+
+impl BatteryGuard {
+    pub fn state(&self) -> BatteryState {
+        BatteryState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    pub fn is_cutoff(&self) -> bool {
+        self.state() == BatteryState::Cutoff
+    }
+}
+
+/// Samples the battery voltage on a background thread, warning when it runs
+/// low and stopping the motors when it drops below the cutoff.
+pub struct BatterySupervisor {
+    guard: BatteryGuard,
+    subscribers: Arc<Mutex<Vec<Sender<BatteryEvent>>>>,
+    config_updates: Arc<Mutex<Option<Receiver<Arc<Config>>>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BatterySupervisor {
+    /// `controller` is a dedicated handle used by the supervisor thread to
+    /// read the voltage, drive the LED and stop the motors. Cutoffs latch
+    /// `latch`, if given.
+    pub fn spawn<D>(
+        mut config: BatteryConfig,
+        mut controller: D,
+        latch: Option<FaultLatch>,
+    ) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        let guard = BatteryGuard {
+            state: Arc::new(AtomicU8::new(BatteryState::Ok.to_u8())),
+        };
+        let subscribers: Arc<Mutex<Vec<Sender<BatteryEvent>>>> = Arc::default();
+        let config_updates: Arc<Mutex<Option<Receiver<Arc<Config>>>>> = Arc::default();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_guard = guard.clone();
+        let thread_subscribers = subscribers.clone();
+        let thread_config_updates = config_updates.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-battery".into())
+            .spawn(move || {
+                let mut led_on = false;
+                while thread_running.load(Ordering::SeqCst) {
+                    if let Some(battery) = latest_battery_config(&thread_config_updates) {
+                        info!(
+                            "Battery thresholds now {:.2}V low, {:.2}V cutoff",
+                            battery.warn_voltage, battery.cutoff_voltage
+                        );
+                        config = battery;
+                    }
+                    let previous = thread_guard.state();
+                    let state = match controller.battery_voltage() {
+                        Ok(voltage) => {
+                            let state = previous.next(voltage, &config);
+                            if state != previous {
+                                thread_guard.state.store(state.to_u8(), Ordering::SeqCst);
+                                log_transition(state, voltage);
+                                if let (BatteryState::Cutoff, Some(ref latch)) = (state, &latch) {
+                                    latch.latch(&format!("battery cutoff at {:.2}V", voltage));
+                                }
+                                broadcast(
+                                    &thread_subscribers,
+                                    BatteryEvent::StateChanged { state, voltage },
+                                );
+                            }
+                            state
+                        }
+                        Err(error) => {
+                            warn!("Could not read battery voltage: {}", error);
+                            broadcast(&thread_subscribers, BatteryEvent::ReadFailed);
+                            previous
+                        }
+                    };
+
+                    if let Err(error) =
+                        react(&mut controller, &config, state, previous, &mut led_on)
+                    {
+                        error!(
+                            "Battery supervisor could not react to {:?}: {}",
+                            state, error
+                        );
+                    }
+                    thread::sleep(Duration::from_millis(config.sample_interval_ms));
+                }
+            })?;
+
+        Ok(BatterySupervisor {
+            guard,
+            subscribers,
+            config_updates,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn guard(&self) -> BatteryGuard {
+        self.guard.clone()
+    }
+
+    pub fn state(&self) -> BatteryState {
+        self.guard.state()
+    }
+
+    /// Returns a channel receiving every subsequent `BatteryEvent`.
+    pub fn events(&self) -> Receiver<BatteryEvent> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+
+    /// Picks up the thresholds and LED flashing of every reloaded `Config`
+    /// with a `[battery]` section, see `vrum::reload`.
+    pub fn set_config_updates(&self, updates: Receiver<Arc<Config>>) {
+        if let Ok(mut config_updates) = self.config_updates.lock() {
+            *config_updates = Some(updates);
+        }
+    }
+}
+
+impl Drop for BatterySupervisor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Battery supervisor thread panicked");
+            }
+        }
+    }
+}
+
+/// Battery chemistry, selecting the discharge curve used by `BatterySoc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum Chemistry {
+    /// Three cell lithium polymer pack, 11.1V nominal.
+    #[serde(rename = "lipo_3s")]
+    LiPo3S,
+    /// Ten cell nickel-metal hydride pack (e.g. 10 x AA), 12V nominal.
+    #[serde(rename = "nimh")]
+    NiMh,
+    /// Six cell sealed lead-acid battery, 12V nominal.
+    #[serde(rename = "lead_acid")]
+    LeadAcid,
+}
+
+impl Chemistry {
+    /// Resting pack voltage to charge percentage, sorted by voltage.
+    fn curve(self) -> &'static [(f32, f32)] {
+        match self {
+            Chemistry::LiPo3S => &LIPO_3S_CURVE,
+            Chemistry::NiMh => &NIMH_CURVE,
+            Chemistry::LeadAcid => &LEAD_ACID_CURVE,
+        }
+    }
+
+    /// Charge percentage for a resting pack voltage, interpolating linearly
+    /// between the points of the discharge curve.
+    pub fn percent(self, voltage: f32) -> f32 {
+        let curve = self.curve();
+        let (empty, full) = (curve[0], curve[curve.len() - 1]);
+        if voltage <= empty.0 {
+            return empty.1;
+        }
+        if voltage >= full.0 {
+            return full.1;
+        }
+        curve
+            .windows(2)
+            .find(|points| voltage <= points[1].0)
+            .map(|points| {
+                let ((low_voltage, low_percent), (high_voltage, high_percent)) =
+                    (points[0], points[1]);
+                let fraction = (voltage - low_voltage) / (high_voltage - low_voltage);
+                low_percent + fraction * (high_percent - low_percent)
+            })
+            .unwrap_or(full.1)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocConfig {
+    pub chemistry: Chemistry,
+    /// Weight of a new reading in the exponential moving average of the
+    /// voltage, in `(0, 1]`. Lower values give a steadier estimate.
+    pub smoothing: f32,
+    /// How far the voltage sags with both motors at full power. Readings are
+    /// raised by this amount scaled by the commanded power, estimating the
+    /// resting voltage the discharge curves are defined for.
+    pub load_sag: f32,
+}
+
+impl Default for SocConfig {
+    fn default() -> Self {
+        SocConfig {
+            chemistry: Chemistry::LiPo3S,
+            smoothing: 0.2,
+            load_sag: 0.6,
+        }
+    }
+}
+
+/// Estimates the battery state of charge from voltage readings, see
+/// `Controller::battery_percent`.
+#[derive(Clone, Debug)]
+pub struct BatterySoc {
+    config: SocConfig,
+    filtered_voltage: Option<f32>,
+}
+
+impl BatterySoc {
+    pub fn new(config: SocConfig) -> Self {
+        BatterySoc {
+            config,
+            filtered_voltage: None,
+        }
+    }
+
+    /// Adds a voltage reading taken while the motors were driven at `load`,
+    /// the mean absolute motor power in `[0, 1]`, and returns the updated
+    /// charge percentage.
+    pub fn update(&mut self, voltage: f32, load: f32) -> f32 {
+        let resting_voltage = voltage + self.config.load_sag * load.clamp(0.0, 1.0);
+        let smoothing = self.config.smoothing.clamp(f32::EPSILON, 1.0);
+        let filtered = match self.filtered_voltage {
+            Some(previous) => previous + smoothing * (resting_voltage - previous),
+            None => resting_voltage,
+        };
+        self.filtered_voltage = Some(filtered);
+        self.config.chemistry.percent(filtered)
+    }
+
+    /// Last estimate, `None` before the first reading.
+    pub fn percent(&self) -> Option<f32> {
+        self.filtered_voltage
+            .map(|voltage| self.config.chemistry.percent(voltage))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMethod {
+    /// Exponential moving average weighting new readings by `smoothing`.
+    Exponential,
+    /// Mean of the last `window` readings.
+    Window,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoltageFilterConfig {
+    pub method: FilterMethod,
+    pub smoothing: f32,
+    pub window: usize,
+    /// Readings outside `[min_valid, max_valid]` are bogus ADC samples and
+    /// always discarded.
+    pub min_valid: f32,
+    pub max_valid: f32,
+    /// Readings further than this from the filtered voltage are discarded as
+    /// outliers, unless `max_rejected` of them arrive in a row, in which case
+    /// the voltage really did change and the filter restarts from there.
+    pub max_jump: f32,
+    pub max_rejected: u32,
+}
+
+impl Default for VoltageFilterConfig {
+    fn default() -> Self {
+        VoltageFilterConfig {
+            method: FilterMethod::Exponential,
+            smoothing: 0.3,
+            window: 8,
+            min_valid: 1.0,
+            max_valid: 30.0,
+            max_jump: 2.0,
+            max_rejected: 3,
+        }
+    }
+}
+
+/// Smooths battery voltage readings and rejects outliers, see
+/// `Controller::get_battery_voltage_filtered`.
+#[derive(Clone, Debug)]
+pub struct VoltageFilter {
+    config: VoltageFilterConfig,
+    readings: VecDeque<f32>,
+    filtered: Option<f32>,
+    rejected: u32,
+}
+
+impl VoltageFilter {
+    pub fn new(config: VoltageFilterConfig) -> Self {
+        VoltageFilter {
+            config,
+            readings: VecDeque::new(),
+            filtered: None,
+            rejected: 0,
+        }
+    }
+
+    /// Adds a raw reading and returns the filtered voltage, `None` if no
+    /// plausible reading has been seen yet.
+    pub fn update(&mut self, voltage: f32) -> Option<f32> {
+        if !(self.config.min_valid..=self.config.max_valid).contains(&voltage) {
+            debug!("Discarding implausible battery voltage {:.2}V", voltage);
+            return self.filtered;
+        }
+        if let Some(filtered) = self.filtered {
+            if (voltage - filtered).abs() > self.config.max_jump {
+                self.rejected += 1;
+                if self.rejected < self.config.max_rejected {
+                    debug!(
+                        "Discarding battery voltage outlier {:.2}V (filtered {:.2}V)",
+                        voltage, filtered
+                    );
+                    return self.filtered;
+                }
+                info!(
+                    "Battery voltage settled at {:.2}V, restarting the filter",
+                    voltage
+                );
+                self.reset();
+            }
+        }
+        self.rejected = 0;
+        let filtered = match self.config.method {
+            FilterMethod::Exponential => {
+                let smoothing = self.config.smoothing.clamp(f32::EPSILON, 1.0);
+                self.filtered.map_or(voltage, |previous| {
+                    previous + smoothing * (voltage - previous)
+                })
+            }
+            FilterMethod::Window => {
+                self.readings.push_back(voltage);
+                while self.readings.len() > self.config.window.max(1) {
+                    self.readings.pop_front();
+                }
+                self.readings.iter().sum::<f32>() / self.readings.len() as f32
+            }
+        };
+        self.filtered = Some(filtered);
+        self.filtered
+    }
+
+    pub fn value(&self) -> Option<f32> {
+        self.filtered
+    }
+
+    pub fn reset(&mut self) {
+        self.readings.clear();
+        self.filtered = None;
+        self.rejected = 0;
+    }
+}
+
+fn react<D: MotorDriver>(
+    controller: &mut D,
+    config: &BatteryConfig,
+    state: BatteryState,
+    previous: BatteryState,
+    led_on: &mut bool,
+) -> Result<(), Error> {
+    match state {
+        BatteryState::Cutoff if previous != BatteryState::Cutoff => {
+            (0..controller.num_motors()).try_for_each(|motor| controller.set_motor(motor, 0.0))?;
+            if config.flash_led {
+                controller.set_led_color(Color::RED)?;
+            }
+        }
+        BatteryState::Low if config.flash_led => {
+            *led_on = !*led_on;
+            controller.set_led_color(if *led_on { Color::RED } else { Color::OFF })?;
+        }
+        BatteryState::Ok if config.flash_led && previous != BatteryState::Ok => {
+            *led_on = false;
+            controller.set_led_color(Color::OFF)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn log_transition(state: BatteryState, voltage: f32) {
+    match state {
+        BatteryState::Ok => info!(
+            message_id = journal::BATTERY_RECOVERED,
+            battery_voltage = voltage,
+            "Battery voltage recovered to {:.2}V",
+            voltage
+        ),
+        BatteryState::Low => warn!(
+            message_id = journal::BATTERY_LOW,
+            battery_voltage = voltage,
+            "Battery low: {:.2}V",
+            voltage
+        ),
+        BatteryState::Cutoff => error!(
+            message_id = journal::BATTERY_CUTOFF,
+            battery_voltage = voltage,
+            "Battery voltage {:.2}V below cutoff, stopping the motors",
+            voltage
+        ),
+    }
+}
+
+/// The `[battery]` section of the last `Config` reloaded, if any.
+fn latest_battery_config(updates: &Mutex<Option<Receiver<Arc<Config>>>>) -> Option<BatteryConfig> {
+    let updates = updates.lock().ok()?;
+    let config = updates.as_ref()?.try_iter().last()?;
+    config.battery.clone()
+}
+
+fn broadcast(subscribers: &Mutex<Vec<Sender<BatteryEvent>>>, event: BatteryEvent) {
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
+
+const LIPO_3S_CURVE: [(f32, f32); 12] = [
+    (9.82, 0.0),
+    (10.83, 5.0),
+    (11.07, 10.0),
+    (11.19, 20.0),
+    (11.31, 30.0),
+    (11.40, 40.0),
+    (11.52, 50.0),
+    (11.61, 60.0),
+    (11.85, 70.0),
+    (12.06, 80.0),
+    (12.33, 90.0),
+    (12.60, 100.0),
+];
+const NIMH_CURVE: [(f32, f32); 8] = [
+    (10.0, 0.0),
+    (11.5, 10.0),
+    (12.0, 20.0),
+    (12.3, 30.0),
+    (12.6, 50.0),
+    (13.0, 70.0),
+    (13.5, 90.0),
+    (14.2, 100.0),
+];
+const LEAD_ACID_CURVE: [(f32, f32); 11] = [
+    (10.50, 0.0),
+    (11.51, 10.0),
+    (11.66, 20.0),
+    (11.81, 30.0),
+    (11.96, 40.0),
+    (12.10, 50.0),
+    (12.24, 60.0),
+    (12.37, 70.0),
+    (12.50, 80.0),
+    (12.62, 90.0),
+    (12.73, 100.0),
+];
@@ -0,0 +1,316 @@
+//! A flight recorder keeping the last few seconds of telemetry and motor
+//! commands in memory, dumped to a timestamped file on a panic, a fatal
+//! error or a latched fault, to reconstruct what the robot was doing when it
+//! misbehaved. Configured in the `[black_box]` section of the configuration
+//! file.
+//!
+//! A dump is a JSON object with the `reason`, the `samples` of telemetry,
+//! the `commands` and the notable `events` of `vrum::history`.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::history::{EventHistory, HistoryEvent};
+use crate::motor_driver::MotorDriver;
+use crate::recorder::RecordedCommand;
+use crate::telemetry::{self, TelemetryPoller, TelemetrySample};
+
+/// The `[black_box]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlackBoxConfig {
+    /// Where dumps are written, as `vrum-black-box-<UTC time>.json`.
+    pub dir: PathBuf,
+    /// How far back a dump goes.
+    pub window_ms: u64,
+    pub sample_interval_ms: u64,
+}
+
+impl Default for BlackBoxConfig {
+    fn default() -> Self {
+        BlackBoxConfig {
+            dir: PathBuf::from("/var/lib/vrum"),
+            window_ms: 10_000,
+            sample_interval_ms: 100,
+        }
+    }
+}
+
+/// A motor or LED command and when it was issued.
+#[derive(Clone, Debug, Serialize)]
+pub struct TimedCommand {
+    /// Seconds since the UNIX epoch.
+    pub timestamp: f64,
+    #[serde(flatten)]
+    pub command: RecordedCommand,
+}
+
+/// The recording, shared between the `BlackBoxRecorder` sampling the board
+/// and the `Controller` issuing the commands, see
+/// `Controller::set_black_box`.
+#[derive(Clone)]
+pub struct BlackBox {
+    config: BlackBoxConfig,
+    recording: Arc<Mutex<Recording>>,
+}
+
+#[derive(Default)]
+struct Recording {
+    samples: VecDeque<TelemetrySample>,
+    commands: VecDeque<TimedCommand>,
+    /// Powers of motors A and B after the last command, the samples are
+    /// read through another handle which doesn't see them.
+    powers: (f32, f32),
+}
+
+#[derive(Serialize)]
+struct Dump<'a> {
+    reason: &'a str,
+    /// Seconds since the UNIX epoch.
+    timestamp: f64,
+    samples: &'a VecDeque<TelemetrySample>,
+    commands: &'a VecDeque<TimedCommand>,
+    events: Vec<HistoryEvent>,
+}
+
+impl BlackBox {
+    pub fn new(config: BlackBoxConfig) -> Self {
+        BlackBox {
+            config,
+            recording: Arc::default(),
+        }
+    }
+
+    /// The black box dumped by `black_box::dump()`, the first one installed.
+    pub fn installed() -> Option<BlackBox> {
+        INSTALLED.get().cloned()
+    }
+
+    /// Makes this the black box dumped by `black_box::dump()`, unless one is
+    /// installed already.
+    pub fn install(&self) {
+        if INSTALLED.set(self.clone()).is_err() {
+            warn!("A black box is installed already, keeping it");
+        }
+    }
+
+    /// Notes a command the controller issued, with the motor powers after it.
+    pub fn record_command(&self, command: &RecordedCommand, powers: (f32, f32)) {
+        let timestamp = telemetry::unix_timestamp();
+        let mut recording = self.lock();
+        recording.commands.push_back(TimedCommand {
+            timestamp,
+            command: command.clone(),
+        });
+        recording.powers = powers;
+        self.forget_before(&mut recording, timestamp);
+    }
+
+    /// Keeps `sample`, with the powers of the last command.
+    pub fn record_sample(&self, mut sample: TelemetrySample) {
+        let timestamp = sample.timestamp;
+        let mut recording = self.lock();
+        (sample.motor_a_power, sample.motor_b_power) = recording.powers;
+        recording.samples.push_back(sample);
+        self.forget_before(&mut recording, timestamp);
+    }
+
+    /// Writes the recording to a new file in the configured directory and
+    /// returns its path.
+    pub fn dump(&self, reason: &str) -> Result<PathBuf, Error> {
+        let timestamp = telemetry::unix_timestamp();
+        let path = self
+            .config
+            .dir
+            .join(format!("vrum-black-box-{}.json", utc_time(timestamp)));
+        let events = EventHistory::global().events();
+        let json = {
+            let recording = self.lock();
+            serde_json::to_vec_pretty(&Dump {
+                reason,
+                timestamp,
+                samples: &recording.samples,
+                commands: &recording.commands,
+                events,
+            })?
+        };
+        fs::create_dir_all(&self.config.dir)?;
+        fs::write(&path, json)?;
+        Ok(path)
+    }
+
+    fn forget_before(&self, recording: &mut Recording, now: f64) {
+        let oldest = now - Duration::from_millis(self.config.window_ms).as_secs_f64();
+        while recording
+            .samples
+            .front()
+            .is_some_and(|sample| sample.timestamp < oldest)
+        {
+            recording.samples.pop_front();
+        }
+        while recording
+            .commands
+            .front()
+            .is_some_and(|command| command.timestamp < oldest)
+        {
+            recording.commands.pop_front();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Recording> {
+        // Entries are pushed whole, a poisoned recording is still consistent,
+        // and a panic is when it matters most.
+        self.recording.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+static INSTALLED: OnceLock<BlackBox> = OnceLock::new();
+
+/// Dumps the installed black box, if any, logging where to.
+pub fn dump(reason: &str) {
+    if let Some(black_box) = BlackBox::installed() {
+        match black_box.dump(reason) {
+            Ok(path) => warn!("Black box dumped to {}", path.display()),
+            Err(error) => error!("Could not dump the black box: {}", error),
+        }
+    }
+}
+
+/// Dumps the installed black box on panics, before the panic is reported
+/// as usual.
+pub fn dump_on_panic() {
+    let report = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        dump(&format!("panic: {}", info));
+        report(info);
+    }));
+}
+
+/// Samples the board, or follows a `TelemetryPoller`, on a background
+/// thread into a `BlackBox`, installed for `black_box::dump()`. Dropping it
+/// stops sampling, the recording is kept.
+pub struct BlackBoxRecorder {
+    black_box: BlackBox,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BlackBoxRecorder {
+    /// `controller` is a dedicated handle used by the recorder thread to
+    /// read the battery, the fault flags and the bus statistics.
+    pub fn spawn<D>(config: BlackBoxConfig, mut controller: D) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        let interval = Duration::from_millis(config.sample_interval_ms);
+        BlackBoxRecorder::start(config, move || {
+            let sample = TelemetrySample::read(&mut controller)
+                .map_err(|error| debug!("Black box could not read a sample: {}", error))
+                .ok();
+            thread::sleep(interval);
+            sample
+        })
+    }
+
+    /// Records the samples of `poller` instead of reading the board, at the
+    /// rate of the poller.
+    pub fn follow(config: BlackBoxConfig, poller: &TelemetryPoller) -> Result<Self, Error> {
+        let interval = Duration::from_millis(config.sample_interval_ms);
+        let samples = poller.samples();
+        BlackBoxRecorder::start(config, move || match samples.recv_timeout(interval) {
+            Ok(sample) => Some(sample),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(interval);
+                None
+            }
+        })
+    }
+
+    pub fn black_box(&self) -> BlackBox {
+        self.black_box.clone()
+    }
+
+    /// Records what `next_sample` returns until dropped, it should block
+    /// for about a sample interval.
+    fn start<F>(config: BlackBoxConfig, mut next_sample: F) -> Result<Self, Error>
+    where
+        F: FnMut() -> Option<TelemetrySample> + Send + 'static,
+    {
+        info!(
+            "Recording the last {:?} to dump to {} on a crash",
+            Duration::from_millis(config.window_ms),
+            config.dir.display()
+        );
+        let black_box = BlackBox::new(config);
+        black_box.install();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_black_box = black_box.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-black-box".into())
+            .spawn(move || {
+                while thread_running.load(Ordering::SeqCst) {
+                    if let Some(sample) = next_sample() {
+                        thread_black_box.record_sample(sample);
+                    }
+                }
+            })?;
+
+        Ok(BlackBoxRecorder {
+            black_box,
+            running,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for BlackBoxRecorder {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Black box recorder thread panicked");
+            }
+        }
+    }
+}
+
+/// `timestamp`, in seconds since the UNIX epoch, as e.g.
+/// `20240131T235959.123Z`.
+pub(crate) fn utc_time(timestamp: f64) -> String {
+    let millis = (timestamp * 1000.0) as i64;
+    let (days, millis_of_day) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+    // Days since the epoch to a civil date, from Howard Hinnant's
+    // `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let seconds = millis_of_day / 1000;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis_of_day % 1000
+    )
+}
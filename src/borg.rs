@@ -0,0 +1,695 @@
+//! Command/response machinery shared by the PiBorg boards. Every board
+//! speaks the same protocol over I2C: a command byte optionally followed by
+//! data, and for queries a fixed length response echoing the command byte.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arrayvec::ArrayVec;
+use i2cdev::core::*;
+use i2cdev::linux::LinuxI2CDevice;
+use rppal::gpio::{Gpio, Mode};
+
+use crate::error::Error;
+use crate::journal;
+use crate::latency::LatencyHistogram;
+
+/// Byte transport to a board, the I2C bus on a real robot.
+pub trait Bus: Send {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+
+    /// Writes `bytes` then reads back into `buffer`. Shared buses (see
+    /// `BusManager`) keep other devices off the bus in between.
+    fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.write(bytes)?;
+        self.read(buffer)
+    }
+
+    /// Opens the bus again when recovering from persistent failures, if the
+    /// transport can.
+    fn reopen(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Bus for LinuxI2CDevice {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        I2CDevice::write(self, bytes)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        I2CDevice::read(self, buffer)?;
+        Ok(())
+    }
+}
+
+/// A board command, `to_wire()` is the command byte sent on the bus.
+pub trait BorgCommand: Display {
+    fn to_wire(&self) -> u8;
+
+    /// Number of bytes the board answers this query with, `None` for the
+    /// usual response length of the board.
+    fn response_len(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A command byte the crate doesn't model, see `BorgDevice::send_raw`.
+struct RawCommand {
+    command: u8,
+    response_len: Option<usize>,
+}
+
+impl Display for RawCommand {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Raw (0x{:x})", self.command)
+    }
+}
+
+impl BorgCommand for RawCommand {
+    fn to_wire(&self) -> u8 {
+        self.command
+    }
+
+    fn response_len(&self) -> Option<usize> {
+        self.response_len
+    }
+}
+
+/// Response to a query, the first byte echoes the command. The accessors
+/// are bounds checked against the number of bytes actually read, so a
+/// truncated response is an error rather than a reading of zeros.
+#[derive(Clone, Copy)]
+pub struct Response {
+    bytes: [u8; MAX_RESPONSE_LEN],
+    len: usize,
+}
+
+impl Response {
+    /// The command byte echoed by the board.
+    pub fn command(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// Every byte read, including the command byte.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// The bytes following the command byte.
+    pub fn data(&self) -> &[u8] {
+        &self.bytes[1..self.len]
+    }
+
+    pub fn byte(&self, offset: usize) -> Result<u8, Error> {
+        Ok(self.field(offset, 1)?[0])
+    }
+
+    /// A byte used as a boolean flag, anything but 0 is set.
+    pub fn flag(&self, offset: usize) -> Result<bool, Error> {
+        Ok(self.byte(offset)? != 0)
+    }
+
+    /// A big endian 16 bit value.
+    pub fn u16_be(&self, offset: usize) -> Result<u16, Error> {
+        let field = self.field(offset, 2)?;
+        Ok(u16::from_be_bytes([field[0], field[1]]))
+    }
+
+    fn field(&self, offset: usize, width: usize) -> Result<&[u8], Error> {
+        self.as_bytes()
+            .get(offset..offset + width)
+            .ok_or_else(|| Error::ResponseTooShort {
+                offset,
+                width,
+                raw: self.as_bytes().to_vec(),
+            })
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Response({:?})", self.as_bytes())
+    }
+}
+
+/// Identification of a board, for inventories of a fleet of robots.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BoardInfo {
+    /// Kind of board, e.g. "ThunderBorg".
+    pub board: String,
+    /// Linux I2C bus the board is on, `None` for other buses.
+    pub bus: Option<PathBuf>,
+    pub address: Option<u16>,
+    /// Identifier byte reported by the board.
+    pub id: u8,
+    /// Firmware revision, `None` if the board can't report it.
+    pub firmware: Option<String>,
+}
+
+impl Display for BoardInfo {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} (id 0x{:x})", self.board, self.id)?;
+        if let (Some(bus), Some(address)) = (&self.bus, self.address) {
+            write!(formatter, " at {} address 0x{:x}", bus.display(), address)?;
+        }
+        if let Some(ref firmware) = self.firmware {
+            write!(formatter, ", firmware {}", firmware)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bus traffic for one command type, see `BorgDevice::comm_stats`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CommandStats {
+    pub name: String,
+    /// Commands written, and queries answered, successfully.
+    pub completed: u64,
+    /// Queries sent again after a bad response.
+    pub retries: u64,
+    /// Responses that didn't echo the command byte, a sign of a flaky bus.
+    pub mismatched_headers: u64,
+    /// Bus errors, and queries that got no valid response in any attempt.
+    pub failures: u64,
+    /// Sum of the round trip times of completed commands, in microseconds.
+    pub total_latency_us: u64,
+    pub max_latency_us: u64,
+    /// Round trip times of completed commands.
+    pub latency: LatencyHistogram,
+}
+
+impl CommandStats {
+    /// Mean round trip time of completed commands.
+    pub fn mean_latency(&self) -> Duration {
+        self.total_latency_us
+            .checked_div(self.completed)
+            .map_or(Duration::default(), Duration::from_micros)
+    }
+
+    fn record_latency(&mut self, started: Instant) -> u64 {
+        let latency_us = started.elapsed().as_micros() as u64;
+        self.completed += 1;
+        self.total_latency_us += latency_us;
+        self.max_latency_us = self.max_latency_us.max(latency_us);
+        self.latency.record(Duration::from_micros(latency_us));
+        latency_us
+    }
+}
+
+/// Bus traffic statistics of a board, by command byte.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CommStats {
+    pub commands: BTreeMap<u8, CommandStats>,
+    /// Times the bus was recovered after persistent failures.
+    pub recoveries: u64,
+}
+
+impl CommStats {
+    /// Statistics summed over every command type.
+    pub fn total(&self) -> CommandStats {
+        self.commands.values().fold(
+            CommandStats {
+                name: "total".into(),
+                ..CommandStats::default()
+            },
+            |mut total, stats| {
+                total.completed += stats.completed;
+                total.retries += stats.retries;
+                total.mismatched_headers += stats.mismatched_headers;
+                total.failures += stats.failures;
+                total.total_latency_us += stats.total_latency_us;
+                total.max_latency_us = total.max_latency_us.max(stats.max_latency_us);
+                total.latency.merge(&stats.latency);
+                total
+            },
+        )
+    }
+
+    fn entry<C: BorgCommand>(&mut self, command: &C) -> &mut CommandStats {
+        self.commands
+            .entry(command.to_wire())
+            .or_insert_with(|| CommandStats {
+                name: command.to_string(),
+                ..CommandStats::default()
+            })
+    }
+}
+
+/// How a `BorgDevice` gets a wedged bus going again, e.g. after a slave
+/// was reset mid-transfer and holds the data line low.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecoveryConfig {
+    pub enabled: bool,
+    /// Consecutive failed commands, each after every retry, before the bus
+    /// is recovered.
+    pub failures_before_recovery: u32,
+    /// BCM number of the I2C clock pin (3 on a Raspberry Pi), clocked nine
+    /// times during recovery to make a stuck slave release the data line.
+    /// Left alone if missing.
+    pub scl_pin: Option<u8>,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        RecoveryConfig {
+            enabled: true,
+            failures_before_recovery: 2,
+            scl_pin: None,
+        }
+    }
+}
+
+/// Query checking a board is there after recovery: `command` should be
+/// answered with `id`.
+#[derive(Clone, Copy, Debug)]
+pub struct Ping {
+    pub command: u8,
+    pub id: u8,
+}
+
+/// A board on a `Bus`, sending commands and reading back responses.
+pub struct BorgDevice {
+    /// Shared with the devices made by `share()`.
+    bus: Arc<Mutex<Box<dyn Bus>>>,
+    response_len: usize,
+    stats: CommStats,
+    location: Option<(PathBuf, u16)>,
+    recovery: Option<(RecoveryConfig, Ping)>,
+    consecutive_failures: u32,
+}
+
+impl BorgDevice {
+    /// `response_len` is the number of bytes the board sends back to a
+    /// query, at most `MAX_RESPONSE_LEN`.
+    pub fn new(bus: Box<dyn Bus>, response_len: usize) -> Self {
+        assert!(response_len <= MAX_RESPONSE_LEN);
+        BorgDevice {
+            bus: Arc::new(Mutex::new(bus)),
+            response_len,
+            stats: CommStats::default(),
+            location: None,
+            recovery: None,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Opens the board at `address` on the Linux I2C bus at `bus_path`.
+    pub fn open<P: AsRef<Path>>(
+        bus_path: P,
+        address: u16,
+        response_len: usize,
+    ) -> Result<Self, Error> {
+        let device = LinuxI2CDevice::new(bus_path.as_ref(), address)?;
+        let mut borg_device = BorgDevice::new(Box::new(device), response_len);
+        borg_device.location = Some((bus_path.as_ref().to_path_buf(), address));
+        Ok(borg_device)
+    }
+
+    /// Enables recovery from persistent bus failures, checking the board
+    /// answers `ping` afterwards. The device is reopened if it was created
+    /// with `open()`, otherwise its `Bus` is, see `Bus::reopen`.
+    pub fn with_recovery(mut self, config: RecoveryConfig, ping: Ping) -> Self {
+        self.recovery = if config.enabled {
+            Some((config, ping))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Another device on the same bus and address, e.g. for a background
+    /// thread, with its own statistics. Transfers of the two don't
+    /// interleave, and the bus recovered by this device is the one the
+    /// other uses too.
+    pub fn share(&self) -> BorgDevice {
+        BorgDevice {
+            bus: self.bus.clone(),
+            response_len: self.response_len,
+            stats: CommStats::default(),
+            location: self.location.clone(),
+            recovery: None,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn comm_stats(&self) -> &CommStats {
+        &self.stats
+    }
+
+    pub fn reset_comm_stats(&mut self) {
+        self.stats = CommStats::default();
+    }
+
+    pub fn command<C: BorgCommand>(&mut self, command: &C, data: &[u8]) -> Result<(), Error> {
+        if data.len() >= MAX_COMMAND_LEN {
+            return Err(Error::CommandTooLong {
+                len: data.len(),
+                max: MAX_COMMAND_LEN - 1,
+            });
+        }
+        let _span = debug_span!("i2c_command", command = %command, data = ?data).entered();
+        let mut command_bytes = ArrayVec::<[u8; MAX_COMMAND_LEN]>::new();
+        command_bytes.push(command.to_wire());
+        command_bytes.extend(data.iter().cloned());
+        let started = Instant::now();
+        let mut result = self.bus().write(&command_bytes);
+        if result.is_err() && self.note_failure() {
+            self.recover()?;
+            result = self.bus().write(&command_bytes);
+        }
+        let stats = self.stats.entry(command);
+        match result {
+            Ok(()) => {
+                let latency_us = stats.record_latency(started);
+                debug!(latency_us, "Command written");
+                self.consecutive_failures = 0;
+            }
+            Err(ref error) => {
+                stats.failures += 1;
+                debug!(%error, "Command failed");
+            }
+        }
+        result
+    }
+
+    /// Sends a query, retrying if the response doesn't echo the command.
+    /// Reads `command.response_len()` bytes, or the usual response length
+    /// of the board.
+    pub fn command_with_response<C: BorgCommand>(
+        &mut self,
+        command: &C,
+    ) -> Result<Response, Error> {
+        let response_len = command.response_len().unwrap_or(self.response_len);
+        assert!(response_len <= MAX_RESPONSE_LEN);
+        match self.query(command, response_len) {
+            Ok(response) => {
+                self.consecutive_failures = 0;
+                Ok(response)
+            }
+            failed => {
+                if !self.note_failure() {
+                    return failed;
+                }
+                self.recover()?;
+                let response = self.query(command, response_len)?;
+                self.consecutive_failures = 0;
+                Ok(response)
+            }
+        }
+    }
+
+    /// Identifies the board, `id_command` being the query answered with its
+    /// identifier byte.
+    pub fn board_info<C: BorgCommand>(
+        &mut self,
+        board: &str,
+        id_command: &C,
+    ) -> Result<BoardInfo, Error> {
+        let response = self.command_with_response(id_command)?;
+        Ok(BoardInfo {
+            board: board.into(),
+            bus: self.location.as_ref().map(|(bus, _)| bus.clone()),
+            address: self.location.as_ref().map(|&(_, address)| address),
+            id: response.byte(1)?,
+            firmware: None,
+        })
+    }
+
+    /// Writes an arbitrary command byte followed by `data`, for commands of
+    /// newer firmware revisions the crate doesn't model yet.
+    pub fn send_raw(&mut self, command: u8, data: &[u8]) -> Result<(), Error> {
+        let command = RawCommand {
+            command,
+            response_len: None,
+        };
+        self.command(&command, data)
+    }
+
+    /// Sends an arbitrary query and reads back `read_len` bytes, including
+    /// the echoed command byte.
+    pub fn query_raw(&mut self, command: u8, read_len: usize) -> Result<Response, Error> {
+        if read_len == 0 || read_len > MAX_RESPONSE_LEN {
+            return Err(Error::InvalidReadLength {
+                len: read_len,
+                max: MAX_RESPONSE_LEN,
+            });
+        }
+        let command = RawCommand {
+            command,
+            response_len: Some(read_len),
+        };
+        self.command_with_response(&command)
+    }
+
+    /// Counts a failed command, true if it is time to recover the bus.
+    fn note_failure(&mut self) -> bool {
+        self.consecutive_failures += 1;
+        self.recovery
+            .as_ref()
+            .is_some_and(|(config, _)| self.consecutive_failures >= config.failures_before_recovery)
+    }
+
+    /// Clocks the bus free, reopens the device and pings the board.
+    fn recover(&mut self) -> Result<(), Error> {
+        let (config, ping) = match self.recovery {
+            Some((ref config, ping)) => (config.clone(), ping),
+            None => return Ok(()),
+        };
+        warn!(
+            message_id = journal::BUS_RECOVERY,
+            "{} failed commands in a row, recovering the I2C bus", self.consecutive_failures
+        );
+        self.consecutive_failures = 0;
+        self.stats.recoveries += 1;
+        if let Some(pin) = config.scl_pin {
+            if let Err(error) = clock_out_bus(pin) {
+                warn!("Could not clock the I2C bus free: {}", error);
+            }
+        }
+        if let Some((ref bus_path, address)) = self.location {
+            *self.bus() = Box::new(LinuxI2CDevice::new(bus_path, address)?);
+        } else {
+            self.bus().reopen()?;
+        }
+
+        let mut response = Response {
+            bytes: [0u8; MAX_RESPONSE_LEN],
+            len: self.response_len,
+        };
+        self.bus()
+            .write_read(&[ping.command], &mut response.bytes[..response.len])?;
+        if response.command() != ping.command || response.byte(1)? != ping.id {
+            return Err(Error::RecoveryFailed {
+                response: response.as_bytes().to_vec(),
+            });
+        }
+        info!("I2C bus recovered");
+        Ok(())
+    }
+
+    /// Sends a query, retrying if the response doesn't echo the command.
+    fn query<C: BorgCommand>(
+        &mut self,
+        command: &C,
+        response_len: usize,
+    ) -> Result<Response, Error> {
+        let _span = debug_span!("i2c_query", command = %command, response_len).entered();
+        let wire_command = command.to_wire();
+        let started = Instant::now();
+        let mut response = Response {
+            bytes: [0u8; MAX_RESPONSE_LEN],
+            len: response_len,
+        };
+        for attempt in 0..COMMAND_NUM_ATTEMPTS {
+            if attempt > 0 {
+                self.stats.entry(command).retries += 1;
+            }
+            let _attempt = debug_span!("attempt", attempt = attempt + 1).entered();
+            response.bytes = [0u8; MAX_RESPONSE_LEN];
+            if let Err(error) = self.query_once(wire_command, &mut response.bytes[..response_len]) {
+                self.stats.entry(command).failures += 1;
+                debug!(%error, "Query failed");
+                return Err(error);
+            }
+            debug!(response = ?response.as_bytes(), "Read response");
+            if response.command() == wire_command {
+                let latency_us = self.stats.entry(command).record_latency(started);
+                debug!(latency_us, "Query answered");
+                return Ok(response);
+            }
+            self.stats.entry(command).mismatched_headers += 1;
+            info!(
+                read = response.command(),
+                "Retrying, the response doesn't echo the command"
+            );
+        }
+        error!(
+            message_id = journal::COMMAND_FAILED,
+            attempts = COMMAND_NUM_ATTEMPTS,
+            "Failed to run command {}",
+            command
+        );
+        self.stats.entry(command).failures += 1;
+        Err(Error::CommandFailed {
+            command: command.to_string(),
+            attempts: COMMAND_NUM_ATTEMPTS,
+            raw: response.as_bytes().to_vec(),
+        })
+    }
+
+    fn query_once(&mut self, wire_command: u8, response: &mut [u8]) -> Result<(), Error> {
+        self.bus().write_read(&[wire_command], response)
+    }
+
+    fn bus(&self) -> MutexGuard<'_, Box<dyn Bus>> {
+        // Nothing is left half updated by a transfer that panicked.
+        self.bus
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Toggles the clock line nine times, enough for a slave stuck mid-byte to
+/// finish it and release the data line. The pin goes back to its I2C
+/// function when dropped.
+fn clock_out_bus(scl_pin: u8) -> Result<(), Error> {
+    let mut scl = Gpio::new()?.get(scl_pin)?.into_io(Mode::Output);
+    for _ in 0..9 {
+        scl.set_low();
+        thread::sleep(SCL_HALF_PERIOD);
+        scl.set_high();
+        thread::sleep(SCL_HALF_PERIOD);
+    }
+    Ok(())
+}
+
+/// Per-channel corrections applied to every command sent to a motor.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MotorConfig {
+    /// Reverse the direction of the motor, for motors wired backwards.
+    pub inverted: bool,
+    /// Scale factor applied to the motor power, used to slow down the faster
+    /// motor so the robot drives straight.
+    pub trim: f32,
+    /// Smallest power that actually turns the motor against static friction.
+    /// Commanded powers in `(dead_zone, 1]` are remapped to `[min_power, 1]`.
+    pub min_power: f32,
+    /// Commanded powers with a magnitude at or below this are sent as zero,
+    /// so noise around zero doesn't jump straight to `min_power`.
+    pub dead_zone: f32,
+}
+
+impl Default for MotorConfig {
+    fn default() -> Self {
+        MotorConfig {
+            inverted: false,
+            trim: 1.0,
+            min_power: 0.0,
+            dead_zone: 0.0,
+        }
+    }
+}
+
+impl MotorConfig {
+    #[inline]
+    pub(crate) fn to_wire_power(self, power: f32) -> f32 {
+        self.orient(self.remap_dead_zone(self.apply_trim(power)))
+    }
+
+    #[inline]
+    pub(crate) fn apply_trim(self, power: f32) -> f32 {
+        clamp_motor_power(power * self.trim)
+    }
+
+    #[inline]
+    pub(crate) fn orient(self, power: f32) -> f32 {
+        if self.inverted {
+            -power
+        } else {
+            power
+        }
+    }
+
+    #[inline]
+    pub(crate) fn remap_dead_zone(self, power: f32) -> f32 {
+        let dead_zone = self.dead_zone.clamp(0.0, 1.0);
+        let magnitude = power.abs();
+        if magnitude <= dead_zone {
+            return 0.0;
+        }
+        let min_power = self.min_power.clamp(0.0, 1.0);
+        let scaled = (magnitude - dead_zone) / (1.0 - dead_zone);
+        (min_power + (1.0 - min_power) * scaled).copysign(power)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MotorsConfig {
+    pub a: MotorConfig,
+    pub b: MotorConfig,
+}
+
+/// Writes `value` to `register` of a plain register based chip, e.g. the
+/// sensors of an XLoBorg or an IMU.
+pub(crate) fn write_register(bus: &mut dyn Bus, register: u8, value: u8) -> Result<(), Error> {
+    bus.write(&[register, value])
+}
+
+/// Reads consecutive registers of a plain register based chip, starting at
+/// `register`.
+pub(crate) fn read_registers(
+    bus: &mut dyn Bus,
+    register: u8,
+    buffer: &mut [u8],
+) -> Result<(), Error> {
+    bus.write_read(&[register], buffer)
+}
+
+/// Clamps a motor power to `[-1, 1]`. NaN is passed through, to be rejected
+/// by `motor_power_to_byte`.
+#[inline]
+pub fn clamp_motor_power(value: f32) -> f32 {
+    value.clamp(-1.0, 1.0)
+}
+
+/// The duty cycle byte sent for a motor power in `[-1, 1]`, the direction
+/// being a separate command. Full power is 255 and powers are rounded to
+/// the nearest step, so e.g. 0.999 is still full power. Fails with
+/// `Error::InvalidMotorPower` on powers out of range, and NaN.
+#[inline]
+pub fn motor_power_to_byte(value: f32) -> Result<u8, Error> {
+    if !(-1.0..=1.0).contains(&value) {
+        return Err(Error::InvalidMotorPower { power: value });
+    }
+    Ok((value.abs() * PWM_MAX).round() as u8)
+}
+
+/// The motor power magnitude of a duty cycle byte, the inverse of
+/// `motor_power_to_byte`.
+#[inline]
+pub fn byte_to_motor_power(byte: u8) -> f32 {
+    f32::from(byte) / PWM_MAX
+}
+
+pub const DEFAULT_I2C_BUS_PATH: &str = "/dev/i2c-1";
+pub const MAX_RESPONSE_LEN: usize = 24;
+
+const MAX_COMMAND_LEN: usize = 6;
+
+// Duty cycle byte of full power.
+const PWM_MAX: f32 = 255.0;
+
+const COMMAND_NUM_ATTEMPTS: usize = 3;
+
+// Half a period of a 100kHz I2C clock.
+const SCL_HALF_PERIOD: Duration = Duration::from_micros(5);
@@ -0,0 +1,265 @@
+//! Bump and limit switches. A `BumperMonitor` listens to switches wired to
+//! GPIO pins at the front and the rear of the robot. A press immediately
+//! runs a stop action on the GPIO interrupt thread, and while a switch is
+//! held the `BumperStop` stage of the drive pipeline, attached with
+//! `Controller::set_bumper_guard`, zeroes the powers driving into it.
+//! Backing away from the obstacle is not limited.
+
+use std::fmt::{self, Display};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rppal::gpio::{Gpio, InputPin, Trigger};
+
+use crate::error::Error;
+use crate::motor_driver::MotorDriver;
+
+/// The `[bumpers]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BumperConfig {
+    pub switches: Vec<SwitchConfig>,
+    pub debounce_ms: u64,
+}
+
+impl Default for BumperConfig {
+    fn default() -> Self {
+        BumperConfig {
+            switches: Vec::new(),
+            debounce_ms: 20,
+        }
+    }
+}
+
+/// One switch, a `[[bumpers.switches]]` entry.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwitchConfig {
+    /// BCM number of the GPIO pin the switch is wired to.
+    pub pin: u8,
+    pub side: Side,
+    /// True if the switch pulls the pin low when pressed (the usual wiring,
+    /// using the internal pull-up), false if it pulls it high.
+    #[serde(default = "default_active_low")]
+    pub active_low: bool,
+}
+
+/// Where a switch is mounted, and so the direction of motion it blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Front,
+    Rear,
+}
+
+impl Display for Side {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Side::Front => "front",
+            Side::Rear => "rear",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BumperEvent {
+    /// The switch on GPIO `pin` was pressed.
+    Pressed {
+        side: Side,
+        pin: u8,
+    },
+    Released {
+        side: Side,
+        pin: u8,
+    },
+}
+
+/// Which switches are pressed, shared between a `BumperMonitor` and the
+/// `BumperStop` stage. Switch states can also be fed in with `set_pressed()`.
+#[derive(Clone, Default)]
+pub struct BumperGuard {
+    state: Arc<GuardState>,
+}
+
+#[derive(Default)]
+struct GuardState {
+    front: Mutex<Vec<u8>>,
+    rear: Mutex<Vec<u8>>,
+}
+
+impl BumperGuard {
+    pub fn new() -> Self {
+        BumperGuard::default()
+    }
+
+    /// Records the state of the switch on `pin`, returning whether it
+    /// changed.
+    pub fn set_pressed(&self, side: Side, pin: u8, pressed: bool) -> bool {
+        let mut pins = self.pins(side);
+        let position = pins.iter().position(|&p| p == pin);
+        match (pressed, position) {
+            (true, None) => pins.push(pin),
+            (false, Some(position)) => {
+                pins.swap_remove(position);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Whether any switch on `side` is pressed.
+    pub fn is_pressed(&self, side: Side) -> bool {
+        !self.pins(side).is_empty()
+    }
+
+    /// Whether `power` drives towards a pressed switch, forward powers being
+    /// positive.
+    pub fn blocks(&self, power: f32) -> bool {
+        (power > 0.0 && self.is_pressed(Side::Front))
+            || (power < 0.0 && self.is_pressed(Side::Rear))
+    }
+
+    fn pins(&self, side: Side) -> MutexGuard<'_, Vec<u8>> {
+        let pins = match side {
+            Side::Front => &self.state.front,
+            Side::Rear => &self.state.rear,
+        };
+        // The pins are only pushed and removed, a panic can't leave them
+        // inconsistent.
+        pins.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Listens to the bump switches on GPIO pins. Dropping the `BumperMonitor`
+/// stops listening.
+pub struct BumperMonitor {
+    _pins: Vec<InputPin>,
+    guard: BumperGuard,
+    subscribers: Arc<Mutex<Vec<Sender<BumperEvent>>>>,
+}
+
+impl BumperMonitor {
+    /// `stop` is called with the side of the switch at every press, on the
+    /// GPIO interrupt thread, and should stop the motors straight away.
+    pub fn spawn<F>(config: &BumperConfig, stop: F) -> Result<Self, Error>
+    where
+        F: FnMut(Side) -> Result<(), Error> + Send + 'static,
+    {
+        let gpio = Gpio::new()?;
+        let guard = BumperGuard::new();
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(Mutex::new(stop));
+        let debounce = Some(Duration::from_millis(config.debounce_ms));
+
+        let mut pins = Vec::with_capacity(config.switches.len());
+        for switch in &config.switches {
+            info!(
+                "Listening for the {} bumper on GPIO {} (active {})",
+                switch.side,
+                switch.pin,
+                if switch.active_low { "low" } else { "high" }
+            );
+            let pin = gpio.get(switch.pin)?;
+            let mut pin = if switch.active_low {
+                pin.into_input_pullup()
+            } else {
+                pin.into_input_pulldown()
+            };
+            let active_low = switch.active_low;
+            let is_active = move |high: bool| high != active_low;
+            let callback_switch = switch.clone();
+
+            let callback_guard = guard.clone();
+            let callback_subscribers = subscribers.clone();
+            let callback_stop = stop.clone();
+            pin.set_async_interrupt(Trigger::Both, debounce, move |event| {
+                let pressed = is_active(event.trigger == Trigger::RisingEdge);
+                switch_changed(
+                    &callback_switch,
+                    pressed,
+                    &callback_guard,
+                    &callback_subscribers,
+                    &callback_stop,
+                );
+            })?;
+
+            if is_active(pin.is_high()) {
+                switch_changed(switch, true, &guard, &subscribers, &stop);
+            }
+            pins.push(pin);
+        }
+
+        Ok(BumperMonitor {
+            _pins: pins,
+            guard,
+            subscribers,
+        })
+    }
+
+    /// Spawns a monitor that owns a dedicated handle to the board, used only
+    /// to switch the motors off when a switch is pressed.
+    pub fn with_controller<D>(config: &BumperConfig, mut controller: D) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        BumperMonitor::spawn(config, move |_| controller.stop_all())
+    }
+
+    pub fn guard(&self) -> BumperGuard {
+        self.guard.clone()
+    }
+
+    /// A channel receiving every press and release from now on.
+    pub fn events(&self) -> Receiver<BumperEvent> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+}
+
+fn switch_changed<F>(
+    switch: &SwitchConfig,
+    pressed: bool,
+    guard: &BumperGuard,
+    subscribers: &Mutex<Vec<Sender<BumperEvent>>>,
+    stop: &Mutex<F>,
+) where
+    F: FnMut(Side) -> Result<(), Error>,
+{
+    if !guard.set_pressed(switch.side, switch.pin, pressed) {
+        return;
+    }
+    let (side, pin) = (switch.side, switch.pin);
+    let event = if pressed {
+        warn!("{} bumper pressed (GPIO {}), stopping", side, pin);
+        run_stop(stop, side);
+        BumperEvent::Pressed { side, pin }
+    } else {
+        info!("{} bumper released (GPIO {})", side, pin);
+        BumperEvent::Released { side, pin }
+    };
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
+
+fn run_stop<F>(stop: &Mutex<F>, side: Side)
+where
+    F: FnMut(Side) -> Result<(), Error>,
+{
+    match stop.lock() {
+        Ok(mut stop) => {
+            if let Err(error) = (*stop)(side) {
+                error!("Bumper could not switch off the motors: {}", error);
+            }
+        }
+        Err(_) => error!("Bumper stop action lock poisoned"),
+    }
+}
+
+fn default_active_low() -> bool {
+    true
+}
@@ -0,0 +1,179 @@
+//! One open I2C bus shared by every device on it. When the ThunderBorg, an
+//! UltraBorg, an IMU and a current sensor sit on the same bus, opening a
+//! file descriptor per device and per background thread leaves them racing:
+//! a query is a write followed by a read, and another thread's transfer in
+//! between can take its response. A `BusManager` hands out `ManagedDevice`
+//! handles which select their address and run each transfer, and each
+//! `Bus::write_read`, under one lock.
+//!
+//! ```no_run
+//! # use vrum::bus_manager::BusManager;
+//! # use vrum::sensors::imu::Mpu6050;
+//! # use vrum::thunder_borg::ControllerBuilder;
+//! let bus = BusManager::open("/dev/i2c-1")?;
+//! let mut controller = ControllerBuilder::new().build_with_bus(Box::new(bus.device(0x15)))?;
+//! let imu = Mpu6050::new(Box::new(bus.device(0x68)))?;
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rppal::i2c::I2c;
+
+use crate::borg::Bus;
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BusManagerError {
+    #[error("{path} is not an I2C bus, expected /dev/i2c-<number>")]
+    UnsupportedPath { path: String },
+}
+
+/// A bus whose slave address can be changed between transfers.
+pub trait AddressedBus: Send {
+    fn set_address(&mut self, address: u16) -> Result<(), Error>;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+}
+
+impl AddressedBus for I2c {
+    fn set_address(&mut self, address: u16) -> Result<(), Error> {
+        self.set_slave_address(address)?;
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        I2c::write(self, bytes)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        I2c::read(self, buffer)?;
+        Ok(())
+    }
+}
+
+/// Handle to a shared bus, cheap to clone.
+#[derive(Clone)]
+pub struct BusManager {
+    inner: Arc<Mutex<SharedBus>>,
+}
+
+struct SharedBus {
+    bus: Box<dyn AddressedBus>,
+    /// Bus number to reopen, `None` if the bus wasn't opened by number.
+    number: Option<u8>,
+    /// Address the bus currently talks to, `None` when unknown.
+    address: Option<u16>,
+}
+
+impl BusManager {
+    /// Opens the Linux I2C bus at `bus_path`, e.g. `/dev/i2c-1`.
+    pub fn open(bus_path: &str) -> Result<Self, Error> {
+        let number = bus_path
+            .strip_prefix("/dev/i2c-")
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| BusManagerError::UnsupportedPath {
+                path: bus_path.to_owned(),
+            })?;
+        info!("Opening shared i2c bus {}", bus_path);
+        let manager = BusManager::new(Box::new(I2c::with_bus(number)?));
+        manager.lock().number = Some(number);
+        Ok(manager)
+    }
+
+    /// Shares `bus`, e.g. a simulated one.
+    pub fn new(bus: Box<dyn AddressedBus>) -> Self {
+        BusManager {
+            inner: Arc::new(Mutex::new(SharedBus {
+                bus,
+                number: None,
+                address: None,
+            })),
+        }
+    }
+
+    /// A handle to the device at `address`.
+    pub fn device(&self, address: u16) -> ManagedDevice {
+        ManagedDevice {
+            manager: self.clone(),
+            address,
+        }
+    }
+
+    /// Closes and opens the bus again, for every handle, e.g. after the bus
+    /// driver was reloaded. Does nothing if the bus wasn't opened by path.
+    pub fn reopen(&self) -> Result<(), Error> {
+        let mut shared = self.lock();
+        if let Some(number) = shared.number {
+            info!("Reopening shared i2c bus {}", number);
+            shared.bus = Box::new(I2c::with_bus(number)?);
+            shared.address = None;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SharedBus> {
+        // A transfer interrupted by a panic leaves nothing inconsistent but
+        // the address, which is selected again.
+        let mut shared = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if self.inner.is_poisoned() {
+            shared.address = None;
+        }
+        shared
+    }
+}
+
+impl SharedBus {
+    fn select(&mut self, address: u16) -> Result<(), Error> {
+        if self.address != Some(address) {
+            self.address = None;
+            self.bus.set_address(address)?;
+            self.address = Some(address);
+        }
+        Ok(())
+    }
+}
+
+/// One device on a shared bus, see `BusManager::device`.
+#[derive(Clone)]
+pub struct ManagedDevice {
+    manager: BusManager,
+    address: u16,
+}
+
+impl ManagedDevice {
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+}
+
+impl Bus for ManagedDevice {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut shared = self.manager.lock();
+        shared.select(self.address)?;
+        shared.bus.write(bytes)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut shared = self.manager.lock();
+        shared.select(self.address)?;
+        shared.bus.read(buffer)
+    }
+
+    fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        let mut shared = self.manager.lock();
+        shared.select(self.address)?;
+        shared.bus.write(bytes)?;
+        shared.bus.read(buffer)
+    }
+
+    fn reopen(&mut self) -> Result<(), Error> {
+        self.manager.reopen()
+    }
+}
@@ -0,0 +1,270 @@
+//! Audible alerts from a piezo buzzer on a GPIO pin, configured in the
+//! `[buzzer]` section of the configuration file:
+//!
+//! ```toml
+//! [buzzer]
+//! pin = 12
+//! frequency = 2700.0
+//! ```
+//!
+//! A `Buzzer` chirps on start, beeps when the battery runs low, sounds an
+//! alarm while the battery is cut off or a motor reports a drive fault, and
+//! beeps while driving backwards, so the robot can be heard when nobody is
+//! watching the logs. It follows the events of a `BatterySupervisor` and a
+//! `FaultMonitor`, and hears of reversing through a `Controller` given its
+//! `Alerts` with `Controller::set_buzzer`.
+//!
+//! Active buzzers sound with the pin high, passive piezos need the tone of
+//! `frequency`, played with software PWM.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rppal::gpio::{Gpio, OutputPin};
+
+use crate::battery::{BatteryEvent, BatteryState};
+use crate::error::Error;
+use crate::faults::FaultEvent;
+
+/// The `[buzzer]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuzzerConfig {
+    /// BCM pin number.
+    pub pin: u8,
+    /// The buzzer sounds with the pin low.
+    #[serde(default)]
+    pub active_low: bool,
+    /// Tone of a passive piezo, in hertz. Active buzzers have none.
+    #[serde(default)]
+    pub frequency: Option<f64>,
+    /// Chirp when vrum starts.
+    #[serde(default = "default_true")]
+    pub boot_chirp: bool,
+    /// Beep while driving backwards.
+    #[serde(default = "default_true")]
+    pub reverse_beeper: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// The sounds of the buzzer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Two short chirps.
+    BootChirp,
+    /// Three beeps, once.
+    LowBattery,
+    /// Slow beeps, repeated while reversing.
+    Reverse,
+    /// Fast triple beeps, repeated while a fault lasts.
+    FaultAlarm,
+}
+
+impl Pattern {
+    /// How long the buzzer sounds and then stays quiet for each beep, in
+    /// milliseconds.
+    pub fn beeps(self) -> &'static [(u64, u64)] {
+        match self {
+            Pattern::BootChirp => &[(50, 50), (50, 0)],
+            Pattern::LowBattery => &[(150, 150), (150, 150), (150, 600)],
+            Pattern::Reverse => &[(400, 400)],
+            Pattern::FaultAlarm => &[(100, 50), (100, 50), (100, 400)],
+        }
+    }
+}
+
+/// Asks a `Buzzer` for sounds, shared with whatever has something to say.
+#[derive(Clone, Debug, Default)]
+pub struct Alerts {
+    cues: Arc<Mutex<Cues>>,
+}
+
+#[derive(Debug, Default)]
+struct Cues {
+    queue: VecDeque<Pattern>,
+    reversing: bool,
+}
+
+impl Alerts {
+    /// Plays `pattern` once, after the patterns already asked for.
+    pub fn play(&self, pattern: Pattern) {
+        self.lock().queue.push_back(pattern);
+    }
+
+    /// Starts or stops the reverse beeper.
+    pub fn set_reversing(&self, reversing: bool) {
+        self.lock().reversing = reversing;
+    }
+
+    pub fn is_reversing(&self) -> bool {
+        self.lock().reversing
+    }
+
+    /// The next pattern to play, if any.
+    fn next(&self) -> Option<Pattern> {
+        let mut cues = self.lock();
+        match cues.queue.pop_front() {
+            Some(pattern) => Some(pattern),
+            None if cues.reversing => Some(Pattern::Reverse),
+            None => None,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cues> {
+        // Cues are plain flags, a poisoned lock still holds valid ones.
+        self.cues.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Plays the `Alerts` on a background thread, until dropped. The fault alarm
+/// takes precedence over every other sound.
+pub struct Buzzer {
+    alerts: Alerts,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Buzzer {
+    /// Claims the pin of `config`, following the `battery` and `faults`
+    /// events if given.
+    pub fn spawn(
+        config: &BuzzerConfig,
+        battery: Option<Receiver<BatteryEvent>>,
+        faults: Option<Receiver<FaultEvent>>,
+    ) -> Result<Self, Error> {
+        let pin = Gpio::new()?.get(config.pin)?.into_output();
+        info!("Buzzer on GPIO {}", config.pin);
+        let mut sounder = Sounder {
+            pin,
+            active_low: config.active_low,
+            frequency: config.frequency,
+        };
+        sounder.sound(false)?;
+        let alerts = Alerts::default();
+        if config.boot_chirp {
+            alerts.play(Pattern::BootChirp);
+        }
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_alerts = alerts.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-buzzer".into())
+            .spawn(move || {
+                let mut cutoff = false;
+                let mut faulted = BTreeSet::new();
+                let mut failing = false;
+                while thread_running.load(Ordering::SeqCst) {
+                    for event in battery.iter().flat_map(Receiver::try_iter) {
+                        if let BatteryEvent::StateChanged { state, .. } = event {
+                            cutoff = state == BatteryState::Cutoff;
+                            if state == BatteryState::Low {
+                                thread_alerts.play(Pattern::LowBattery);
+                            }
+                        }
+                    }
+                    for event in faults.iter().flat_map(Receiver::try_iter) {
+                        match event {
+                            FaultEvent::Raised(motor) => {
+                                faulted.insert(motor);
+                            }
+                            FaultEvent::Cleared(motor) => {
+                                faulted.remove(&motor);
+                            }
+                            FaultEvent::ReadFailed => {}
+                        }
+                    }
+                    let pattern = if cutoff || !faulted.is_empty() {
+                        Some(Pattern::FaultAlarm)
+                    } else {
+                        thread_alerts.next()
+                    };
+                    let played = match pattern {
+                        Some(pattern) => sounder.play(pattern, &thread_running),
+                        None => {
+                            thread::sleep(IDLE_POLL_INTERVAL);
+                            Ok(())
+                        }
+                    };
+                    match played {
+                        Ok(()) => failing = false,
+                        // Once, rather than every pattern while it fails.
+                        Err(error) if !failing => {
+                            warn!("Could not sound the buzzer: {}", error);
+                            failing = true;
+                        }
+                        Err(_) => thread::sleep(IDLE_POLL_INTERVAL),
+                    }
+                }
+                if let Err(error) = sounder.sound(false) {
+                    error!("Could not silence the buzzer: {}", error);
+                }
+            })?;
+
+        Ok(Buzzer {
+            alerts,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn alerts(&self) -> Alerts {
+        self.alerts.clone()
+    }
+}
+
+impl Drop for Buzzer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Buzzer thread panicked");
+            }
+        }
+    }
+}
+
+struct Sounder {
+    pin: OutputPin,
+    active_low: bool,
+    frequency: Option<f64>,
+}
+
+impl Sounder {
+    /// Plays the beeps of `pattern`, cut short when `running` is cleared.
+    fn play(&mut self, pattern: Pattern, running: &AtomicBool) -> Result<(), Error> {
+        for &(on_ms, off_ms) in pattern.beeps() {
+            if !running.load(Ordering::SeqCst) {
+                break;
+            }
+            self.sound(true)?;
+            thread::sleep(Duration::from_millis(on_ms));
+            self.sound(false)?;
+            thread::sleep(Duration::from_millis(off_ms));
+        }
+        Ok(())
+    }
+
+    fn sound(&mut self, on: bool) -> Result<(), Error> {
+        match self.frequency {
+            // A square wave, the same whichever level is active.
+            Some(frequency) if on => self.pin.set_pwm_frequency(frequency, 0.5)?,
+            Some(_) => {
+                self.pin.clear_pwm()?;
+                self.pin.write(self.active_low.into());
+            }
+            None => self.pin.write((on != self.active_low).into()),
+        }
+        Ok(())
+    }
+}
+
+/// How often the buzzer checks for something to play while quiet.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(20);
@@ -0,0 +1,113 @@
+//! Time as seen by the timing-dependent parts of vrum: the `Ramp` stage, the
+//! `Watchdog`, the `Motion` primitives and the `sim` robot all read it from a
+//! `Clock`. They default to the `SystemClock`; give them a shared
+//! `ManualClock` to run through their timeline deterministically, and as
+//! fast as the host allows:
+//!
+//! ```
+//! # use std::sync::Arc;
+//! # use std::time::Duration;
+//! # use vrum::clock::{Clock, ManualClock};
+//! # use vrum::pipeline::{DriveCommand, Ramp, Stage};
+//! let clock = ManualClock::new();
+//! let mut ramp = Ramp::with_clock(2.0, Arc::new(clock.clone()));
+//! ramp.apply(DriveCommand::new(0.0, 0.0))?;
+//! clock.advance(Duration::from_millis(100));
+//! assert_eq!(ramp.apply(DriveCommand::new(1.0, 1.0))?, DriveCommand::new(0.2, 0.2));
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Returns once `duration` has passed on this clock.
+    fn sleep(&self, duration: Duration);
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+/// The wall clock, `Instant::now()` and `thread::sleep()`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// The wall clock, shared.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when told to. `advance()` moves it forward, and so
+/// does `sleep()`, which returns straight away: code sleeping on one thread
+/// runs through its timeline instantly. Clones share the same time.
+///
+/// Background threads (the watchdog's) still poll on the wall clock, but
+/// measure time on this one, so advancing it is what makes them act.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    start: Instant,
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            start: Instant::now(),
+            offset: Arc::new(Mutex::new(Duration::default())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.lock() += duration;
+    }
+
+    /// Time the clock was moved forward by since it was created.
+    pub fn total(&self) -> Duration {
+        *self.lock()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Duration> {
+        // Adding durations can't leave the offset half updated.
+        self.offset.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.lock()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Colour of the ThunderBorg LED.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ColorError {
+    #[error("invalid colour `{color}`, expected a name, `#rrggbb` or `red,green,blue`")]
+    Invalid { color: String },
+}
+
+impl Color {
+    pub const OFF: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const RED: Color = Color::rgb(255, 0, 0);
+    pub const GREEN: Color = Color::rgb(0, 255, 0);
+    pub const BLUE: Color = Color::rgb(0, 0, 255);
+    pub const YELLOW: Color = Color::rgb(255, 255, 0);
+    pub const CYAN: Color = Color::rgb(0, 255, 255);
+    pub const MAGENTA: Color = Color::rgb(255, 0, 255);
+    pub const ORANGE: Color = Color::rgb(255, 136, 0);
+    pub const PURPLE: Color = Color::rgb(128, 0, 255);
+
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Color { red, green, blue }
+    }
+
+    /// Parses `#rrggbb` or the short form `#rgb`, the `#` is optional.
+    pub fn from_hex(hex: &str) -> Result<Self, ColorError> {
+        let invalid = || ColorError::Invalid {
+            color: hex.to_owned(),
+        };
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        if !digits.chars().all(|digit| digit.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |index: usize, width: usize| {
+            let value = u8::from_str_radix(&digits[index * width..(index + 1) * width], 16)
+                .expect("validated hex digits");
+            if width == 1 {
+                value * 0x11
+            } else {
+                value
+            }
+        };
+        match digits.len() {
+            3 => Ok(Color::rgb(channel(0, 1), channel(1, 1), channel(2, 1))),
+            6 => Ok(Color::rgb(channel(0, 2), channel(1, 2), channel(2, 2))),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// `hue` in degrees (wrapped to `[0, 360)`), `saturation` and `value`
+    /// clamped to `[0, 1]`.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let saturation = saturation.clamp(0.0, 1.0);
+        let value = value.clamp(0.0, 1.0);
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let rising = chroma * sector.fract();
+        let falling = chroma - rising;
+        let (red, green, blue) = match sector as u8 {
+            0 => (chroma, rising, 0.0),
+            1 => (falling, chroma, 0.0),
+            2 => (0.0, chroma, rising),
+            3 => (0.0, falling, chroma),
+            4 => (rising, 0.0, chroma),
+            _ => (chroma, 0.0, falling),
+        };
+        let minimum = value - chroma;
+        let channel = |component: f32| ((component + minimum) * 255.0).round() as u8;
+        Color::rgb(channel(red), channel(green), channel(blue))
+    }
+
+    /// One of the named constants, case insensitive.
+    pub fn named(name: &str) -> Option<Self> {
+        let color = match name.to_ascii_lowercase().as_str() {
+            "off" | "black" => Color::OFF,
+            "white" => Color::WHITE,
+            "red" => Color::RED,
+            "green" => Color::GREEN,
+            "blue" => Color::BLUE,
+            "yellow" => Color::YELLOW,
+            "cyan" => Color::CYAN,
+            "magenta" => Color::MAGENTA,
+            "orange" => Color::ORANGE,
+            "purple" => Color::PURPLE,
+            _ => return None,
+        };
+        Some(color)
+    }
+
+    /// Multiplies every channel by `factor`, clamped to `[0, 1]`.
+    pub fn scale(self, factor: f32) -> Self {
+        let factor = factor.clamp(0.0, 1.0);
+        let channel = |value: u8| (f32::from(value) * factor).round() as u8;
+        Color::rgb(channel(self.red), channel(self.green), channel(self.blue))
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((red, green, blue): (u8, u8, u8)) -> Self {
+        Color::rgb(red, green, blue)
+    }
+}
+
+impl FromStr for Color {
+    type Err = ColorError;
+
+    /// Accepts a colour name, `#rrggbb`/`#rgb` or `red,green,blue`.
+    fn from_str(color: &str) -> Result<Self, Self::Err> {
+        let color = color.trim();
+        if let Some(named) = Color::named(color) {
+            return Ok(named);
+        }
+        if color.contains(',') {
+            let channels = color
+                .split(',')
+                .map(|channel| channel.trim().parse::<u8>())
+                .collect::<Result<Vec<_>, _>>();
+            return match channels.as_deref() {
+                Ok(&[red, green, blue]) => Ok(Color::rgb(red, green, blue)),
+                _ => Err(ColorError::Invalid {
+                    color: color.to_owned(),
+                }),
+            };
+        }
+        Color::from_hex(color)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "#{:02x}{:02x}{:02x}",
+            self.red, self.green, self.blue
+        )
+    }
+}
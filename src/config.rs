@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use toml_edit::{value, DocumentMut};
+
+use crate::arming::ArmingConfig;
+use crate::auth::AuthConfig;
+use crate::battery::{BatteryConfig, SocConfig, VoltageFilterConfig};
+use crate::black_box::BlackBoxConfig;
+use crate::borg::RecoveryConfig;
+use crate::bumper::BumperConfig;
+use crate::buzzer::BuzzerConfig;
+use crate::encoding::EncodingConfig;
+use crate::energy::EnergyConfig;
+use crate::error::Error;
+use crate::estimation::EstimatorConfig;
+use crate::estop::EStopConfig;
+use crate::faults::{FaultConfig, FaultLatchConfig};
+use crate::gears::GearConfig;
+use crate::gpio_outputs::GpioOutputConfig;
+use crate::gyro::GyroConfig;
+use crate::heading::HeadingHoldConfig;
+use crate::heartbeat::HeartbeatConfig;
+use crate::influx::InfluxConfig;
+use crate::line_follower::LineFollowerConfig;
+use crate::maintenance::MaintenanceConfig;
+use crate::obstacle::ObstacleConfig;
+use crate::pan_tilt::PanTiltConfig;
+use crate::pca9685::Pca9685Config;
+use crate::pipeline::PipelineConfig;
+use crate::profile::{Profile, ProfileError};
+use crate::rc::RcConfig;
+use crate::realtime::RealtimeConfig;
+use crate::reload::ReloadConfig;
+#[cfg(feature = "sqlite")]
+use crate::run_log::RunLogConfig;
+use crate::sensors::gps::GpsConfig;
+#[cfg(feature = "sim")]
+use crate::sim::SimConfig;
+use crate::stall::StallConfig;
+use crate::sticks::StickConfig;
+use crate::telemetry::TelemetryPollerConfig;
+use crate::thermal::ThermalConfig;
+use crate::thunder_borg::{ControllerBuilder, DropPolicy, MotorsConfig, VoltageCalibration};
+#[cfg(feature = "web")]
+use crate::tls::TlsConfig;
+use crate::waypoint::WaypointConfig;
+
+/// Settings read from the TOML configuration file. Every section is
+/// optional and the corresponding feature is disabled when it is missing.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Whether motor commands need the motors armed first.
+    pub arming: ArmingConfig,
+    /// Token required by the remote control frontends.
+    pub auth: AuthConfig,
+    pub battery: Option<BatteryConfig>,
+    pub battery_soc: SocConfig,
+    /// Recording dumped on a crash, see `vrum::black_box`.
+    pub black_box: Option<BlackBoxConfig>,
+    pub bumpers: Option<BumperConfig>,
+    /// Audible alerts, see `vrum::buzzer`.
+    pub buzzer: Option<BuzzerConfig>,
+    /// What the controller does to the board when vrum exits.
+    pub drop_policy: DropPolicy,
+    /// Encoding of the messages of each network frontend.
+    pub encoding: EncodingConfig,
+    /// Energy used by each run, see `vrum::energy`.
+    pub energy: Option<EnergyConfig>,
+    /// Noise model of `PoseEstimator`.
+    pub estimation: EstimatorConfig,
+    pub estop: Option<EStopConfig>,
+    /// Whether drive faults and battery cutoffs block motor commands until
+    /// cleared.
+    pub fault_latch: FaultLatchConfig,
+    pub faults: Option<FaultConfig>,
+    /// Speed steps, see `vrum --gear`.
+    pub gears: Option<GearConfig>,
+    /// Named outputs like headlights, see `vrum aux`.
+    pub gpio_outputs: BTreeMap<String, GpioOutputConfig>,
+    pub gps: Option<GpsConfig>,
+    pub gyro: Option<GyroConfig>,
+    /// Gains of `HeadingHold`, for robots with a compass.
+    pub heading_hold: HeadingHoldConfig,
+    /// Dead-man timeouts of the network frontends.
+    pub heartbeat: HeartbeatConfig,
+    /// Export of telemetry to InfluxDB.
+    pub influx: Option<InfluxConfig>,
+    pub line_follower: Option<LineFollowerConfig>,
+    /// Hour meter and maintenance counters, see `vrum stats`.
+    pub maintenance: Option<MaintenanceConfig>,
+    pub motors: MotorsConfig,
+    pub obstacle: Option<ObstacleConfig>,
+    /// Camera servos, aimed from `vrum rc` and `vrum serve`.
+    pub pan_tilt: Option<PanTiltConfig>,
+    /// PWM channels for servos and lights.
+    pub pca9685: Option<Pca9685Config>,
+    pub pipeline: PipelineConfig,
+    /// Name of the driving profile used unless another is selected.
+    pub profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+    pub rc: Option<RcConfig>,
+    /// Real-time scheduling of the thread driving the board.
+    pub realtime: Option<RealtimeConfig>,
+    pub recovery: RecoveryConfig,
+    /// Reloading of this file while vrum runs, see `vrum::reload`.
+    pub reload: ReloadConfig,
+    /// Storage of every run in SQLite.
+    #[cfg(feature = "sqlite")]
+    pub run_log: Option<RunLogConfig>,
+    /// The robot driven by `vrum --simulate`.
+    #[cfg(feature = "sim")]
+    pub sim: SimConfig,
+    pub stall: Option<StallConfig>,
+    /// Response curves of the teleop sticks.
+    pub sticks: StickConfig,
+    /// Background polling of the board shared by the telemetry consumers.
+    pub telemetry: Option<TelemetryPollerConfig>,
+    /// Temperature monitoring and derating, see `vrum::thermal`.
+    pub thermal: Option<ThermalConfig>,
+    /// Certificate of `vrum serve`, served over HTTPS with one.
+    #[cfg(feature = "web")]
+    pub tls: Option<TlsConfig>,
+    pub voltage_calibration: VoltageCalibration,
+    pub voltage_filter: VoltageFilterConfig,
+    /// Settings of `vrum go-to`, for robots with a GPS.
+    pub waypoint: WaypointConfig,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        info!("Loading configuration from {}", path.as_ref().display());
+        let contents = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&contents)?;
+        if let Some(ref name) = config.profile {
+            config.find_profile(name)?;
+        }
+        Ok(config)
+    }
+
+    pub fn find_profile(&self, name: &str) -> Result<&Profile, Error> {
+        self.profiles.get(name).ok_or_else(|| {
+            ProfileError::Unknown {
+                name: name.to_owned(),
+            }
+            .into()
+        })
+    }
+
+    /// A `ControllerBuilder` with the board settings from this configuration,
+    /// including the selected driving profile.
+    pub fn controller_builder(&self) -> ControllerBuilder {
+        let builder = ControllerBuilder::new()
+            .motors(self.motors.clone())
+            .voltage_calibration(self.voltage_calibration)
+            .battery_soc(self.battery_soc.clone())
+            .voltage_filter(self.voltage_filter.clone())
+            .pipeline(self.pipeline.clone())
+            .recovery(self.recovery.clone())
+            .drop_policy(self.drop_policy);
+        match self
+            .profile
+            .as_ref()
+            .and_then(|name| self.profiles.get(name))
+        {
+            Some(profile) => builder.profile(profile.clone()),
+            None => builder,
+        }
+    }
+
+    /// Writes `calibration` to the `[voltage_calibration]` section of the
+    /// configuration file at `path`, creating the file if needed and keeping
+    /// the rest of its contents and comments intact.
+    pub fn store_voltage_calibration<P: AsRef<Path>>(
+        path: P,
+        calibration: VoltageCalibration,
+    ) -> Result<(), Error> {
+        let path = path.as_ref();
+        let contents = if path.exists() {
+            fs::read_to_string(path)?
+        } else {
+            String::new()
+        };
+        let mut document = contents.parse::<DocumentMut>()?;
+        document["voltage_calibration"]["pin_max"] = value(round_for_storage(calibration.pin_max));
+        document["voltage_calibration"]["correction"] =
+            value(round_for_storage(calibration.correction));
+        fs::write(path, document.to_string())?;
+        info!("Stored voltage calibration in {}", path.display());
+        Ok(())
+    }
+
+    /// Makes `name` the driving profile used by default, in the configuration
+    /// file at `path`.
+    pub fn store_profile<P: AsRef<Path>>(path: P, name: &str) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut document = fs::read_to_string(path)?.parse::<DocumentMut>()?;
+        document["profile"] = value(name);
+        fs::write(path, document.to_string())?;
+        info!("Selected driving profile {:?} in {}", name, path.display());
+        Ok(())
+    }
+}
+
+/// Avoids writing out the float noise of the `f32` to `f64` conversion, the
+/// ADC resolution is only around 35mV anyway.
+#[inline]
+fn round_for_storage(value: f32) -> f64 {
+    (f64::from(value) * 1000.0).round() / 1000.0
+}
@@ -0,0 +1,113 @@
+//! Cruise control for teleop: latches the powers being driven so the operator
+//! can let go of the stick on long straight runs, e.g. with the `cruise`
+//! switch of `vrum rc`. Once the stick is back in the centre, moving it again
+//! cancels, as do braking, a drive fault and an obstacle ahead:
+//!
+//! ```
+//! # use vrum::cruise::CruiseControl;
+//! let mut cruise = CruiseControl::new();
+//! cruise.engage(0.6, 0.6);
+//! assert_eq!(cruise.update(0.6, 0.6), (0.6, 0.6));
+//! assert_eq!(cruise.update(0.0, 0.0), (0.6, 0.6));
+//! assert_eq!(cruise.update(0.2, -0.2), (0.2, -0.2));
+//! assert!(!cruise.is_engaged());
+//! ```
+
+use crate::faults::FaultGuard;
+use crate::obstacle::ObstacleGuard;
+
+#[derive(Clone, Default)]
+pub struct CruiseControl {
+    latched: Option<Latched>,
+    fault_guard: Option<FaultGuard>,
+    obstacle_guard: Option<ObstacleGuard>,
+}
+
+#[derive(Clone, Copy)]
+struct Latched {
+    sides: (f32, f32),
+    /// The stick went back to the centre since engaging, so moving it now
+    /// is the operator taking over.
+    released: bool,
+}
+
+impl CruiseControl {
+    pub fn new() -> Self {
+        CruiseControl::default()
+    }
+
+    /// Cancels on drive faults.
+    pub fn fault_guard(mut self, guard: FaultGuard) -> Self {
+        self.fault_guard = Some(guard);
+        self
+    }
+
+    /// Cancels when an obstacle slows down the robot going forwards.
+    pub fn obstacle_guard(mut self, guard: ObstacleGuard) -> Self {
+        self.obstacle_guard = Some(guard);
+        self
+    }
+
+    /// Latches the powers `left` and `right` of the sides, does nothing
+    /// when stopped.
+    pub fn engage(&mut self, left: f32, right: f32) {
+        if left == 0.0 && right == 0.0 {
+            info!("Not engaging cruise control, the robot is stopped");
+            return;
+        }
+        info!("Cruise control on at {:.2} {:.2}", left, right);
+        self.latched = Some(Latched {
+            sides: (left, right),
+            released: false,
+        });
+    }
+
+    /// Braking, a loss of signal or anything else giving control back to
+    /// the stick.
+    pub fn cancel(&mut self, reason: &str) {
+        if self.latched.take().is_some() {
+            info!("Cruise control off: {}", reason);
+        }
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.latched.is_some()
+    }
+
+    /// The powers to drive with the stick at `left` and `right`: the
+    /// latched ones while engaged, the stick's otherwise.
+    pub fn update(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let latched = match self.latched {
+            Some(ref mut latched) => latched,
+            None => return (left, right),
+        };
+        let centred = left == 0.0 && right == 0.0;
+        latched.released |= centred;
+        let (latched_left, latched_right) = latched.sides;
+        let cancel = if latched.released && !centred {
+            Some("stick moved")
+        } else if self
+            .fault_guard
+            .as_ref()
+            .is_some_and(FaultGuard::is_faulted)
+        {
+            Some("drive fault")
+        } else if latched_left + latched_right > 0.0
+            && self
+                .obstacle_guard
+                .as_ref()
+                .is_some_and(|guard| guard.forward_scale() < 1.0)
+        {
+            Some("obstacle ahead")
+        } else {
+            None
+        };
+        match cancel {
+            Some(reason) => {
+                self.cancel(reason);
+                (left, right)
+            }
+            None => (latched_left, latched_right),
+        }
+    }
+}
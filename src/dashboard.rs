@@ -0,0 +1,360 @@
+//! Live terminal dashboard, `vrum monitor`: the battery voltage over the
+//! last minute, the power and fault flag of each motor, the bus statistics
+//! and the latest log lines, redrawn every `refresh`.
+//!
+//! The dashboard only reads from the board, so it can watch a robot driven
+//! by another vrum process. Powers are read back from the board rather than
+//! taken from the commands this process sends.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::symbols::Marker;
+use ratatui::text::Line;
+use ratatui::widgets::{Axis, Block, Chart, Dataset, Gauge, GraphType, List, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::borg::CommStats;
+use crate::error::Error;
+use crate::shutdown::Shutdown;
+use crate::thunder_borg::Controller;
+
+/// What the dashboard shows of the board, read at every refresh.
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    pub battery_voltage: Option<f32>,
+    pub battery_percent: Option<f32>,
+    /// Motors A (left) and B (right), missing if they couldn't be read.
+    pub powers: Option<(f32, f32)>,
+    pub faults: Option<(bool, bool)>,
+    pub comm_stats: CommStats,
+    /// The last error reading the board, if any reading failed.
+    pub error: Option<String>,
+}
+
+impl Snapshot {
+    /// Reads everything shown, carrying on past failed readings.
+    pub fn read(controller: &mut Controller) -> Self {
+        let mut error = None;
+        let battery_voltage = noting_error(controller.get_battery_voltage(), &mut error);
+        let battery_percent = noting_error(controller.battery_percent(), &mut error);
+        let powers = noting_error(controller.read_motor_powers(), &mut error);
+        let faults = noting_error(
+            controller
+                .get_drive_fault_a()
+                .and_then(|a| Ok((a, controller.get_drive_fault_b()?))),
+            &mut error,
+        );
+        Snapshot {
+            battery_voltage,
+            battery_percent,
+            powers,
+            faults,
+            comm_stats: controller.comm_stats().clone(),
+            error,
+        }
+    }
+}
+
+fn noting_error<T>(result: Result<T, Error>, error: &mut Option<String>) -> Option<T> {
+    result.map_err(|e| *error = Some(e.to_string())).ok()
+}
+
+pub struct Dashboard {
+    refresh: Duration,
+    started: Instant,
+    /// Seconds since `started` and battery voltages, over `HISTORY`.
+    voltages: VecDeque<(f64, f64)>,
+    snapshot: Snapshot,
+    logs: LogBuffer,
+}
+
+impl Dashboard {
+    pub fn new(refresh: Duration) -> Self {
+        Dashboard {
+            refresh: refresh.max(MIN_REFRESH),
+            started: Instant::now(),
+            voltages: VecDeque::new(),
+            snapshot: Snapshot::default(),
+            logs: LogBuffer::global(),
+        }
+    }
+
+    /// Takes over the terminal until `q`, Esc or Ctrl-C is pressed, or a
+    /// shutdown is requested, restoring it on the way out.
+    pub fn run(&mut self, controller: &mut Controller, shutdown: &Shutdown) -> Result<(), Error> {
+        let mut terminal = ratatui::try_init()?;
+        let result = self.run_on(&mut terminal, controller, shutdown);
+        ratatui::try_restore()?;
+        result
+    }
+
+    /// Records a new reading of the board.
+    pub fn update(&mut self, snapshot: Snapshot) {
+        let now = self.started.elapsed().as_secs_f64();
+        if let Some(voltage) = snapshot.battery_voltage {
+            self.voltages.push_back((now, f64::from(voltage)));
+        }
+        while let Some(&(at, _)) = self.voltages.front() {
+            if now - at <= HISTORY.as_secs_f64() {
+                break;
+            }
+            self.voltages.pop_front();
+        }
+        self.snapshot = snapshot;
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [battery, motors, stats, logs, help] = Layout::vertical([
+            Constraint::Min(8),
+            Constraint::Length(3),
+            Constraint::Length(4),
+            Constraint::Length(LOG_LINES as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        self.draw_battery(frame, battery);
+        self.draw_motors(frame, motors);
+        self.draw_stats(frame, stats);
+        self.draw_logs(frame, logs);
+        frame.render_widget(
+            Line::from(format!(
+                " q to quit, refreshing every {}ms",
+                self.refresh.as_millis()
+            ))
+            .dark_gray(),
+            help,
+        );
+    }
+
+    fn run_on(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        controller: &mut Controller,
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        loop {
+            shutdown.check()?;
+            self.update(Snapshot::read(controller));
+            terminal.draw(|frame| self.draw(frame))?;
+            // Waits out the refresh period, unless a key comes first.
+            let next = Instant::now() + self.refresh;
+            while let Some(wait) = next.checked_duration_since(Instant::now()) {
+                if !event::poll(wait)? {
+                    break;
+                }
+                if let Event::Key(key) = event::read()? {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    let quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc);
+                    if key.kind == KeyEventKind::Press && (quit || ctrl_c) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_battery(&self, frame: &mut Frame, area: Rect) {
+        let title = match (self.snapshot.battery_voltage, self.snapshot.battery_percent) {
+            (Some(voltage), Some(percent)) => {
+                format!(" Battery {:.2}V ({:.0}%) ", voltage, percent)
+            }
+            (Some(voltage), None) => format!(" Battery {:.2}V ", voltage),
+            _ => " Battery ".into(),
+        };
+        let points: Vec<(f64, f64)> = self.voltages.iter().copied().collect();
+        let now = self.started.elapsed().as_secs_f64();
+        let (low, high) = points
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(low, high), &(_, v)| {
+                (low.min(v), high.max(v))
+            });
+        let (low, high) = if points.is_empty() {
+            (0.0, 1.0)
+        } else {
+            ((low - 0.5).max(0.0), high + 0.5)
+        };
+        let dataset = Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Cyan))
+            .data(&points);
+        let chart = Chart::new(vec![dataset])
+            .block(Block::bordered().title(title))
+            .x_axis(
+                Axis::default()
+                    .bounds([now - HISTORY.as_secs_f64(), now])
+                    .labels([format!("-{}s", HISTORY.as_secs()), "now".into()]),
+            )
+            .y_axis(
+                Axis::default()
+                    .bounds([low, high])
+                    .labels([format!("{:.1}V", low), format!("{:.1}V", high)]),
+            );
+        frame.render_widget(chart, area);
+    }
+
+    fn draw_motors(&self, frame: &mut Frame, area: Rect) {
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(area);
+        let powers = self.snapshot.powers.map(|(a, b)| [a, b]);
+        let faults = self.snapshot.faults.map(|(a, b)| [a, b]);
+        let motors = [("Motor A (left)", left), ("Motor B (right)", right)];
+        for (index, &(name, area)) in motors.iter().enumerate() {
+            let fault = faults.is_some_and(|faults| faults[index]);
+            let mut title = Line::from(format!(" {} ", name));
+            if fault {
+                title.push_span("FAULT ".red().bold());
+            }
+            let power = powers.map(|powers| powers[index]);
+            let color = match power {
+                Some(power) if power < 0.0 => Color::Yellow,
+                _ => Color::Green,
+            };
+            let gauge = Gauge::default()
+                .block(Block::bordered().title(title))
+                .gauge_style(Style::default().fg(color))
+                .ratio(power.map_or(0.0, |power| f64::from(power.abs().min(1.0))))
+                .label(power.map_or_else(|| "?".into(), |power| format!("{:+.2}", power)));
+            frame.render_widget(gauge, area);
+        }
+    }
+
+    fn draw_stats(&self, frame: &mut Frame, area: Rect) {
+        let stats = &self.snapshot.comm_stats;
+        let total = stats.total();
+        let mean_latency = total
+            .total_latency_us
+            .checked_div(total.completed)
+            .unwrap_or_default();
+        let mut lines = vec![Line::from(format!(
+            "{} commands, {} retries, {} mismatched, {} failures, {} recoveries",
+            total.completed,
+            total.retries,
+            total.mismatched_headers,
+            total.failures,
+            stats.recoveries
+        ))];
+        lines.push(match self.snapshot.error {
+            Some(ref error) => Line::from(format!("Last error: {}", error)).red(),
+            None => Line::from(format!(
+                "Latency {}us mean, under {}us p99, {}us max",
+                mean_latency,
+                total.latency.quantile(0.99).as_micros(),
+                total.max_latency_us
+            )),
+        });
+        let paragraph = Paragraph::new(lines).block(Block::bordered().title(" Bus "));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_logs(&self, frame: &mut Frame, area: Rect) {
+        let list = List::new(self.logs.lines()).block(Block::bordered().title(" Log "));
+        frame.render_widget(list, area);
+    }
+}
+
+/// The latest lines logged, for the dashboard to show in place of stderr,
+/// which it takes over. Global like the tracing subscriber writing them:
+/// log with `fmt().with_writer(LogBuffer::global())`.
+#[derive(Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogBuffer {
+    pub fn global() -> Self {
+        static GLOBAL: OnceLock<LogBuffer> = OnceLock::new();
+        GLOBAL
+            .get_or_init(|| LogBuffer {
+                lines: Arc::new(Mutex::new(VecDeque::with_capacity(LOG_LINES))),
+            })
+            .clone()
+    }
+
+    /// The latest lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lock();
+        if lines.len() == LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<String>> {
+        // Lines are pushed whole, a poisoned buffer is still consistent.
+        self.lines.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> LogWriter {
+        LogWriter {
+            buffer: self.clone(),
+            line: Vec::new(),
+        }
+    }
+}
+
+/// Writes one event into a `LogBuffer`, a line at a time.
+pub struct LogWriter {
+    buffer: LogBuffer,
+    line: Vec<u8>,
+}
+
+impl LogWriter {
+    fn push_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.line).trim_end().to_string();
+        self.line.clear();
+        if !line.is_empty() {
+            self.buffer.push(line);
+        }
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for &byte in bytes {
+            if byte == b'\n' {
+                self.push_line();
+            } else {
+                self.line.push(byte);
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        self.push_line();
+    }
+}
+
+/// Log lines kept, and shown.
+const LOG_LINES: usize = 8;
+
+/// How far back the battery voltage graph goes.
+const HISTORY: Duration = Duration::from_secs(60);
+
+/// Every refresh reads five registers, faster would load the bus for
+/// little gain.
+const MIN_REFRESH: Duration = Duration::from_millis(50);
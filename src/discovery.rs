@@ -0,0 +1,219 @@
+//! Zero-configuration discovery on the LAN: a robot serving its dashboard
+//! advertises a `_vrum._tcp` service over mDNS, and `vrum discover` lists the
+//! robots answering, so a laptop finds them without hardcoding addresses.
+//!
+//! The service's TXT record carries
+//!
+//! - `name`, the name of the robot,
+//! - `boards`, the number of boards it drives,
+//! - `battery`, its battery level in percent, refreshed every
+//!   `battery_interval`,
+//! - `scheme`, `http` or `https`, and `version`, the vrum version.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::error::Error;
+use crate::motor_driver::MotorDriver;
+use crate::telemetry::host_name;
+
+/// The mDNS service type of vrum robots.
+pub const SERVICE_TYPE: &str = "_vrum._tcp.local.";
+
+#[derive(Clone, Debug)]
+pub struct AdvertiseConfig {
+    /// Instance name of the service, the host name by default.
+    pub name: String,
+    /// Port of the dashboard.
+    pub port: u16,
+    pub scheme: &'static str,
+    pub boards: usize,
+    pub battery_interval: Duration,
+}
+
+impl Default for AdvertiseConfig {
+    fn default() -> Self {
+        AdvertiseConfig {
+            name: host_name().unwrap_or_else(|| "vrum".into()),
+            port: 8080,
+            scheme: "http",
+            boards: 1,
+            battery_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Advertises the robot on a background thread, until dropped.
+pub struct Advertiser {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Advertiser {
+    /// `controller` is a dedicated handle used to read the battery level.
+    pub fn spawn<D>(config: AdvertiseConfig, mut controller: D) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        let daemon = ServiceDaemon::new()?;
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-mdns".into())
+            .spawn(move || {
+                let mut advertised = None;
+                while thread_running.load(Ordering::SeqCst) {
+                    // Unknown, rather than stale, when it can't be read.
+                    let battery = controller
+                        .battery_percent()
+                        .map(|percent| percent.round() as i32)
+                        .ok();
+                    if advertised != Some(battery) {
+                        match register(&daemon, &config, battery) {
+                            Ok(()) => advertised = Some(battery),
+                            Err(error) => warn!("Could not advertise over mDNS: {}", error),
+                        }
+                    }
+                    // Unparked early when dropped.
+                    thread::park_timeout(config.battery_interval);
+                }
+                if let Err(error) = daemon.shutdown() {
+                    warn!("Could not stop advertising over mDNS: {}", error);
+                }
+            })?;
+        Ok(Advertiser {
+            running,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Advertiser {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                error!("mDNS advertiser thread panicked");
+            }
+        }
+    }
+}
+
+fn register(
+    daemon: &ServiceDaemon,
+    config: &AdvertiseConfig,
+    battery: Option<i32>,
+) -> Result<(), Error> {
+    let mut properties = vec![
+        ("name", config.name.clone()),
+        ("boards", config.boards.to_string()),
+        ("scheme", config.scheme.to_string()),
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+    ];
+    if let Some(battery) = battery {
+        properties.push(("battery", battery.to_string()));
+    }
+    let host = format!("{}.local.", host_name().unwrap_or_else(|| "vrum".into()));
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &config.name,
+        &host,
+        "",
+        config.port,
+        &properties[..],
+    )?
+    .enable_addr_auto();
+    // Registering again replaces the TXT record.
+    daemon.register(service)?;
+    debug!(
+        "Advertising {} over mDNS, battery {:?}%",
+        config.name, battery
+    );
+    Ok(())
+}
+
+/// A robot answering `discover()`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Robot {
+    pub name: String,
+    /// mDNS host name, e.g. `robot.local.`.
+    pub host: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    pub scheme: String,
+    pub boards: Option<usize>,
+    pub battery_percent: Option<f32>,
+    pub version: Option<String>,
+}
+
+impl Robot {
+    /// Where its dashboard is, at its first address.
+    pub fn url(&self) -> Option<String> {
+        let address = self.addresses.first()?;
+        Some(match address {
+            IpAddr::V4(address) => format!("{}://{}:{}/", self.scheme, address, self.port),
+            IpAddr::V6(address) => format!("{}://[{}]:{}/", self.scheme, address, self.port),
+        })
+    }
+}
+
+impl Display for Robot {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} ({})", self.name, self.host)?;
+        if let Some(url) = self.url() {
+            write!(formatter, " {}", url)?;
+        }
+        if let Some(boards) = self.boards {
+            write!(formatter, ", {} board(s)", boards)?;
+        }
+        if let Some(battery) = self.battery_percent {
+            write!(formatter, ", battery {:.0}%", battery)?;
+        }
+        Ok(())
+    }
+}
+
+/// Browses for robots for `timeout`, returning those found by name.
+pub fn discover(timeout: Duration) -> Result<Vec<Robot>, Error> {
+    let daemon = ServiceDaemon::new()?;
+    let events = daemon.browse(SERVICE_TYPE)?;
+    let deadline = Instant::now() + timeout;
+    let mut robots = BTreeMap::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        if let ServiceEvent::ServiceResolved(service) = event {
+            let property = |key| service.get_property_val_str(key);
+            let mut addresses: Vec<IpAddr> = service
+                .get_addresses()
+                .iter()
+                .map(|address| address.to_ip_addr())
+                .collect();
+            // IPv4 first, as the most likely to be reachable.
+            addresses.sort_by_key(|address| (address.is_ipv6(), *address));
+            let robot = Robot {
+                name: property("name")
+                    .unwrap_or_else(|| service.get_fullname())
+                    .to_string(),
+                host: service.get_hostname().to_string(),
+                addresses,
+                port: service.get_port(),
+                scheme: property("scheme").unwrap_or("http").to_string(),
+                boards: property("boards").and_then(|boards| boards.parse().ok()),
+                battery_percent: property("battery").and_then(|battery| battery.parse().ok()),
+                version: property("version").map(str::to_string),
+            };
+            robots.insert(service.get_fullname().to_string(), robot);
+        }
+    }
+    if let Err(error) = daemon.shutdown() {
+        debug!("Could not stop the mDNS browser: {}", error);
+    }
+    Ok(robots.into_values().collect())
+}
@@ -0,0 +1,62 @@
+//! Encodings of the telemetry and commands of the network frontends. JSON
+//! is the default, and what the page of `vrum serve` speaks. CBOR (RFC 8949)
+//! carries the same messages in roughly half the bytes, for teleop over a
+//! slow or flaky Wi-Fi link. Each transport picks its own in the
+//! `[encoding]` section of the configuration file:
+//!
+//! ```toml
+//! [encoding]
+//! mqtt = "cbor"
+//! web = "json"
+//! ```
+//!
+//! The packets of `vrum udp-teleop` are compact binary already, see
+//! `vrum::udp`.
+//!
+//! ```
+//! # use vrum::encoding::Encoding;
+//! let bytes = Encoding::Cbor.encode(&[0.5f32, -0.5])?;
+//! assert_eq!(Encoding::Cbor.decode::<[f32; 2]>(&bytes)?, [0.5, -0.5]);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::Error;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoding::Json => Ok(serde_json::to_vec(value)?),
+            Encoding::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, Error> {
+        match self {
+            Encoding::Json => Ok(serde_json::from_slice(bytes)?),
+            Encoding::Cbor => Ok(ciborium::from_reader(bytes)?),
+        }
+    }
+}
+
+/// The `[encoding]` section of the configuration file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncodingConfig {
+    pub mqtt: Encoding,
+    pub web: Encoding,
+}
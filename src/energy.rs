@@ -0,0 +1,453 @@
+//! Energy used by each run of vrum, and by each step of a mission, for
+//! knowing how long the battery will last and how it ages. Configured in the
+//! `[energy]` section of the configuration file:
+//!
+//! ```toml
+//! [energy]
+//! capacity_wh = 24.4
+//!
+//! [energy.ina219]
+//! shunt_ohms = 0.1
+//! ```
+//!
+//! An `EnergyMonitor` follows the telemetry samples. With an INA219 it
+//! integrates the power it measures, without one it estimates the energy
+//! from the drop of the state of charge read from the battery voltage, as a
+//! share of `capacity_wh`. The remaining runtime is the energy left in the
+//! battery at the mean power of the run so far.
+//!
+//! The running totals are kept in `state_file`, for `vrum status` to show
+//! from another process, and every finished run is appended to `log` as a
+//! line of JSON, so the capacity of the battery can be followed over its
+//! life.
+
+use std::fmt::{self, Display};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+use crate::sensors::ina219::{EnergyMeter, Ina219, Ina219Config, PowerReading};
+use crate::telemetry::{self, TelemetrySample};
+
+/// The `[energy]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnergyConfig {
+    /// Usable energy of a full battery, in watt hours. 24.4Wh for a 3S
+    /// 2200mAh LiPo.
+    pub capacity_wh: f32,
+    /// Current sensor on the pack, if any.
+    pub ina219: Option<Ina219Config>,
+    /// Totals of the current run, read by `vrum status`.
+    pub state_file: PathBuf,
+    /// Every finished run, one JSON object per line.
+    pub log: PathBuf,
+    /// How often the `state_file` is written.
+    pub write_interval_ms: u64,
+}
+
+impl Default for EnergyConfig {
+    fn default() -> Self {
+        EnergyConfig {
+            capacity_wh: 24.4,
+            ina219: None,
+            state_file: PathBuf::from("/var/lib/vrum/energy.json"),
+            log: PathBuf::from("/var/lib/vrum/energy.jsonl"),
+            write_interval_ms: 5000,
+        }
+    }
+}
+
+/// How the energy used was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnergySource {
+    /// Integrated from the power measured by a current sensor.
+    Current,
+    /// Estimated from the drop of the state of charge.
+    Voltage,
+}
+
+/// Energy used by part of a run, e.g. a mission step.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SegmentEnergy {
+    pub name: String,
+    /// Seconds since the UNIX epoch.
+    pub started: f64,
+    /// `None` while under way.
+    pub ended: Option<f64>,
+    pub energy_wh: f32,
+}
+
+/// The energy used by a run so far, as shown by `vrum status`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnergyReport {
+    /// Seconds since the UNIX epoch.
+    pub started: f64,
+    pub updated: f64,
+    /// `None` while running, or if vrum didn't exit cleanly.
+    pub ended: Option<f64>,
+    pub source: EnergySource,
+    pub energy_wh: f32,
+    /// Charge drawn, with a current sensor.
+    pub charge_mah: Option<f32>,
+    /// Highest current seen, with a current sensor.
+    pub peak_current: Option<f32>,
+    /// Over the run so far, in watts.
+    pub mean_power: f32,
+    /// State of charge when the run started, in `[0, 100]`.
+    pub start_percent: f32,
+    pub battery_voltage: f32,
+    pub battery_percent: f32,
+    /// At the mean power so far, `None` until some energy was used.
+    pub remaining_runtime_s: Option<f32>,
+    pub segments: Vec<SegmentEnergy>,
+}
+
+impl Display for EnergyReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let state = if self.ended.is_some() {
+            "Last run"
+        } else {
+            "This run"
+        };
+        let source = match self.source {
+            EnergySource::Current => "measured",
+            EnergySource::Voltage => "estimated from the voltage",
+        };
+        write!(
+            formatter,
+            "{} used {:.2}Wh ({}) over {:.0}s, {:.1}W on average",
+            state,
+            self.energy_wh,
+            source,
+            self.updated - self.started,
+            self.mean_power
+        )?;
+        if let Some(charge) = self.charge_mah {
+            write!(formatter, ", {:.0}mAh", charge)?;
+        }
+        if let Some(runtime) = self.remaining_runtime_s {
+            write!(
+                formatter,
+                "\nAbout {:.0} min of runtime left",
+                runtime / 60.0
+            )?;
+        }
+        for segment in &self.segments {
+            write!(
+                formatter,
+                "\n  {:.2}Wh  {}",
+                segment.energy_wh, segment.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds up the energy used over a run from telemetry samples.
+#[derive(Clone, Debug)]
+pub struct EnergyAccount {
+    capacity_wh: f32,
+    meter: Option<EnergyMeter>,
+    started: f64,
+    start_percent: Option<f32>,
+    last: Option<TelemetrySample>,
+    segments: Vec<SegmentEnergy>,
+    /// Energy used when the open segment started.
+    segment_start_wh: f32,
+}
+
+impl EnergyAccount {
+    /// `measured` when the power comes from a current sensor, see
+    /// `update_power`.
+    pub fn new(capacity_wh: f32, measured: bool) -> Self {
+        EnergyAccount {
+            capacity_wh,
+            meter: if measured {
+                Some(EnergyMeter::new())
+            } else {
+                None
+            },
+            started: telemetry::unix_timestamp(),
+            start_percent: None,
+            last: None,
+            segments: Vec::new(),
+            segment_start_wh: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, sample: TelemetrySample) {
+        if self.start_percent.is_none() {
+            self.start_percent = Some(sample.battery_percent);
+        }
+        self.last = Some(sample);
+    }
+
+    /// Adds the power measured by a current sensor.
+    pub fn update_power(&mut self, reading: PowerReading) {
+        if let Some(ref mut meter) = self.meter {
+            meter.update(reading);
+        }
+    }
+
+    pub fn energy_wh(&self) -> f32 {
+        match (&self.meter, &self.last, self.start_percent) {
+            (Some(meter), _, _) => meter.energy_wh(),
+            (None, Some(last), Some(start)) => {
+                (start - last.battery_percent).max(0.0) / 100.0 * self.capacity_wh
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Ends the open segment, if any, and starts one called `name`.
+    pub fn begin_segment(&mut self, name: &str) {
+        self.end_segment();
+        self.segment_start_wh = self.energy_wh();
+        self.segments.push(SegmentEnergy {
+            name: name.into(),
+            started: telemetry::unix_timestamp(),
+            ended: None,
+            energy_wh: 0.0,
+        });
+    }
+
+    pub fn end_segment(&mut self) {
+        let energy = self.energy_wh();
+        if let Some(segment) = self.segments.last_mut().filter(|s| s.ended.is_none()) {
+            segment.ended = Some(telemetry::unix_timestamp());
+            segment.energy_wh = energy - self.segment_start_wh;
+        }
+    }
+
+    pub fn report(&self) -> EnergyReport {
+        let updated = self
+            .last
+            .as_ref()
+            .map_or(self.started, |sample| sample.timestamp);
+        let energy_wh = self.energy_wh();
+        let hours = ((updated - self.started) / 3600.0) as f32;
+        let mean_power = if hours > 0.0 { energy_wh / hours } else { 0.0 };
+        let (battery_voltage, battery_percent) = self
+            .last
+            .as_ref()
+            .map_or((0.0, 0.0), |s| (s.battery_voltage, s.battery_percent));
+        let remaining_wh = self.capacity_wh * battery_percent / 100.0;
+        let mut segments = self.segments.clone();
+        if let Some(segment) = segments.last_mut().filter(|s| s.ended.is_none()) {
+            segment.energy_wh = energy_wh - self.segment_start_wh;
+        }
+        EnergyReport {
+            started: self.started,
+            updated,
+            ended: None,
+            source: match self.meter {
+                Some(_) => EnergySource::Current,
+                None => EnergySource::Voltage,
+            },
+            energy_wh,
+            charge_mah: self.meter.as_ref().map(EnergyMeter::charge_mah),
+            peak_current: self.meter.as_ref().map(EnergyMeter::peak_current),
+            mean_power,
+            start_percent: self.start_percent.unwrap_or(battery_percent),
+            battery_voltage,
+            battery_percent,
+            remaining_runtime_s: Some(remaining_wh / mean_power * 3600.0)
+                .filter(|_| mean_power > 0.0),
+            segments,
+        }
+    }
+}
+
+/// Marks segments of the run of an `EnergyMonitor`, e.g. mission steps.
+#[derive(Clone, Debug)]
+pub struct EnergyHandle {
+    account: Arc<Mutex<EnergyAccount>>,
+}
+
+impl EnergyHandle {
+    /// Ends the open segment, if any, and starts one called `name`.
+    pub fn begin_segment(&self, name: &str) {
+        self.lock().begin_segment(name);
+    }
+
+    pub fn end_segment(&self) {
+        self.lock().end_segment();
+    }
+
+    pub fn report(&self) -> EnergyReport {
+        self.lock().report()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, EnergyAccount> {
+        // Updated field by field from plain values, a poisoned account is
+        // still usable.
+        self.account.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Adds up the energy used from telemetry `samples` on a background thread,
+/// writing the totals to the `state_file` as it goes and to the `log` when
+/// dropped.
+pub struct EnergyMonitor {
+    handle: EnergyHandle,
+    config: EnergyConfig,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EnergyMonitor {
+    /// Opens the INA219 of `config`, if any, on `bus`.
+    pub fn spawn(
+        config: EnergyConfig,
+        samples: Receiver<TelemetrySample>,
+        bus: &BusManager,
+    ) -> Result<Self, Error> {
+        let mut sensor = match config.ina219 {
+            Some(ref ina219_config) => Some(Ina219::new(
+                Box::new(bus.device(ina219_config.address)),
+                ina219_config,
+            )?),
+            None => None,
+        };
+        let handle = EnergyHandle {
+            account: Arc::new(Mutex::new(EnergyAccount::new(
+                config.capacity_wh,
+                sensor.is_some(),
+            ))),
+        };
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_handle = handle.clone();
+        let thread_running = running.clone();
+        let state_file = config.state_file.clone();
+        let write_interval = Duration::from_millis(config.write_interval_ms);
+        let thread = thread::Builder::new()
+            .name("vrum-energy".into())
+            .spawn(move || {
+                let mut written: Option<Instant> = None;
+                let mut failing = false;
+                while thread_running.load(Ordering::SeqCst) {
+                    let sample = match samples.recv_timeout(RECV_TIMEOUT) {
+                        Ok(sample) => sample,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    let reading = sensor.as_mut().map(Ina219::read).transpose();
+                    let mut account = thread_handle.lock();
+                    account.update(sample);
+                    match reading {
+                        Ok(Some(reading)) => account.update_power(reading),
+                        Ok(None) => {}
+                        Err(error) => debug!("Could not read the pack current: {}", error),
+                    }
+                    drop(account);
+                    if written.is_none_or(|at| at.elapsed() >= write_interval) {
+                        written = Some(Instant::now());
+                        match write_report(&state_file, &thread_handle.report()) {
+                            Ok(()) => failing = false,
+                            Err(error) if !failing => {
+                                warn!(
+                                    "Could not write the energy used to {}: {}",
+                                    state_file.display(),
+                                    error
+                                );
+                                failing = true;
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                }
+            })?;
+
+        Ok(EnergyMonitor {
+            handle,
+            config,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn handle(&self) -> EnergyHandle {
+        self.handle.clone()
+    }
+
+    pub fn report(&self) -> EnergyReport {
+        self.handle.report()
+    }
+}
+
+impl Drop for EnergyMonitor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Energy monitor thread panicked");
+            }
+        }
+        self.handle.end_segment();
+        let mut report = self.handle.report();
+        report.ended = Some(telemetry::unix_timestamp());
+        info!(
+            energy_wh = report.energy_wh,
+            "Used {:.2}Wh, {:.1}W on average, battery at {:.0}%",
+            report.energy_wh,
+            report.mean_power,
+            report.battery_percent
+        );
+        let stored = write_report(&self.config.state_file, &report)
+            .and_then(|()| append_report(&self.config.log, &report));
+        if let Err(error) = stored {
+            error!("Could not store the energy used by the run: {}", error);
+        }
+    }
+}
+
+/// The report in the `state_file` of an `EnergyConfig`, `None` if no run
+/// wrote one yet.
+pub fn read_report<P: AsRef<Path>>(path: P) -> Result<Option<EnergyReport>, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Replaces the file at `path`, whole, so `vrum status` never reads half of
+/// it.
+fn write_report(path: &Path, report: &EnergyReport) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, serde_json::to_vec(report)?)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+fn append_report(path: &Path, report: &EnergyReport) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_vec(report)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)?;
+    Ok(())
+}
+
+/// How long the thread waits for a sample before checking whether it should
+/// stop.
+const RECV_TIMEOUT: Duration = Duration::from_millis(200);
@@ -0,0 +1,210 @@
+//! The error type returned throughout the crate.
+
+use std::io;
+
+use i2cdev::linux::LinuxI2CError;
+
+use crate::arbiter::ArbitrationError;
+use crate::arming::ArmingError;
+use crate::auth::AuthError;
+use crate::bus_manager::BusManagerError;
+use crate::color::ColorError;
+use crate::estop::EStopError;
+use crate::faults::FaultError;
+use crate::gears::GearError;
+use crate::gpio_outputs::AuxError;
+use crate::heartbeat::HeartbeatError;
+use crate::influx::InfluxError;
+use crate::line_follower::LineFollowerError;
+use crate::mission::MissionError;
+use crate::motion::MotionError;
+use crate::motor_driver::MotorDriverError;
+use crate::obstacle::ObstacleError;
+use crate::pan_tilt::PanTiltError;
+use crate::pca9685::Pca9685Error;
+use crate::pipeline::PipelineError;
+use crate::profile::ProfileError;
+use crate::rc::RcError;
+use crate::realtime::RealtimeError;
+#[cfg(feature = "ros")]
+use crate::ros::RosError;
+#[cfg(feature = "sqlite")]
+use crate::run_log::RunLogError;
+#[cfg(feature = "scripting")]
+use crate::scripting::ScriptError;
+use crate::sensors::ads1115::Ads1115Error;
+use crate::sensors::gps::GpsError;
+use crate::sensors::temperature::TemperatureError;
+use crate::sensors::ultrasonic::UltrasonicError;
+use crate::shared::SharedControllerError;
+use crate::shutdown::ShutdownError;
+use crate::thunder_borg::ControllerError;
+#[cfg(feature = "web")]
+use crate::tls::TlsError;
+use crate::udp::UdpError;
+use crate::waypoint::WaypointError;
+
+/// Everything that can go wrong driving a robot. Failures of the bus and of
+/// the boards on it have their own variants, the errors specific to a
+/// subsystem (e.g. the drive pipeline or the emergency stop) are wrapped as
+/// they are.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("I2C error: {0}")]
+    I2c(#[from] LinuxI2CError),
+    #[error("GPIO error: {0}")]
+    Gpio(#[from] rppal::gpio::Error),
+    /// From a bus shared through a `BusManager`.
+    #[error("I2C error: {0}")]
+    SharedI2c(#[from] rppal::i2c::Error),
+    #[error("serial port error: {0}")]
+    Uart(#[from] rppal::uart::Error),
+    #[error("no valid response to {command} after {attempts} attempts, last read {raw:?}")]
+    CommandFailed {
+        command: String,
+        attempts: usize,
+        raw: Vec<u8>,
+    },
+    #[error("found a board with id 0x{id:x}, the {board} id is 0x{expected:x}")]
+    UnexpectedBoardId {
+        board: &'static str,
+        expected: u8,
+        id: u8,
+    },
+    #[error("response {raw:?} is too short to read {width} bytes at offset {offset}")]
+    ResponseTooShort {
+        offset: usize,
+        width: usize,
+        raw: Vec<u8>,
+    },
+    #[error("{len} data bytes given, a command takes at most {max}")]
+    CommandTooLong { len: usize, max: usize },
+    #[error("cannot read {len} bytes, a response is 1 to {max} bytes")]
+    InvalidReadLength { len: usize, max: usize },
+    #[error("motor power {power} is not in [-1, 1]")]
+    InvalidMotorPower { power: f32 },
+    #[error("bus recovery failed, the board answered the ping with {response:?}")]
+    RecoveryFailed { response: Vec<u8> },
+    #[error("cannot decode CBOR: {0}")]
+    CborDecode(#[from] ciborium::de::Error<io::Error>),
+    #[error("cannot encode CBOR: {0}")]
+    CborEncode(#[from] ciborium::ser::Error<io::Error>),
+    #[error("invalid configuration: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error("cannot edit the configuration: {0}")]
+    ConfigEdit(#[from] toml_edit::TomlError),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[cfg(feature = "grpc")]
+    #[error("gRPC server error: {0}")]
+    Grpc(#[from] tonic::transport::Error),
+    #[cfg(feature = "mdns")]
+    #[error("mDNS error: {0}")]
+    Mdns(#[from] mdns_sd::Error),
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT client error: {0}")]
+    MqttClient(#[from] rumqttc::ClientError),
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT connection error: {0}")]
+    MqttConnection(Box<rumqttc::ConnectionError>),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(any(feature = "ros", feature = "web"))]
+    #[error("websocket error: {0}")]
+    WebSocket(Box<tungstenite::Error>),
+    #[error(transparent)]
+    Ads1115(#[from] Ads1115Error),
+    #[error(transparent)]
+    Arbitration(#[from] ArbitrationError),
+    #[error(transparent)]
+    Arming(#[from] ArmingError),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Aux(#[from] AuxError),
+    #[error(transparent)]
+    BusManager(#[from] BusManagerError),
+    #[error(transparent)]
+    Color(#[from] ColorError),
+    #[error(transparent)]
+    Controller(#[from] ControllerError),
+    #[error(transparent)]
+    EStop(#[from] EStopError),
+    #[error(transparent)]
+    Fault(#[from] FaultError),
+    #[error(transparent)]
+    Gear(#[from] GearError),
+    #[error(transparent)]
+    Gps(#[from] GpsError),
+    #[error(transparent)]
+    Heartbeat(#[from] HeartbeatError),
+    #[error(transparent)]
+    Influx(#[from] InfluxError),
+    #[error(transparent)]
+    LineFollower(#[from] LineFollowerError),
+    #[error(transparent)]
+    Mission(#[from] MissionError),
+    #[error(transparent)]
+    Motion(#[from] MotionError),
+    #[error(transparent)]
+    MotorDriver(#[from] MotorDriverError),
+    #[error(transparent)]
+    Obstacle(#[from] ObstacleError),
+    #[error(transparent)]
+    PanTilt(#[from] PanTiltError),
+    #[error(transparent)]
+    Pca9685(#[from] Pca9685Error),
+    #[error(transparent)]
+    Pipeline(#[from] PipelineError),
+    #[error(transparent)]
+    Profile(#[from] ProfileError),
+    #[error(transparent)]
+    Rc(#[from] RcError),
+    #[error(transparent)]
+    Realtime(#[from] RealtimeError),
+    #[cfg(feature = "ros")]
+    #[error(transparent)]
+    Ros(#[from] RosError),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    RunLog(#[from] RunLogError),
+    #[cfg(feature = "scripting")]
+    #[error(transparent)]
+    Script(#[from] ScriptError),
+    #[error(transparent)]
+    Shared(#[from] SharedControllerError),
+    #[error(transparent)]
+    Shutdown(#[from] ShutdownError),
+    #[cfg(feature = "web")]
+    #[error(transparent)]
+    Tls(#[from] TlsError),
+    #[error(transparent)]
+    Temperature(#[from] TemperatureError),
+    #[error(transparent)]
+    Udp(#[from] UdpError),
+    #[error(transparent)]
+    Ultrasonic(#[from] UltrasonicError),
+    #[error(transparent)]
+    Waypoint(#[from] WaypointError),
+}
+
+// Boxed to keep `Error`, and so every `Result` in the crate, small.
+
+#[cfg(feature = "mqtt")]
+impl From<rumqttc::ConnectionError> for Error {
+    fn from(error: rumqttc::ConnectionError) -> Self {
+        Error::MqttConnection(Box::new(error))
+    }
+}
+
+#[cfg(any(feature = "ros", feature = "web"))]
+impl From<tungstenite::Error> for Error {
+    fn from(error: tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(error))
+    }
+}
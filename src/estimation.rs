@@ -0,0 +1,406 @@
+//! Pose estimation. Dead reckoning drifts: wheels slip, motors don't turn
+//! at the speed commanded, and small heading errors grow into large
+//! position errors. A `PoseEstimator` is an extended Kalman filter on the
+//! pose, predicting from wheel odometry (or, without encoders, from the
+//! powers commanded) and correcting with the heading from an IMU or
+//! compass and, outdoors, the position from a GPS. The estimate comes with
+//! its covariance, so consumers know how far to trust it.
+//!
+//! ```
+//! # use vrum::estimation::{EstimatorConfig, PoseEstimator};
+//! # use vrum::kinematics::{DiffDrive, Pose};
+//! let mut estimator =
+//!     PoseEstimator::new(DiffDrive::new(0.2, 1.0), Pose::default(), EstimatorConfig::default());
+//! estimator.predict_wheels(1.0, 1.0);
+//! estimator.update_heading(0.0, 0.01);
+//! let estimate = estimator.estimate();
+//! assert!((estimate.pose.x - 1.0).abs() < 1e-3);
+//! assert!(estimate.position_std() > 0.0);
+//! ```
+
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::heading::Compass;
+use crate::kinematics::{DiffDrive, Pose};
+use crate::sensors::gps::{GpsReceiver, Position};
+
+/// The `[estimation]` section of the configuration file. The noise
+/// parameters are standard deviations.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EstimatorConfig {
+    /// Error of the distance from the wheel encoders, as a fraction of the
+    /// distance.
+    pub distance_noise: f32,
+    /// Error of the rotation from the wheel encoders, as a fraction of the
+    /// rotation.
+    pub turn_noise: f32,
+    /// Heading drift while driving, in radians per metre.
+    pub drift_noise: f32,
+    /// Factor on the noise when predicting from the powers commanded,
+    /// without encoders.
+    pub open_loop_factor: f32,
+    /// Error of a heading reading, in degrees.
+    pub heading_std_deg: f32,
+    /// Error of a GPS position per unit of HDOP, in metres.
+    pub gps_uere: f32,
+    /// Error of the starting pose, in metres and degrees.
+    pub initial_position_std: f32,
+    pub initial_heading_std_deg: f32,
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        EstimatorConfig {
+            distance_noise: 0.05,
+            turn_noise: 0.1,
+            drift_noise: 0.02,
+            open_loop_factor: 4.0,
+            heading_std_deg: 2.0,
+            gps_uere: 4.0,
+            initial_position_std: 0.01,
+            initial_heading_std_deg: 1.0,
+        }
+    }
+}
+
+/// Covariance of `(x, y, heading)`, in metres and radians.
+pub type Covariance = [[f32; 3]; 3];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct PoseEstimate {
+    pub pose: Pose,
+    pub covariance: Covariance,
+}
+
+impl PoseEstimate {
+    /// Standard deviation of the position, the root of the larger
+    /// eigenvalue of its covariance, in metres.
+    pub fn position_std(&self) -> f32 {
+        let [[xx, xy, _], [_, yy, _], _] = self.covariance;
+        let mean = (xx + yy) / 2.0;
+        let spread = (((xx - yy) / 2.0).powi(2) + xy * xy).sqrt();
+        (mean + spread).max(0.0).sqrt()
+    }
+
+    /// Standard deviation of the heading, in radians.
+    pub fn heading_std(&self) -> f32 {
+        self.covariance[2][2].max(0.0).sqrt()
+    }
+}
+
+/// The latest estimate of a `PoseEstimator`, e.g. for telemetry.
+#[derive(Clone, Default)]
+pub struct PoseHandle {
+    latest: Arc<Mutex<PoseEstimate>>,
+}
+
+impl PoseHandle {
+    pub fn latest(&self) -> PoseEstimate {
+        // The estimate is only ever replaced whole, a panic can't leave it
+        // half written.
+        *self
+            .latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn publish(&self, estimate: PoseEstimate) {
+        *self
+            .latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = estimate;
+    }
+}
+
+pub struct PoseEstimator {
+    drive: DiffDrive,
+    config: EstimatorConfig,
+    pose: Pose,
+    covariance: Covariance,
+    handle: PoseHandle,
+}
+
+impl PoseEstimator {
+    pub fn new(drive: DiffDrive, start: Pose, config: EstimatorConfig) -> Self {
+        let mut estimator = PoseEstimator {
+            drive,
+            config,
+            pose: start,
+            covariance: Covariance::default(),
+            handle: PoseHandle::default(),
+        };
+        estimator.reset(start);
+        estimator
+    }
+
+    pub fn estimate(&self) -> PoseEstimate {
+        PoseEstimate {
+            pose: self.pose,
+            covariance: self.covariance,
+        }
+    }
+
+    /// A handle following the estimate as it is updated.
+    pub fn handle(&self) -> PoseHandle {
+        self.handle.clone()
+    }
+
+    /// Starts over from `pose`, with the initial uncertainty.
+    pub fn reset(&mut self, pose: Pose) {
+        let position_var = self.config.initial_position_std.powi(2);
+        let heading_var = self.config.initial_heading_std_deg.to_radians().powi(2);
+        self.pose = pose;
+        self.covariance = [
+            [position_var, 0.0, 0.0],
+            [0.0, position_var, 0.0],
+            [0.0, 0.0, heading_var],
+        ];
+        self.publish();
+    }
+
+    /// Predicts from the distances travelled by the left and right wheels
+    /// since the last prediction, in metres.
+    pub fn predict_wheels(&mut self, left: f32, right: f32) {
+        let (distance, rotation) = self.drive.body_velocity(left, right);
+        self.predict(distance, rotation, 1.0);
+    }
+
+    /// Predicts from the left and right motor powers applied for `elapsed`,
+    /// through the drive model, with `open_loop_factor` times the noise.
+    pub fn predict_open_loop(&mut self, left: f32, right: f32, elapsed: Duration) {
+        let travel = self.drive.max_wheel_speed * elapsed.as_secs_f32();
+        let (distance, rotation) = self.drive.body_velocity(left * travel, right * travel);
+        self.predict(distance, rotation, self.config.open_loop_factor);
+    }
+
+    /// Corrects with a heading reading in radians, counter-clockwise from
+    /// the x axis like `Pose::heading`, with standard deviation `std`.
+    pub fn update_heading(&mut self, heading: f32, std: f32) {
+        self.update(2, heading, std * std);
+        self.publish();
+    }
+
+    /// Corrects with a position reading in metres, with standard deviation
+    /// `std` on each axis.
+    pub fn update_position(&mut self, x: f32, y: f32, std: f32) {
+        self.update(0, x, std * std);
+        self.update(1, y, std * std);
+        self.publish();
+    }
+
+    /// One filter step: predicts from the wheel encoders in `sensors` or,
+    /// without them, from the motor powers `(left, right)` applied for
+    /// `elapsed`, then corrects with whatever else `sensors` read. Sensors
+    /// that fail to read are skipped.
+    pub fn step(
+        &mut self,
+        sensors: &mut PoseSensors,
+        powers: (f32, f32),
+        elapsed: Duration,
+    ) -> PoseEstimate {
+        match sensors.wheel_travel() {
+            Some((left, right)) => self.predict_wheels(left, right),
+            None => self.predict_open_loop(powers.0, powers.1, elapsed),
+        }
+        if let Some(ref mut read) = sensors.heading {
+            match read() {
+                Ok(heading) => {
+                    self.update(2, heading, self.config.heading_std_deg.to_radians().powi(2))
+                }
+                Err(error) => warn!("Could not read the heading: {}", error),
+            }
+        }
+        if let Some(ref mut read) = sensors.position {
+            match read() {
+                Ok(Some(reading)) => {
+                    let variance = reading.std * reading.std;
+                    self.update(0, reading.x, variance);
+                    self.update(1, reading.y, variance);
+                }
+                Ok(None) => {}
+                Err(error) => warn!("Could not read the position: {}", error),
+            }
+        }
+        self.publish();
+        self.estimate()
+    }
+
+    /// Moves the pose `distance` along the mean heading, turning by
+    /// `rotation`, and grows the covariance with the noise scaled by
+    /// `noise_factor`.
+    fn predict(&mut self, distance: f32, rotation: f32, noise_factor: f32) {
+        let config = &self.config;
+        let (sin, cos) = (self.pose.heading + rotation / 2.0).sin_cos();
+        self.pose.x += distance * cos;
+        self.pose.y += distance * sin;
+        self.pose.heading += rotation;
+
+        // Jacobians with respect to the state and to (distance, rotation).
+        let f = [
+            [1.0, 0.0, -distance * sin],
+            [0.0, 1.0, distance * cos],
+            [0.0, 0.0, 1.0],
+        ];
+        let g = [
+            [cos, -distance / 2.0 * sin],
+            [sin, distance / 2.0 * cos],
+            [0.0, 1.0],
+        ];
+        let distance_var = (noise_factor * config.distance_noise * distance).powi(2);
+        let rotation_var = (noise_factor
+            * (config.turn_noise * rotation.abs() + config.drift_noise * distance.abs()))
+        .powi(2);
+
+        let p = self.covariance;
+        let mut next = Covariance::default();
+        for (i, row) in next.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                let mut sum = g[i][0] * g[j][0] * distance_var + g[i][1] * g[j][1] * rotation_var;
+                for k in 0..3 {
+                    for l in 0..3 {
+                        sum += f[i][k] * p[k][l] * f[j][l];
+                    }
+                }
+                *cell = sum;
+            }
+        }
+        self.covariance = next;
+        self.publish();
+    }
+
+    /// Kalman update with a reading of state component `index`.
+    fn update(&mut self, index: usize, value: f32, variance: f32) {
+        let state = [self.pose.x, self.pose.y, self.pose.heading];
+        let mut innovation = value - state[index];
+        if index == 2 {
+            innovation = (innovation + PI).rem_euclid(2.0 * PI) - PI;
+        }
+        let p = self.covariance;
+        let innovation_var = p[index][index] + variance;
+        if innovation_var <= 0.0 {
+            return;
+        }
+        let gain = [
+            p[0][index] / innovation_var,
+            p[1][index] / innovation_var,
+            p[2][index] / innovation_var,
+        ];
+        self.pose.x += gain[0] * innovation;
+        self.pose.y += gain[1] * innovation;
+        self.pose.heading += gain[2] * innovation;
+        for (i, row) in self.covariance.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = p[i][j] - gain[i] * p[index][j];
+            }
+        }
+    }
+
+    fn publish(&self) {
+        self.handle.publish(self.estimate());
+    }
+}
+
+/// A position reading in the frame of the pose, in metres.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionReading {
+    pub x: f32,
+    pub y: f32,
+    /// Standard deviation on each axis.
+    pub std: f32,
+}
+
+type Reader<T> = Box<dyn FnMut() -> Result<T, Error> + Send>;
+
+/// The sensors a `PoseEstimator` reads at every `step()`, all optional.
+#[derive(Default)]
+pub struct PoseSensors {
+    wheels: Option<Reader<(f32, f32)>>,
+    last_wheels: Option<(f32, f32)>,
+    heading: Option<Reader<f32>>,
+    position: Option<Reader<Option<PositionReading>>>,
+}
+
+impl PoseSensors {
+    pub fn new() -> Self {
+        PoseSensors::default()
+    }
+
+    /// `read` returns the total distance travelled by the left and right
+    /// wheels, in metres, e.g. from encoder counts.
+    pub fn wheels<F>(mut self, read: F) -> Self
+    where
+        F: FnMut() -> Result<(f32, f32), Error> + Send + 'static,
+    {
+        self.wheels = Some(Box::new(read));
+        self
+    }
+
+    /// `read` returns the heading in radians, counter-clockwise from the x
+    /// axis like `Pose::heading`.
+    pub fn heading<F>(mut self, read: F) -> Self
+    where
+        F: FnMut() -> Result<f32, Error> + Send + 'static,
+    {
+        self.heading = Some(Box::new(read));
+        self
+    }
+
+    /// Headings from `compass`, in degrees clockwise. `offset` is the pose
+    /// heading when the compass reads 0, e.g. π/2 for a frame with the x axis
+    /// east and a compass reading from north.
+    pub fn compass<C>(self, mut compass: C, offset: f32) -> Self
+    where
+        C: Compass + Send + 'static,
+    {
+        self.heading(move || Ok(offset - compass.heading()?.to_radians()))
+    }
+
+    /// `read` returns a position, `None` when there is no reading.
+    pub fn position<F>(mut self, read: F) -> Self
+    where
+        F: FnMut() -> Result<Option<PositionReading>, Error> + Send + 'static,
+    {
+        self.position = Some(Box::new(read));
+        self
+    }
+
+    /// Positions from the fixes of `gps`, in a frame with the x axis east
+    /// and the y axis north centred on `origin`. The error grows with the
+    /// HDOP of the fix, `uere` metres per unit.
+    pub fn gps(self, gps: Arc<GpsReceiver>, origin: Position, uere: f32) -> Self {
+        self.position(move || {
+            Ok(gps.fix().map(|fix| {
+                let (east, north) = fix.position.offset_from(&origin);
+                PositionReading {
+                    x: east as f32,
+                    y: north as f32,
+                    std: uere * fix.hdop.unwrap_or(DEFAULT_HDOP),
+                }
+            }))
+        })
+    }
+
+    /// Distances travelled by the wheels since the last call, `None` without
+    /// encoders or if they could not be read.
+    fn wheel_travel(&mut self) -> Option<(f32, f32)> {
+        let read = self.wheels.as_mut()?;
+        let (left, right) = match read() {
+            Ok(total) => total,
+            Err(error) => {
+                warn!("Could not read the wheel encoders: {}", error);
+                return None;
+            }
+        };
+        let last = self.last_wheels.replace((left, right));
+        Some(last.map_or((0.0, 0.0), |(last_left, last_right)| {
+            (left - last_left, right - last_right)
+        }))
+    }
+}
+
+/// Assumed for fixes that don't report it.
+const DEFAULT_HDOP: f32 = 2.0;
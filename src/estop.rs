@@ -0,0 +1,172 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rppal::gpio::{Gpio, InputPin, Trigger};
+
+use crate::error::Error;
+use crate::journal;
+use crate::motor_driver::MotorDriver;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EStopError {
+    #[error("cannot reset the emergency stop while the button is still pressed")]
+    StillPressed,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EStopConfig {
+    /// BCM number of the GPIO pin the e-stop button is wired to.
+    pub pin: u8,
+    /// True if the button pulls the pin low when pressed (the usual wiring,
+    /// using the internal pull-up), false if it pulls it high.
+    #[serde(default = "default_active_low")]
+    pub active_low: bool,
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+}
+
+/// Shared emergency-stop state. Once triggered it stays latched, and motor
+/// commands are rejected, until `reset()` is called with the button released.
+#[derive(Clone, Default)]
+pub struct EStopLatch {
+    state: Arc<LatchState>,
+}
+
+#[derive(Default)]
+struct LatchState {
+    latched: AtomicBool,
+    input_active: AtomicBool,
+    triggers: AtomicU64,
+}
+
+impl EStopLatch {
+    pub fn new() -> Self {
+        EStopLatch::default()
+    }
+
+    /// Latches the emergency stop, as if the button had been pressed.
+    pub fn trigger(&self) {
+        if !self.state.latched.swap(true, Ordering::SeqCst) {
+            self.state.triggers.fetch_add(1, Ordering::SeqCst);
+            error!(
+                message_id = journal::ESTOP_TRIGGERED,
+                "Emergency stop triggered"
+            );
+        }
+    }
+
+    pub fn is_latched(&self) -> bool {
+        self.state.latched.load(Ordering::SeqCst)
+    }
+
+    /// How many times it latched, to tell if it did in between two looks.
+    pub fn triggers(&self) -> u64 {
+        self.state.triggers.load(Ordering::SeqCst)
+    }
+
+    pub fn reset(&self) -> Result<(), Error> {
+        if self.state.input_active.load(Ordering::SeqCst) {
+            return Err(EStopError::StillPressed.into());
+        }
+        if self.state.latched.swap(false, Ordering::SeqCst) {
+            info!(message_id = journal::ESTOP_RESET, "Emergency stop reset");
+        }
+        Ok(())
+    }
+
+    fn set_input_active(&self, active: bool) {
+        self.state.input_active.store(active, Ordering::SeqCst);
+        if active {
+            self.trigger();
+        }
+    }
+}
+
+/// Listens for presses of a physical emergency-stop button on a GPIO pin.
+/// A press latches the `EStopLatch` and immediately runs the stop action, on
+/// the GPIO interrupt thread. Dropping the `EStop` stops listening.
+pub struct EStop {
+    _pin: InputPin,
+    latch: EStopLatch,
+}
+
+impl EStop {
+    pub fn spawn<F>(config: &EStopConfig, stop: F) -> Result<Self, Error>
+    where
+        F: FnMut() -> Result<(), Error> + Send + 'static,
+    {
+        info!(
+            "Listening for emergency stop on GPIO {} (active {})",
+            config.pin,
+            if config.active_low { "low" } else { "high" }
+        );
+        let pin = Gpio::new()?.get(config.pin)?;
+        let mut pin = if config.active_low {
+            pin.into_input_pullup()
+        } else {
+            pin.into_input_pulldown()
+        };
+
+        let latch = EStopLatch::new();
+        let stop = Arc::new(Mutex::new(stop));
+        let active_low = config.active_low;
+        let is_active = move |high: bool| high != active_low;
+
+        let callback_latch = latch.clone();
+        let callback_stop = stop.clone();
+        pin.set_async_interrupt(
+            Trigger::Both,
+            Some(Duration::from_millis(config.debounce_ms)),
+            move |event| {
+                let active = is_active(event.trigger == Trigger::RisingEdge);
+                callback_latch.set_input_active(active);
+                if active {
+                    run_stop(&callback_stop);
+                }
+            },
+        )?;
+
+        if is_active(pin.is_high()) {
+            latch.set_input_active(true);
+            run_stop(&stop);
+        }
+        Ok(EStop { _pin: pin, latch })
+    }
+
+    /// Spawns an e-stop listener that owns a dedicated handle to the board,
+    /// used only to switch everything off when the button is pressed.
+    pub fn with_controller<D>(config: &EStopConfig, mut controller: D) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        EStop::spawn(config, move || controller.stop_all())
+    }
+
+    pub fn latch(&self) -> EStopLatch {
+        self.latch.clone()
+    }
+}
+
+fn run_stop<F>(stop: &Mutex<F>)
+where
+    F: FnMut() -> Result<(), Error>,
+{
+    match stop.lock() {
+        Ok(mut stop) => {
+            if let Err(error) = (*stop)() {
+                error!("Emergency stop could not switch off the motors: {}", error);
+            }
+        }
+        Err(_) => error!("Emergency stop action lock poisoned"),
+    }
+}
+
+fn default_active_low() -> bool {
+    true
+}
+
+fn default_debounce_ms() -> u64 {
+    20
+}
@@ -0,0 +1,212 @@
+//! A `Bus` wrapper injecting the failures of a flaky I2C bus, to test how
+//! the crate copes with them without waiting for real hardware to misbehave:
+//!
+//! ```
+//! # use vrum::fault_injection::FaultyBus;
+//! # use vrum::simulator::SimulatedBoard;
+//! # use vrum::thunder_borg::ControllerBuilder;
+//! let board = SimulatedBoard::new();
+//! let faults = FaultyBus::new(board.bus(), 42);
+//! let mut controller = ControllerBuilder::new().build_with_bus(faults.bus())?;
+//! faults.wrong_echo_rate(0.2);
+//! for _ in 0..20 {
+//!     controller.get_battery_voltage()?;
+//! }
+//! assert!(faults.injected().wrong_echoes > 0);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+//!
+//! Faults are drawn from a generator seeded by the caller, so a failing run
+//! is reproduced by reusing its seed.
+
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::borg::Bus;
+use crate::error::Error;
+
+/// Number of faults injected so far, by kind.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InjectedFaults {
+    pub dropped_reads: u64,
+    pub wrong_echoes: u64,
+    pub flipped_bytes: u64,
+    pub timeouts: u64,
+}
+
+/// Handle to a `Bus` failing or corrupting some of its transfers, cheap to
+/// clone. `bus()` is handed to a controller while the handle changes the
+/// faults injected, e.g. once the controller is built:
+///
+/// - `drop_every_nth_read(n)`: every `n`th read fails with a bus error, as
+///   when the board doesn't acknowledge its address.
+/// - `wrong_echo_rate(p)`: responses start with a byte other than the
+///   command, which `BorgDevice` queries retry on.
+/// - `flip_rate(p)`: a bit of the data following the command byte is
+///   flipped. The protocol has no checksum, so these go unnoticed.
+/// - `timeout_rate(p)`: transfers fail with a timeout, after
+///   `timeout_delay`.
+///
+/// Rates are probabilities per transfer, in `[0, 1]`.
+#[derive(Clone)]
+pub struct FaultyBus {
+    inner: Arc<Mutex<Faults>>,
+}
+
+struct Faults {
+    bus: Box<dyn Bus>,
+    rng: XorShift,
+    drop_every_nth_read: Option<u64>,
+    wrong_echo_rate: f64,
+    flip_rate: f64,
+    timeout_rate: f64,
+    timeout_delay: Duration,
+    reads: u64,
+    injected: InjectedFaults,
+}
+
+impl FaultyBus {
+    /// Wraps `bus` without any faults enabled yet.
+    pub fn new(bus: Box<dyn Bus>, seed: u64) -> Self {
+        FaultyBus {
+            inner: Arc::new(Mutex::new(Faults {
+                bus,
+                rng: XorShift::new(seed),
+                drop_every_nth_read: None,
+                wrong_echo_rate: 0.0,
+                flip_rate: 0.0,
+                timeout_rate: 0.0,
+                timeout_delay: Duration::default(),
+                reads: 0,
+                injected: InjectedFaults::default(),
+            })),
+        }
+    }
+
+    /// A `Bus` injecting the faults, for `ControllerBuilder::build_with_bus`.
+    pub fn bus(&self) -> Box<dyn Bus> {
+        Box::new(self.clone())
+    }
+
+    /// Drops every `n`th read from now on, none if `n` is 0.
+    pub fn drop_every_nth_read(&self, n: u64) {
+        let mut faults = self.lock();
+        faults.drop_every_nth_read = Some(n).filter(|&n| n > 0);
+        faults.reads = 0;
+    }
+
+    pub fn wrong_echo_rate(&self, rate: f64) {
+        self.lock().wrong_echo_rate = rate;
+    }
+
+    pub fn flip_rate(&self, rate: f64) {
+        self.lock().flip_rate = rate;
+    }
+
+    pub fn timeout_rate(&self, rate: f64) {
+        self.lock().timeout_rate = rate;
+    }
+
+    /// How long a transfer hangs before timing out, not at all by default.
+    pub fn timeout_delay(&self, delay: Duration) {
+        self.lock().timeout_delay = delay;
+    }
+
+    /// Stops injecting faults.
+    pub fn heal(&self) {
+        let mut faults = self.lock();
+        faults.drop_every_nth_read = None;
+        faults.wrong_echo_rate = 0.0;
+        faults.flip_rate = 0.0;
+        faults.timeout_rate = 0.0;
+    }
+
+    pub fn injected(&self) -> InjectedFaults {
+        self.lock().injected.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Faults> {
+        // As for `SimulatedBoard`, a panicking test can't break the faults.
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Faults {
+    fn time_out(&mut self) -> Result<(), Error> {
+        if !self.rng.chance(self.timeout_rate) {
+            return Ok(());
+        }
+        self.injected.timeouts += 1;
+        thread::sleep(self.timeout_delay);
+        Err(io::Error::new(io::ErrorKind::TimedOut, "injected bus timeout").into())
+    }
+}
+
+impl Bus for FaultyBus {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut faults = self.lock();
+        faults.time_out()?;
+        faults.bus.write(bytes)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut faults = self.lock();
+        faults.reads += 1;
+        let reads = faults.reads;
+        if faults
+            .drop_every_nth_read
+            .is_some_and(|n| reads.is_multiple_of(n))
+        {
+            faults.injected.dropped_reads += 1;
+            return Err(io::Error::other("injected dropped read").into());
+        }
+        faults.time_out()?;
+        faults.bus.read(buffer)?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let wrong_echo_rate = faults.wrong_echo_rate;
+        if faults.rng.chance(wrong_echo_rate) {
+            faults.injected.wrong_echoes += 1;
+            buffer[0] = buffer[0].wrapping_add(1 + faults.rng.below(255) as u8);
+        }
+        let flip_rate = faults.flip_rate;
+        if buffer.len() > 1 && faults.rng.chance(flip_rate) {
+            faults.injected.flipped_bytes += 1;
+            let index = 1 + faults.rng.below(buffer.len() as u64 - 1) as usize;
+            buffer[index] ^= 1 << faults.rng.below(8);
+        }
+        Ok(())
+    }
+}
+
+/// Small deterministic generator (xorshift64*), plenty for picking faults.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Mixed so that small seeds don't start with mostly zero bits, a zero
+        // state would only ever produce zeros.
+        XorShift((seed ^ 0x9E37_79B9_7F4A_7C15).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    /// True with the given probability.
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
@@ -0,0 +1,440 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::black_box;
+use crate::error::Error;
+use crate::journal;
+use crate::motor_driver::MotorDriver;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FaultError {
+    #[error("cannot clear the latched fault while a drive fault is raised")]
+    StillFaulted,
+    #[error("cannot clear the latched fault while the battery is below the cutoff voltage")]
+    BatteryCutoff,
+}
+
+/// What the `FaultMonitor` does when a drive fault is raised.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultPolicy {
+    /// Stop both motors.
+    Stop,
+    /// Limit the power of subsequent commands to both motors to
+    /// `reduced_power` until the fault clears.
+    ReducePower,
+    /// Only log the fault and send a `FaultEvent`.
+    Log,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    pub policy: FaultPolicy,
+    pub reduced_power: f32,
+    pub poll_interval_ms: u64,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            policy: FaultPolicy::Stop,
+            reduced_power: 0.3,
+            poll_interval_ms: 250,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultEvent {
+    /// The drive fault flag of a motor, identified by its index, was set,
+    /// indicating e.g. a short circuit or under voltage.
+    Raised(usize),
+    Cleared(usize),
+    /// The fault flags could not be read.
+    ReadFailed,
+}
+
+/// The `[fault_latch]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultLatchConfig {
+    /// Latch battery cutoffs, and drive faults under `FaultPolicy::Stop`,
+    /// until cleared.
+    pub enabled: bool,
+    /// Keeps the latch across restarts, and lets `vrum faults clear` clear
+    /// the latch of another vrum process.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for FaultLatchConfig {
+    fn default() -> Self {
+        FaultLatchConfig {
+            enabled: true,
+            file: None,
+        }
+    }
+}
+
+/// Latched by a drive fault or a battery cutoff, after which motor commands
+/// are rejected until the operator clears it, instead of the next command
+/// driving a motor that may be shorted. Attached to a `Controller` with
+/// `Controller::set_fault_latch`.
+#[derive(Clone)]
+pub struct FaultLatch {
+    state: Arc<FaultLatchState>,
+}
+
+struct FaultLatchState {
+    /// What latched it, `None` while clear.
+    cause: Mutex<Option<String>>,
+    file: Option<PathBuf>,
+}
+
+impl FaultLatch {
+    /// A latch kept in memory.
+    pub fn new() -> Self {
+        FaultLatch::with_file(None)
+    }
+
+    /// A latch kept in `file` too, if any, latched if `file` exists. The
+    /// file is read again on a background thread, so motor commands never
+    /// wait on it.
+    pub fn with_file(file: Option<PathBuf>) -> Self {
+        let watched = file.is_some();
+        let latch = FaultLatch {
+            state: Arc::new(FaultLatchState {
+                cause: Mutex::new(None),
+                file,
+            }),
+        };
+        if watched {
+            latch.state.sync();
+            let state = Arc::downgrade(&latch.state);
+            let spawned = thread::Builder::new()
+                .name("vrum-fault-latch".into())
+                .spawn(move || watch_file(state));
+            if let Err(error) = spawned {
+                warn!(
+                    "Could not watch the latched fault file, a latch cleared by \
+                     another vrum goes unnoticed: {}",
+                    error
+                );
+            }
+        }
+        if let Some(cause) = latch.cause() {
+            warn!(
+                "Fault latched before start: {}, clear it with `vrum faults clear`",
+                cause
+            );
+        }
+        latch
+    }
+
+    pub fn from_config(config: &FaultLatchConfig) -> Option<Self> {
+        if config.enabled {
+            Some(FaultLatch::with_file(config.file.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Latches, unless already latched.
+    pub fn latch(&self, cause: &str) {
+        let mut current = self.current();
+        if current.is_some() {
+            return;
+        }
+        error!(
+            message_id = journal::FAULT_LATCHED,
+            "Fault latched: {}, clear it with `vrum faults clear`", cause
+        );
+        *current = Some(cause.to_string());
+        if let Some(ref file) = self.state.file {
+            let written = file
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::write(file, cause));
+            if let Err(error) = written {
+                warn!("Could not store the latched fault in {:?}: {}", file, error);
+            }
+        }
+        drop(current);
+        black_box::dump(&format!("fault latched: {}", cause));
+    }
+
+    /// What latched it, `None` while clear.
+    pub fn cause(&self) -> Option<String> {
+        self.current().clone()
+    }
+
+    pub fn is_latched(&self) -> bool {
+        self.current().is_some()
+    }
+
+    /// Clears the latch, see `Controller::clear_faults` for a version
+    /// checking that the faults went away first.
+    pub fn clear(&self) -> Result<(), Error> {
+        let mut current = self.current();
+        if let Some(ref file) = self.state.file {
+            match fs::remove_file(file) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+        }
+        if current.take().is_some() {
+            info!(message_id = journal::FAULT_CLEARED, "Latched fault cleared");
+        }
+        Ok(())
+    }
+
+    fn current(&self) -> MutexGuard<'_, Option<String>> {
+        self.state.current()
+    }
+}
+
+impl FaultLatchState {
+    fn current(&self) -> MutexGuard<'_, Option<String>> {
+        // Only ever replaced whole, a poisoned lock is still consistent.
+        self.cause.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Brings the cause in line with the file, which another vrum process
+    /// may have written or removed.
+    fn sync(&self) {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return,
+        };
+        let mut current = self.current();
+        match fs::read_to_string(file) {
+            Ok(cause) if current.is_none() => *current = Some(cause),
+            Err(error) if error.kind() == io::ErrorKind::NotFound && current.is_some() => {
+                info!(message_id = journal::FAULT_CLEARED, "Latched fault cleared");
+                *current = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Syncs the latch with its file until every handle to it is dropped.
+fn watch_file(state: Weak<FaultLatchState>) {
+    loop {
+        thread::sleep(LATCH_FILE_POLL_INTERVAL);
+        match state.upgrade() {
+            Some(state) => state.sync(),
+            None => return,
+        }
+    }
+}
+
+impl Default for FaultLatch {
+    fn default() -> Self {
+        FaultLatch::new()
+    }
+}
+
+/// Power limit imposed by a `FaultMonitor` using `FaultPolicy::ReducePower`,
+/// attached to a `Controller` with `Controller::set_fault_guard`.
+#[derive(Clone)]
+pub struct FaultGuard {
+    power_limit: Arc<AtomicU32>,
+    faulted: Arc<AtomicBool>,
+}
+
+impl FaultGuard {
+    fn new() -> Self {
+        FaultGuard {
+            power_limit: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            faulted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Whether a drive fault is raised on any motor.
+    pub fn is_faulted(&self) -> bool {
+        self.faulted.load(Ordering::SeqCst)
+    }
+
+    /// Maximum absolute motor power currently allowed, 1.0 when unrestricted.
+    pub fn power_limit(&self) -> f32 {
+        f32::from_bits(self.power_limit.load(Ordering::SeqCst))
+    }
+
+    fn set_power_limit(&self, limit: f32) {
+        self.power_limit.store(limit.to_bits(), Ordering::SeqCst);
+    }
+}
+
+/// Polls the drive fault flags on a background thread and applies a
+/// `FaultPolicy` when one is raised.
+pub struct FaultMonitor {
+    guard: FaultGuard,
+    subscribers: Arc<Mutex<Vec<Sender<FaultEvent>>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FaultMonitor {
+    /// `controller` is a dedicated handle used by the monitor thread to read
+    /// the fault flags and stop the motors. Under `FaultPolicy::Stop`, drive
+    /// faults latch `latch`, if given; the other policies keep driving.
+    pub fn spawn<D>(
+        config: FaultConfig,
+        mut controller: D,
+        latch: Option<FaultLatch>,
+    ) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        let guard = FaultGuard::new();
+        let subscribers: Arc<Mutex<Vec<Sender<FaultEvent>>>> = Arc::default();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_guard = guard.clone();
+        let thread_subscribers = subscribers.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-faults".into())
+            .spawn(move || {
+                let mut faulted = vec![false; controller.num_motors()];
+                while thread_running.load(Ordering::SeqCst) {
+                    match read_faults(&mut controller) {
+                        Ok(current) => {
+                            for (motor, (now, before)) in current.iter().zip(&faulted).enumerate() {
+                                if now == before {
+                                    continue;
+                                }
+                                let event = if *now {
+                                    error!(
+                                        message_id = journal::DRIVE_FAULT,
+                                        motor, "Drive fault on motor {}", motor
+                                    );
+                                    if let (FaultPolicy::Stop, Some(ref latch)) =
+                                        (config.policy, &latch)
+                                    {
+                                        latch.latch(&format!("drive fault on motor {}", motor));
+                                    }
+                                    FaultEvent::Raised(motor)
+                                } else {
+                                    info!(
+                                        message_id = journal::DRIVE_FAULT_CLEARED,
+                                        motor, "Drive fault on motor {} cleared", motor
+                                    );
+                                    FaultEvent::Cleared(motor)
+                                };
+                                broadcast(&thread_subscribers, event);
+                            }
+                            let newly_raised = current.iter().zip(&faulted).any(|(c, f)| *c && !f);
+                            faulted = current;
+                            thread_guard
+                                .faulted
+                                .store(faulted.contains(&true), Ordering::SeqCst);
+                            if let Err(error) = apply_policy(
+                                &mut controller,
+                                &config,
+                                &thread_guard,
+                                newly_raised,
+                                faulted.contains(&true),
+                            ) {
+                                error!(
+                                    "Could not apply fault policy {:?}: {}",
+                                    config.policy, error
+                                );
+                            }
+                        }
+                        Err(error) => {
+                            warn!("Could not read drive fault flags: {}", error);
+                            broadcast(&thread_subscribers, FaultEvent::ReadFailed);
+                        }
+                    }
+                    thread::sleep(Duration::from_millis(config.poll_interval_ms));
+                }
+            })?;
+
+        Ok(FaultMonitor {
+            guard,
+            subscribers,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn guard(&self) -> FaultGuard {
+        self.guard.clone()
+    }
+
+    /// Returns a channel receiving every subsequent `FaultEvent`.
+    pub fn events(&self) -> Receiver<FaultEvent> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+}
+
+impl Drop for FaultMonitor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Fault monitor thread panicked");
+            }
+        }
+    }
+}
+
+fn read_faults<D: MotorDriver>(controller: &mut D) -> Result<Vec<bool>, Error> {
+    (0..controller.num_motors())
+        .map(|motor| controller.fault(motor))
+        .collect()
+}
+
+fn apply_policy<D: MotorDriver>(
+    controller: &mut D,
+    config: &FaultConfig,
+    guard: &FaultGuard,
+    newly_raised: bool,
+    any_faulted: bool,
+) -> Result<(), Error> {
+    match config.policy {
+        FaultPolicy::Stop if newly_raised => {
+            warn!("Stopping motors because of a drive fault");
+            (0..controller.num_motors()).try_for_each(|motor| controller.set_motor(motor, 0.0))
+        }
+        FaultPolicy::ReducePower => {
+            let limit = if any_faulted {
+                config.reduced_power.clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+            if limit != guard.power_limit() {
+                if any_faulted {
+                    warn!("Limiting motor power to {:.2}", limit);
+                } else {
+                    info!("Drive faults cleared, lifting the motor power limit");
+                }
+                guard.set_power_limit(limit);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn broadcast(subscribers: &Mutex<Vec<Sender<FaultEvent>>>, event: FaultEvent) {
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
+
+/// How long a latch set or cleared by another vrum process takes to be
+/// noticed.
+const LATCH_FILE_POLL_INTERVAL: Duration = Duration::from_millis(250);
@@ -0,0 +1,125 @@
+//! Speed steps, or gears, scaling every motor command so fine manoeuvring
+//! and fast driving coexist: a low gear turns full stick into a crawl. The
+//! steps are set in the `[gears]` section of the configuration file:
+//!
+//! ```toml
+//! [gears]
+//! steps = [0.25, 0.5, 0.75, 1.0]
+//! initial = 2
+//! ```
+//!
+//! Gears count from 1. Pick the starting one with `vrum --gear 1`, and shift
+//! with the `gear_up` and `gear_down` buttons of `vrum rc`. The `Gears`
+//! stage of the pipeline applies them after the `Governor`'s power limit:
+//!
+//! ```
+//! # use vrum::gears::{GearConfig, GearSelector};
+//! let gears = GearSelector::new(&GearConfig::default())?;
+//! assert_eq!(gears.scale(), 0.5);
+//! gears.shift_up();
+//! assert_eq!((gears.gear(), gears.scale()), (3, 0.75));
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GearError {
+    #[error("there is no gear {gear}, gears are 1 to {gears}")]
+    InvalidGear { gear: usize, gears: usize },
+    #[error("gear steps are scales in (0, 1], not {step}")]
+    InvalidStep { step: f32 },
+}
+
+/// The `[gears]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GearConfig {
+    /// Scale of the motor powers in each gear, lowest first.
+    pub steps: Vec<f32>,
+    /// Gear on start.
+    pub initial: usize,
+}
+
+impl Default for GearConfig {
+    fn default() -> Self {
+        GearConfig {
+            steps: vec![0.25, 0.5, 0.75, 1.0],
+            initial: 2,
+        }
+    }
+}
+
+/// The selected gear, shared between what shifts and the `Gears` stage of
+/// a `Controller`, attached with `Controller::set_gears`.
+#[derive(Clone, Debug)]
+pub struct GearSelector {
+    steps: Arc<[f32]>,
+    gear: Arc<AtomicUsize>,
+}
+
+impl GearSelector {
+    pub fn new(config: &GearConfig) -> Result<Self, Error> {
+        if let Some(&step) = config
+            .steps
+            .iter()
+            .find(|step| !(**step > 0.0 && **step <= 1.0))
+        {
+            return Err(GearError::InvalidStep { step }.into());
+        }
+        let selector = GearSelector {
+            steps: config.steps.clone().into(),
+            gear: Arc::new(AtomicUsize::new(1)),
+        };
+        selector.select(config.initial)?;
+        Ok(selector)
+    }
+
+    /// The selected gear, from 1.
+    pub fn gear(&self) -> usize {
+        self.gear.load(Ordering::SeqCst)
+    }
+
+    /// How many gears there are.
+    pub fn gears(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Scale of the motor powers in the selected gear.
+    pub fn scale(&self) -> f32 {
+        self.steps[self.gear() - 1]
+    }
+
+    pub fn select(&self, gear: usize) -> Result<(), Error> {
+        if gear == 0 || gear > self.gears() {
+            return Err(GearError::InvalidGear {
+                gear,
+                gears: self.gears(),
+            }
+            .into());
+        }
+        self.gear.store(gear, Ordering::SeqCst);
+        info!(
+            "Gear {} of {}, {:.0}% power",
+            gear,
+            self.gears(),
+            self.scale() * 100.0
+        );
+        Ok(())
+    }
+
+    /// Selects the next gear up, if any, returning the selected gear.
+    pub fn shift_up(&self) -> usize {
+        let _ = self.select(self.gear() + 1);
+        self.gear()
+    }
+
+    /// Selects the next gear down, if any, returning the selected gear.
+    pub fn shift_down(&self) -> usize {
+        let _ = self.select(self.gear() - 1);
+        self.gear()
+    }
+}
@@ -0,0 +1,225 @@
+//! Auxiliary outputs on the GPIO header, like headlights, a horn or relays,
+//! each named in the `[gpio_outputs]` section of the configuration file:
+//!
+//! ```toml
+//! [gpio_outputs.headlights]
+//! pin = 17
+//! pwm_frequency = 200.0
+//!
+//! [gpio_outputs.horn]
+//! pin = 27
+//! ```
+//!
+//! Outputs with a `pwm_frequency` are dimmed with software PWM, the others
+//! are only on or off. They are switched with `vrum aux headlights on`, the
+//! switches of `vrum rc` listed in `[rc.channels.aux]`, `aux` messages of
+//! `vrum serve` and `aux` steps of missions.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rppal::gpio::{Gpio, OutputPin};
+use serde::de::{Deserialize, Deserializer};
+
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuxError {
+    #[error("there is no output {name} in [gpio_outputs]")]
+    UnknownOutput { name: String },
+    #[error("output {name} has no pwm_frequency to dim it")]
+    NotDimmable { name: String },
+    #[error("{state} is not on, off, toggle or a level in [0, 1]")]
+    InvalidState { state: String },
+}
+
+/// One output of the `[gpio_outputs]` section.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GpioOutputConfig {
+    /// BCM pin number.
+    pub pin: u8,
+    /// The load is on with the pin low, as with many relay boards.
+    #[serde(default)]
+    pub active_low: bool,
+    /// Frequency of the software PWM dimming the output, in hertz.
+    #[serde(default)]
+    pub pwm_frequency: Option<f64>,
+}
+
+/// What to do with an output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputState {
+    On,
+    Off,
+    Toggle,
+    /// Dimmed to a level in `[0, 1]`.
+    Level(f32),
+}
+
+impl FromStr for OutputState {
+    type Err = Error;
+
+    fn from_str(state: &str) -> Result<Self, Error> {
+        match state {
+            "on" => Ok(OutputState::On),
+            "off" => Ok(OutputState::Off),
+            "toggle" => Ok(OutputState::Toggle),
+            _ => match state.parse::<f32>() {
+                Ok(level) if (0.0..=1.0).contains(&level) => Ok(OutputState::Level(level)),
+                _ => Err(AuxError::InvalidState {
+                    state: state.into(),
+                }
+                .into()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for OutputState {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OutputState::On => write!(formatter, "on"),
+            OutputState::Off => write!(formatter, "off"),
+            OutputState::Toggle => write!(formatter, "toggle"),
+            OutputState::Level(level) => write!(formatter, "{}", level),
+        }
+    }
+}
+
+/// `on`, `off`, `toggle`, a level or a boolean.
+impl<'de> Deserialize<'de> for OutputState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Switch(bool),
+            Level(f32),
+            Text(String),
+        }
+        match Value::deserialize(deserializer)? {
+            Value::Switch(true) => Ok(OutputState::On),
+            Value::Switch(false) => Ok(OutputState::Off),
+            Value::Level(level) if (0.0..=1.0).contains(&level) => Ok(OutputState::Level(level)),
+            Value::Level(level) => Err(serde::de::Error::custom(AuxError::InvalidState {
+                state: level.to_string(),
+            })),
+            Value::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// The outputs of the `[gpio_outputs]` section, shared by whatever switches
+/// them. The pins are reset to inputs when the last handle is dropped,
+/// unless `leave_on_exit` says otherwise.
+#[derive(Clone, Debug)]
+pub struct GpioOutputs {
+    outputs: Arc<Mutex<BTreeMap<String, Output>>>,
+}
+
+#[derive(Debug)]
+struct Output {
+    pin: OutputPin,
+    config: GpioOutputConfig,
+    level: f32,
+}
+
+impl GpioOutputs {
+    /// Claims the pins of `configs`, every output off.
+    pub fn open(configs: &BTreeMap<String, GpioOutputConfig>) -> Result<Self, Error> {
+        let gpio = Gpio::new()?;
+        let mut outputs = BTreeMap::new();
+        for (name, config) in configs {
+            let mut pin = gpio.get(config.pin)?.into_output();
+            pin.write(config.active_low.into());
+            info!("Output {} on GPIO {}", name, config.pin);
+            outputs.insert(
+                name.clone(),
+                Output {
+                    pin,
+                    config: *config,
+                    level: 0.0,
+                },
+            );
+        }
+        Ok(GpioOutputs {
+            outputs: Arc::new(Mutex::new(outputs)),
+        })
+    }
+
+    /// Names of the outputs, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.lock().keys().cloned().collect()
+    }
+
+    /// Level of output `name`, in `[0, 1]`.
+    pub fn level(&self, name: &str) -> Result<f32, Error> {
+        let outputs = self.lock();
+        let output = outputs.get(name).ok_or_else(|| unknown(name))?;
+        Ok(output.level)
+    }
+
+    /// Switches or dims output `name`, returning its new level.
+    pub fn set(&self, name: &str, state: OutputState) -> Result<f32, Error> {
+        let mut outputs = self.lock();
+        let output = outputs.get_mut(name).ok_or_else(|| unknown(name))?;
+        let level = match state {
+            OutputState::On => 1.0,
+            OutputState::Off => 0.0,
+            OutputState::Toggle if output.level > 0.0 => 0.0,
+            OutputState::Toggle => 1.0,
+            OutputState::Level(level) => level.clamp(0.0, 1.0),
+        };
+        let duty = if output.config.active_low {
+            1.0 - level
+        } else {
+            level
+        };
+        match output.config.pwm_frequency {
+            Some(frequency) if level > 0.0 && level < 1.0 => {
+                output.pin.set_pwm_frequency(frequency, f64::from(duty))?;
+            }
+            None if level > 0.0 && level < 1.0 => {
+                return Err(AuxError::NotDimmable { name: name.into() }.into());
+            }
+            _ => {
+                output.pin.clear_pwm()?;
+                output.pin.write((duty >= 0.5).into());
+            }
+        }
+        if level != output.level {
+            info!("Output {} at {:.0}%", name, level * 100.0);
+        }
+        output.level = level;
+        Ok(level)
+    }
+
+    /// Switches every output off.
+    pub fn all_off(&self) -> Result<(), Error> {
+        for name in self.names() {
+            self.set(&name, OutputState::Off)?;
+        }
+        Ok(())
+    }
+
+    /// Leaves the outputs as they are when dropped, so one switched on from
+    /// the command line stays on. Dimming ends with the process.
+    pub fn leave_on_exit(&self) {
+        for output in self.lock().values_mut() {
+            output.pin.set_reset_on_drop(false);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Output>> {
+        // The outputs stay usable if a thread panicked holding the lock.
+        self.outputs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn unknown(name: &str) -> Error {
+    AuxError::UnknownOutput { name: name.into() }.into()
+}
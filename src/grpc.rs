@@ -0,0 +1,139 @@
+//! `VrumControl` gRPC service, see `proto/vrum.proto` for the schema.
+
+// `tonic::Status` is large, but it is the error type the generated service
+// trait requires.
+#![allow(clippy::result_large_err)]
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::color::Color;
+use crate::error::Error;
+use crate::thunder_borg::Controller;
+
+pub mod proto {
+    tonic::include_proto!("vrum");
+}
+
+use self::proto::vrum_control_server::{VrumControl, VrumControlServer};
+use self::proto::{
+    Ack, DriveRequest, LedRequest, RobotStatus, StatusRequest, StopRequest, TelemetryRequest,
+};
+
+/// Serves the `VrumControl` service on `address`, blocking until the server
+/// shuts down.
+pub fn serve(controller: Controller, address: SocketAddr) -> Result<(), Error> {
+    info!("Serving VrumControl gRPC service on {}", address);
+    let service = ControlService {
+        controller: Arc::new(Mutex::new(controller)),
+    };
+    Runtime::new()?.block_on(
+        Server::builder()
+            .add_service(VrumControlServer::new(service))
+            .serve(address),
+    )?;
+    Ok(())
+}
+
+struct ControlService {
+    controller: Arc<Mutex<Controller>>,
+}
+
+#[tonic::async_trait]
+impl VrumControl for ControlService {
+    async fn drive(&self, request: Request<DriveRequest>) -> Result<Response<Ack>, Status> {
+        let DriveRequest { left, right } = request.into_inner();
+        with_controller(&self.controller, |controller| {
+            controller.set_motor_a(left)?;
+            controller.set_motor_b(right)
+        })?;
+        Ok(Response::new(Ack {}))
+    }
+
+    async fn stop(&self, _: Request<StopRequest>) -> Result<Response<Ack>, Status> {
+        with_controller(&self.controller, |controller| controller.stop())?;
+        Ok(Response::new(Ack {}))
+    }
+
+    async fn set_led(&self, request: Request<LedRequest>) -> Result<Response<Ack>, Status> {
+        let LedRequest { red, green, blue } = request.into_inner();
+        with_controller(&self.controller, |controller| {
+            controller.set_led(Color::rgb(
+                to_channel(red),
+                to_channel(green),
+                to_channel(blue),
+            ))
+        })?;
+        Ok(Response::new(Ack {}))
+    }
+
+    async fn get_status(&self, _: Request<StatusRequest>) -> Result<Response<RobotStatus>, Status> {
+        Ok(Response::new(read_status(&self.controller)?))
+    }
+
+    type StreamTelemetryStream = ReceiverStream<Result<RobotStatus, Status>>;
+
+    async fn stream_telemetry(
+        &self,
+        request: Request<TelemetryRequest>,
+    ) -> Result<Response<Self::StreamTelemetryStream>, Status> {
+        let interval = match request.into_inner().interval_ms {
+            0 => DEFAULT_TELEMETRY_INTERVAL,
+            interval_ms => Duration::from_millis(u64::from(interval_ms)),
+        };
+        let controller = self.controller.clone();
+        let (sender, receiver) = mpsc::channel(TELEMETRY_CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if sender.send(read_status(&controller)).await.is_err() {
+                    debug!("Telemetry client disconnected");
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+fn read_status(controller: &Mutex<Controller>) -> Result<RobotStatus, Status> {
+    with_controller(controller, |controller| {
+        let battery_voltage = controller.get_battery_voltage()?;
+        let comm = controller.comm_stats().total();
+        Ok(RobotStatus {
+            battery_voltage,
+            drive_fault_a: controller.get_drive_fault_a()?,
+            drive_fault_b: controller.get_drive_fault_b()?,
+            battery_percent: controller.estimate_battery_percent(battery_voltage),
+            i2c_retries: comm.retries,
+            i2c_failures: comm.failures,
+            i2c_latency_ms: comm.mean_latency().as_secs_f32() * 1000.0,
+        })
+    })
+}
+
+fn with_controller<T, F>(controller: &Mutex<Controller>, action: F) -> Result<T, Status>
+where
+    F: FnOnce(&mut Controller) -> Result<T, Error>,
+{
+    let mut controller = controller
+        .lock()
+        .map_err(|_| Status::internal("controller lock poisoned"))?;
+    action(&mut controller).map_err(|error| Status::internal(error.to_string()))
+}
+
+#[inline]
+fn to_channel(value: u32) -> u8 {
+    value.min(u32::from(u8::MAX)) as u8
+}
+
+const DEFAULT_TELEMETRY_INTERVAL: Duration = Duration::from_millis(1000);
+const TELEMETRY_CHANNEL_CAPACITY: usize = 4;
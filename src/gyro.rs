@@ -0,0 +1,244 @@
+//! Gyro-assisted straight driving. Mismatched motors make a robot curve even
+//! when both sides get the same power; a `GyroMonitor` reads the yaw rate
+//! from an IMU on the I2C bus and, while the robot is asked to drive
+//! straight, trims the two sides to keep the yaw rate at zero.
+//!
+//! The correction is applied by the `GyroCorrection` stage of the drive
+//! pipeline. Given a `SharedController` the monitor also runs the last
+//! command through the pipeline again at every update, so a single
+//! `set_motors()` keeps driving straight. Otherwise the correction is
+//! applied whenever a command is sent, which suits control loops.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+use crate::pid::{Pid, PidConfig};
+use crate::sensors::imu::{self, Imu, ImuConfig};
+use crate::shared::{Priority, SharedController};
+
+/// The `[gyro]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GyroConfig {
+    pub sensor: ImuConfig,
+    /// How often the yaw rate is read and the correction updated.
+    pub rate_hz: f32,
+    /// Readings averaged at startup to measure the gyro bias, the robot has
+    /// to be at rest meanwhile.
+    pub bias_samples: u32,
+    /// Gains on the yaw rate error in degrees per second, the output being
+    /// the power added to one side and taken from the other.
+    pub pid: PidConfig,
+}
+
+impl Default for GyroConfig {
+    fn default() -> Self {
+        GyroConfig {
+            sensor: ImuConfig::default(),
+            rate_hz: 50.0,
+            bias_samples: 100,
+            pid: PidConfig {
+                kp: 0.005,
+                ki: 0.01,
+                kd: 0.0,
+                output_limit: 0.2,
+                integral_limit: 0.1,
+            },
+        }
+    }
+}
+
+/// A gyro measuring the rotation of the robot around the vertical axis.
+pub trait YawRateSensor {
+    /// Degrees per second, positive counter-clockwise seen from above.
+    fn yaw_rate(&mut self) -> Result<f32, Error>;
+}
+
+impl<I: Imu + ?Sized> YawRateSensor for I {
+    fn yaw_rate(&mut self) -> Result<f32, Error> {
+        Ok(self.angular_velocity()?.z)
+    }
+}
+
+/// The correction computed by a `GyroMonitor`, attached to a `Controller`
+/// with `Controller::set_gyro_guard`. Engaged by the `GyroCorrection` stage
+/// while the command is to drive straight.
+#[derive(Clone)]
+pub struct GyroGuard {
+    state: Arc<GuardState>,
+}
+
+struct GuardState {
+    engaged: AtomicBool,
+    correction: AtomicU32,
+}
+
+impl GyroGuard {
+    fn new() -> Self {
+        GyroGuard {
+            state: Arc::new(GuardState {
+                engaged: AtomicBool::new(false),
+                correction: AtomicU32::new(0.0f32.to_bits()),
+            }),
+        }
+    }
+
+    /// Power to take from the left side and add to the right, 0 unless
+    /// engaged.
+    pub fn correction(&self) -> f32 {
+        if self.is_engaged() {
+            f32::from_bits(self.state.correction.load(Ordering::SeqCst))
+        } else {
+            0.0
+        }
+    }
+
+    /// True while the robot is asked to drive straight.
+    pub fn is_engaged(&self) -> bool {
+        self.state.engaged.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_engaged(&self, engaged: bool) {
+        self.state.engaged.store(engaged, Ordering::SeqCst);
+    }
+
+    fn set_correction(&self, correction: f32) {
+        self.state
+            .correction
+            .store(correction.to_bits(), Ordering::SeqCst);
+    }
+}
+
+/// Reads the yaw rate on a background thread and updates the correction of
+/// a `GyroGuard` while it is engaged.
+pub struct GyroMonitor {
+    guard: GyroGuard,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GyroMonitor {
+    /// Measures the gyro bias, then starts the correction loop. With a
+    /// `controller` the guard is attached to it and the last command is run
+    /// through its pipeline again at every update; the controller isn't
+    /// dropped until the monitor is.
+    pub fn spawn<S>(
+        config: &GyroConfig,
+        mut sensor: S,
+        controller: Option<SharedController>,
+    ) -> Result<Self, Error>
+    where
+        S: YawRateSensor + Send + 'static,
+    {
+        let guard = GyroGuard::new();
+        if let Some(ref controller) = controller {
+            let stage_guard = guard.clone();
+            controller.call(move |controller| controller.set_gyro_guard(stage_guard))?;
+        }
+        let period = Duration::from_secs_f32(1.0 / config.rate_hz.max(1.0));
+        let bias = measure_bias(&mut sensor, config.bias_samples, period)?;
+        info!("Gyro bias: {:.3}°/s", bias);
+
+        let running = Arc::new(AtomicBool::new(true));
+        let mut pid = Pid::new(config.pid);
+        let thread_guard = guard.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-gyro".into())
+            .spawn(move || {
+                let mut last_update = None;
+                while thread_running.load(Ordering::SeqCst) {
+                    thread::sleep(period);
+                    if !thread_guard.is_engaged() {
+                        pid.reset();
+                        thread_guard.set_correction(0.0);
+                        last_update = None;
+                        continue;
+                    }
+                    let yaw_rate = match sensor.yaw_rate() {
+                        Ok(yaw_rate) => yaw_rate - bias,
+                        Err(error) => {
+                            warn!("Could not read the yaw rate: {}", error);
+                            continue;
+                        }
+                    };
+                    let now = Instant::now();
+                    let elapsed = last_update.map_or(period, |at| now.duration_since(at));
+                    last_update = Some(now);
+                    let correction = pid.update(-yaw_rate, elapsed);
+                    trace!(yaw_rate, correction, "Correcting the course");
+                    thread_guard.set_correction(correction);
+                    if let Some(ref controller) = controller {
+                        refresh(controller, &thread_guard);
+                    }
+                }
+            })?;
+
+        Ok(GyroMonitor {
+            guard,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Opens the IMU in `config.sensor` on `bus` and spawns a monitor
+    /// reading it.
+    pub fn from_config(
+        config: &GyroConfig,
+        bus: &BusManager,
+        controller: Option<SharedController>,
+    ) -> Result<Self, Error> {
+        GyroMonitor::spawn(config, imu::open(&config.sensor, bus)?, controller)
+    }
+
+    pub fn guard(&self) -> GyroGuard {
+        self.guard.clone()
+    }
+}
+
+impl Drop for GyroMonitor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Gyro monitor thread panicked");
+            }
+        }
+    }
+}
+
+/// Runs the last command through the pipeline again, unless it was
+/// replaced by one that isn't straight while waiting in the queue.
+fn refresh(controller: &SharedController, guard: &GyroGuard) {
+    let guard = guard.clone();
+    let result = controller.call_with_priority(Priority::Drive, move |controller| {
+        if guard.is_engaged() {
+            controller.refresh_drive()
+        } else {
+            Ok(())
+        }
+    });
+    if let Err(error) = result.and_then(|refreshed| refreshed) {
+        debug!("Could not apply the gyro correction: {}", error);
+    }
+}
+
+fn measure_bias<S: YawRateSensor>(
+    sensor: &mut S,
+    samples: u32,
+    period: Duration,
+) -> Result<f32, Error> {
+    if samples == 0 {
+        return Ok(0.0);
+    }
+    let mut total = 0.0;
+    for _ in 0..samples {
+        total += sensor.yaw_rate()?;
+        thread::sleep(period);
+    }
+    Ok(total / samples as f32)
+}
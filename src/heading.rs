@@ -0,0 +1,207 @@
+//! Heading hold: drives on a fixed compass bearing, trimming the power of
+//! the two sides with a PID on the heading error. Motors of cheap robots
+//! are never quite matched, so without feedback they drift off course
+//! whatever the trim.
+
+use std::time::{Duration, Instant};
+
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+use crate::latency::LoopTimer;
+use crate::motor_driver::MotorDriver;
+use crate::pid::{Pid, PidConfig};
+use crate::sensors::imu::{self, Bno055, Bno055Mode, Imu};
+use crate::shutdown::Shutdown;
+use crate::xlo_borg;
+
+/// A source of the robot's heading, in degrees clockwise from north.
+pub trait Compass {
+    fn heading(&mut self) -> Result<f32, Error>;
+}
+
+impl Compass for xlo_borg::Controller {
+    fn heading(&mut self) -> Result<f32, Error> {
+        self.get_heading()
+    }
+}
+
+impl<C: Compass + ?Sized> Compass for Box<C> {
+    fn heading(&mut self) -> Result<f32, Error> {
+        (**self).heading()
+    }
+}
+
+/// From magnetic north in `Bno055Mode::Ndof`, from the heading at startup
+/// in `Bno055Mode::Imu`.
+impl Compass for Bno055 {
+    fn heading(&mut self) -> Result<f32, Error> {
+        Ok(self
+            .orientation()?
+            .map_or(0.0, |orientation| orientation.heading))
+    }
+}
+
+/// Which compass to steer by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum CompassConfig {
+    #[default]
+    XloBorg,
+    /// A BNO055 in `Bno055Mode::Ndof`, for a heading from magnetic north.
+    Bno055 {
+        #[serde(default = "default_bno055_address")]
+        address: u16,
+    },
+}
+
+fn default_bno055_address() -> u16 {
+    imu::BNO055_ADDR
+}
+
+/// Opens the compass in `config` on `bus`.
+pub fn open_compass(
+    config: &CompassConfig,
+    bus: &BusManager,
+) -> Result<Box<dyn Compass + Send>, Error> {
+    Ok(match *config {
+        CompassConfig::XloBorg => Box::new(xlo_borg::Controller::builder().build_with_buses(
+            Box::new(bus.device(xlo_borg::ACCELEROMETER_ADDR)),
+            Box::new(bus.device(xlo_borg::COMPASS_ADDR)),
+        )?),
+        CompassConfig::Bno055 { address } => Box::new(Bno055::new(
+            Box::new(bus.device(address)),
+            Bno055Mode::Ndof,
+        )?),
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadingHoldConfig {
+    /// Gains on the heading error in degrees, the output being the power
+    /// added to one side and taken from the other.
+    pub pid: PidConfig,
+    /// How often `drive_heading_for()` corrects the course.
+    pub period_ms: u64,
+}
+
+impl Default for HeadingHoldConfig {
+    fn default() -> Self {
+        HeadingHoldConfig {
+            pid: PidConfig {
+                kp: 0.01,
+                ki: 0.002,
+                kd: 0.001,
+                output_limit: 0.3,
+                integral_limit: 0.1,
+            },
+            period_ms: 50,
+        }
+    }
+}
+
+pub struct HeadingHold<C: Compass> {
+    compass: C,
+    config: HeadingHoldConfig,
+    pid: Pid,
+    bearing: Option<f32>,
+    last_update: Option<Instant>,
+}
+
+impl<C: Compass> HeadingHold<C> {
+    pub fn new(compass: C, config: HeadingHoldConfig) -> Self {
+        HeadingHold {
+            compass,
+            pid: Pid::new(config.pid),
+            config,
+            bearing: None,
+            last_update: None,
+        }
+    }
+
+    pub fn compass(&mut self) -> &mut C {
+        &mut self.compass
+    }
+
+    /// One correction step: drives at `power` trimmed to steer towards
+    /// `bearing_deg`, clockwise from north. Call it in a loop at a steady
+    /// rate, see `drive_heading_for()`.
+    pub fn drive_heading<D: MotorDriver + ?Sized>(
+        &mut self,
+        driver: &mut D,
+        power: f32,
+        bearing_deg: f32,
+    ) -> Result<(), Error> {
+        if self.bearing != Some(bearing_deg) {
+            self.pid.reset();
+            self.bearing = Some(bearing_deg);
+        }
+        self.track_heading(driver, power, bearing_deg)
+    }
+
+    /// Like `drive_heading()`, for a bearing that moves gradually (e.g.
+    /// towards a waypoint): changes of `bearing_deg` keep the accumulated
+    /// correction.
+    pub fn track_heading<D: MotorDriver + ?Sized>(
+        &mut self,
+        driver: &mut D,
+        power: f32,
+        bearing_deg: f32,
+    ) -> Result<(), Error> {
+        let now = Instant::now();
+        self.bearing = Some(bearing_deg);
+        let elapsed = self
+            .last_update
+            .map_or(Duration::default(), |at| now.duration_since(at));
+        self.last_update = Some(now);
+        let error = heading_error(bearing_deg, self.compass.heading()?);
+        // A positive correction turns clockwise whichever way it drives.
+        let correction = self.pid.update(error, elapsed);
+        debug!(heading_error = error, correction, "Holding heading");
+        driver.set_sides(power + correction, power - correction)
+    }
+
+    /// Holds `bearing_deg` at `power` for `duration`, then stops the motors.
+    /// A shutdown request stops the motors and ends it early.
+    pub fn drive_heading_for<D: MotorDriver + ?Sized>(
+        &mut self,
+        driver: &mut D,
+        power: f32,
+        bearing_deg: f32,
+        duration: Duration,
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        self.reset();
+        let period = Duration::from_millis(self.config.period_ms.max(1));
+        let started = Instant::now();
+        let mut timer = LoopTimer::new("heading hold", period);
+        let result = loop {
+            timer.tick();
+            let elapsed = started.elapsed();
+            if elapsed >= duration {
+                break Ok(());
+            }
+            if let Err(error) = self
+                .drive_heading(driver, power, bearing_deg)
+                .and_then(|()| shutdown.sleep(period.min(duration - elapsed)))
+            {
+                break Err(error);
+            }
+        };
+        let stopped = driver.set_sides(0.0, 0.0);
+        result.and(stopped)
+    }
+
+    /// Starts afresh, forgetting the accumulated correction.
+    pub fn reset(&mut self) {
+        self.pid.reset();
+        self.bearing = None;
+        self.last_update = None;
+    }
+}
+
+/// Shortest signed angle from `heading` to `bearing`, in `[-180, 180)`,
+/// positive clockwise.
+pub fn heading_error(bearing: f32, heading: f32) -> f32 {
+    (bearing - heading + 180.0).rem_euclid(360.0) - 180.0
+}
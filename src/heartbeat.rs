@@ -0,0 +1,230 @@
+//! Dead-man semantics shared by the network frontends: a client driving the
+//! motors has to refresh, with drive commands or heartbeats, within the
+//! timeout of its transport or its motors are stopped, so a phone that lost
+//! Wi-Fi or a crashed script never leaves the robot driving.
+//!
+//! The `[heartbeat]` section of the configuration file sets the timeout and
+//! policy of every transport, and each can override them:
+//!
+//! ```toml
+//! [heartbeat]
+//! timeout_ms = 500
+//!
+//! [heartbeat.udp]
+//! timeout_ms = 200
+//! policy = "latch"
+//! ```
+
+use std::fmt::{self, Display};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HeartbeatError {
+    #[error("no heartbeat for {timeout:?}, drive commands are ignored until a stop")]
+    Latched { timeout: Duration },
+}
+
+/// The network frontends a client can drive through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Mqtt,
+    Udp,
+    Web,
+}
+
+impl Display for Transport {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Transport::Mqtt => "MQTT",
+            Transport::Udp => "UDP",
+            Transport::Web => "web",
+        })
+    }
+}
+
+/// What happens when a driving client goes quiet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadManPolicy {
+    /// Stop the motors, the next drive command runs them again.
+    #[default]
+    Stop,
+    /// Stop the motors and ignore drive commands until the client sends a
+    /// stop, acknowledging it lost control.
+    Latch,
+}
+
+/// The `[heartbeat]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub timeout_ms: u64,
+    pub policy: DeadManPolicy,
+    pub mqtt: TransportHeartbeat,
+    pub udp: TransportHeartbeat,
+    pub web: TransportHeartbeat,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            timeout_ms: 500,
+            policy: DeadManPolicy::Stop,
+            mqtt: TransportHeartbeat::default(),
+            udp: TransportHeartbeat::default(),
+            web: TransportHeartbeat::default(),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// The dead-man settings of `transport`, its own where it has any.
+    pub fn transport(&self, transport: Transport) -> DeadManConfig {
+        let overrides = match transport {
+            Transport::Mqtt => &self.mqtt,
+            Transport::Udp => &self.udp,
+            Transport::Web => &self.web,
+        };
+        DeadManConfig {
+            timeout: Duration::from_millis(overrides.timeout_ms.unwrap_or(self.timeout_ms)),
+            policy: overrides.policy.unwrap_or(self.policy),
+        }
+    }
+}
+
+/// Overrides for one transport, e.g. `[heartbeat.udp]`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportHeartbeat {
+    pub timeout_ms: Option<u64>,
+    pub policy: Option<DeadManPolicy>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeadManConfig {
+    /// Motors are stopped if a driving client sends nothing for this long.
+    pub timeout: Duration,
+    pub policy: DeadManPolicy,
+}
+
+impl Default for DeadManConfig {
+    fn default() -> Self {
+        HeartbeatConfig::default().transport(Transport::Web)
+    }
+}
+
+/// Tracks whether one client is still there while it drives. The frontend
+/// reports what the client sends and polls `expired()`, stopping the motors
+/// when it returns true.
+pub struct DeadMan {
+    config: DeadManConfig,
+    clock: Arc<dyn Clock>,
+    /// When the client last refreshed, while its motors run.
+    refreshed: Option<Instant>,
+    latched: bool,
+}
+
+impl DeadMan {
+    pub fn new(config: DeadManConfig) -> Self {
+        DeadMan::with_clock(config, clock::system())
+    }
+
+    pub fn with_clock(config: DeadManConfig, clock: Arc<dyn Clock>) -> Self {
+        DeadMan {
+            config,
+            clock,
+            refreshed: None,
+            latched: false,
+        }
+    }
+
+    pub fn config(&self) -> DeadManConfig {
+        self.config
+    }
+
+    /// Records a drive command, failing if the dead man latched and it isn't
+    /// a stop. A stop, both powers zero, releases the latch.
+    pub fn drive(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        if left == 0.0 && right == 0.0 {
+            self.stopped();
+            return Ok(());
+        }
+        if self.latched {
+            return Err(HeartbeatError::Latched {
+                timeout: self.config.timeout,
+            }
+            .into());
+        }
+        self.refreshed = Some(self.clock.now());
+        Ok(())
+    }
+
+    /// Records a heartbeat, keeping the motors running at their powers.
+    pub fn heartbeat(&mut self) {
+        if self.refreshed.is_some() {
+            self.refreshed = Some(self.clock.now());
+        }
+    }
+
+    /// Records the motors being stopped by the client.
+    pub fn stopped(&mut self) {
+        self.refreshed = None;
+        self.latched = false;
+    }
+
+    /// True, once, when the client stopped refreshing for longer than the
+    /// timeout while its motors run.
+    pub fn expired(&mut self) -> bool {
+        match self.refreshed {
+            Some(at) if self.clock.elapsed(at) > self.config.timeout => {
+                self.refreshed = None;
+                self.latched = self.config.policy == DeadManPolicy::Latch;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the client has the motors running.
+    pub fn is_driving(&self) -> bool {
+        self.refreshed.is_some()
+    }
+
+    pub fn is_latched(&self) -> bool {
+        self.latched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn dead_man_latches_when_the_heartbeats_stop() {
+        let clock = ManualClock::new();
+        let config = DeadManConfig {
+            timeout: Duration::from_millis(200),
+            policy: DeadManPolicy::Latch,
+        };
+        let mut dead_man = DeadMan::with_clock(config, Arc::new(clock.clone()));
+
+        dead_man.drive(0.5, 0.5).unwrap();
+        clock.advance(Duration::from_millis(150));
+        dead_man.heartbeat();
+        clock.advance(Duration::from_millis(150));
+        assert!(!dead_man.expired(), "the heartbeat refreshed it");
+
+        clock.advance(Duration::from_millis(100));
+        assert!(dead_man.expired());
+        assert!(!dead_man.expired(), "expires once");
+        assert!(dead_man.drive(0.5, 0.5).is_err());
+        dead_man.drive(0.0, 0.0).unwrap();
+        dead_man.drive(0.5, 0.5).unwrap();
+        assert!(dead_man.is_driving());
+    }
+}
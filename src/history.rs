@@ -0,0 +1,108 @@
+//! The latest notable events, e.g. drive faults, emergency stops, battery
+//! cutoffs, failed commands and arming changes, kept in memory with their
+//! time so intermittent problems can be looked into after the fact, with
+//! `Controller::events()` or `GET /events` of `vrum serve`.
+//!
+//! Notable events are the ones logged with a `message_id` from
+//! `vrum::journal`. `EventHistory::global()` is a tracing layer picking them
+//! out, add it to the subscriber as in
+//! `registry().with(fmt::layer()).with(EventHistory::global())`.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use tracing::field::{Field, Visit};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::journal;
+use crate::telemetry;
+
+/// How many events are kept, older ones are dropped.
+pub const HISTORY_LEN: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HistoryEvent {
+    /// Seconds since the Unix epoch.
+    pub at: f64,
+    /// What happened, e.g. `battery_cutoff`, see `journal::name`.
+    pub kind: &'static str,
+    /// `ERROR`, `WARN` or `INFO`.
+    pub level: &'static str,
+    pub message: String,
+}
+
+/// The latest `HISTORY_LEN` notable events. Global like the tracing
+/// subscriber recording them.
+#[derive(Clone)]
+pub struct EventHistory {
+    events: Arc<Mutex<VecDeque<HistoryEvent>>>,
+}
+
+impl EventHistory {
+    pub fn global() -> Self {
+        static GLOBAL: OnceLock<EventHistory> = OnceLock::new();
+        GLOBAL
+            .get_or_init(|| EventHistory {
+                events: Arc::new(Mutex::new(VecDeque::with_capacity(HISTORY_LEN))),
+            })
+            .clone()
+    }
+
+    /// The latest events, oldest first.
+    pub fn events(&self) -> Vec<HistoryEvent> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn push(&self, event: HistoryEvent) {
+        let mut events = self.lock();
+        if events.len() == HISTORY_LEN {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<HistoryEvent>> {
+        // Events are pushed whole, a poisoned history is still consistent.
+        self.events.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventHistory {
+    fn on_event(&self, event: &tracing::Event<'_>, _context: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        if let Some(kind) = fields.kind {
+            self.push(HistoryEvent {
+                at: telemetry::unix_timestamp(),
+                kind,
+                level: event.metadata().level().as_str(),
+                message: fields.message,
+            });
+        }
+    }
+}
+
+/// The fields of an event the history keeps.
+#[derive(Default)]
+struct Fields {
+    kind: Option<&'static str>,
+    message: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message_id" => self.kind = journal::name(value),
+            "message" => self.message = value.to_string(),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Pushes telemetry samples to InfluxDB, or anything else accepting the
+//! line protocol over HTTP like Telegraf or VictoriaMetrics, for long term
+//! dashboards of battery health and usage. Configured in the `[influx]`
+//! section of the configuration file:
+//!
+//! ```toml
+//! [influx]
+//! url = "http://influx.local:8086/api/v2/write?org=home&bucket=vrum&precision=ms"
+//! token = "..."
+//! ```
+//!
+//! A sample of the `TelemetryPoller` every `sample_interval_ms` is one point
+//! of the `measurement`, `vrum` by default, tagged with the name of the
+//! robot and the kind and address of its board, with a field for each field
+//! of `TelemetrySample` and a timestamp in milliseconds, so the URL has to
+//! ask for `precision=ms`.
+//! Points are sent in batches, and kept while the endpoint is unreachable.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::borg::BoardInfo;
+use crate::error::Error;
+use crate::telemetry::{self, TelemetrySample};
+
+#[derive(Debug, thiserror::Error)]
+pub enum InfluxError {
+    #[error("{url} is not an http:// URL")]
+    InvalidUrl { url: String },
+    #[error("the line protocol endpoint answered {status}")]
+    Rejected { status: String },
+}
+
+/// The `[influx]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxConfig {
+    /// Write endpoint, with `precision=ms`. Plain HTTP only, for HTTPS
+    /// go through a local Telegraf.
+    pub url: String,
+    /// Sent as `Authorization: Token <token>`.
+    pub token: Option<String>,
+    pub measurement: String,
+    /// The `robot` tag, the host name by default.
+    pub robot: Option<String>,
+    pub sample_interval_ms: u64,
+    pub batch_interval_ms: u64,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        InfluxConfig {
+            url: "http://localhost:8086/api/v2/write?bucket=vrum&precision=ms".into(),
+            token: None,
+            measurement: "vrum".into(),
+            robot: None,
+            sample_interval_ms: 1000,
+            batch_interval_ms: 10_000,
+        }
+    }
+}
+
+/// Sends the samples it receives on a background thread, until dropped.
+pub struct InfluxExporter {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InfluxExporter {
+    /// Exports `samples`, e.g. from `TelemetryPoller::samples`, of the
+    /// robot driving `board`.
+    pub fn spawn(
+        config: InfluxConfig,
+        board: &BoardInfo,
+        samples: Receiver<TelemetrySample>,
+    ) -> Result<Self, Error> {
+        let endpoint = Endpoint::parse(&config.url)?;
+        let robot = config
+            .robot
+            .clone()
+            .or_else(telemetry::host_name)
+            .unwrap_or_else(|| "vrum".into());
+        let mut series = escape(&config.measurement, &[',', ' ']);
+        let tags = [
+            (
+                "address",
+                board.address.map(|address| format!("0x{:x}", address)),
+            ),
+            ("board", Some(board.board.clone())),
+            ("robot", Some(robot)),
+        ];
+        for (tag, value) in tags.iter() {
+            if let Some(value) = value {
+                let _ = write!(series, ",{}={}", tag, escape(value, &[',', '=', ' ']));
+            }
+        }
+        info!("Exporting telemetry to {}", config.url);
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-influx".into())
+            .spawn(move || {
+                let sample_interval = Duration::from_millis(config.sample_interval_ms);
+                let interval = Duration::from_millis(config.batch_interval_ms);
+                let mut lines = VecDeque::new();
+                let mut last_sample = None::<Instant>;
+                let mut last_batch = Instant::now();
+                let mut failing = false;
+                while thread_running.load(Ordering::SeqCst) {
+                    let wait = interval
+                        .checked_sub(last_batch.elapsed())
+                        .unwrap_or_default()
+                        .min(INFLUX_POLL_INTERVAL);
+                    match samples.recv_timeout(wait) {
+                        Ok(_) if last_sample.is_some_and(|at| at.elapsed() < sample_interval) => {}
+                        Ok(sample) => {
+                            last_sample = Some(Instant::now());
+                            if lines.len() == MAX_BUFFERED_LINES {
+                                lines.pop_front();
+                            }
+                            lines.push_back(line(&series, &sample));
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    if last_batch.elapsed() >= interval && !lines.is_empty() {
+                        last_batch = Instant::now();
+                        match endpoint.write(config.token.as_deref(), &lines) {
+                            Ok(()) => {
+                                if failing {
+                                    info!("Exporting telemetry again");
+                                    failing = false;
+                                }
+                                lines.clear();
+                            }
+                            // Once, rather than every batch while it is down.
+                            Err(error) if !failing => {
+                                warn!("Could not export telemetry, retrying: {}", error);
+                                failing = true;
+                            }
+                            Err(error) => debug!("Could not export telemetry: {}", error),
+                        }
+                    }
+                }
+                if !lines.is_empty() {
+                    if let Err(error) = endpoint.write(config.token.as_deref(), &lines) {
+                        warn!("Dropping {} telemetry points: {}", lines.len(), error);
+                    }
+                }
+            })?;
+
+        Ok(InfluxExporter {
+            running,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for InfluxExporter {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Influx exporter thread panicked");
+            }
+        }
+    }
+}
+
+/// The line protocol point of `sample`, in series `series`.
+fn line(series: &str, sample: &TelemetrySample) -> String {
+    let mut fields = String::new();
+    if let Ok(serde_json::Value::Object(values)) = serde_json::to_value(sample) {
+        for (name, value) in values {
+            let value = match value {
+                serde_json::Value::Bool(value) => value.to_string(),
+                serde_json::Value::Number(ref value) if value.is_u64() => format!("{}i", value),
+                serde_json::Value::Number(value) => match value.as_f64() {
+                    // Shortest, as the fields are mostly `f32`s.
+                    Some(value) if f64::from(value as f32) == value => (value as f32).to_string(),
+                    _ => value.to_string(),
+                },
+                _ => continue,
+            };
+            if name != "timestamp" {
+                let separator = if fields.is_empty() { "" } else { "," };
+                let _ = write!(fields, "{}{}={}", separator, name, value);
+            }
+        }
+    }
+    format!(
+        "{} {} {}",
+        series,
+        fields,
+        (sample.timestamp * 1000.0).round() as i64
+    )
+}
+
+/// Where the points are written, from an `http://host[:port]/path` URL.
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, Error> {
+        let invalid = || InfluxError::InvalidUrl { url: url.into() };
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid().into());
+        }
+        Ok(Endpoint {
+            host: host.into(),
+            port,
+            path: path.into(),
+        })
+    }
+
+    fn write(&self, token: Option<&str>, lines: &VecDeque<String>) -> Result<(), Error> {
+        let mut body = String::new();
+        for line in lines {
+            body.push_str(line);
+            body.push('\n');
+        }
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| InfluxError::InvalidUrl {
+                url: self.host.clone(),
+            })?;
+        let mut stream = TcpStream::connect_timeout(&address, INFLUX_TIMEOUT)?;
+        stream.set_read_timeout(Some(INFLUX_TIMEOUT))?;
+        stream.set_write_timeout(Some(INFLUX_TIMEOUT))?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        if let Some(token) = token {
+            let _ = write!(request, "Authorization: Token {}\r\n", token);
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body.as_bytes())?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        let status = status.trim_end();
+        // E.g. `HTTP/1.1 204 No Content`.
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(InfluxError::Rejected {
+                status: status.into(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// `value` with `special` characters backslash escaped, as the line
+/// protocol wants them in measurements and tags.
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if special.contains(&character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+/// Points kept while the endpoint is unreachable, a few hours of samples
+/// every second, older ones are dropped.
+const MAX_BUFFERED_LINES: usize = 10_000;
+/// How often the exporter checks whether it is dropped, at least.
+const INFLUX_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const INFLUX_TIMEOUT: Duration = Duration::from_secs(5);
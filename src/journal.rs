@@ -0,0 +1,47 @@
+//! `MESSAGE_ID`s of notable events, attached to them as `message_id` fields.
+//! With the journald backend (`vrum --journald`, feature `journald`) they
+//! let e.g. `journalctl MESSAGE_ID=e8a85629...` pick out every battery
+//! cutoff without parsing messages. Identifiers never change once released.
+
+pub const BATTERY_LOW: &str = "edb08c6e72774059b9595dd1a574766e";
+pub const BATTERY_CUTOFF: &str = "e8a856292a5f476b861eb2a56e052e35";
+pub const BATTERY_RECOVERED: &str = "1a0cfe95a7c44b258664eb45f97ff92f";
+pub const ESTOP_TRIGGERED: &str = "e750a477b56545d7809f283bf093aebc";
+pub const ESTOP_RESET: &str = "8afe5bef19074f449529cb528c9f767e";
+pub const DRIVE_FAULT: &str = "572be9d3eb204263889a513cac384650";
+pub const DRIVE_FAULT_CLEARED: &str = "9def6bd2efac40498ab33cb59df6b512";
+pub const FAULT_LATCHED: &str = "d01eabcfc9e24e34ad3a0711d4ee6efd";
+pub const FAULT_CLEARED: &str = "dfbc673b403c447eb53cb31cc120c9fc";
+pub const WATCHDOG_TRIPPED: &str = "359fa416f017484aa6a65aa1a82909b8";
+pub const BUS_RECOVERY: &str = "8dfa001a84144338a3f7eae3bd940e20";
+pub const COMMAND_FAILED: &str = "04da7946e64440979012b4ad9258d350";
+pub const MOTORS_ARMED: &str = "97d9ab6a07cd46909f82399df7e3b8a6";
+pub const MOTORS_DISARMED: &str = "dc9c002dea754780aa450d13c6f1b51a";
+pub const THERMAL_DERATED: &str = "10a3359ab79947d48493c334e117cb61";
+pub const THERMAL_RECOVERED: &str = "a5c54cdcbc4946e58f17e6e98478e2bf";
+pub const CONFIG_RELOADED: &str = "1e3cdf98fc0d4afcbfb0a98b951b3b7c";
+
+/// The name of an event in `vrum::history`, e.g. `battery_cutoff` for
+/// `BATTERY_CUTOFF`.
+pub fn name(message_id: &str) -> Option<&'static str> {
+    match message_id {
+        BATTERY_LOW => Some("battery_low"),
+        BATTERY_CUTOFF => Some("battery_cutoff"),
+        BATTERY_RECOVERED => Some("battery_recovered"),
+        ESTOP_TRIGGERED => Some("estop_triggered"),
+        ESTOP_RESET => Some("estop_reset"),
+        DRIVE_FAULT => Some("drive_fault"),
+        DRIVE_FAULT_CLEARED => Some("drive_fault_cleared"),
+        FAULT_LATCHED => Some("fault_latched"),
+        FAULT_CLEARED => Some("fault_cleared"),
+        WATCHDOG_TRIPPED => Some("watchdog_tripped"),
+        BUS_RECOVERY => Some("bus_recovery"),
+        COMMAND_FAILED => Some("command_failed"),
+        MOTORS_ARMED => Some("motors_armed"),
+        MOTORS_DISARMED => Some("motors_disarmed"),
+        THERMAL_DERATED => Some("thermal_derated"),
+        THERMAL_RECOVERED => Some("thermal_recovered"),
+        CONFIG_RELOADED => Some("config_reloaded"),
+        _ => None,
+    }
+}
@@ -0,0 +1,391 @@
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::motor_driver::MotorDriver;
+
+/// Differential-drive kinematics for a robot with one motor (or bank of
+/// motors) per side, see `MotorDriver::set_sides` for which motors drive
+/// which side.
+#[derive(Clone, Copy, Debug)]
+pub struct DiffDrive {
+    /// Distance between the left and right wheels, in metres.
+    pub wheel_base: f32,
+    /// Wheel ground speed at full motor power, in metres per second.
+    pub max_wheel_speed: f32,
+}
+
+impl DiffDrive {
+    pub fn new(wheel_base: f32, max_wheel_speed: f32) -> Self {
+        assert!(wheel_base > 0.0 && max_wheel_speed > 0.0);
+        DiffDrive {
+            wheel_base,
+            max_wheel_speed,
+        }
+    }
+
+    /// Left and right wheel speeds in m/s for a body velocity given as
+    /// `linear` (m/s, forward positive) and `angular` (rad/s, counter-clockwise
+    /// positive).
+    pub fn wheel_speeds(&self, linear: f32, angular: f32) -> (f32, f32) {
+        let half_track = angular * self.wheel_base / 2.0;
+        (linear - half_track, linear + half_track)
+    }
+
+    /// Left and right wheel speeds in m/s for driving at `speed` (m/s,
+    /// forward positive) along a circle of `radius` metres, turning left for
+    /// a positive radius and right for a negative one: the inner wheel slows
+    /// down and the outer one speeds up. An infinite radius drives straight,
+    /// and a radius of 0 spins on the spot counter-clockwise with the wheels
+    /// at `speed`.
+    pub fn arc_speeds(&self, speed: f32, radius: f32) -> (f32, f32) {
+        if radius == 0.0 {
+            return (-speed, speed);
+        }
+        self.wheel_speeds(speed, speed / radius)
+    }
+
+    /// Body velocity `(linear, angular)` from left and right wheel speeds.
+    pub fn body_velocity(&self, left: f32, right: f32) -> (f32, f32) {
+        ((left + right) / 2.0, (right - left) / self.wheel_base)
+    }
+
+    /// Left and right motor powers in `[-1, 1]`. If either wheel would need
+    /// more than full power both are scaled down together, preserving the
+    /// turning radius at the expense of speed.
+    pub fn motor_powers(&self, linear: f32, angular: f32) -> (f32, f32) {
+        let (left, right) = self.wheel_speeds(linear, angular);
+        let (left, right) = (left / self.max_wheel_speed, right / self.max_wheel_speed);
+        let peak = left.abs().max(right.abs());
+        if peak > 1.0 {
+            (left / peak, right / peak)
+        } else {
+            (left, right)
+        }
+    }
+
+    /// Left and right motor powers in `[-1, 1]` for `arc_speeds()`, scaled
+    /// down together like `motor_powers()` so the radius is kept.
+    pub fn arc_powers(&self, speed: f32, radius: f32) -> (f32, f32) {
+        let (left, right) = self.arc_speeds(speed, radius);
+        self.motor_powers((left + right) / 2.0, (right - left) / self.wheel_base)
+    }
+
+    pub fn set_velocity<D: MotorDriver + ?Sized>(
+        &self,
+        driver: &mut D,
+        linear: f32,
+        angular: f32,
+    ) -> Result<(), Error> {
+        let (left, right) = self.motor_powers(linear, angular);
+        driver.set_sides(left, right)
+    }
+
+    /// Drives at `speed` m/s along a circle of `radius` metres, see
+    /// `arc_speeds()`.
+    pub fn drive_arc<D: MotorDriver + ?Sized>(
+        &self,
+        driver: &mut D,
+        speed: f32,
+        radius: f32,
+    ) -> Result<(), Error> {
+        let (left, right) = self.arc_powers(speed, radius);
+        driver.set_sides(left, right)
+    }
+}
+
+/// Differential-drive kinematics for skid-steer robots with several motors
+/// per side, like the six wheeled DiddyBorg, whose motors may be on separate
+/// channels or boards, e.g. a `DualDriver`. The wheels of a side all drive
+/// at the same speed, and the same velocities as with a `DiffDrive` are
+/// commanded through `set_velocity`:
+///
+/// ```
+/// # use vrum::kinematics::{SkidSteer, WheelMotor};
+/// let drive = SkidSteer::new(0.2, 1.0).scrub_factor(1.5).sides(
+///     vec![WheelMotor::motor(0), WheelMotor::motor(1), WheelMotor::motor(2)],
+///     vec![WheelMotor::motor(3), WheelMotor::motor(4), WheelMotor::motor(5)],
+/// );
+/// // Turning in place at 1 rad/s takes 1.5 times the wheel speed.
+/// assert_eq!(drive.wheel_speeds(0.0, 1.0), (-0.15, 0.15));
+/// ```
+///
+/// Without motors for the sides, the driver's `MotorDriver::set_sides`
+/// drives them, as when each side is wired to one channel.
+#[derive(Clone, Debug)]
+pub struct SkidSteer {
+    /// Distance between the left and right wheels, in metres.
+    pub track_width: f32,
+    /// Wheel ground speed at full motor power, in metres per second.
+    pub max_wheel_speed: f32,
+    /// How much faster than a `DiffDrive` the wheels have to turn for the
+    /// robot to turn, as the wheels scrub sideways on the ground: the
+    /// effective track width over `track_width`, 1 or more, found by
+    /// timing a spin on the surface driven on.
+    pub scrub_factor: f32,
+    /// Motors of the left side, front to rear.
+    pub left: Vec<WheelMotor>,
+    /// Motors of the right side, front to rear.
+    pub right: Vec<WheelMotor>,
+}
+
+impl SkidSteer {
+    pub fn new(track_width: f32, max_wheel_speed: f32) -> Self {
+        assert!(track_width > 0.0 && max_wheel_speed > 0.0);
+        SkidSteer {
+            track_width,
+            max_wheel_speed,
+            scrub_factor: 1.0,
+            left: Vec::new(),
+            right: Vec::new(),
+        }
+    }
+
+    pub fn scrub_factor(mut self, scrub_factor: f32) -> Self {
+        assert!(scrub_factor >= 1.0);
+        self.scrub_factor = scrub_factor;
+        self
+    }
+
+    /// The motors of the `left` and `right` sides.
+    pub fn sides(mut self, left: Vec<WheelMotor>, right: Vec<WheelMotor>) -> Self {
+        self.left = left;
+        self.right = right;
+        self
+    }
+
+    /// The differential drive turning like this robot, on the effective
+    /// track width.
+    pub fn effective(&self) -> DiffDrive {
+        DiffDrive::new(self.track_width * self.scrub_factor, self.max_wheel_speed)
+    }
+
+    /// Left and right wheel speeds in m/s, see `DiffDrive::wheel_speeds`.
+    pub fn wheel_speeds(&self, linear: f32, angular: f32) -> (f32, f32) {
+        self.effective().wheel_speeds(linear, angular)
+    }
+
+    /// Body velocity `(linear, angular)` from left and right wheel speeds.
+    pub fn body_velocity(&self, left: f32, right: f32) -> (f32, f32) {
+        self.effective().body_velocity(left, right)
+    }
+
+    /// Left and right side powers in `[-1, 1]`, see
+    /// `DiffDrive::motor_powers`.
+    pub fn motor_powers(&self, linear: f32, angular: f32) -> (f32, f32) {
+        self.effective().motor_powers(linear, angular)
+    }
+
+    pub fn set_velocity<D: MotorDriver + ?Sized>(
+        &self,
+        driver: &mut D,
+        linear: f32,
+        angular: f32,
+    ) -> Result<(), Error> {
+        let (left, right) = self.motor_powers(linear, angular);
+        if self.left.is_empty() && self.right.is_empty() {
+            return driver.set_sides(left, right);
+        }
+        let sides = self.left.iter().map(|wheel| (wheel, left));
+        for (wheel, power) in sides.chain(self.right.iter().map(|wheel| (wheel, right))) {
+            driver.set_motor(wheel.motor, wheel.power(power))?;
+        }
+        Ok(())
+    }
+}
+
+/// Kinematics of a robot on four mecanum wheels, or omni wheels at 45
+/// degrees, which can also drive sideways: a body velocity `(vx, vy, omega)`
+/// maps to the four wheel powers, see `MotorDriver::set_motor`. The wheels
+/// are in the order front left, front right, rear left, rear right, by
+/// default on motors 0 to 3, as on a `DualDriver` of two ThunderBorgs with
+/// the front one first:
+///
+/// ```
+/// # use vrum::kinematics::{MecanumDrive, WheelMotor};
+/// let drive = MecanumDrive::new(0.2, 0.2, 1.0).wheels([
+///     // A ZeroBorg, motors 1 and 2 on the right, one wired backwards.
+///     WheelMotor::motor(2),
+///     WheelMotor::motor(0).inverted(),
+///     WheelMotor::motor(3),
+///     WheelMotor::motor(1),
+/// ]);
+/// // Sideways to the left at 0.5 m/s.
+/// assert_eq!(drive.motor_powers(0.0, 0.5, 0.0), [-0.5, -0.5, 0.5, -0.5]);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct MecanumDrive {
+    /// Distance between the left and right wheels, in metres.
+    pub track_width: f32,
+    /// Distance between the front and rear axles, in metres.
+    pub wheel_base: f32,
+    /// Wheel ground speed at full motor power, in metres per second.
+    pub max_wheel_speed: f32,
+    /// Front left, front right, rear left and rear right.
+    pub wheels: [WheelMotor; 4],
+}
+
+/// The motor channel driving a wheel of a `MecanumDrive` or a `SkidSteer`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WheelMotor {
+    pub motor: usize,
+    /// Reverse the power, for motors mounted or wired backwards.
+    pub inverted: bool,
+    /// Scale factor applied to the power, to slow down a faster motor.
+    pub scale: f32,
+}
+
+impl WheelMotor {
+    pub fn motor(motor: usize) -> Self {
+        WheelMotor {
+            motor,
+            inverted: false,
+            scale: 1.0,
+        }
+    }
+
+    pub fn inverted(mut self) -> Self {
+        self.inverted = true;
+        self
+    }
+
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// The power to send the motor for the wheel to drive at `power`.
+    pub fn power(&self, power: f32) -> f32 {
+        let direction = if self.inverted { -1.0 } else { 1.0 };
+        (power * self.scale * direction).clamp(-1.0, 1.0)
+    }
+}
+
+impl MecanumDrive {
+    pub fn new(track_width: f32, wheel_base: f32, max_wheel_speed: f32) -> Self {
+        assert!(track_width > 0.0 && wheel_base > 0.0 && max_wheel_speed > 0.0);
+        MecanumDrive {
+            track_width,
+            wheel_base,
+            max_wheel_speed,
+            wheels: [
+                WheelMotor::motor(0),
+                WheelMotor::motor(1),
+                WheelMotor::motor(2),
+                WheelMotor::motor(3),
+            ],
+        }
+    }
+
+    /// The motors of the front left, front right, rear left and rear right
+    /// wheels.
+    pub fn wheels(mut self, wheels: [WheelMotor; 4]) -> Self {
+        self.wheels = wheels;
+        self
+    }
+
+    /// Wheel speeds in m/s, front left, front right, rear left and rear
+    /// right, for a body velocity given as `vx` (m/s, forward positive), `vy`
+    /// (m/s, left positive) and `omega` (rad/s, counter-clockwise positive).
+    /// The rollers of the front left and rear right wheels point forward
+    /// right, those of the others forward left, as seen from above.
+    pub fn wheel_speeds(&self, vx: f32, vy: f32, omega: f32) -> [f32; 4] {
+        let turn = omega * (self.track_width + self.wheel_base) / 2.0;
+        [
+            vx - vy - turn,
+            vx + vy + turn,
+            vx + vy - turn,
+            vx - vy + turn,
+        ]
+    }
+
+    /// Body velocity `(vx, vy, omega)` from the four wheel speeds.
+    pub fn body_velocity(&self, speeds: [f32; 4]) -> (f32, f32, f32) {
+        let [front_left, front_right, rear_left, rear_right] = speeds;
+        (
+            (front_left + front_right + rear_left + rear_right) / 4.0,
+            (-front_left + front_right + rear_left - rear_right) / 4.0,
+            (-front_left + front_right - rear_left + rear_right)
+                / (2.0 * (self.track_width + self.wheel_base)),
+        )
+    }
+
+    /// Powers in `[-1, 1]` of the motors of the wheels, in the order of
+    /// `wheels`. If any wheel would need more than full power all are
+    /// scaled down together, preserving the direction of travel at the
+    /// expense of speed.
+    pub fn motor_powers(&self, vx: f32, vy: f32, omega: f32) -> [f32; 4] {
+        let speeds = self.wheel_speeds(vx, vy, omega);
+        let peak = speeds
+            .iter()
+            .fold(0.0f32, |peak, speed| peak.max(speed.abs()))
+            / self.max_wheel_speed;
+        let scale = if peak > 1.0 { peak } else { 1.0 };
+        let mut powers = [0.0; 4];
+        for ((power, speed), wheel) in powers.iter_mut().zip(&speeds).zip(&self.wheels) {
+            *power = wheel.power(speed / self.max_wheel_speed / scale);
+        }
+        powers
+    }
+
+    pub fn set_velocity<D: MotorDriver + ?Sized>(
+        &self,
+        driver: &mut D,
+        vx: f32,
+        vy: f32,
+        omega: f32,
+    ) -> Result<(), Error> {
+        let powers = self.motor_powers(vx, vy, omega);
+        for (wheel, power) in self.wheels.iter().zip(powers) {
+            driver.set_motor(wheel.motor, power)?;
+        }
+        Ok(())
+    }
+}
+
+/// Position in metres and heading in radians (counter-clockwise from the x
+/// axis) of the robot in a fixed frame, e.g. from odometry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+}
+
+impl Pose {
+    pub fn new(x: f32, y: f32, heading: f32) -> Self {
+        Pose { x, y, heading }
+    }
+
+    /// The pose after driving at `linear` m/s and `angular` rad/s for
+    /// `duration`, along the arc this traces. Dead reckoning from the
+    /// commanded velocities is all an open loop robot has for odometry.
+    pub fn advance(self, linear: f32, angular: f32, duration: Duration) -> Pose {
+        let dt = duration.as_secs_f32();
+        let turned = angular * dt;
+        let heading = self.heading + turned;
+        let (dx, dy) = if turned.abs() < 1e-6 {
+            (
+                linear * dt * self.heading.cos(),
+                linear * dt * self.heading.sin(),
+            )
+        } else {
+            let radius = linear / angular;
+            (
+                radius * (heading.sin() - self.heading.sin()),
+                radius * (self.heading.cos() - heading.cos()),
+            )
+        };
+        Pose {
+            x: self.x + dx,
+            y: self.y + dy,
+            heading,
+        }
+    }
+
+    /// `(x, y)` in the frame of the robot: x forward, y to the left.
+    pub fn to_local(&self, x: f32, y: f32) -> (f32, f32) {
+        let (dx, dy) = (x - self.x, y - self.y);
+        let (sin, cos) = self.heading.sin_cos();
+        (cos * dx + sin * dy, cos * dy - sin * dx)
+    }
+}
@@ -0,0 +1,227 @@
+//! Latency histograms of the I2C transactions, see `CommandStats::latency`,
+//! and of the periods of the control loops, see `LoopTimer`, to tell how
+//! fast the loops can realistically run on given hardware. `vrum bench i2c`
+//! measures the command rates the bus sustains.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::borg::CommandStats;
+use crate::color::Color;
+use crate::error::Error;
+use crate::shutdown::Shutdown;
+use crate::thunder_borg::Controller;
+
+/// Number of buckets of a `LatencyHistogram`, the last one counting
+/// latencies of 2^(LATENCY_BUCKETS - 2) microseconds, about 8 seconds, and
+/// more.
+pub const LATENCY_BUCKETS: usize = 25;
+
+/// Counts of latencies in power of two buckets: `buckets[0]` counts those
+/// under 1 microsecond and `buckets[i]` those from 2^(i - 1) up to 2^i
+/// microseconds.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LatencyHistogram {
+    pub buckets: [u64; LATENCY_BUCKETS],
+    pub max_us: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram::default()
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let latency_us = latency.as_micros().min(u128::from(u64::MAX)) as u64;
+        let bucket = (u64::BITS - latency_us.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+        self.max_us = self.max_us.max(latency_us);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Upper bound of the latency under which a `quantile` in `[0, 1]` of
+    /// the recorded ones fall, the maximum for 1. Zero when empty.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &bucket_count) in self.buckets.iter().enumerate() {
+            seen += bucket_count;
+            if seen >= rank {
+                let upper_us = 1u64 << bucket;
+                return Duration::from_micros(upper_us.min(self.max_us));
+            }
+        }
+        Duration::from_micros(self.max_us)
+    }
+
+    /// Adds the latencies recorded in `other`.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, &count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.max_us = self.max_us.max(other.max_us);
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "p50 < {:?}, p90 < {:?}, p99 < {:?}, max {:?}",
+            self.quantile(0.5),
+            self.quantile(0.9),
+            self.quantile(0.99),
+            Duration::from_micros(self.max_us)
+        )
+    }
+}
+
+/// Measures the periods of a control loop meant to run every `period`,
+/// from one `tick()` to the next. Logs them when dropped, as a warning if
+/// the loop runs late, e.g. because the bus is too slow for the period.
+pub struct LoopTimer {
+    name: &'static str,
+    period: Duration,
+    last_tick: Option<Instant>,
+    periods: LatencyHistogram,
+}
+
+impl LoopTimer {
+    pub fn new(name: &'static str, period: Duration) -> Self {
+        LoopTimer {
+            name,
+            period,
+            last_tick: None,
+            periods: LatencyHistogram::new(),
+        }
+    }
+
+    /// Marks the start of an iteration.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(last_tick) = self.last_tick.replace(now) {
+            self.periods.record(now - last_tick);
+        }
+    }
+
+    /// Periods measured so far.
+    pub fn periods(&self) -> &LatencyHistogram {
+        &self.periods
+    }
+}
+
+impl Drop for LoopTimer {
+    fn drop(&mut self) {
+        if self.periods.count() == 0 {
+            return;
+        }
+        // The bound of a bucket is under twice the latencies in it, past
+        // twice the period the p90 is surely late.
+        if self.periods.quantile(0.9) > 2 * self.period {
+            warn!(
+                "The {} loop ran late, meant to run every {:?}: {}",
+                self.name, self.period, self.periods
+            );
+        } else {
+            debug!(
+                "The {} loop ran every {:?}: {}",
+                self.name, self.period, self.periods
+            );
+        }
+    }
+}
+
+/// What `bench_i2c` measured for one kind of transaction.
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub name: &'static str,
+    pub elapsed: Duration,
+    /// Commands completed, retries and failures, with their latencies.
+    pub stats: CommandStats,
+}
+
+impl BenchResult {
+    /// Commands completed per second.
+    pub fn rate(&self) -> f64 {
+        self.stats.completed as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// As `name`, `seconds`, `rate` and `stats`, for `vrum bench i2c --json`.
+impl Serialize for BenchResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut result = serializer.serialize_struct("BenchResult", 4)?;
+        result.serialize_field("name", self.name)?;
+        result.serialize_field("seconds", &self.elapsed.as_secs_f64())?;
+        result.serialize_field("rate", &self.rate())?;
+        result.serialize_field("stats", &self.stats)?;
+        result.end()
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let attempts = (self.stats.completed + self.stats.retries + self.stats.failures).max(1);
+        write!(
+            formatter,
+            "{}: {:.0} per second, {} retries ({:.2}%), {} failures, latency {}",
+            self.name,
+            self.rate(),
+            self.stats.retries,
+            100.0 * self.stats.retries as f64 / attempts as f64,
+            self.stats.failures,
+            self.stats.latency
+        )
+    }
+}
+
+/// Sends queries (battery readings), then writes (LED colours, a barely
+/// visible blue alternating with off), as fast as the bus takes them for
+/// `duration` each. Failed commands are counted, not returned.
+pub fn bench_i2c(
+    controller: &mut Controller,
+    duration: Duration,
+    shutdown: &Shutdown,
+) -> Result<Vec<BenchResult>, Error> {
+    let dim = Color {
+        red: 0,
+        green: 0,
+        blue: 1,
+    };
+    let mut results = Vec::new();
+    for &name in &["queries", "writes"] {
+        info!("Measuring {} for {:?}", name, duration);
+        controller.reset_comm_stats();
+        let started = Instant::now();
+        let mut lit = false;
+        while started.elapsed() < duration {
+            shutdown.check()?;
+            let sent = if name == "queries" {
+                controller.get_battery_voltage().map(drop)
+            } else {
+                lit = !lit;
+                controller.set_led(if lit { dim } else { Color::OFF })
+            };
+            match sent {
+                Ok(()) | Err(Error::CommandFailed { .. }) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        results.push(BenchResult {
+            name,
+            elapsed: started.elapsed(),
+            stats: controller.comm_stats().total(),
+        });
+    }
+    controller.set_led(Color::OFF)?;
+    Ok(results)
+}
@@ -0,0 +1,133 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::color::Color;
+use crate::error::Error;
+use crate::estop::EStopLatch;
+use crate::thunder_borg::Controller;
+
+/// An animation played on the ThunderBorg LED, see `Controller::led_effect`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// Alternate between `color` and off, `period` is a full on/off cycle.
+    Blink { color: Color, period: Duration },
+    /// Smoothly fade `color` in and out, `period` is a full cycle.
+    Breathe { color: Color, period: Duration },
+    /// Cycle through the hues of the colour wheel once per `period`.
+    Rainbow { period: Duration },
+    /// Show the battery charge as a colour from red (`empty_voltage`) to
+    /// green (`full_voltage`).
+    BatteryGauge {
+        empty_voltage: f32,
+        full_voltage: f32,
+    },
+}
+
+impl Effect {
+    fn frame_interval(&self) -> Duration {
+        match *self {
+            Effect::Blink { period, .. } => period / 2,
+            Effect::Breathe { .. } | Effect::Rainbow { .. } => SMOOTH_FRAME_INTERVAL,
+            Effect::BatteryGauge { .. } => GAUGE_FRAME_INTERVAL,
+        }
+    }
+
+    fn frame(&self, controller: &mut Controller, elapsed: Duration) -> Result<Color, Error> {
+        Ok(match *self {
+            Effect::Blink { color, period } => {
+                if phase(elapsed, period) < 0.5 {
+                    color
+                } else {
+                    Color::OFF
+                }
+            }
+            Effect::Breathe { color, period } => {
+                let brightness = 0.5 - 0.5 * (2.0 * PI * phase(elapsed, period)).cos();
+                color.scale(brightness)
+            }
+            Effect::Rainbow { period } => Color::from_hsv(360.0 * phase(elapsed, period), 1.0, 1.0),
+            Effect::BatteryGauge {
+                empty_voltage,
+                full_voltage,
+            } => {
+                let voltage = controller.get_battery_voltage()?;
+                let level =
+                    ((voltage - empty_voltage) / (full_voltage - empty_voltage)).clamp(0.0, 1.0);
+                // Red through yellow to green.
+                Color::from_hsv(120.0 * level, 1.0, 1.0)
+            }
+        })
+    }
+}
+
+/// Plays an `Effect` on a background thread until dropped, or until the
+/// emergency stop is latched. The LED is switched off when the effect ends.
+pub struct LedAnimator {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LedAnimator {
+    /// `controller` is used only to drive the LED, e.g. one sharing the bus
+    /// of the controller driving the motors.
+    pub fn spawn(
+        effect: Effect,
+        mut controller: Controller,
+        estop: Option<EStopLatch>,
+    ) -> Result<Self, Error> {
+        debug!("Starting LED effect {:?}", effect);
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-led".into())
+            .spawn(move || {
+                let started = Instant::now();
+                while thread_running.load(Ordering::SeqCst) {
+                    if estop.as_ref().is_some_and(EStopLatch::is_latched) {
+                        info!("Emergency stop latched, ending LED effect");
+                        break;
+                    }
+                    let result = effect
+                        .frame(&mut controller, started.elapsed())
+                        .and_then(|color| controller.set_led(color));
+                    if let Err(error) = result {
+                        warn!("Could not update LED effect: {}", error);
+                    }
+                    thread::sleep(effect.frame_interval());
+                }
+                if let Err(error) = controller.set_led(Color::OFF) {
+                    warn!("Could not switch off the LED: {}", error);
+                }
+            })?;
+        Ok(LedAnimator {
+            running,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for LedAnimator {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("LED effect thread panicked");
+            }
+        }
+    }
+}
+
+/// Fraction of `period` elapsed in the current cycle, in `[0, 1)`.
+#[inline]
+fn phase(elapsed: Duration, period: Duration) -> f32 {
+    if period.as_nanos() == 0 {
+        return 0.0;
+    }
+    (elapsed.as_secs_f32() / period.as_secs_f32()).fract()
+}
+
+const SMOOTH_FRAME_INTERVAL: Duration = Duration::from_millis(33);
+const GAUGE_FRAME_INTERVAL: Duration = Duration::from_secs(1);
@@ -0,0 +1,126 @@
+extern crate arrayvec;
+extern crate ciborium;
+extern crate i2cdev;
+extern crate libc;
+#[cfg(feature = "mdns")]
+extern crate mdns_sd;
+#[cfg(feature = "grpc")]
+extern crate prost;
+#[cfg(feature = "web")]
+extern crate rcgen;
+#[cfg(feature = "scripting")]
+extern crate rhai;
+extern crate rppal;
+#[cfg(feature = "mqtt")]
+extern crate rumqttc;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "web")]
+extern crate rustls;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate signal_hook;
+extern crate thiserror;
+#[cfg(feature = "grpc")]
+extern crate tokio;
+#[cfg(feature = "grpc")]
+extern crate tokio_stream;
+extern crate toml;
+extern crate toml_edit;
+#[macro_use]
+extern crate tracing;
+#[cfg(feature = "grpc")]
+extern crate tonic;
+#[cfg(any(feature = "ros", feature = "web"))]
+extern crate tungstenite;
+
+pub mod arbiter;
+pub mod arming;
+pub mod auth;
+pub mod battery;
+pub mod black_box;
+pub mod borg;
+pub mod bumper;
+pub mod bus_manager;
+pub mod buzzer;
+pub mod clock;
+pub mod color;
+pub mod config;
+pub mod cruise;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "mdns")]
+pub mod discovery;
+pub mod encoding;
+pub mod energy;
+pub mod error;
+pub mod estimation;
+pub mod estop;
+pub mod fault_injection;
+pub mod faults;
+pub mod gears;
+pub mod gpio_outputs;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod gyro;
+pub mod heading;
+pub mod heartbeat;
+pub mod history;
+pub mod influx;
+pub mod journal;
+pub mod kinematics;
+pub mod latency;
+pub mod led;
+pub mod line_follower;
+pub mod maintenance;
+pub mod mission;
+pub mod motion;
+pub mod motion_profile;
+pub mod motor_driver;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod obstacle;
+pub mod pan_tilt;
+pub mod pca9685;
+pub mod pico_borg;
+pub mod pid;
+pub mod pipeline;
+pub mod profile;
+pub mod pure_pursuit;
+pub mod rc;
+pub mod realtime;
+pub mod recorder;
+pub mod reload;
+#[cfg(feature = "ros")]
+pub mod ros;
+#[cfg(feature = "sqlite")]
+pub mod run_log;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod sensors;
+pub mod shared;
+pub mod shutdown;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod simulator;
+pub mod stall;
+pub mod sticks;
+pub mod systemd;
+pub mod telemetry;
+pub mod thermal;
+pub mod thunder_borg;
+#[cfg(feature = "web")]
+pub mod tls;
+pub mod udp;
+pub mod ultra_borg;
+pub mod watchdog;
+pub mod waypoint;
+#[cfg(feature = "web")]
+pub mod web;
+pub mod xlo_borg;
+pub mod zero_borg;
+
+pub use crate::error::Error;
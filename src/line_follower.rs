@@ -0,0 +1,176 @@
+//! Line following: steers along a dark line on the floor with a PID on the
+//! position of the line under a row of reflectance sensors, see
+//! `sensors::line`.
+
+use std::time::{Duration, Instant};
+
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+use crate::latency::LoopTimer;
+use crate::motor_driver::MotorDriver;
+use crate::pid::{Pid, PidConfig};
+use crate::sensors::line::{self, LineSensor, LineSensorConfig};
+use crate::shutdown::Shutdown;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LineFollowerError {
+    #[error("lost the line for {ms}ms")]
+    LineLost { ms: u64 },
+    #[error("line following needs a [line_follower] section in the configuration")]
+    NotConfigured,
+}
+
+/// The `[line_follower]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LineFollowerConfig {
+    pub sensor: LineSensorConfig,
+    /// Power of both sides with the line centred.
+    #[serde(default = "default_power")]
+    pub power: f32,
+    /// Gains on the line position in `[-1, 1]`, the output being the power
+    /// added to one side and taken from the other.
+    #[serde(default = "default_pid")]
+    pub pid: PidConfig,
+    #[serde(default = "default_period_ms")]
+    pub period_ms: u64,
+    /// How long the line may be out of sight, the robot turning back to the
+    /// side it was last seen on meanwhile, before giving up.
+    #[serde(default = "default_lost_timeout_ms")]
+    pub lost_timeout_ms: u64,
+}
+
+/// What a `LineFollower` step saw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineStatus {
+    /// The line is at this position, from -1 far left to 1 far right.
+    Found(f32),
+    /// No sensor sees the line, for this long.
+    Lost(Duration),
+}
+
+pub struct LineFollower<S: LineSensor> {
+    sensor: S,
+    config: LineFollowerConfig,
+    pid: Pid,
+    last_position: Option<f32>,
+    last_update: Option<Instant>,
+    lost_since: Option<Instant>,
+}
+
+impl LineFollower<Box<dyn LineSensor>> {
+    /// A follower reading the sensors in `config`, `bus` being the I2C bus
+    /// of an ADS1115.
+    pub fn from_config(config: LineFollowerConfig, bus: &BusManager) -> Result<Self, Error> {
+        let sensor = line::open(&config.sensor, bus)?;
+        Ok(LineFollower::new(sensor, config))
+    }
+}
+
+impl<S: LineSensor> LineFollower<S> {
+    pub fn new(sensor: S, config: LineFollowerConfig) -> Self {
+        LineFollower {
+            sensor,
+            pid: Pid::new(config.pid),
+            config,
+            last_position: None,
+            last_update: None,
+            lost_since: None,
+        }
+    }
+
+    pub fn sensor(&mut self) -> &mut S {
+        &mut self.sensor
+    }
+
+    /// One correction step: reads the sensors and steers towards the line.
+    /// While the line is lost it keeps correcting as if the line were where
+    /// it was last seen. Call it in a loop at a steady rate, see `follow()`.
+    pub fn step<D: MotorDriver + ?Sized>(&mut self, driver: &mut D) -> Result<LineStatus, Error> {
+        let now = Instant::now();
+        let elapsed = self
+            .last_update
+            .map_or(Duration::default(), |at| now.duration_since(at));
+        self.last_update = Some(now);
+
+        let status = match line::line_position(&self.sensor.read()?) {
+            Some(position) => {
+                self.last_position = Some(position);
+                self.lost_since = None;
+                LineStatus::Found(position)
+            }
+            None => LineStatus::Lost(now - *self.lost_since.get_or_insert(now)),
+        };
+        let position = self.last_position.unwrap_or(0.0);
+        // A line to the right needs a right turn, speeding up the left side.
+        let correction = self.pid.update(position, elapsed);
+        debug!(line_position = position, correction, "Following line");
+        let power = self.config.power;
+        driver.set_sides(power + correction, power - correction)?;
+        Ok(status)
+    }
+
+    /// Follows the line until a shutdown is requested, returning
+    /// `ShutdownError::Requested`, or until it has been out of sight for
+    /// `lost_timeout_ms`, returning `LineFollowerError::LineLost`. The
+    /// motors are stopped either way.
+    pub fn follow<D: MotorDriver + ?Sized>(
+        &mut self,
+        driver: &mut D,
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        self.reset();
+        let period = Duration::from_millis(self.config.period_ms.max(1));
+        let lost_timeout = Duration::from_millis(self.config.lost_timeout_ms);
+        let mut timer = LoopTimer::new("line follower", period);
+        let result: Result<(), Error> = loop {
+            timer.tick();
+            match self.step(driver) {
+                Ok(LineStatus::Lost(lost)) if lost >= lost_timeout => {
+                    break Err(LineFollowerError::LineLost {
+                        ms: lost.as_millis() as u64,
+                    }
+                    .into());
+                }
+                Ok(_) => {}
+                Err(error) => break Err(error),
+            }
+            if let Err(error) = shutdown.sleep(period) {
+                break Err(error);
+            }
+        };
+        let stopped = driver.set_sides(0.0, 0.0);
+        result.and(stopped)
+    }
+
+    /// Starts afresh, forgetting the accumulated correction and where the
+    /// line was.
+    pub fn reset(&mut self) {
+        self.pid.reset();
+        self.last_position = None;
+        self.last_update = None;
+        self.lost_since = None;
+    }
+}
+
+fn default_power() -> f32 {
+    0.3
+}
+
+fn default_pid() -> PidConfig {
+    PidConfig {
+        kp: 0.4,
+        ki: 0.0,
+        kd: 0.05,
+        output_limit: 0.5,
+        integral_limit: 0.2,
+    }
+}
+
+fn default_period_ms() -> u64 {
+    20
+}
+
+fn default_lost_timeout_ms() -> u64 {
+    500
+}
@@ -1,95 +1,1434 @@
-extern crate arrayvec;
-extern crate env_logger;
+extern crate clap;
+extern crate serde;
+extern crate serde_json;
 #[macro_use]
-extern crate failure;
-extern crate i2cdev;
-#[macro_use]
-extern crate log;
-
-mod thunder_borg;
+extern crate tracing;
+#[cfg(feature = "journald")]
+extern crate tracing_journald;
+extern crate tracing_subscriber;
+extern crate vrum;
 
-use std::process;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
 use std::env;
-use env_logger::LogBuilder;
-use log::{LogLevelFilter, LogRecord};
-use failure::Error;
-use thunder_borg::Controller;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, BufRead};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::thread;
 use std::time::Duration;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+#[cfg(feature = "web")]
+use vrum::arbiter::{Arbiter, ClaimingClient, ControlPriority};
+use vrum::arming::Arming;
+use vrum::battery::BatterySupervisor;
+use vrum::black_box::{self, BlackBoxRecorder};
+use vrum::borg;
+use vrum::bumper::BumperMonitor;
+use vrum::bus_manager::BusManager;
+use vrum::buzzer::Buzzer;
+use vrum::color::{Color, ColorError};
+use vrum::config::Config;
+use vrum::cruise::CruiseControl;
+#[cfg(feature = "tui")]
+use vrum::dashboard::{Dashboard, LogBuffer};
+#[cfg(feature = "mdns")]
+use vrum::discovery;
+#[cfg(all(feature = "mdns", feature = "web"))]
+use vrum::discovery::{AdvertiseConfig, Advertiser};
+use vrum::energy::{self, EnergyMonitor};
+use vrum::estop::EStop;
+#[cfg(feature = "web")]
+use vrum::estop::EStopLatch;
+use vrum::faults::{FaultLatch, FaultMonitor};
+use vrum::gears::GearSelector;
+use vrum::gpio_outputs::{GpioOutputs, OutputState};
+use vrum::gyro::GyroMonitor;
+use vrum::heading::{self, HeadingHold};
+use vrum::heartbeat::Transport;
+use vrum::history::EventHistory;
+use vrum::influx::InfluxExporter;
+use vrum::kinematics::DiffDrive;
+use vrum::latency;
+use vrum::led::Effect;
+use vrum::line_follower::{LineFollower, LineFollowerError};
+use vrum::maintenance::{self, HourMeter};
+use vrum::mission::{Mission, MissionControl, MissionRunner};
+use vrum::motion::Motion;
+use vrum::motion_profile::{self, MotionProfile};
+use vrum::obstacle::ObstacleMonitor;
+use vrum::pan_tilt::{PanTiltDriver, ServoBoard};
+use vrum::pca9685::Pca9685;
+use vrum::rc::{RcConfig, RcError, RcReceiver};
+use vrum::recorder;
+use vrum::reload::ConfigWatcher;
+#[cfg(feature = "sqlite")]
+use vrum::run_log::{RunLog, RunRecorder};
+#[cfg(feature = "scripting")]
+use vrum::scripting;
+use vrum::sensors::gps::{GpsReceiver, Position};
+#[cfg(feature = "web")]
+use vrum::shared::{SharedController, SharedControllerConfig};
+use vrum::shutdown::{Shutdown, ShutdownError};
+#[cfg(feature = "sim")]
+use vrum::sim::SimRobot;
+use vrum::simulator::SimulatedBoard;
+use vrum::stall::{StallDetector, StallSensors};
+use vrum::systemd::{self, SystemdNotifier};
+use vrum::telemetry::{TelemetryPoller, TelemetryPollerConfig};
+use vrum::thermal::ThermalMonitor;
+use vrum::thunder_borg::{self, Controller, DropPolicy};
+use vrum::udp::{UdpConfig, UdpReceiver};
+use vrum::ultra_borg;
+use vrum::waypoint::{WaypointError, WaypointNavigator};
+#[cfg(feature = "web")]
+use vrum::web::{WebConfig, WebServer};
+use vrum::Error;
+
+#[derive(Parser)]
+#[command(name = "vrum", about = "Drive a ThunderBorg based robot")]
+struct Cli {
+    /// TOML configuration file
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Driving profile from the configuration to use instead of the selected
+    /// one
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    /// Cap the absolute power of the motors, in [0, 1]
+    #[arg(long, global = true)]
+    power_limit: Option<f32>,
+    /// Start in this gear of the `[gears]` section, from 1, scaling the
+    /// motor powers on top of the power limit
+    #[arg(long, global = true)]
+    gear: Option<usize>,
+    /// Run as a systemd `Type=notify` service: report readiness and ping the
+    /// service watchdog
+    #[arg(long, global = true)]
+    systemd: bool,
+    /// Print what query commands like `status` and `id` report as JSON,
+    /// for scripts and fleet tooling
+    #[arg(long, global = true)]
+    json: bool,
+    /// Print JSON, indented
+    #[arg(long, global = true)]
+    pretty: bool,
+    /// Log JSON objects, one per line, e.g. to ship them to a log collector
+    #[arg(long, global = true)]
+    log_json: bool,
+    /// Log to systemd-journald with structured fields, instead of stderr
+    #[cfg(feature = "journald")]
+    #[arg(long, global = true)]
+    journald: bool,
+    /// Arm the motors on start, when the `[arming]` section requires it
+    #[arg(long, global = true)]
+    arm: bool,
+    /// Drive a ThunderBorg simulated in software instead of the real board
+    #[arg(long, global = true)]
+    simulate: bool,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Drive forwards and backwards a couple of times (the default)
+    Demo {
+        /// Record every motor and LED command to this file
+        #[arg(long)]
+        record: Option<PathBuf>,
+    },
+    /// Drive straight for a time (`--for 2s`) or a distance (`--distance
+    /// 1.5m`), backwards for a negative power, then slow down and stop. The
+    /// distance is driven open loop through the wheel base and speed
+    #[command(allow_negative_numbers = true)]
+    Drive {
+        /// Motor power of both sides, in [-1, 1]
+        power: f32,
+        /// How long to drive for, e.g. `2s`, `500ms` or `1min`
+        #[arg(
+            long = "for",
+            value_parser = parse_duration,
+            required_unless_present = "distance",
+            conflicts_with = "distance"
+        )]
+        duration: Option<Duration>,
+        /// How far to drive, e.g. `1.5m` or `30cm`
+        #[arg(long, value_parser = parse_distance)]
+        distance: Option<f32>,
+        /// How long to take slowing down to a stop at the end
+        #[arg(long, default_value = "500ms", value_parser = parse_duration)]
+        ramp_down: Duration,
+        /// Distance between the left and right wheels, in metres
+        #[arg(long, default_value_t = 0.2)]
+        wheel_base: f32,
+        /// Wheel speed at full power, in metres per second
+        #[arg(long, default_value_t = 1.0)]
+        max_wheel_speed: f32,
+    },
+    /// Measure what the hardware sustains
+    Bench {
+        #[command(subcommand)]
+        target: BenchTarget,
+    },
+    /// Calibrate the board against external measurements
+    Calibrate {
+        #[command(subcommand)]
+        target: CalibrateTarget,
+    },
+    /// List the robots advertising themselves on the local network
+    #[cfg(feature = "mdns")]
+    Discover {
+        /// How long to listen for answers, in milliseconds
+        #[arg(long, default_value_t = 3000)]
+        timeout_ms: u64,
+    },
+    /// Manage the latched drive faults and battery cutoffs
+    Faults {
+        #[command(subcommand)]
+        action: FaultsAction,
+    },
+    /// Identify the board, for fleet inventories
+    Id,
+    /// Report the battery, motors and faults of the board
+    Status,
+    /// Report the hours the motors were driven, the distance travelled and
+    /// the drive faults, over every run with a `[maintenance]` section
+    Stats,
+    /// Switch or dim an output of the `[gpio_outputs]` section, e.g.
+    /// `vrum aux headlights on`
+    Aux {
+        name: String,
+        /// `on`, `off`, `toggle`, or a level in `[0, 1]` held until
+        /// interrupted
+        #[arg(value_parser = parse_output_state)]
+        state: OutputState,
+    },
+    /// Play an effect on the LED
+    Led {
+        #[arg(value_enum)]
+        effect: LedEffect,
+        /// Colour used by `blink` and `breathe`: a name, `#rrggbb` or
+        /// `red,green,blue`
+        #[arg(long, default_value = "blue", value_parser = parse_color)]
+        color: Color,
+        /// Length of one cycle of the effect, in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        period_ms: u64,
+        /// Battery voltage shown as red by `gauge`
+        #[arg(long, default_value_t = 9.9)]
+        empty_voltage: f32,
+        /// Battery voltage shown as green by `gauge`
+        #[arg(long, default_value_t = 12.6)]
+        full_voltage: f32,
+        /// How long to play the effect for, in seconds
+        #[arg(long, default_value_t = 10)]
+        seconds: u64,
+    },
+    /// Select the driving profile used by default, storing it in the
+    /// `--config` file
+    Profile { name: String },
+    /// Write a systemd unit file running vrum as a supervised service
+    InstallService {
+        /// Where to write the unit file
+        #[arg(long, default_value = "/etc/systemd/system/vrum.service")]
+        path: PathBuf,
+        /// Seconds without a watchdog ping before systemd restarts the
+        /// service
+        #[arg(long, default_value_t = 5)]
+        watchdog_sec: u64,
+        /// Arguments the service runs vrum with, e.g. `-- run drive.rhai`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Run autonomous missions written in YAML or JSON
+    Mission {
+        #[command(subcommand)]
+        action: MissionAction,
+    },
+    /// Follow a line on the floor with the sensors in the `[line_follower]`
+    /// section of the configuration, until interrupted or the line is lost
+    FollowLine {
+        /// Power of both sides with the line centred, instead of the
+        /// configured one
+        #[arg(long)]
+        power: Option<f32>,
+    },
+    /// Drive to a latitude and longitude with the GPS in the `[gps]` section
+    /// of the configuration, steering on the compass
+    #[command(allow_negative_numbers = true)]
+    GoTo {
+        /// Degrees north, negative south
+        latitude: f64,
+        /// Degrees east, negative west
+        longitude: f64,
+    },
+    /// Show a live dashboard of the battery, motors, bus and log, leaving the
+    /// board running on exit so it can watch another vrum process
+    #[cfg(feature = "tui")]
+    Monitor {
+        /// How often to read the board and redraw, in milliseconds
+        #[arg(long, default_value_t = 250)]
+        refresh_ms: u64,
+    },
+    /// Serve a web page to drive the robot from a phone, with a joystick,
+    /// the battery level, fault lights and an emergency stop. The RC
+    /// transmitter of the `[rc]` section, if any, overrides it
+    #[cfg(feature = "web")]
+    Serve {
+        /// Address and port to listen on
+        #[arg(long, default_value = "0.0.0.0:8080")]
+        listen: SocketAddr,
+    },
+    /// Drive with a hobby RC transmitter through the receiver in the `[rc]`
+    /// section of the configuration
+    Rc,
+    /// Drive from the packets of the low latency UDP protocol, see
+    /// `vrum::udp`
+    UdpTeleop {
+        /// Address and port to listen on
+        #[arg(long, default_value = "0.0.0.0:9000")]
+        listen: SocketAddr,
+        /// Stop the motors when no packet arrives for this long, in
+        /// milliseconds, instead of the `[heartbeat]` timeout
+        #[arg(long)]
+        timeout_ms: Option<u64>,
+    },
+    /// Drive a built-in manoeuvre, for demos and to calibrate the trim and
+    /// the odometry: the robot should end where it started
+    Shape {
+        #[command(subcommand)]
+        shape: ShapeKind,
+        /// Motor power of the wheels, of the outer one on curves
+        #[arg(long, global = true, default_value_t = 0.5)]
+        power: f32,
+        /// Distance between the left and right wheels, in metres
+        #[arg(long, global = true, default_value_t = 0.2)]
+        wheel_base: f32,
+        /// Wheel speed at full power, in metres per second
+        #[arg(long, global = true, default_value_t = 1.0)]
+        max_wheel_speed: f32,
+    },
+    /// Play back a file recorded with `vrum demo --record`
+    Replay { file: PathBuf },
+    /// Look into the runs stored in the `[run_log]` database
+    #[cfg(feature = "sqlite")]
+    Runs {
+        #[command(subcommand)]
+        action: RunsAction,
+    },
+    /// Run a Rhai motion script
+    #[cfg(feature = "scripting")]
+    Run {
+        script: PathBuf,
+        /// Distance between the left and right wheels, in metres
+        #[arg(long, default_value_t = 0.2)]
+        wheel_base: f32,
+        /// Wheel speed at full power, in metres per second
+        #[arg(long, default_value_t = 1.0)]
+        max_wheel_speed: f32,
+        /// Motor power used by `turn()`
+        #[arg(long, default_value_t = 0.5)]
+        turn_power: f32,
+    },
+}
+
+#[derive(Subcommand)]
+enum MissionAction {
+    /// Run a mission file. Type `pause`, `resume` or `abort` and Enter to
+    /// control it while it runs
+    Run {
+        file: PathBuf,
+        /// Distance between the left and right wheels, in metres
+        #[arg(long, default_value_t = 0.2)]
+        wheel_base: f32,
+        /// Wheel speed at full power, in metres per second
+        #[arg(long, default_value_t = 1.0)]
+        max_wheel_speed: f32,
+        /// Motor power of `turn` steps
+        #[arg(long, default_value_t = 0.5)]
+        turn_power: f32,
+    },
+}
+
+#[derive(Subcommand)]
+enum FaultsAction {
+    /// Allow motor commands again once the faults are gone. Clears the latch
+    /// of a running vrum through the `file` in the `[fault_latch]` section
+    Clear,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Subcommand)]
+enum RunsAction {
+    /// List the runs, oldest first
+    List,
+    /// Summarize a run and list its notable events
+    Show { id: i64 },
+    /// Write a run's commands, telemetry and events as JSON
+    Export {
+        id: i64,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ShapeKind {
+    /// A circle to the left then one to the right
+    FigureEight {
+        /// Length of the eight, e.g. `1m`
+        #[arg(long, default_value = "1m", value_parser = parse_distance)]
+        size: f32,
+    },
+    /// Turn in place, clockwise for negative turns
+    #[command(allow_negative_numbers = true)]
+    Spin {
+        #[arg(long, default_value_t = 1.0)]
+        turns: f32,
+    },
+    /// A square turning left at the corners, the sides accelerating and
+    /// decelerating smoothly
+    Square {
+        /// Length of the sides, e.g. `50cm`
+        #[arg(long, default_value = "0.5m", value_parser = parse_distance)]
+        side: f32,
+        /// Acceleration along the sides, in m/s²
+        #[arg(long, default_value_t = 0.5)]
+        acceleration: f32,
+    },
+}
+
+#[derive(Subcommand)]
+enum BenchTarget {
+    /// Send I2C queries, then writes, as fast as the bus takes them and
+    /// report the rates, latencies and retries, to tell how fast control
+    /// loops can run. The LED flickers, the motors don't move
+    I2c {
+        /// How long to send each kind of command for, in seconds
+        #[arg(long, default_value_t = 5)]
+        seconds: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum CalibrateTarget {
+    /// Correct the battery voltage reading, storing the result in the
+    /// `--config` file
+    Battery {
+        /// Battery voltage measured with a multimeter
+        #[arg(long)]
+        measured: f32,
+        /// Number of readings averaged
+        #[arg(long, default_value_t = 10)]
+        samples: u32,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LedEffect {
+    Blink,
+    Breathe,
+    Rainbow,
+    Gauge,
+}
+
+fn run(cli: Cli) -> Result<(), Error> {
+    let shutdown = Shutdown::new();
+    shutdown.install_signal_handlers()?;
+    let mut config = match cli.config {
+        Some(ref path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(CliCommand::Profile { ref name }) = cli.command {
+        config.find_profile(name)?;
+        return match cli.config {
+            Some(ref path) => Config::store_profile(path, name),
+            None => {
+                warn!(
+                    "No --config given, add `profile = {:?}` to your configuration",
+                    name
+                );
+                Ok(())
+            }
+        };
+    }
+    if let Some(CliCommand::InstallService {
+        ref path,
+        watchdog_sec,
+        ref args,
+    }) = cli.command
+    {
+        return install_service(&cli, path, Duration::from_secs(watchdog_sec), args);
+    }
+    let output = if cli.pretty {
+        Output::PrettyJson
+    } else if cli.json {
+        Output::Json
+    } else {
+        Output::Text
+    };
+    #[cfg(feature = "mdns")]
+    {
+        if let Some(CliCommand::Discover { timeout_ms }) = cli.command {
+            let robots = discovery::discover(Duration::from_millis(timeout_ms))?;
+            return output.print_all(&robots, "No robots found");
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    {
+        if let Some(CliCommand::Runs { ref action }) = cli.command {
+            let path = config.run_log.unwrap_or_default().path;
+            return show_runs(&RunLog::open(path)?, action, output);
+        }
+    }
+    if let Some(CliCommand::Stats) = cli.command {
+        let path = config.maintenance.unwrap_or_default().state_file;
+        return match maintenance::read_stats(&path)? {
+            Some(stats) => output.print(&stats),
+            None => {
+                println!("No maintenance counters in {}", path.display());
+                Ok(())
+            }
+        };
+    }
+    if let Some(CliCommand::Aux { ref name, state }) = cli.command {
+        let outputs = GpioOutputs::open(&config.gpio_outputs)?;
+        let level = outputs.set(name, state)?;
+        if level == 0.0 || level == 1.0 {
+            outputs.leave_on_exit();
+            return Ok(());
+        }
+        // Dimming is software PWM, which ends with the process.
+        loop {
+            shutdown.sleep(Duration::from_secs(1))?;
+        }
+    }
+    if let Some(ref name) = cli.profile {
+        config.find_profile(name)?;
+        info!("Using driving profile {:?}", name);
+        config.profile = Some(name.clone());
+    }
+    let simulated = if cli.simulate {
+        info!("Driving a simulated ThunderBorg");
+        Some(SimulatedBoard::new())
+    } else {
+        None
+    };
+    // The board's motors drive a simulated robot, its battery sagging under
+    // load.
+    #[cfg(feature = "sim")]
+    let _sim = match simulated {
+        Some(ref board) => Some(SimRobot::new(config.sim).drive_board(board.clone(), SIM_PERIOD)?),
+        None => None,
+    };
+    // Every board and sensor talks over the one bus, so the transfers of
+    // the background threads don't interleave.
+    let bus = match simulated {
+        Some(ref board) => board.bus_manager(),
+        None => BusManager::open(borg::DEFAULT_I2C_BUS_PATH)?,
+    };
+    let thunder_borg = || Box::new(bus.device(thunder_borg::THUNDERBORG_SLAVE_ADDR));
+    let build_controller = || config.controller_builder().build_with_bus(thunder_borg());
+    // Commands only reading the board leave alone the motors another vrum
+    // may be driving.
+    let build_observer = || {
+        config
+            .controller_builder()
+            .drop_policy(DropPolicy::LeaveRunning)
+            .build_with_bus(thunder_borg())
+    };
+    #[cfg(feature = "tui")]
+    {
+        if let Some(CliCommand::Monitor { refresh_ms }) = cli.command {
+            return Dashboard::new(Duration::from_millis(refresh_ms))
+                .run(&mut build_observer()?, &shutdown);
+        }
+    }
+    match cli.command {
+        Some(CliCommand::Id) => return output.print(&build_observer()?.board_info()?),
+        Some(CliCommand::Status) => {
+            let mut status = build_observer()?.status()?;
+            if let Some(ref energy_config) = config.energy {
+                status.energy = energy::read_report(&energy_config.state_file)?;
+            }
+            return output.print(&status);
+        }
+        _ => {}
+    }
+    let mut controller = build_controller()?;
+    if let Some(limit) = cli.power_limit {
+        controller.set_power_limit(limit);
+    }
+    let gears = match (config.gears.clone(), cli.gear) {
+        (None, None) => None,
+        (gears_config, gear) => {
+            let mut gears_config = gears_config.unwrap_or_default();
+            gears_config.initial = gear.unwrap_or(gears_config.initial);
+            let gears = GearSelector::new(&gears_config)?;
+            controller.set_gears(gears.clone());
+            Some(gears)
+        }
+    };
+    if config.arming.required {
+        controller.set_arming(Arming::new());
+    }
+    // The exporter, the energy monitor, the hour meter and the run log need
+    // samples even without a `[telemetry]` section.
+    let needs_samples =
+        config.influx.is_some() || config.energy.is_some() || config.maintenance.is_some();
+    #[cfg(feature = "sqlite")]
+    let needs_samples = needs_samples || config.run_log.is_some();
+    let thermal = match config.thermal {
+        Some(ref thermal_config) => {
+            let monitor = ThermalMonitor::spawn(thermal_config.clone(), &bus)?;
+            controller.set_thermal_guard(monitor.guard());
+            Some(monitor)
+        }
+        None => None,
+    };
+    let telemetry = match config.telemetry {
+        Some(ref telemetry_config) => Some(TelemetryPoller::spawn(
+            telemetry_config.clone(),
+            build_controller()?,
+            thermal.as_ref().map(ThermalMonitor::guard),
+        )?),
+        None if needs_samples => Some(TelemetryPoller::spawn(
+            TelemetryPollerConfig::default(),
+            build_controller()?,
+            thermal.as_ref().map(ThermalMonitor::guard),
+        )?),
+        None => None,
+    };
+    let _influx = match (&config.influx, &telemetry) {
+        (Some(influx_config), Some(poller)) => Some(InfluxExporter::spawn(
+            influx_config.clone(),
+            &controller.board_info()?,
+            poller.samples(),
+        )?),
+        _ => None,
+    };
+    let energy = match (&config.energy, &telemetry) {
+        (Some(energy_config), Some(poller)) => Some(EnergyMonitor::spawn(
+            energy_config.clone(),
+            poller.samples(),
+            &bus,
+        )?),
+        _ => None,
+    };
+    let _hour_meter = match (&config.maintenance, &telemetry) {
+        (Some(maintenance_config), Some(poller)) => {
+            Some(HourMeter::spawn(maintenance_config, poller.samples())?)
+        }
+        _ => None,
+    };
+    let _black_box = match config.black_box {
+        Some(ref black_box_config) => {
+            let recorder = match telemetry {
+                Some(ref poller) => BlackBoxRecorder::follow(black_box_config.clone(), poller)?,
+                None => BlackBoxRecorder::spawn(black_box_config.clone(), build_controller()?)?,
+            };
+            controller.set_black_box(recorder.black_box());
+            Some(recorder)
+        }
+        None => None,
+    };
+    let fault_latch = FaultLatch::from_config(&config.fault_latch);
+    if let Some(ref latch) = fault_latch {
+        controller.set_fault_latch(latch.clone());
+    }
+    let _estop = match config.estop {
+        Some(ref estop_config) => {
+            let estop = EStop::with_controller(estop_config, build_controller()?)?;
+            controller.set_estop(estop.latch());
+            Some(estop)
+        }
+        None => None,
+    };
+    let battery = match config.battery {
+        Some(ref battery_config) => {
+            let supervisor = BatterySupervisor::spawn(
+                battery_config.clone(),
+                build_controller()?,
+                fault_latch.clone(),
+            )?;
+            controller.set_battery_guard(supervisor.guard());
+            Some(supervisor)
+        }
+        None => None,
+    };
+    let _config_watcher = match cli.config {
+        Some(ref path) => {
+            let profile = cli.profile.clone();
+            let power_limit = cli.power_limit;
+            // Settings given on the command line win over the file, as they
+            // did on start.
+            let watcher = ConfigWatcher::spawn(path, &config.reload, move |config| {
+                if profile.is_some() {
+                    config.profile = profile.clone();
+                }
+                if power_limit.is_some() {
+                    config.pipeline.power_limit = power_limit;
+                    for profile in config.profiles.values_mut() {
+                        profile.power_limit = None;
+                    }
+                }
+            })?;
+            controller.set_config_updates(watcher.updates());
+            if let Some(ref supervisor) = battery {
+                supervisor.set_config_updates(watcher.updates());
+            }
+            Some(watcher)
+        }
+        None => None,
+    };
+    let _bumpers = match config.bumpers {
+        Some(ref bumper_config) => {
+            let monitor = BumperMonitor::with_controller(bumper_config, build_controller()?)?;
+            controller.set_bumper_guard(monitor.guard());
+            Some(monitor)
+        }
+        None => None,
+    };
+    let faults = match config.faults {
+        Some(ref fault_config) => {
+            let monitor = FaultMonitor::spawn(
+                fault_config.clone(),
+                build_controller()?,
+                fault_latch.clone(),
+            )?;
+            controller.set_fault_guard(monitor.guard());
+            Some(monitor)
+        }
+        None => None,
+    };
+    let _buzzer = match config.buzzer {
+        Some(ref buzzer_config) => {
+            let buzzer = Buzzer::spawn(
+                buzzer_config,
+                battery.as_ref().map(BatterySupervisor::events),
+                faults.as_ref().map(FaultMonitor::events),
+            )?;
+            if buzzer_config.reverse_beeper {
+                controller.set_buzzer(buzzer.alerts());
+            }
+            Some(buzzer)
+        }
+        None => None,
+    };
+    let _stall = match config.stall {
+        Some(ref stall_config) => {
+            let detector = StallDetector::spawn(
+                stall_config.clone(),
+                StallSensors::new(),
+                build_controller()?,
+            )?;
+            controller.set_stall_guard(detector.guard());
+            Some(detector)
+        }
+        None => None,
+    };
+    let obstacle = match config.obstacle {
+        Some(ref obstacle_config) => {
+            let monitor = ObstacleMonitor::from_config(obstacle_config, &bus)?;
+            if let Some(ref monitor) = monitor {
+                controller.set_obstacle_guard(monitor.guard());
+            }
+            monitor
+        }
+        None => None,
+    };
+    let pan_tilt = match config.pan_tilt {
+        Some(ref pan_tilt_config) => Some(match pan_tilt_config.servos {
+            ServoBoard::UltraBorg => {
+                let servos = ultra_borg::Controller::builder()
+                    .build_with_bus(Box::new(bus.device(ultra_borg::ULTRABORG_SLAVE_ADDR)))?;
+                PanTiltDriver::spawn(pan_tilt_config, servos)?
+            }
+            ServoBoard::Pca9685 => {
+                let pca9685_config = config.pca9685.clone().unwrap_or_default();
+                let servos = Pca9685::new(
+                    Box::new(bus.device(pca9685_config.address)),
+                    &pca9685_config,
+                )?;
+                PanTiltDriver::spawn(pan_tilt_config, servos)?
+            }
+        }),
+        None => None,
+    };
+    let outputs = if config.gpio_outputs.is_empty() {
+        None
+    } else {
+        Some(GpioOutputs::open(&config.gpio_outputs)?)
+    };
+    // `vrum serve` drives from the worker thread of its `SharedController`.
+    #[cfg(feature = "web")]
+    let drives_from_worker = matches!(cli.command, Some(CliCommand::Serve { .. }));
+    #[cfg(not(feature = "web"))]
+    let drives_from_worker = false;
+    // Corrects the powers of the commands sent. A shared controller is given
+    // to the monitor where there is one, see `vrum serve`, so the correction
+    // also carries on between commands.
+    let _gyro = match config.gyro {
+        Some(ref gyro_config) if !drives_from_worker => {
+            let monitor = GyroMonitor::from_config(gyro_config, &bus, None)?;
+            controller.set_gyro_guard(monitor.guard());
+            Some(monitor)
+        }
+        _ => None,
+    };
+
+    if cli.arm {
+        controller.arm()?;
+    }
+
+    #[cfg(feature = "sqlite")]
+    let _run = match config.run_log {
+        Some(ref run_log_config) => Some(RunRecorder::start(
+            run_log_config,
+            &env::args().skip(1).collect::<Vec<_>>().join(" "),
+            controller.commands(),
+            telemetry.as_ref().map(TelemetryPoller::samples),
+        )?),
+        None => None,
+    };
+
+    // After spawning the monitors, which would inherit the scheduling. `vrum
+    // serve` drives from the worker thread instead.
+    if let Some(ref realtime) = config.realtime {
+        if !drives_from_worker {
+            realtime.apply()?;
+        }
+    }
+
+    let _systemd = if cli.systemd {
+        Some(SystemdNotifier::start()?)
+    } else {
+        None
+    };
+
+    // For `vrum rc`, and alongside the dashboard of `vrum serve`.
+    let rc_receiver = |rc_config: &RcConfig, arming: Option<&Arming>| {
+        let mut receiver = RcReceiver::spawn(rc_config)?;
+        if let Some(arming) = arming {
+            receiver.set_arming(arming.clone());
+        }
+        receiver.set_sticks(config.sticks.clone());
+        let mut cruise = CruiseControl::new();
+        if let Some(ref monitor) = faults {
+            cruise = cruise.fault_guard(monitor.guard());
+        }
+        if let Some(ref monitor) = obstacle {
+            cruise = cruise.obstacle_guard(monitor.guard());
+        }
+        receiver.set_cruise(cruise);
+        if let Some(ref gears) = gears {
+            receiver.set_gears(gears.clone());
+        }
+        if let Some(ref driver) = pan_tilt {
+            receiver.set_pan_tilt(driver.pan_tilt());
+        }
+        if let Some(ref outputs) = outputs {
+            receiver.set_outputs(outputs.clone());
+        }
+        Ok::<_, Error>(receiver)
+    };
 
-fn run() -> Result<(), Error> {
-    let mut controller = Controller::new()?;
-    let mut num_iter = 0;
-    while num_iter < 2 {
-        controller.set_motors(0.1)?;
-        thread::sleep(Duration::from_millis(100));
-        info!(
-            "A fault: {} | B fault: {} | Battery voltage: {:.2}V",
-            controller.get_drive_fault_a()?,
-            controller.get_drive_fault_b()?,
-            controller.get_battery_voltage()?
-        );
-
-        controller.set_motors(0.8)?;
-        thread::sleep(Duration::from_millis(1800));
-        controller.set_motors(0.1)?;
-        thread::sleep(Duration::from_millis(100));
-        controller.set_motors(0.0)?;
-
-        thread::sleep(Duration::from_millis(5000));
-
-        controller.set_motors(-0.1)?;
-        thread::sleep(Duration::from_millis(100));
-        controller.set_motors(-0.8)?;
-        thread::sleep(Duration::from_millis(1800));
-        controller.set_motors(-0.1)?;
-        thread::sleep(Duration::from_millis(100));
-        controller.set_motors(0.0)?;
-
-        thread::sleep(Duration::from_millis(3000));
-
-        num_iter += 1;
-        info!(
-            "A fault: {} | B fault: {} | Battery voltage: {:.2}V",
-            controller.get_drive_fault_a()?,
-            controller.get_drive_fault_b()?,
-            controller.get_battery_voltage()?
-        );
-    }
-    controller.stop()?;
+    match cli.command.unwrap_or(CliCommand::Demo { record: None }) {
+        CliCommand::Demo { record } => {
+            if let Some(path) = record {
+                controller.start_recording(path)?;
+            }
+            run_demo(&mut controller, &shutdown)?;
+            controller.stop_recording()
+        }
+        CliCommand::Drive {
+            power,
+            duration,
+            distance,
+            ramp_down,
+            wheel_base,
+            max_wheel_speed,
+        } => {
+            let mut motion = Motion::new(&mut controller, &shutdown)
+                .drive_model(DiffDrive::new(wheel_base, max_wheel_speed))
+                .ramp_down(ramp_down);
+            match (duration, distance) {
+                (Some(duration), _) => motion.drive_timed(power, duration),
+                (None, Some(metres)) => motion.drive_distance(metres, power),
+                (None, None) => unreachable!("clap requires --for or --distance"),
+            }
+        }
+        CliCommand::Bench {
+            target: BenchTarget::I2c { seconds },
+        } => {
+            let results =
+                latency::bench_i2c(&mut controller, Duration::from_secs(seconds), &shutdown)?;
+            output.print_all(&results, "Nothing measured")
+        }
+        CliCommand::Calibrate {
+            target: CalibrateTarget::Battery { measured, samples },
+        } => {
+            let reading = average_battery_voltage(&mut controller, samples)?;
+            let calibration = config.voltage_calibration.corrected(reading, measured);
+            info!(
+                "Battery reads {:.2}V, measured {:.2}V: correction {:.3}V",
+                reading, measured, calibration.correction
+            );
+            match cli.config {
+                Some(path) => Config::store_voltage_calibration(path, calibration),
+                None => {
+                    warn!(
+                        "No --config given, add `correction = {:.3}` to the \
+                         [voltage_calibration] section of your configuration",
+                        calibration.correction
+                    );
+                    Ok(())
+                }
+            }
+        }
+        CliCommand::Led {
+            effect,
+            color,
+            period_ms,
+            empty_voltage,
+            full_voltage,
+            seconds,
+        } => {
+            let period = Duration::from_millis(period_ms);
+            let effect = match effect {
+                LedEffect::Blink => Effect::Blink { color, period },
+                LedEffect::Breathe => Effect::Breathe { color, period },
+                LedEffect::Rainbow => Effect::Rainbow { period },
+                LedEffect::Gauge => Effect::BatteryGauge {
+                    empty_voltage,
+                    full_voltage,
+                },
+            };
+            controller.led_effect(effect)?;
+            shutdown.sleep(Duration::from_secs(seconds))?;
+            controller.stop_led_effect();
+            Ok(())
+        }
+        CliCommand::Faults {
+            action: FaultsAction::Clear,
+        } => match controller.fault_latch().and_then(FaultLatch::cause) {
+            Some(cause) => {
+                info!("Clearing the latched fault: {}", cause);
+                controller.clear_faults()
+            }
+            None => {
+                info!("No fault latched");
+                Ok(())
+            }
+        },
+        CliCommand::Id | CliCommand::Status => {
+            unreachable!("handled before starting the monitors")
+        }
+        #[cfg(feature = "mdns")]
+        CliCommand::Discover { .. } => unreachable!("handled before opening the board"),
+        #[cfg(feature = "tui")]
+        CliCommand::Monitor { .. } => unreachable!("handled before opening the board"),
+        #[cfg(feature = "sqlite")]
+        CliCommand::Runs { .. } => unreachable!("handled before opening the board"),
+        CliCommand::Aux { .. }
+        | CliCommand::InstallService { .. }
+        | CliCommand::Profile { .. }
+        | CliCommand::Stats => {
+            unreachable!("handled before opening the board")
+        }
+        CliCommand::Mission {
+            action:
+                MissionAction::Run {
+                    file,
+                    wheel_base,
+                    max_wheel_speed,
+                    turn_power,
+                },
+        } => {
+            let mission = Mission::load(file)?;
+            let drive = DiffDrive::new(wheel_base, max_wheel_speed);
+            let mut runner =
+                MissionRunner::new(&mut controller, drive, &shutdown).turn_power(turn_power);
+            if let Some(ref monitor) = obstacle {
+                runner = runner.obstacle_guard(monitor.guard());
+            }
+            if let Some(ref outputs) = outputs {
+                runner = runner.outputs(outputs.clone());
+            }
+            if let Some(ref monitor) = energy {
+                runner = runner.energy(monitor.handle());
+            }
+            spawn_mission_console(runner.control())?;
+            runner.run(&mission)
+        }
+        CliCommand::FollowLine { power } => {
+            let mut follower_config = config
+                .line_follower
+                .clone()
+                .ok_or(LineFollowerError::NotConfigured)?;
+            if let Some(power) = power {
+                follower_config.power = power;
+            }
+            let mut follower = LineFollower::from_config(follower_config, &bus)?;
+            follower.follow(&mut controller, &shutdown)
+        }
+        CliCommand::GoTo {
+            latitude,
+            longitude,
+        } => {
+            let gps = GpsReceiver::spawn(config.gps.as_ref().ok_or(WaypointError::NotConfigured)?)?;
+            let compass = heading::open_compass(&config.waypoint.compass, &bus)?;
+            let hold = HeadingHold::new(compass, config.heading_hold);
+            WaypointNavigator::new(&gps, hold, config.waypoint).go_to(
+                &mut controller,
+                Position::new(latitude, longitude),
+                &shutdown,
+            )
+        }
+        #[cfg(feature = "web")]
+        CliCommand::Serve { listen } => {
+            // Without an e-stop button the page's is the only one.
+            let estop = match _estop {
+                Some(ref estop) => estop.latch(),
+                None => {
+                    let latch = EStopLatch::new();
+                    controller.set_estop(latch.clone());
+                    latch
+                }
+            };
+            let rc = match config.rc {
+                Some(ref rc_config) => Some((
+                    rc_config.clone(),
+                    rc_receiver(rc_config, controller.arming())?,
+                )),
+                None => None,
+            };
+            let shared_config = SharedControllerConfig {
+                realtime: config.realtime.clone(),
+                ..SharedControllerConfig::default()
+            };
+            let controller = SharedController::spawn(shared_config, controller)?;
+            let _gyro = match config.gyro {
+                Some(ref gyro_config) => Some(GyroMonitor::from_config(
+                    gyro_config,
+                    &bus,
+                    Some(controller.clone()),
+                )?),
+                None => None,
+            };
+            #[cfg(feature = "mdns")]
+            let _advertiser = Advertiser::spawn(
+                AdvertiseConfig {
+                    port: listen.port(),
+                    scheme: if config.tls.is_some() {
+                        "https"
+                    } else {
+                        "http"
+                    },
+                    ..AdvertiseConfig::default()
+                },
+                controller.clone(),
+            )?;
+            let config = WebConfig {
+                listen,
+                heartbeat: config.heartbeat.transport(Transport::Web),
+                token: config.auth.token.clone(),
+                tls: config.tls.clone(),
+                encoding: config.encoding.web,
+                sticks: config.sticks.clone(),
+                pan_tilt: pan_tilt.as_ref().map(PanTiltDriver::pan_tilt),
+                outputs: outputs.clone(),
+                ..WebConfig::default()
+            };
+            let arbiter = Arbiter::new(controller);
+            // The transmitter of a safety operator overrides the dashboard,
+            // taking control whenever its sticks move.
+            let rc = match rc {
+                Some((rc_config, receiver)) => {
+                    let mut client = ClaimingClient::new(
+                        arbiter.client("RC transmitter", ControlPriority::Operator),
+                        RC_RELEASE_AFTER,
+                    );
+                    let shutdown = shutdown.clone();
+                    Some(
+                        thread::Builder::new()
+                            .name("vrum-rc".into())
+                            .spawn(move || receiver.drive(&rc_config, &mut client, &shutdown))?,
+                    )
+                }
+                None => None,
+            };
+            let served = WebServer::bind(config, arbiter, estop).and_then(|web| web.run(&shutdown));
+            match rc {
+                Some(thread) => {
+                    shutdown.request();
+                    let driven = thread.join().unwrap_or_else(|_| {
+                        error!("RC thread panicked");
+                        Ok(())
+                    });
+                    served.and(driven)
+                }
+                None => served,
+            }
+        }
+        CliCommand::Rc => {
+            let rc_config = config.rc.as_ref().ok_or(RcError::NotConfigured)?;
+            rc_receiver(rc_config, controller.arming())?.drive(
+                rc_config,
+                &mut controller,
+                &shutdown,
+            )
+        }
+        CliCommand::UdpTeleop { listen, timeout_ms } => {
+            let mut heartbeat = config.heartbeat.transport(Transport::Udp);
+            if let Some(timeout_ms) = timeout_ms {
+                heartbeat.timeout = Duration::from_millis(timeout_ms);
+            }
+            let config = UdpConfig {
+                listen,
+                heartbeat,
+                token: config.auth.token.clone(),
+            };
+            UdpReceiver::bind(&mut controller, config)?.run(&shutdown)
+        }
+        CliCommand::Shape {
+            shape,
+            power,
+            wheel_base,
+            max_wheel_speed,
+        } => {
+            let drive = DiffDrive::new(wheel_base, max_wheel_speed);
+            let mut motion = Motion::new(&mut controller, &shutdown)
+                .drive_model(drive)
+                .turn_power(power);
+            match shape {
+                ShapeKind::FigureEight { size } => motion.figure_eight(size, power),
+                ShapeKind::Spin { turns } => motion.spin(360.0 * turns),
+                ShapeKind::Square { side, acceleration } => {
+                    let profile = MotionProfile::new(
+                        motion_profile::Shape::Trapezoidal,
+                        side,
+                        power.abs() * drive.max_wheel_speed,
+                        acceleration,
+                    );
+                    for _ in 0..4 {
+                        motion.drive_profile(&profile)?;
+                        motion.spin(90.0)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+        CliCommand::Replay { file } => {
+            recorder::replay(&mut controller, file, &shutdown)?;
+            controller.stop()
+        }
+        #[cfg(feature = "scripting")]
+        CliCommand::Run {
+            script,
+            wheel_base,
+            max_wheel_speed,
+            turn_power,
+        } => {
+            let config = scripting::ScriptConfig {
+                drive: DiffDrive::new(wheel_base, max_wheel_speed),
+                turn_power,
+            };
+            scripting::run_file(controller, config, script, &shutdown)
+        }
+    }
+}
+
+fn run_demo(controller: &mut Controller, shutdown: &Shutdown) -> Result<(), Error> {
+    let mut motion = Motion::new(controller, shutdown);
+    for _ in 0..2 {
+        motion.drive_for(0.1, Duration::from_millis(100))?;
+        log_status(motion.driver())?;
 
+        motion.drive_for(0.8, Duration::from_millis(1800))?;
+        motion.drive_for(0.1, Duration::from_millis(100))?;
+        motion.pause(Duration::from_millis(5000))?;
+
+        motion.drive_for(-0.1, Duration::from_millis(100))?;
+        motion.drive_for(-0.8, Duration::from_millis(1800))?;
+        motion.drive_for(-0.1, Duration::from_millis(100))?;
+        motion.pause(Duration::from_millis(3000))?;
+
+        log_status(motion.driver())?;
+    }
+    controller.stop()
+}
+
+/// Reads mission commands from stdin. The thread is left blocked on stdin
+/// when the mission ends, it doesn't hold anything up.
+fn spawn_mission_console(control: MissionControl) -> Result<(), Error> {
+    thread::Builder::new()
+        .name("vrum-mission-console".into())
+        .spawn(move || {
+            for line in io::stdin().lock().lines() {
+                match line.as_ref().map(|line| line.trim()) {
+                    Ok("pause") | Ok("p") => control.pause(),
+                    Ok("resume") | Ok("r") => control.resume(),
+                    Ok("abort") | Ok("a") => control.abort(),
+                    Ok("") => {}
+                    Ok(command) => warn!("Unknown mission command {:?}", command),
+                    Err(_) => break,
+                }
+            }
+        })?;
     Ok(())
 }
 
-fn init_env_logger() -> Result<(), Error> {
-    let format = |record: &LogRecord| format!("[{}]: {}", record.level(), record.args());
+fn log_status(controller: &mut Controller) -> Result<(), Error> {
+    info!(
+        "A fault: {} | B fault: {} | Battery voltage: {:.2}V",
+        controller.get_drive_fault_a()?,
+        controller.get_drive_fault_b()?,
+        controller.get_battery_voltage()?
+    );
+    Ok(())
+}
 
-    let mut builder = LogBuilder::new();
-    builder.format(format).filter(None, LogLevelFilter::Info);
+/// How query commands print what they report.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Text,
+    Json,
+    PrettyJson,
+}
 
-    if env::var("RUST_LOG").is_ok() {
-        builder.parse(&env::var("RUST_LOG").unwrap());
+impl Output {
+    fn print<T: Serialize + Display>(self, value: &T) -> Result<(), Error> {
+        match self {
+            Output::Text => println!("{}", value),
+            Output::Json => println!("{}", serde_json::to_string(value)?),
+            Output::PrettyJson => println!("{}", serde_json::to_string_pretty(value)?),
+        }
+        Ok(())
     }
 
-    builder.init()?;
+    /// Prints `values` one per line, or `empty` if there are none, as text,
+    /// and as an array as JSON.
+    fn print_all<T: Serialize + Display>(self, values: &[T], empty: &str) -> Result<(), Error> {
+        match self {
+            Output::Text if values.is_empty() => println!("{}", empty),
+            Output::Text => {
+                for value in values {
+                    println!("{}", value);
+                }
+            }
+            Output::Json => println!("{}", serde_json::to_string(values)?),
+            Output::PrettyJson => println!("{}", serde_json::to_string_pretty(values)?),
+        }
+        Ok(())
+    }
+}
+
+/// Prints what `vrum runs` asks for.
+#[cfg(feature = "sqlite")]
+fn show_runs(log: &RunLog, action: &RunsAction, output: Output) -> Result<(), Error> {
+    match *action {
+        RunsAction::List => output.print_all(&log.runs()?, "No runs stored")?,
+        RunsAction::Show { id } if output != Output::Text => {
+            let run = serde_json::json!({ "run": log.run(id)?, "events": log.events(id)? });
+            output.print(&run)?;
+        }
+        RunsAction::Show { id } => {
+            println!("{}", log.run(id)?);
+            for event in log.events(id)? {
+                println!(
+                    "  {:.3}  {:<5}  {}: {}",
+                    event.at, event.level, event.kind, event.message
+                );
+            }
+        }
+        RunsAction::Export {
+            id,
+            output: ref path,
+        } => {
+            let export = log.export(id)?;
+            match path {
+                Some(path) => fs::write(path, serde_json::to_vec_pretty(&export)?)?,
+                None => println!("{}", serde_json::to_string_pretty(&export)?),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn install_service(
+    cli: &Cli,
+    path: &Path,
+    watchdog: Duration,
+    args: &[String],
+) -> Result<(), Error> {
+    let mut arguments = Vec::new();
+    if let Some(ref config) = cli.config {
+        arguments.push("--config".into());
+        arguments.push(fs::canonicalize(config)?.display().to_string());
+    }
+    if let Some(ref profile) = cli.profile {
+        arguments.push("--profile".into());
+        arguments.push(profile.clone());
+    }
+    if let Some(limit) = cli.power_limit {
+        arguments.push("--power-limit".into());
+        arguments.push(limit.to_string());
+    }
+    arguments.extend(args.iter().cloned());
+    let unit = systemd::unit_file(&env::current_exe()?, &arguments, watchdog);
+    fs::write(path, unit)?;
+    info!(
+        "Wrote {}, enable it with `systemctl enable --now {}`",
+        path.display(),
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
     Ok(())
 }
 
+fn average_battery_voltage(controller: &mut Controller, samples: u32) -> Result<f32, Error> {
+    let samples = samples.max(1);
+    let mut total = 0.0;
+    for _ in 0..samples {
+        total += controller.get_battery_voltage()?;
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(total / samples as f32)
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    value.parse().map_err(|error: ColorError| error.to_string())
+}
+
+fn parse_output_state(value: &str) -> Result<OutputState, String> {
+    value.parse().map_err(|error: Error| error.to_string())
+}
+
+/// A number with a unit, `value` times the factor of the unit, or of
+/// `default_unit` if there is none.
+fn parse_quantity(value: &str, units: &[(&str, f64)], default_unit: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|character: char| character.is_ascii_alphabetic())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let unit = if unit.is_empty() { default_unit } else { unit };
+    let factor = units
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .map(|&(_, factor)| factor)
+        .ok_or_else(|| {
+            let names = units.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            format!(
+                "unknown unit {:?}, expected one of {}",
+                unit,
+                names.join(", ")
+            )
+        })?;
+    match number.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number * factor),
+        _ => Err(format!("{:?} is not a number", number)),
+    }
+}
+
+/// E.g. `2s`, `500ms`, `1.5min`, seconds without a unit.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let seconds = parse_quantity(value, &[("ms", 1e-3), ("s", 1.0), ("min", 60.0)], "s")?;
+    Duration::try_from_secs_f64(seconds).map_err(|error| error.to_string())
+}
+
+/// E.g. `1.5m`, `30cm`, in metres, metres without a unit.
+fn parse_distance(value: &str) -> Result<f32, String> {
+    let metres = parse_quantity(value, &[("mm", 1e-3), ("cm", 1e-2), ("m", 1.0)], "m")?;
+    Ok(metres as f32)
+}
+
+/// Logs at info level, or as set by `RUST_LOG` e.g. `RUST_LOG=vrum::borg=debug`
+/// to trace every I2C transaction.
+fn init_logging(cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    #[cfg(feature = "journald")]
+    {
+        if cli.journald {
+            match tracing_journald::layer() {
+                // Fields are sent as they are, e.g. `message_id` as
+                // `MESSAGE_ID` and `battery_voltage` as `BATTERY_VOLTAGE`.
+                Ok(journald) => {
+                    let journald = journald
+                        .with_field_prefix(None)
+                        .with_syslog_identifier("vrum".into());
+                    return Ok(tracing_subscriber::registry()
+                        .with(filter)
+                        .with(journald)
+                        .with(EventHistory::global())
+                        .try_init()?);
+                }
+                Err(error) => eprintln!("journald unavailable, logging to stderr: {}", error),
+            }
+        }
+    }
+    // The dashboard takes over the terminal and shows the log itself.
+    #[cfg(feature = "tui")]
+    {
+        if let Some(CliCommand::Monitor { .. }) = cli.command {
+            return tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .with_target(false)
+                .with_ansi(false)
+                .with_writer(LogBuffer::global())
+                .finish()
+                .with(EventHistory::global())
+                .try_init()
+                .map_err(Into::into);
+        }
+    }
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_writer(io::stderr);
+    // Notable events are kept for `Controller::events()` as well.
+    let history = EventHistory::global();
+    if cli.log_json {
+        Ok(subscriber.json().finish().with(history).try_init()?)
+    } else {
+        Ok(subscriber.finish().with(history).try_init()?)
+    }
+}
+
+/// How long the RC sticks rest before `vrum serve` hands control back to the
+/// dashboard.
+#[cfg(feature = "web")]
+const RC_RELEASE_AFTER: Duration = Duration::from_secs(2);
+
+/// How often the simulated robot follows the simulated board.
+#[cfg(feature = "sim")]
+const SIM_PERIOD: Duration = Duration::from_millis(10);
+
 fn exit_with_error(error: &Error) -> ! {
-    error!("Fatal error: {} {}", error.cause(), error.backtrace());
+    error!("Fatal error: {}", error);
+    black_box::dump(&format!("fatal error: {}", error));
     process::exit(1);
 }
 
 fn main() {
-    if let Err(error) = init_env_logger() {
-        println!(
-            "Could not initialize logger, exiting: {} {}",
-            error.cause(),
-            error.backtrace()
-        );
+    let cli = Cli::parse();
+    if let Err(error) = init_logging(&cli) {
+        println!("Could not initialize logger, exiting: {}", error);
         process::exit(1);
     }
-    if let Err(ref error) = run() {
-        exit_with_error(error);
+    black_box::dump_on_panic();
+    match run(cli) {
+        Ok(()) => {}
+        // Everything was dropped on the way out, stopping the motors.
+        Err(Error::Shutdown(ShutdownError::Requested)) => {
+            info!("Shut down cleanly");
+        }
+        Err(ref error) => exit_with_error(error),
     }
 }
@@ -0,0 +1,305 @@
+//! Hour meter and maintenance counters, kept across restarts for knowing
+//! when the brushes of the motors are due, configured in the
+//! `[maintenance]` section of the configuration file:
+//!
+//! ```toml
+//! [maintenance]
+//! state_file = "/var/lib/vrum/maintenance.json"
+//! max_wheel_speed = 1.0
+//! ```
+//!
+//! A `HourMeter` follows the telemetry samples, adding up how long each
+//! motor was driven, the distance travelled and the drive faults raised, on
+//! top of the totals in `state_file` from the runs before. The distance
+//! comes from the estimated pose when the samples have one and is otherwise
+//! reckoned from the motor powers, at `max_wheel_speed` for full power.
+//! `vrum stats` shows the totals.
+
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::telemetry::{self, TelemetrySample};
+
+/// The `[maintenance]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    /// Totals over every run, read by `vrum stats`.
+    pub state_file: PathBuf,
+    /// Wheel speed at full power, in metres per second, for reckoning the
+    /// distance without a pose estimate.
+    pub max_wheel_speed: f32,
+    /// How often the `state_file` is written.
+    pub write_interval_ms: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            state_file: PathBuf::from("/var/lib/vrum/maintenance.json"),
+            max_wheel_speed: 1.0,
+            write_interval_ms: 10_000,
+        }
+    }
+}
+
+/// Totals over every run, as shown by `vrum stats`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceStats {
+    /// Seconds since the UNIX epoch.
+    pub since: f64,
+    pub updated: f64,
+    pub runs: u64,
+    /// Time each motor was driven, in seconds.
+    pub motor_a_seconds: f64,
+    pub motor_b_seconds: f64,
+    /// In metres.
+    pub distance: f64,
+    pub drive_faults_a: u64,
+    pub drive_faults_b: u64,
+}
+
+impl MaintenanceStats {
+    pub fn motor_a_hours(&self) -> f64 {
+        self.motor_a_seconds / 3600.0
+    }
+
+    pub fn motor_b_hours(&self) -> f64 {
+        self.motor_b_seconds / 3600.0
+    }
+}
+
+impl Display for MaintenanceStats {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            formatter,
+            "Motor A driven {:.1}h, motor B {:.1}h",
+            self.motor_a_hours(),
+            self.motor_b_hours()
+        )?;
+        writeln!(formatter, "Travelled {:.2}km", self.distance / 1000.0)?;
+        writeln!(
+            formatter,
+            "Drive faults: {} on motor A, {} on motor B",
+            self.drive_faults_a, self.drive_faults_b
+        )?;
+        write!(
+            formatter,
+            "Over {} run{}",
+            self.runs,
+            if self.runs == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// Adds telemetry samples to `MaintenanceStats`.
+#[derive(Clone, Debug)]
+pub struct MaintenanceCounter {
+    stats: MaintenanceStats,
+    max_wheel_speed: f32,
+    last: Option<TelemetrySample>,
+}
+
+impl MaintenanceCounter {
+    /// Counts a new run on top of `stats`.
+    pub fn new(mut stats: MaintenanceStats, max_wheel_speed: f32) -> Self {
+        let now = telemetry::unix_timestamp();
+        if stats.runs == 0 {
+            stats.since = now;
+        }
+        stats.runs += 1;
+        stats.updated = now;
+        MaintenanceCounter {
+            stats,
+            max_wheel_speed,
+            last: None,
+        }
+    }
+
+    pub fn update(&mut self, sample: TelemetrySample) {
+        if let Some(ref last) = self.last {
+            let elapsed = sample.timestamp - last.timestamp;
+            // A gap in the samples says nothing of what the motors did.
+            if elapsed > 0.0 && elapsed <= MAX_SAMPLE_GAP {
+                if last.motor_a_power.abs() > MOTOR_ON_THRESHOLD {
+                    self.stats.motor_a_seconds += elapsed;
+                }
+                if last.motor_b_power.abs() > MOTOR_ON_THRESHOLD {
+                    self.stats.motor_b_seconds += elapsed;
+                }
+                self.stats.distance += self.distance(last, &sample, elapsed);
+            }
+            self.stats.drive_faults_a += u64::from(sample.drive_fault_a && !last.drive_fault_a);
+            self.stats.drive_faults_b += u64::from(sample.drive_fault_b && !last.drive_fault_b);
+        } else {
+            self.stats.drive_faults_a += u64::from(sample.drive_fault_a);
+            self.stats.drive_faults_b += u64::from(sample.drive_fault_b);
+        }
+        self.stats.updated = sample.timestamp;
+        self.last = Some(sample);
+    }
+
+    pub fn stats(&self) -> &MaintenanceStats {
+        &self.stats
+    }
+
+    /// Between two samples, from the pose if both have one, otherwise from
+    /// the powers of the earlier one.
+    fn distance(&self, last: &TelemetrySample, sample: &TelemetrySample, elapsed: f64) -> f64 {
+        match (last.pose_x, last.pose_y, sample.pose_x, sample.pose_y) {
+            (Some(x0), Some(y0), Some(x1), Some(y1)) => {
+                let step = f64::from((x1 - x0).hypot(y1 - y0));
+                // A jump this large is the pose being reset, not travel.
+                if step <= MAX_POSE_STEP {
+                    step
+                } else {
+                    0.0
+                }
+            }
+            _ => {
+                let power = (last.motor_a_power + last.motor_b_power) / 2.0;
+                f64::from(power.abs() * self.max_wheel_speed) * elapsed
+            }
+        }
+    }
+}
+
+/// Adds up the maintenance counters from telemetry `samples` on a background
+/// thread, writing them to the `state_file` as it goes and when dropped.
+pub struct HourMeter {
+    counter: Arc<Mutex<MaintenanceCounter>>,
+    state_file: PathBuf,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HourMeter {
+    /// Carries on from the totals in the `state_file` of `config`, if any.
+    pub fn spawn(
+        config: &MaintenanceConfig,
+        samples: Receiver<TelemetrySample>,
+    ) -> Result<Self, Error> {
+        let stats = read_stats(&config.state_file)?.unwrap_or_default();
+        info!(
+            "Motors driven {:.1}h and {:.1}h so far, over {} runs",
+            stats.motor_a_hours(),
+            stats.motor_b_hours(),
+            stats.runs
+        );
+        let counter = Arc::new(Mutex::new(MaintenanceCounter::new(
+            stats,
+            config.max_wheel_speed,
+        )));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_counter = counter.clone();
+        let thread_running = running.clone();
+        let state_file = config.state_file.clone();
+        let write_interval = Duration::from_millis(config.write_interval_ms);
+        let thread = thread::Builder::new()
+            .name("vrum-hour-meter".into())
+            .spawn(move || {
+                let mut written: Option<Instant> = None;
+                let mut failing = false;
+                while thread_running.load(Ordering::SeqCst) {
+                    let sample = match samples.recv_timeout(RECV_TIMEOUT) {
+                        Ok(sample) => sample,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    let mut counter = lock(&thread_counter);
+                    counter.update(sample);
+                    let stats = counter.stats().clone();
+                    drop(counter);
+                    if written.is_none_or(|at| at.elapsed() >= write_interval) {
+                        written = Some(Instant::now());
+                        match write_stats(&state_file, &stats) {
+                            Ok(()) => failing = false,
+                            Err(error) if !failing => {
+                                warn!(
+                                    "Could not write the maintenance counters to {}: {}",
+                                    state_file.display(),
+                                    error
+                                );
+                                failing = true;
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                }
+            })?;
+
+        Ok(HourMeter {
+            counter,
+            state_file: config.state_file.clone(),
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn stats(&self) -> MaintenanceStats {
+        lock(&self.counter).stats().clone()
+    }
+}
+
+impl Drop for HourMeter {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Hour meter thread panicked");
+            }
+        }
+        if let Err(error) = write_stats(&self.state_file, &self.stats()) {
+            error!("Could not store the maintenance counters: {}", error);
+        }
+    }
+}
+
+fn lock(counter: &Mutex<MaintenanceCounter>) -> MutexGuard<'_, MaintenanceCounter> {
+    // Updated field by field from plain values, a poisoned counter is still
+    // usable.
+    counter.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// The totals in the `state_file` of a `MaintenanceConfig`, `None` if no run
+/// wrote them yet.
+pub fn read_stats<P: AsRef<Path>>(path: P) -> Result<Option<MaintenanceStats>, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Replaces the file at `path`, whole, so a crash never leaves half of it
+/// and loses the totals.
+fn write_stats(path: &Path, stats: &MaintenanceStats) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, serde_json::to_vec(stats)?)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Motor powers above this count as driven.
+const MOTOR_ON_THRESHOLD: f32 = 0.01;
+/// Longest time between samples counted, in seconds.
+const MAX_SAMPLE_GAP: f64 = 5.0;
+/// Longest move of the pose between samples counted, in metres.
+const MAX_POSE_STEP: f64 = 5.0;
+/// How long the thread waits for a sample before checking whether it should
+/// stop.
+const RECV_TIMEOUT: Duration = Duration::from_millis(200);
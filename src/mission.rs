@@ -0,0 +1,506 @@
+//! Autonomous routines written as a list of steps in YAML or JSON, run with
+//! `vrum mission run mission.yaml`:
+//!
+//! ```yaml
+//! name: patrol
+//! speed: 0.3
+//! steps:
+//!   - led: green
+//!   - aux: {headlights: on}
+//!   - go_to: {x: 1.0, y: 0.0}
+//!   - go_to: {x: 1.0, y: 1.0, speed: 0.2}
+//!   - turn: 90
+//!   - dwell: 2.5
+//!   - wait_until: {battery_above: 11.0, timeout: 60}
+//!   - path: [{x: 0.0, y: 1.0}, {x: 0.0, y: 0.0}]
+//!   - stop
+//! ```
+//!
+//! Positions are in metres from where the robot starts, facing along the x
+//! axis, and are dead reckoned from the commanded velocities: the robot
+//! drives open loop.
+//!
+//! Steps:
+//!
+//! * `go_to: {x, y, speed}`: drive to a point along pure pursuit arcs,
+//!   `speed` in m/s defaulting to the mission `speed`
+//! * `path: [{x, y}, ...]`: drive through the points in order
+//! * `turn: degrees`: turn in place, counter-clockwise if positive
+//! * `dwell: seconds`: wait with the motors stopped
+//! * `led: color`: set the LED to a name, `#rrggbb` or `red,green,blue`
+//! * `aux: {output: state}`: switch outputs of `[gpio_outputs]` `on`, `off`,
+//!   `toggle` or dim them to a level in `[0, 1]`
+//! * `wait_until: {condition, timeout}`: wait with the motors stopped until
+//!   `battery_above: volts`, `no_drive_fault: true` or `path_clear: true`
+//!   (needs an obstacle sensor). The mission fails if `timeout` seconds pass
+//!   first, it waits forever without one
+//! * `stop`: stop the motors
+//!
+//! With an `[energy]` section the energy used by each step is reported by
+//! `vrum status`, see `vrum::energy`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::color::Color;
+use crate::energy::EnergyHandle;
+use crate::error::Error;
+use crate::gpio_outputs::{AuxError, GpioOutputs, OutputState};
+use crate::kinematics::{DiffDrive, Pose};
+use crate::motor_driver::MotorDriver;
+use crate::obstacle::ObstacleGuard;
+use crate::pure_pursuit::{PurePursuit, PurePursuitConfig, Waypoint};
+use crate::shutdown::Shutdown;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MissionError {
+    #[error("mission files are .yaml, .yml or .json, not {path}")]
+    UnknownFormat { path: String },
+    #[error("invalid mission: {reason}")]
+    Invalid { reason: String },
+    #[error("mission aborted at step {step}")]
+    Aborted { step: usize },
+    #[error("step {step} timed out waiting for {condition}")]
+    Timeout { step: usize, condition: String },
+    #[error("step {step} waits for a clear path, but there is no obstacle sensor")]
+    NoObstacleSensor { step: usize },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mission {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Default driving speed in m/s.
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    /// Distance ahead of the robot it steers towards on paths, in metres.
+    #[serde(default = "default_lookahead")]
+    pub lookahead: f32,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    GoTo {
+        x: f32,
+        y: f32,
+        #[serde(default)]
+        speed: Option<f32>,
+    },
+    Path(Vec<Waypoint>),
+    Turn(f32),
+    Dwell(f32),
+    Led(String),
+    Aux(BTreeMap<String, OutputState>),
+    WaitUntil {
+        #[serde(flatten)]
+        condition: Condition,
+        #[serde(default)]
+        timeout: Option<f32>,
+    },
+    Stop,
+}
+
+impl Step {
+    /// The name of the step in mission files, e.g. `go_to`.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Step::GoTo { .. } => "go_to",
+            Step::Path(_) => "path",
+            Step::Turn(_) => "turn",
+            Step::Dwell(_) => "dwell",
+            Step::Led(_) => "led",
+            Step::Aux(_) => "aux",
+            Step::WaitUntil { .. } => "wait_until",
+            Step::Stop => "stop",
+        }
+    }
+}
+
+/// What a `wait_until` step waits for.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    BatteryAbove(f32),
+    NoDriveFault(bool),
+    PathClear(bool),
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::BatteryAbove(volts) => write!(formatter, "battery above {}V", volts),
+            Condition::NoDriveFault(true) => write!(formatter, "no drive fault"),
+            Condition::NoDriveFault(false) => write!(formatter, "a drive fault"),
+            Condition::PathClear(true) => write!(formatter, "a clear path"),
+            Condition::PathClear(false) => write!(formatter, "an obstacle"),
+        }
+    }
+}
+
+impl Mission {
+    /// Loads a mission, YAML or JSON depending on the extension of `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mission: Mission = match path.extension().and_then(|extension| extension.to_str()) {
+            // Steps are single key maps as in JSON, rather than the YAML
+            // tags serde_yaml expects of enums by default.
+            Some("yaml") | Some("yml") => serde_yaml::with::singleton_map_recursive::deserialize(
+                serde_yaml::Deserializer::from_str(&text),
+            )?,
+            Some("json") => serde_json::from_str(&text)?,
+            _ => {
+                return Err(MissionError::UnknownFormat {
+                    path: path.display().to_string(),
+                }
+                .into())
+            }
+        };
+        mission.validate()?;
+        Ok(mission)
+    }
+
+    /// Checks the LED colours and speeds, so a mistake fails the mission
+    /// before the robot moves rather than halfway through.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: String| Err(MissionError::Invalid { reason }.into());
+        if not_positive(self.speed) {
+            return invalid(format!("speed {} is not positive", self.speed));
+        }
+        if not_positive(self.lookahead) {
+            return invalid(format!("lookahead {} is not positive", self.lookahead));
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            match *step {
+                Step::Led(ref color) => {
+                    color.parse::<Color>()?;
+                }
+                Step::GoTo {
+                    speed: Some(speed), ..
+                } if not_positive(speed) => {
+                    return invalid(format!(
+                        "step {}: speed {} is not positive",
+                        index + 1,
+                        speed
+                    ));
+                }
+                Step::Dwell(seconds) if seconds.is_nan() || seconds < 0.0 => {
+                    return invalid(format!("step {}: cannot dwell {}s", index + 1, seconds));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pauses, resumes or aborts a running mission from another thread.
+#[derive(Clone, Default)]
+pub struct MissionControl {
+    state: Arc<AtomicU8>,
+}
+
+impl MissionControl {
+    pub fn new() -> Self {
+        MissionControl::default()
+    }
+
+    /// Stops the motors and holds the mission where it is until resumed.
+    pub fn pause(&self) {
+        let _ = self
+            .state
+            .compare_exchange(RUNNING, PAUSED, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        let _ = self
+            .state
+            .compare_exchange(PAUSED, RUNNING, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    /// Ends the mission with `MissionError::Aborted`, stopping the motors.
+    pub fn abort(&self) {
+        self.state.store(ABORTED, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == PAUSED
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == ABORTED
+    }
+}
+
+/// Runs missions on a robot.
+pub struct MissionRunner<'a, D: MotorDriver + ?Sized> {
+    driver: &'a mut D,
+    drive: DiffDrive,
+    turn_power: f32,
+    control: MissionControl,
+    shutdown: Shutdown,
+    obstacle: Option<ObstacleGuard>,
+    outputs: Option<GpioOutputs>,
+    energy: Option<EnergyHandle>,
+    pose: Pose,
+    step: usize,
+}
+
+impl<'a, D: MotorDriver + ?Sized> MissionRunner<'a, D> {
+    pub fn new(driver: &'a mut D, drive: DiffDrive, shutdown: &Shutdown) -> Self {
+        MissionRunner {
+            driver,
+            drive,
+            turn_power: DEFAULT_TURN_POWER,
+            control: MissionControl::new(),
+            shutdown: shutdown.clone(),
+            obstacle: None,
+            outputs: None,
+            energy: None,
+            pose: Pose::default(),
+            step: 0,
+        }
+    }
+
+    /// Motor power of `turn` steps.
+    pub fn turn_power(mut self, power: f32) -> Self {
+        self.turn_power = power.abs().min(1.0);
+        self
+    }
+
+    /// Sensor checked by `wait_until: {path_clear: true}`.
+    pub fn obstacle_guard(mut self, guard: ObstacleGuard) -> Self {
+        self.obstacle = Some(guard);
+        self
+    }
+
+    /// Outputs switched by `aux` steps.
+    pub fn outputs(mut self, outputs: GpioOutputs) -> Self {
+        self.outputs = Some(outputs);
+        self
+    }
+
+    /// Accounts the energy used by each step as a segment of the run.
+    pub fn energy(mut self, energy: EnergyHandle) -> Self {
+        self.energy = Some(energy);
+        self
+    }
+
+    pub fn control(&self) -> MissionControl {
+        self.control.clone()
+    }
+
+    /// Where the robot should be, dead reckoned from the start.
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Runs every step of `mission` in order, stopping the motors at the
+    /// end, or on the way out if interrupted.
+    pub fn run(&mut self, mission: &Mission) -> Result<(), Error> {
+        info!(
+            "Starting mission {} ({} steps)",
+            mission.name.as_deref().unwrap_or("(unnamed)"),
+            mission.steps.len()
+        );
+        let result = self.run_steps(mission);
+        if let Some(ref energy) = self.energy {
+            energy.end_segment();
+        }
+        if let Err(error) = self.driver.set_sides(0.0, 0.0) {
+            error!(
+                "Could not stop the motors at the end of the mission: {}",
+                error
+            );
+        }
+        if result.is_ok() {
+            info!("Mission complete");
+        }
+        result
+    }
+
+    fn run_steps(&mut self, mission: &Mission) -> Result<(), Error> {
+        for (index, step) in mission.steps.iter().enumerate() {
+            self.step = index + 1;
+            debug!(step = self.step, ?step, "Mission step");
+            if let Some(ref energy) = self.energy {
+                energy.begin_segment(&format!(
+                    "{} step {}: {}",
+                    mission.name.as_deref().unwrap_or("mission"),
+                    self.step,
+                    step.kind()
+                ));
+            }
+            match *step {
+                Step::GoTo { x, y, speed } => {
+                    let start = Waypoint::new(self.pose.x, self.pose.y);
+                    let speed = speed.unwrap_or(mission.speed);
+                    self.follow(vec![start, Waypoint::new(x, y)], speed, mission)?;
+                }
+                Step::Path(ref waypoints) => {
+                    let mut path = vec![Waypoint::new(self.pose.x, self.pose.y)];
+                    path.extend(waypoints.iter().cloned());
+                    self.follow(path, mission.speed, mission)?;
+                }
+                Step::Turn(degrees) => self.turn(degrees)?,
+                Step::Dwell(seconds) => {
+                    self.driver.set_sides(0.0, 0.0)?;
+                    let duration = Duration::from_secs_f32(seconds.max(0.0));
+                    let mut waited = Duration::default();
+                    while waited < duration {
+                        waited += self.tick((duration - waited).min(UPDATE_PERIOD))?;
+                    }
+                }
+                Step::Led(ref color) => self.driver.set_led_color(color.parse()?)?,
+                Step::Aux(ref states) => {
+                    for (name, &state) in states {
+                        match self.outputs {
+                            Some(ref outputs) => outputs.set(name, state)?,
+                            None => {
+                                return Err(AuxError::UnknownOutput { name: name.clone() }.into())
+                            }
+                        };
+                    }
+                }
+                Step::WaitUntil {
+                    ref condition,
+                    timeout,
+                } => self.wait_until(condition, timeout)?,
+                Step::Stop => self.driver.set_sides(0.0, 0.0)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn follow(&mut self, path: Vec<Waypoint>, speed: f32, mission: &Mission) -> Result<(), Error> {
+        let config = PurePursuitConfig {
+            lookahead: mission.lookahead,
+            speed,
+            ..PurePursuitConfig::default()
+        };
+        let mut follower = PurePursuit::new(path, config);
+        while let Some((linear, angular)) = follower.update(&self.pose) {
+            let (left, right) = self.drive.motor_powers(linear, angular);
+            self.driver.set_sides(left, right)?;
+            let elapsed = self.tick(UPDATE_PERIOD)?;
+            let (linear, angular) = self.drive.body_velocity(
+                left * self.drive.max_wheel_speed,
+                right * self.drive.max_wheel_speed,
+            );
+            self.pose = self.pose.advance(linear, angular, elapsed);
+        }
+        self.driver.set_sides(0.0, 0.0)
+    }
+
+    fn turn(&mut self, degrees: f32) -> Result<(), Error> {
+        let power = self.turn_power.copysign(degrees);
+        let wheel_speed = power * self.drive.max_wheel_speed;
+        let (_, angular) = self.drive.body_velocity(-wheel_speed, wheel_speed);
+        if degrees == 0.0 || angular == 0.0 {
+            return Ok(());
+        }
+        let duration = Duration::from_secs_f32(degrees.to_radians() / angular);
+        let mut turned = Duration::default();
+        while turned < duration {
+            self.driver.set_sides(-power, power)?;
+            let elapsed = self.tick((duration - turned).min(UPDATE_PERIOD))?;
+            self.pose = self.pose.advance(0.0, angular, elapsed);
+            turned += elapsed;
+        }
+        self.driver.set_sides(0.0, 0.0)
+    }
+
+    fn wait_until(&mut self, condition: &Condition, timeout: Option<f32>) -> Result<(), Error> {
+        self.driver.set_sides(0.0, 0.0)?;
+        let timeout = timeout.map(|seconds| Duration::from_secs_f32(seconds.max(0.0)));
+        let mut waited = Duration::default();
+        while !self.is_met(condition)? {
+            if timeout.is_some_and(|timeout| waited >= timeout) {
+                return Err(MissionError::Timeout {
+                    step: self.step,
+                    condition: condition.to_string(),
+                }
+                .into());
+            }
+            waited += self.tick(UPDATE_PERIOD)?;
+        }
+        Ok(())
+    }
+
+    fn is_met(&mut self, condition: &Condition) -> Result<bool, Error> {
+        Ok(match *condition {
+            Condition::BatteryAbove(volts) => self.driver.battery_voltage()? > volts,
+            Condition::NoDriveFault(wanted) => {
+                let mut faulty = false;
+                for index in 0..self.driver.num_motors() {
+                    faulty |= self.driver.fault(index)?;
+                }
+                faulty != wanted
+            }
+            Condition::PathClear(wanted) => match self.obstacle {
+                Some(ref guard) => (guard.forward_scale() >= 1.0) == wanted,
+                None => return Err(MissionError::NoObstacleSensor { step: self.step }.into()),
+            },
+        })
+    }
+
+    /// Sleeps for up to `period`, returning for how long the mission ran.
+    /// While paused the motors are stopped and no time passes for the
+    /// mission, the caller sends its motor command again afterwards.
+    fn tick(&mut self, period: Duration) -> Result<Duration, Error> {
+        self.check()?;
+        if !self.control.is_paused() {
+            let started = Instant::now();
+            self.shutdown.sleep(period)?;
+            return Ok(started.elapsed());
+        }
+        info!("Mission paused at step {}", self.step);
+        self.driver.set_sides(0.0, 0.0)?;
+        while self.control.is_paused() {
+            self.check()?;
+            thread::sleep(UPDATE_PERIOD);
+        }
+        self.check()?;
+        info!("Mission resumed");
+        Ok(Duration::default())
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        self.shutdown.check()?;
+        if self.control.is_aborted() {
+            return Err(MissionError::Aborted { step: self.step }.into());
+        }
+        Ok(())
+    }
+}
+
+/// True for NaN too.
+fn not_positive(value: f32) -> bool {
+    value.is_nan() || value <= 0.0
+}
+
+fn default_speed() -> f32 {
+    PurePursuitConfig::default().speed
+}
+
+fn default_lookahead() -> f32 {
+    PurePursuitConfig::default().lookahead
+}
+
+const RUNNING: u8 = 0;
+const PAUSED: u8 = 1;
+const ABORTED: u8 = 2;
+
+const DEFAULT_TURN_POWER: f32 = 0.5;
+
+/// How often the motor powers are updated and the controls checked.
+const UPDATE_PERIOD: Duration = Duration::from_millis(20);
@@ -0,0 +1,406 @@
+//! Reusable motion primitives on any `MotorDriver`, cut short by a shutdown
+//! request:
+//!
+//! ```
+//! # use std::time::{Duration, Instant};
+//! # use vrum::kinematics::DiffDrive;
+//! # use vrum::motion::Motion;
+//! # use vrum::shutdown::Shutdown;
+//! # use vrum::simulator::SimulatedBoard;
+//! # use vrum::thunder_borg::ControllerBuilder;
+//! # let board = SimulatedBoard::new();
+//! # let mut controller = ControllerBuilder::new().build_with_bus(board.bus())?;
+//! let shutdown = Shutdown::new();
+//! let mut motion = Motion::new(&mut controller, &shutdown)
+//!     .drive_model(DiffDrive::new(0.15, 20.0))
+//!     .turn_power(1.0);
+//! motion.drive_for(0.5, Duration::from_millis(10))?;
+//! motion.spin(90.0)?;
+//! motion.square(0.1, 1.0)?;
+//! motion.figure_eight(0.1, 1.0)?;
+//! assert_eq!(board.state().motor_a, 0.0);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+//!
+//! Timed primitives (`drive_for()`, `turn_in_place()`) leave the motors
+//! running when done, so consecutive ones chain without stopping in between.
+//! Those with a goal (`spin()`, `arc()`, `drive_timed()`, `drive_distance()`,
+//! `drive_profile()`, `follow_path()`, `follow_path_estimated()`, `square()`,
+//! `figure_eight()`) stop the motors once reached, `drive_timed()` and
+//! `drive_distance()` slowing down over the `ramp_down()` first. An
+//! interrupted primitive stops the motors before returning
+//! `ShutdownError::Requested`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{self, Clock};
+use crate::error::Error;
+use crate::estimation::{PoseEstimate, PoseEstimator, PoseSensors};
+use crate::kinematics::{DiffDrive, Pose};
+use crate::motion_profile::MotionProfile;
+use crate::motor_driver::MotorDriver;
+use crate::pure_pursuit::PurePursuit;
+use crate::shutdown::Shutdown;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MotionError {
+    #[error("{primitive} needs the drive model of the robot")]
+    NoDriveModel { primitive: &'static str },
+    #[error("measuring distances needs the wheel encoders")]
+    NoWheels,
+}
+
+type Reader<T> = Box<dyn FnMut() -> Result<T, Error> + Send>;
+
+pub struct Motion<'a, D: MotorDriver + ?Sized> {
+    driver: &'a mut D,
+    shutdown: Shutdown,
+    drive: Option<DiffDrive>,
+    turn_power: f32,
+    ramp_down: Duration,
+    wheels: Option<Reader<(f32, f32)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<'a, D: MotorDriver + ?Sized> Motion<'a, D> {
+    pub fn new(driver: &'a mut D, shutdown: &Shutdown) -> Self {
+        Motion {
+            driver,
+            shutdown: shutdown.clone(),
+            drive: None,
+            turn_power: DEFAULT_TURN_POWER,
+            ramp_down: Duration::ZERO,
+            wheels: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Kinematics of the robot, needed to turn by an angle or drive a
+    /// distance open loop.
+    pub fn drive_model(mut self, drive: DiffDrive) -> Self {
+        self.drive = Some(drive);
+        self
+    }
+
+    /// Motor power `spin()` turns at.
+    pub fn turn_power(mut self, power: f32) -> Self {
+        self.turn_power = power.abs().min(1.0);
+        self
+    }
+
+    /// How long `drive_timed()` and `drive_distance()` take to slow down to
+    /// a stop, linearly, so the robot doesn't jerk or skid. They stop
+    /// abruptly by default.
+    pub fn ramp_down(mut self, duration: Duration) -> Self {
+        self.ramp_down = duration;
+        self
+    }
+
+    /// `read` returns the total distance travelled by the left and right
+    /// wheels, in metres, e.g. from encoder counts, for `drive_distance()` to
+    /// measure the distance rather than predict it from the drive model.
+    pub fn wheels<F>(mut self, read: F) -> Self
+    where
+        F: FnMut() -> Result<(f32, f32), Error> + Send + 'static,
+    {
+        self.wheels = Some(Box::new(read));
+        self
+    }
+
+    /// Clock the primitives time and sleep on, the wall clock by default.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The driver, e.g. to read the battery between primitives.
+    pub fn driver(&mut self) -> &mut D {
+        self.driver
+    }
+
+    /// Drives straight at `power`, backwards if negative, for `duration`.
+    pub fn drive_for(&mut self, power: f32, duration: Duration) -> Result<(), Error> {
+        self.driver.set_sides(power, power)?;
+        self.hold(duration)
+    }
+
+    /// Drives straight at `power` for `duration`, the end of which slowing
+    /// down over the `ramp_down()`, then stops.
+    pub fn drive_timed(&mut self, power: f32, duration: Duration) -> Result<(), Error> {
+        let cruise = duration.saturating_sub(self.ramp_down);
+        self.drive_for(power, cruise)?;
+        self.slow_down(power, duration - cruise, |_| Ok(false))
+    }
+
+    /// Turns in place for `duration`, counter-clockwise for a positive
+    /// `power`.
+    pub fn turn_in_place(&mut self, power: f32, duration: Duration) -> Result<(), Error> {
+        self.driver.set_sides(-power, power)?;
+        self.hold(duration)
+    }
+
+    /// Stops the motors for `duration`.
+    pub fn pause(&mut self, duration: Duration) -> Result<(), Error> {
+        self.stop()?;
+        self.hold(duration)
+    }
+
+    /// Sets both sides to zero power.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.driver.set_sides(0.0, 0.0)
+    }
+
+    /// Turns in place by `degrees`, counter-clockwise if positive, for the
+    /// time the drive model predicts the turn takes at `turn_power`.
+    pub fn spin(&mut self, degrees: f32) -> Result<(), Error> {
+        let drive = self.require_drive("spin")?;
+        let wheel_speed = self.turn_power * drive.max_wheel_speed;
+        let (_, angular) = drive.body_velocity(-wheel_speed, wheel_speed);
+        if degrees == 0.0 || angular <= 0.0 {
+            return Ok(());
+        }
+        let duration = Duration::from_secs_f32(degrees.abs().to_radians() / angular);
+        self.turn_in_place(self.turn_power.copysign(degrees), duration)?;
+        self.stop()
+    }
+
+    /// Drives forwards along an arc of `radius` metres for `degrees`,
+    /// turning left if positive, the outer wheel at `power`, for the time the
+    /// drive model predicts it takes.
+    pub fn arc(&mut self, radius: f32, degrees: f32, power: f32) -> Result<(), Error> {
+        self.drive_arc(radius, degrees, power)?;
+        self.stop()
+    }
+
+    /// Drives `metres` straight at `power`, backwards if either is negative,
+    /// until the `wheels()` have travelled it or, without them, for the time
+    /// the drive model predicts it takes.
+    pub fn drive_distance(&mut self, metres: f32, power: f32) -> Result<(), Error> {
+        let power = power.abs().min(1.0) * metres.signum() * power.signum();
+        if metres == 0.0 || power == 0.0 {
+            return Ok(());
+        }
+        if self.wheels.is_some() {
+            return self.drive_measured(metres.abs(), power);
+        }
+        let drive = self.require_drive("drive_distance")?;
+        let speed = power.abs() * drive.max_wheel_speed;
+        // Slowing down linearly covers half the distance of cruising.
+        let duration = Duration::from_secs_f32(metres.abs() / speed) + self.ramp_down / 2;
+        self.drive_timed(power, duration)
+    }
+
+    /// Drives straight following the velocity setpoints of `profile`, open
+    /// loop through the drive model, then stops.
+    pub fn drive_profile(&mut self, profile: &MotionProfile) -> Result<(), Error> {
+        let drive = self.require_drive("drive_profile")?;
+        let started = self.clock.now();
+        loop {
+            let elapsed = self.clock.elapsed(started);
+            if elapsed >= profile.duration() {
+                return self.stop();
+            }
+            drive.set_velocity(self.driver, profile.velocity(elapsed), 0.0)?;
+            self.hold(UPDATE_PERIOD.min(profile.duration() - elapsed))?;
+        }
+    }
+
+    /// Follows the path of `follower` open loop from `start`, dead reckoning
+    /// the pose from the velocities commanded, then stops. Returns where the
+    /// robot should have ended.
+    pub fn follow_path(&mut self, follower: &mut PurePursuit, start: Pose) -> Result<Pose, Error> {
+        let drive = self.require_drive("follow_path")?;
+        let mut pose = start;
+        let mut last_update = self.clock.now();
+        while let Some((linear, angular)) = follower.update(&pose) {
+            let (left, right) = drive.motor_powers(linear, angular);
+            self.driver.set_sides(left, right)?;
+            self.hold(UPDATE_PERIOD)?;
+            let now = self.clock.now();
+            // The velocity actually driven, lower if a wheel saturates.
+            let (linear, angular) =
+                drive.body_velocity(left * drive.max_wheel_speed, right * drive.max_wheel_speed);
+            pose = pose.advance(linear, angular, now - last_update);
+            last_update = now;
+        }
+        self.stop()?;
+        Ok(pose)
+    }
+
+    /// Follows the path of `follower` closed loop on the pose from
+    /// `estimator`, stepped with `sensors` at every update, then stops.
+    /// Returns the final estimate.
+    pub fn follow_path_estimated(
+        &mut self,
+        follower: &mut PurePursuit,
+        estimator: &mut PoseEstimator,
+        sensors: &mut PoseSensors,
+    ) -> Result<PoseEstimate, Error> {
+        let drive = self.require_drive("follow_path_estimated")?;
+        let mut last_update = self.clock.now();
+        while let Some((linear, angular)) = follower.update(&estimator.estimate().pose) {
+            let (left, right) = drive.motor_powers(linear, angular);
+            self.driver.set_sides(left, right)?;
+            self.hold(UPDATE_PERIOD)?;
+            let now = self.clock.now();
+            estimator.step(sensors, (left, right), now - last_update);
+            last_update = now;
+        }
+        self.stop()?;
+        Ok(estimator.estimate())
+    }
+
+    /// Drives a square with sides of `side_len` metres at `power`, turning
+    /// left at the corners, ending where and how it started.
+    pub fn square(&mut self, side_len: f32, power: f32) -> Result<(), Error> {
+        for _ in 0..4 {
+            self.drive_distance(side_len, power)?;
+            self.spin(90.0)?;
+        }
+        Ok(())
+    }
+
+    /// Drives a figure of eight `size` metres long at `power`: a circle to
+    /// the left then one to the right, ending where and how it started.
+    pub fn figure_eight(&mut self, size: f32, power: f32) -> Result<(), Error> {
+        self.drive_arc(size / 4.0, 360.0, power)?;
+        self.drive_arc(size / 4.0, -360.0, power)?;
+        self.stop()
+    }
+
+    /// `arc()` without stopping, so arcs chain smoothly.
+    fn drive_arc(&mut self, radius: f32, degrees: f32, power: f32) -> Result<(), Error> {
+        let drive = self.require_drive("arc")?;
+        // Powers for 1 m/s, scaled to the outer wheel at `power`.
+        let (left, right) = drive.arc_powers(1.0, radius.abs().copysign(degrees));
+        let peak = left.abs().max(right.abs());
+        let power = power.abs().min(1.0);
+        if degrees == 0.0 || power == 0.0 || peak == 0.0 {
+            return Ok(());
+        }
+        let (left, right) = (left * power / peak, right * power / peak);
+        let (_, angular) =
+            drive.body_velocity(left * drive.max_wheel_speed, right * drive.max_wheel_speed);
+        let duration = Duration::from_secs_f32(degrees.abs().to_radians() / angular.abs());
+        self.driver.set_sides(left, right)?;
+        self.hold(duration)
+    }
+
+    /// Drives at `power` until the wheels have travelled `metres`, slowing
+    /// down when the ramp down at the measured speed would cover the rest.
+    fn drive_measured(&mut self, metres: f32, power: f32) -> Result<(), Error> {
+        let start = self.wheel_travel()?;
+        let travelled = |motion: &mut Self| -> Result<f32, Error> {
+            Ok((motion.wheel_travel()? - start).abs())
+        };
+        self.driver.set_sides(power, power)?;
+        let mut last = (self.clock.now(), 0.0);
+        loop {
+            let distance = travelled(self)?;
+            if distance >= metres {
+                return self.stop();
+            }
+            let now = self.clock.now();
+            let elapsed = (now - last.0).as_secs_f32();
+            if elapsed > 0.0 {
+                let speed = (distance - last.1) / elapsed;
+                if speed * self.ramp_down.as_secs_f32() / 2.0 >= metres - distance {
+                    return self.slow_down(power, self.ramp_down, |motion| {
+                        Ok(travelled(motion)? >= metres)
+                    });
+                }
+                last = (now, distance);
+            }
+            self.hold(UPDATE_PERIOD)?;
+        }
+    }
+
+    /// Mean distance travelled by the wheels.
+    fn wheel_travel(&mut self) -> Result<f32, Error> {
+        let read = self.wheels.as_mut().ok_or(MotionError::NoWheels)?;
+        let (left, right) = read()?;
+        Ok((left + right) / 2.0)
+    }
+
+    /// Lowers the power linearly from `power` to zero over `duration`, or
+    /// until `done`, then stops.
+    fn slow_down<F>(&mut self, power: f32, duration: Duration, mut done: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Self) -> Result<bool, Error>,
+    {
+        let started = self.clock.now();
+        loop {
+            let elapsed = self.clock.elapsed(started);
+            if elapsed >= duration || done(self)? {
+                return self.stop();
+            }
+            let power = power * (1.0 - elapsed.as_secs_f32() / duration.as_secs_f32());
+            self.driver.set_sides(power, power)?;
+            self.hold(UPDATE_PERIOD.min(duration - elapsed))?;
+        }
+    }
+
+    fn require_drive(&self, primitive: &'static str) -> Result<DiffDrive, Error> {
+        self.drive
+            .ok_or_else(|| MotionError::NoDriveModel { primitive }.into())
+    }
+
+    /// Keeps the motors as they are for `duration`, stopping them if
+    /// interrupted.
+    fn hold(&mut self, duration: Duration) -> Result<(), Error> {
+        let result = self.shutdown.sleep_on(&*self.clock, duration);
+        if result.is_err() {
+            if let Err(error) = self.stop() {
+                error!(
+                    "Could not stop the motors of an interrupted motion: {}",
+                    error
+                );
+            }
+        }
+        result
+    }
+}
+
+const DEFAULT_TURN_POWER: f32 = 0.5;
+
+/// How often `drive_profile()`, `follow_path()` and the ramp down update the
+/// motor powers, and `drive_distance()` reads the wheels.
+const UPDATE_PERIOD: Duration = Duration::from_millis(20);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::simulator::SimulatedBoard;
+    use crate::thunder_borg::ControllerBuilder;
+
+    #[test]
+    fn motion_slows_down_over_a_measured_distance() {
+        let clock = ManualClock::new();
+        let board = SimulatedBoard::new();
+        let mut controller = ControllerBuilder::new()
+            .build_with_bus(board.bus())
+            .unwrap();
+        let shutdown = Shutdown::new();
+        // Wheels going 1 m/s at full power.
+        let (wheel_clock, wheel_board) = (clock.clone(), board.clone());
+        let mut travelled = (clock.now(), 0.0);
+        let mut motion = Motion::new(&mut controller, &shutdown)
+            .ramp_down(Duration::from_millis(500))
+            .clock(Arc::new(clock.clone()))
+            .wheels(move || {
+                let now = wheel_clock.now();
+                travelled.1 += wheel_board.state().motor_a * (now - travelled.0).as_secs_f32();
+                travelled.0 = now;
+                Ok((travelled.1, travelled.1))
+            });
+
+        motion.drive_distance(1.0, 0.5).unwrap();
+
+        // 1.75 s cruising, then up to 0.5 s slowing down over the last 12.5 cm.
+        let total = clock.total().as_secs_f32();
+        assert!((2.0..=2.3).contains(&total), "took {} s", total);
+        assert_eq!(board.state().motor_a, 0.0);
+    }
+}
@@ -0,0 +1,187 @@
+//! Velocity setpoints over time for smooth point-to-point moves: the robot
+//! accelerates to a peak speed, cruises, then decelerates to a stop at the
+//! requested distance.
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use vrum::motion_profile::{MotionProfile, Shape};
+//! let profile = MotionProfile::new(Shape::Trapezoidal, 1.0, 0.5, 0.25);
+//! assert_eq!(profile.duration(), Duration::from_secs(4));
+//! assert_eq!(profile.velocity(Duration::from_secs(2)), 0.5);
+//! assert_eq!(profile.position(profile.duration()), 1.0);
+//! ```
+//!
+//! `Motion::drive_profile()` drives a profile open loop through the drive
+//! model of the robot.
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+/// How the velocity changes while accelerating and decelerating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    /// Constant acceleration: quickest, but the acceleration steps at the
+    /// start and end of each ramp jerk the robot.
+    Trapezoidal,
+    /// Acceleration rising and falling smoothly (a cycloidal ramp, peaking
+    /// at the maximum acceleration), so the wheels are less likely to slip.
+    /// Ramps take twice as long as trapezoidal ones.
+    SCurve,
+}
+
+/// A move of `distance` metres (backwards if negative) starting and ending
+/// at rest, within a peak speed and acceleration. Moves too short to reach
+/// the peak speed accelerate then decelerate straight away.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MotionProfile {
+    shape: Shape,
+    direction: f32,
+    distance: f32,
+    /// Speed reached, `max_velocity` unless the move is too short.
+    peak_velocity: f32,
+    /// Duration of each of the acceleration and deceleration ramps, seconds.
+    ramp_time: f32,
+    /// Duration of the constant speed part, seconds.
+    cruise_time: f32,
+}
+
+/// Where the robot should be, and how fast it should go, at `time` into a
+/// profile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Setpoint {
+    pub time: Duration,
+    /// Metres from the start.
+    pub position: f32,
+    /// Metres per second.
+    pub velocity: f32,
+}
+
+impl MotionProfile {
+    /// `max_velocity` in m/s and `max_acceleration` in m/s², both taken as
+    /// absolute values. A non-positive limit makes an empty profile.
+    pub fn new(shape: Shape, distance: f32, max_velocity: f32, max_acceleration: f32) -> Self {
+        let (max_velocity, max_acceleration) = (max_velocity.abs(), max_acceleration.abs());
+        let direction = distance.signum();
+        let distance = distance.abs();
+        if !(distance > 0.0 && max_velocity > 0.0 && max_acceleration > 0.0) {
+            return MotionProfile {
+                shape,
+                direction,
+                distance: 0.0,
+                peak_velocity: 0.0,
+                ramp_time: 0.0,
+                cruise_time: 0.0,
+            };
+        }
+        // Both ramps together cover `peak * ramp_time` whatever the shape,
+        // the peak acceleration of a cycloidal ramp being twice its mean.
+        let ramp_factor = match shape {
+            Shape::Trapezoidal => 1.0,
+            Shape::SCurve => 2.0,
+        };
+        let peak_velocity = max_velocity.min((distance * max_acceleration / ramp_factor).sqrt());
+        let ramp_time = ramp_factor * peak_velocity / max_acceleration;
+        let cruise_time = (distance - peak_velocity * ramp_time).max(0.0) / peak_velocity;
+        MotionProfile {
+            shape,
+            direction,
+            distance,
+            peak_velocity,
+            ramp_time,
+            cruise_time,
+        }
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    /// Total distance moved, negative for moves backwards.
+    pub fn distance(&self) -> f32 {
+        self.direction * self.distance
+    }
+
+    /// Highest speed reached, in m/s.
+    pub fn peak_velocity(&self) -> f32 {
+        self.peak_velocity
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f32(2.0 * self.ramp_time + self.cruise_time)
+    }
+
+    /// Velocity setpoint at `time`, 0 before the start and after the end.
+    pub fn velocity(&self, time: Duration) -> f32 {
+        self.direction * self.peak_velocity * self.speed_fraction(time.as_secs_f32())
+    }
+
+    /// Distance from the start at `time`.
+    pub fn position(&self, time: Duration) -> f32 {
+        let time = time.as_secs_f32();
+        let decelerating = self.ramp_time + self.cruise_time;
+        let position = if time <= self.ramp_time {
+            self.ramp_distance(time)
+        } else if time <= decelerating {
+            self.ramp_distance(self.ramp_time) + self.peak_velocity * (time - self.ramp_time)
+        } else {
+            let remaining = (2.0 * self.ramp_time + self.cruise_time - time).max(0.0);
+            self.distance - self.ramp_distance(remaining)
+        };
+        self.direction * position
+    }
+
+    pub fn setpoint(&self, time: Duration) -> Setpoint {
+        Setpoint {
+            time,
+            position: self.position(time),
+            velocity: self.velocity(time),
+        }
+    }
+
+    /// Setpoints every `period` from the start, the last one at the end of
+    /// the profile.
+    pub fn setpoints(&self, period: Duration) -> impl Iterator<Item = Setpoint> + '_ {
+        let duration = self.duration();
+        let steps = if period > Duration::default() {
+            (duration.as_secs_f64() / period.as_secs_f64()).ceil() as u32
+        } else {
+            0
+        };
+        (0..=steps).map(move |step| self.setpoint((period * step).min(duration)))
+    }
+
+    /// Speed as a fraction of the peak speed, `time` in seconds.
+    fn speed_fraction(&self, time: f32) -> f32 {
+        let total = 2.0 * self.ramp_time + self.cruise_time;
+        if !(time > 0.0 && time < total) {
+            return 0.0;
+        }
+        let ramp = time.min(total - time);
+        if ramp >= self.ramp_time {
+            return 1.0;
+        }
+        let progress = ramp / self.ramp_time;
+        match self.shape {
+            Shape::Trapezoidal => progress,
+            Shape::SCurve => progress - (2.0 * PI * progress).sin() / (2.0 * PI),
+        }
+    }
+
+    /// Distance covered `time` seconds into the acceleration ramp.
+    fn ramp_distance(&self, time: f32) -> f32 {
+        if self.ramp_time <= 0.0 {
+            return 0.0;
+        }
+        let time = time.clamp(0.0, self.ramp_time);
+        let ramp = self.ramp_time;
+        let distance = match self.shape {
+            Shape::Trapezoidal => time * time / (2.0 * ramp),
+            Shape::SCurve => {
+                time * time / (2.0 * ramp)
+                    + ramp / (4.0 * PI * PI) * ((2.0 * PI * time / ramp).cos() - 1.0)
+            }
+        };
+        self.peak_velocity * distance
+    }
+}
@@ -0,0 +1,164 @@
+use crate::borg::CommStats;
+use crate::color::Color;
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MotorDriverError {
+    #[error("no motor {index}, the board has {num_motors} motor channels")]
+    InvalidMotor { index: usize, num_motors: usize },
+    #[error("the board does not support {feature}")]
+    Unsupported { feature: &'static str },
+}
+
+/// Motor control and monitoring common to every supported board, so the
+/// kinematics, safety and telemetry layers work the same whichever board
+/// drives the robot.
+///
+/// Monitoring not every board has, like fault flags or a battery monitor,
+/// fails with `MotorDriverError::Unsupported` by default.
+pub trait MotorDriver {
+    /// Number of motor channels on the board.
+    fn num_motors(&self) -> usize;
+
+    /// Sets motor `index`, counting from 0, to `power` in `[-1, 1]`.
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error>;
+
+    /// Last power commanded to motor `index`, 0 for an invalid index.
+    fn motor_power(&self, index: usize) -> f32;
+
+    /// Power of motor `index` read back from the board, including commands
+    /// sent through other handles.
+    fn motor_readback(&mut self, _index: usize) -> Result<f32, Error> {
+        Err(unsupported("motor power readback"))
+    }
+
+    /// Drives the motors on the left side of the robot at `left` and those
+    /// on the right at `right`.
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error>;
+
+    /// Switches off every motor.
+    fn stop_all(&mut self) -> Result<(), Error>;
+
+    /// True if the drive fault flag of motor `index` is set, indicating e.g.
+    /// a short circuit or under voltage.
+    fn fault(&mut self, _index: usize) -> Result<bool, Error> {
+        Err(unsupported("drive fault flags"))
+    }
+
+    /// Battery voltage in volts, filtered if the board supports it.
+    fn battery_voltage(&mut self) -> Result<f32, Error> {
+        Err(unsupported("battery voltage readings"))
+    }
+
+    /// Estimated battery state of charge, in `[0, 100]`.
+    fn battery_percent(&mut self) -> Result<f32, Error> {
+        Err(unsupported("battery state of charge estimation"))
+    }
+
+    /// Bus traffic statistics since the board was opened.
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        Err(unsupported("communication statistics"))
+    }
+
+    /// Shows `color` on the board LED, as well as the LED can.
+    fn set_led_color(&mut self, _color: Color) -> Result<(), Error> {
+        Err(unsupported("an LED"))
+    }
+}
+
+/// Fails with `MotorDriverError::InvalidMotor` unless `index` is a valid
+/// motor of a board with `num_motors` channels.
+pub(crate) fn check_motor_index(index: usize, num_motors: usize) -> Result<(), Error> {
+    if index < num_motors {
+        Ok(())
+    } else {
+        Err(MotorDriverError::InvalidMotor { index, num_motors }.into())
+    }
+}
+
+fn unsupported(feature: &'static str) -> Error {
+    MotorDriverError::Unsupported { feature }.into()
+}
+
+/// Two boards driven as one with the motors of `first` followed by those of
+/// `second`, e.g. two ThunderBorgs, one for the front wheels and one for the
+/// rear, as motors 0 to 3. Each side is driven by both.
+pub struct DualDriver<A, B> {
+    pub first: A,
+    pub second: B,
+}
+
+impl<A: MotorDriver, B: MotorDriver> DualDriver<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        DualDriver { first, second }
+    }
+}
+
+impl<A: MotorDriver, B: MotorDriver> MotorDriver for DualDriver<A, B> {
+    fn num_motors(&self) -> usize {
+        self.first.num_motors() + self.second.num_motors()
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        check_motor_index(index, self.num_motors())?;
+        match index.checked_sub(self.first.num_motors()) {
+            None => self.first.set_motor(index, power),
+            Some(index) => self.second.set_motor(index, power),
+        }
+    }
+
+    fn motor_power(&self, index: usize) -> f32 {
+        match index.checked_sub(self.first.num_motors()) {
+            None => self.first.motor_power(index),
+            Some(index) => self.second.motor_power(index),
+        }
+    }
+
+    fn motor_readback(&mut self, index: usize) -> Result<f32, Error> {
+        check_motor_index(index, self.num_motors())?;
+        match index.checked_sub(self.first.num_motors()) {
+            None => self.first.motor_readback(index),
+            Some(index) => self.second.motor_readback(index),
+        }
+    }
+
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        let first = self.first.set_sides(left, right);
+        let second = self.second.set_sides(left, right);
+        first.and(second)
+    }
+
+    /// Stops both boards, even when the first fails to.
+    fn stop_all(&mut self) -> Result<(), Error> {
+        let first = self.first.stop_all();
+        let second = self.second.stop_all();
+        first.and(second)
+    }
+
+    fn fault(&mut self, index: usize) -> Result<bool, Error> {
+        check_motor_index(index, self.num_motors())?;
+        match index.checked_sub(self.first.num_motors()) {
+            None => self.first.fault(index),
+            Some(index) => self.second.fault(index),
+        }
+    }
+
+    /// Read by the first board, both being on the same battery.
+    fn battery_voltage(&mut self) -> Result<f32, Error> {
+        self.first.battery_voltage()
+    }
+
+    fn battery_percent(&mut self) -> Result<f32, Error> {
+        self.first.battery_percent()
+    }
+
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        self.first.comm_stats()
+    }
+
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        let first = self.first.set_led_color(color);
+        let second = self.second.set_led_color(color);
+        first.and(second)
+    }
+}
@@ -0,0 +1,291 @@
+use std::time::{Duration, Instant};
+
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, Publish, QoS, RecvTimeoutError};
+
+use crate::auth::{self, Token};
+use crate::color::Color;
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::heartbeat::{DeadMan, DeadManConfig};
+use crate::motor_driver::MotorDriver;
+use crate::thunder_borg::Controller;
+
+/// Arming command, payload is a JSON object `{"armed": true}`, see
+/// `vrum::arming`.
+pub const TOPIC_CMD_ARM: &str = "vrum/cmd/arm";
+/// Clears a latched drive fault or battery cutoff, with any payload, see
+/// `FaultLatch`.
+pub const TOPIC_CMD_CLEAR_FAULTS: &str = "vrum/cmd/clear_faults";
+/// Drive command, payload is either a single power applied to both motors
+/// (e.g. `0.5`) or a JSON object `{"left": 0.5, "right": -0.5}`. The motors
+/// are stopped unless a drive command or a heartbeat arrives within the
+/// heartbeat timeout while they run, see `vrum::heartbeat`.
+pub const TOPIC_CMD_DRIVE: &str = "vrum/cmd/drive";
+/// Heartbeat keeping the motors running as they are, with any payload.
+pub const TOPIC_CMD_HEARTBEAT: &str = "vrum/cmd/heartbeat";
+/// LED command, payload is a JSON object `{"red": 255, "green": 0, "blue": 0}`.
+pub const TOPIC_CMD_LED: &str = "vrum/cmd/led";
+/// Whether the motors are armed, published as `true` or `false` when arming
+/// is required.
+pub const TOPIC_TELEMETRY_ARMED: &str = "vrum/telemetry/armed";
+/// Battery voltage in volts, published as a plain number.
+pub const TOPIC_TELEMETRY_BATTERY: &str = "vrum/telemetry/battery";
+/// What latched the fault blocking motor commands, published as a JSON
+/// string, or `null` while clear, when faults are latched.
+pub const TOPIC_TELEMETRY_FAULT_LATCHED: &str = "vrum/telemetry/fault_latched";
+/// Drive fault flags, published as a JSON object `{"a": false, "b": false}`.
+pub const TOPIC_TELEMETRY_FAULTS: &str = "vrum/telemetry/faults";
+/// I2C bus statistics summed over all commands, published as a JSON object
+/// with the fields of `borg::CommandStats`.
+pub const TOPIC_TELEMETRY_I2C: &str = "vrum/telemetry/i2c";
+
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub keep_alive: Duration,
+    pub telemetry_interval: Duration,
+    pub heartbeat: DeadManConfig,
+    /// Encoding of the payloads, JSON as documented for each topic or the
+    /// same values in CBOR.
+    pub encoding: Encoding,
+    /// Commands without it in a `token` field of their JSON payload, e.g.
+    /// `{"left": 0.5, "right": 0.5, "token": "..."}`, are ignored.
+    pub token: Option<Token>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            host: "localhost".into(),
+            port: 1883,
+            client_id: "vrum".into(),
+            keep_alive: Duration::from_secs(5),
+            telemetry_interval: Duration::from_secs(1),
+            heartbeat: DeadManConfig::default(),
+            encoding: Encoding::Json,
+            token: None,
+        }
+    }
+}
+
+/// Connects a `Controller` to an MQTT broker, executing commands received on
+/// the `vrum/cmd/*` topics and periodically publishing telemetry.
+pub struct MqttBridge<'a> {
+    controller: &'a mut Controller,
+    config: MqttConfig,
+    client: Client,
+    connection: Connection,
+    dead_man: DeadMan,
+}
+
+impl<'a> MqttBridge<'a> {
+    pub fn new(controller: &'a mut Controller, config: MqttConfig) -> Self {
+        let mut options =
+            MqttOptions::new(config.client_id.clone(), config.host.clone(), config.port);
+        options.set_keep_alive(config.keep_alive);
+        let (client, connection) = Client::new(options, MQTT_REQUEST_CAPACITY);
+        MqttBridge {
+            controller,
+            dead_man: DeadMan::new(config.heartbeat),
+            config,
+            client,
+            connection,
+        }
+    }
+
+    /// Runs the bridge until the connection to the broker fails.
+    pub fn run(&mut self) -> Result<(), Error> {
+        info!(
+            "Connecting to MQTT broker at {}:{}",
+            self.config.host, self.config.port
+        );
+        self.client.subscribe(TOPIC_CMD_ARM, QoS::AtMostOnce)?;
+        self.client
+            .subscribe(TOPIC_CMD_CLEAR_FAULTS, QoS::AtMostOnce)?;
+        self.client.subscribe(TOPIC_CMD_DRIVE, QoS::AtMostOnce)?;
+        self.client
+            .subscribe(TOPIC_CMD_HEARTBEAT, QoS::AtMostOnce)?;
+        self.client.subscribe(TOPIC_CMD_LED, QoS::AtMostOnce)?;
+
+        let mut last_telemetry = Instant::now();
+        loop {
+            let wait = self
+                .config
+                .telemetry_interval
+                .checked_sub(last_telemetry.elapsed())
+                .unwrap_or_default()
+                .min(MQTT_POLL_INTERVAL);
+            match self.connection.recv_timeout(wait) {
+                Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                    self.handle_publish(&publish)?
+                }
+                Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => info!("Connected to MQTT broker"),
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(error)) => return Err(error.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            if self.dead_man.expired() {
+                warn!(
+                    "No MQTT drive command or heartbeat for {:?}, stopping motors",
+                    self.config.heartbeat.timeout
+                );
+                self.controller.set_motors(0.0)?;
+            }
+
+            if last_telemetry.elapsed() >= self.config.telemetry_interval {
+                self.publish_telemetry()?;
+                last_telemetry = Instant::now();
+            }
+        }
+    }
+
+    fn handle_publish(&mut self, publish: &Publish) -> Result<(), Error> {
+        debug!(
+            "Received MQTT message on {}: {:?}",
+            publish.topic, publish.payload
+        );
+        let encoding = self.config.encoding;
+        let token = payload_token(encoding, &publish.payload);
+        if !auth::is_authorized(
+            self.config.token.as_ref(),
+            token.as_ref().map(|token| token.as_bytes()),
+        ) {
+            warn!(
+                "Ignoring MQTT command on {} without the token",
+                publish.topic
+            );
+            return Ok(());
+        }
+        if publish.topic == TOPIC_CMD_DRIVE {
+            match parse_drive(encoding, &publish.payload) {
+                Some(DriveCommand { left, right }) => match self.dead_man.drive(left, right) {
+                    Ok(()) => match self.controller.set_sides(left, right) {
+                        // Refused by the robot, e.g. while e-stopped, rather
+                        // than failed.
+                        Err(
+                            error @ (Error::Arming(_)
+                            | Error::Pipeline(_)
+                            | Error::InvalidMotorPower { .. }),
+                        ) => warn!("Ignoring drive command: {}", error),
+                        result => result?,
+                    },
+                    Err(error) => warn!("Ignoring drive command: {}", error),
+                },
+                None => warn!("Ignoring malformed drive command {:?}", publish.payload),
+            }
+        } else if publish.topic == TOPIC_CMD_ARM {
+            match encoding.decode::<ArmCommand>(&publish.payload) {
+                Ok(ArmCommand { armed: true }) => {
+                    if let Err(error) = self.controller.arm() {
+                        warn!("Not arming: {}", error);
+                    }
+                }
+                Ok(ArmCommand { armed: false }) => {
+                    self.dead_man.stopped();
+                    self.controller.disarm()?;
+                }
+                Err(error) => warn!("Ignoring malformed arming command: {}", error),
+            }
+        } else if publish.topic == TOPIC_CMD_CLEAR_FAULTS {
+            if let Err(error) = self.controller.clear_faults() {
+                warn!("Not clearing the latched fault: {}", error);
+            }
+        } else if publish.topic == TOPIC_CMD_HEARTBEAT {
+            self.dead_man.heartbeat();
+        } else if publish.topic == TOPIC_CMD_LED {
+            match encoding.decode::<Color>(&publish.payload) {
+                Ok(color) => self.controller.set_led(color)?,
+                Err(error) => warn!("Ignoring malformed LED command: {}", error),
+            }
+        }
+        Ok(())
+    }
+
+    fn publish_telemetry(&mut self) -> Result<(), Error> {
+        let encoding = self.config.encoding;
+        // To the centivolt, as the readings aren't more precise.
+        let voltage = (self.controller.get_battery_voltage()? * 100.0).round() / 100.0;
+        let faults = FaultsTelemetry {
+            a: self.controller.get_drive_fault_a()?,
+            b: self.controller.get_drive_fault_b()?,
+        };
+        self.client.try_publish(
+            TOPIC_TELEMETRY_BATTERY,
+            QoS::AtMostOnce,
+            false,
+            encoding.encode(&voltage)?,
+        )?;
+        self.client.try_publish(
+            TOPIC_TELEMETRY_FAULTS,
+            QoS::AtMostOnce,
+            false,
+            encoding.encode(&faults)?,
+        )?;
+        if let Some(latch) = self.controller.fault_latch() {
+            self.client.try_publish(
+                TOPIC_TELEMETRY_FAULT_LATCHED,
+                QoS::AtMostOnce,
+                false,
+                encoding.encode(&latch.cause())?,
+            )?;
+        }
+        if let Some(arming) = self.controller.arming() {
+            self.client.try_publish(
+                TOPIC_TELEMETRY_ARMED,
+                QoS::AtMostOnce,
+                false,
+                encoding.encode(&arming.is_armed())?,
+            )?;
+        }
+        self.client.try_publish(
+            TOPIC_TELEMETRY_I2C,
+            QoS::AtMostOnce,
+            false,
+            encoding.encode(&self.controller.comm_stats().total())?,
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ArmCommand {
+    armed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DriveCommand {
+    left: f32,
+    right: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signed {
+    token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FaultsTelemetry {
+    a: bool,
+    b: bool,
+}
+
+fn parse_drive(encoding: Encoding, payload: &[u8]) -> Option<DriveCommand> {
+    if let Ok(power) = encoding.decode::<f32>(payload) {
+        return Some(DriveCommand {
+            left: power,
+            right: power,
+        });
+    }
+    encoding.decode(payload).ok()
+}
+
+fn payload_token(encoding: Encoding, payload: &[u8]) -> Option<String> {
+    encoding.decode::<Signed>(payload).ok()?.token
+}
+
+const MQTT_REQUEST_CAPACITY: usize = 16;
+/// How often the bridge checks the heartbeat timeout, at least.
+const MQTT_POLL_INTERVAL: Duration = Duration::from_millis(50);
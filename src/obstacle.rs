@@ -0,0 +1,213 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+use crate::sensors::ultrasonic::HcSr04;
+use crate::ultra_borg::{self, Channel};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ObstacleError {
+    #[error("no UltraBorg ultrasonic channel {channel}, expected 1 to 4")]
+    InvalidChannel { channel: u8 },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ObstacleConfig {
+    pub sensor: Option<SensorConfig>,
+    /// Forward motor commands are zeroed when an object is this close, in
+    /// metres.
+    pub stop_distance: f32,
+    /// Forward motor commands are scaled down linearly between
+    /// `slow_distance` and `stop_distance`, in metres.
+    pub slow_distance: f32,
+    pub poll_interval_ms: u64,
+    /// Readings older than this are not trusted and forward motion is
+    /// stopped, so a dead sensor doesn't leave the robot driving blind.
+    pub stale_after_ms: u64,
+}
+
+impl Default for ObstacleConfig {
+    fn default() -> Self {
+        ObstacleConfig {
+            sensor: None,
+            stop_distance: 0.15,
+            slow_distance: 0.5,
+            poll_interval_ms: 60,
+            stale_after_ms: 500,
+        }
+    }
+}
+
+/// Where an `ObstacleMonitor` reads distances from.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SensorConfig {
+    /// Ultrasonic input `channel` (1 to 4) of an UltraBorg.
+    UltraBorg { channel: u8 },
+    /// An HC-SR04 wired directly to the GPIO header, BCM pin numbers. Each
+    /// reading is the median of `samples` pings.
+    HcSr04 {
+        trigger_pin: u8,
+        echo_pin: u8,
+        #[serde(default = "default_samples")]
+        samples: usize,
+    },
+}
+
+fn default_samples() -> usize {
+    1
+}
+
+/// Scales down forward motor commands when an obstacle is close, attached to
+/// a `Controller` with `Controller::set_obstacle_guard`. Readings come from
+/// an `ObstacleMonitor` or are fed in with `update()`.
+#[derive(Clone)]
+pub struct ObstacleGuard {
+    state: Arc<GuardState>,
+}
+
+struct GuardState {
+    stop_distance: f32,
+    slow_distance: f32,
+    stale_after: Duration,
+    scale: AtomicU32,
+    last_update: Mutex<Option<Instant>>,
+}
+
+impl ObstacleGuard {
+    pub fn new(config: &ObstacleConfig) -> Self {
+        ObstacleGuard {
+            state: Arc::new(GuardState {
+                stop_distance: config.stop_distance,
+                slow_distance: config.slow_distance.max(config.stop_distance),
+                stale_after: Duration::from_millis(config.stale_after_ms),
+                scale: AtomicU32::new(0.0f32.to_bits()),
+                last_update: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Records a distance reading in metres, `None` meaning nothing is in
+    /// range of the sensor.
+    pub fn update(&self, distance: Option<f32>) {
+        let state = &self.state;
+        let scale = match distance {
+            None => 1.0,
+            Some(distance) if distance <= state.stop_distance => 0.0,
+            Some(distance) if distance >= state.slow_distance => 1.0,
+            Some(distance) => {
+                (distance - state.stop_distance) / (state.slow_distance - state.stop_distance)
+            }
+        };
+        let previous = f32::from_bits(state.scale.swap(scale.to_bits(), Ordering::SeqCst));
+        if scale == 0.0 && previous != 0.0 {
+            warn!("Obstacle ahead, stopping forward motion");
+        } else if scale != 0.0 && previous == 0.0 {
+            info!("Path ahead clear");
+        }
+        if let Ok(mut last_update) = state.last_update.lock() {
+            *last_update = Some(Instant::now());
+        }
+    }
+
+    /// Factor in `[0, 1]` applied to forward motor powers, 0 until the first
+    /// reading and when the last one is stale.
+    pub fn forward_scale(&self) -> f32 {
+        let fresh = self
+            .state
+            .last_update
+            .lock()
+            .map(|last_update| last_update.is_some_and(|at| at.elapsed() < self.state.stale_after))
+            .unwrap_or(false);
+        if fresh {
+            f32::from_bits(self.state.scale.load(Ordering::SeqCst))
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Polls a distance sensor on a background thread, feeding the readings to
+/// an `ObstacleGuard`.
+pub struct ObstacleMonitor {
+    guard: ObstacleGuard,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ObstacleMonitor {
+    /// `read` returns the distance to the nearest obstacle in metres, `None`
+    /// if nothing is in range.
+    pub fn spawn<F>(config: &ObstacleConfig, mut read: F) -> Result<Self, Error>
+    where
+        F: FnMut() -> Result<Option<f32>, Error> + Send + 'static,
+    {
+        let guard = ObstacleGuard::new(config);
+        let running = Arc::new(AtomicBool::new(true));
+        let poll_interval = Duration::from_millis(config.poll_interval_ms);
+
+        let thread_guard = guard.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-obstacle".into())
+            .spawn(move || {
+                while thread_running.load(Ordering::SeqCst) {
+                    match read() {
+                        Ok(distance) => thread_guard.update(distance),
+                        Err(error) => warn!("Could not read obstacle distance: {}", error),
+                    }
+                    thread::sleep(poll_interval);
+                }
+            })?;
+
+        Ok(ObstacleMonitor {
+            guard,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Spawns a monitor for the sensor in `config.sensor`, `None` if there
+    /// is none configured. `bus` is the I2C bus of an UltraBorg.
+    pub fn from_config(config: &ObstacleConfig, bus: &BusManager) -> Result<Option<Self>, Error> {
+        let monitor = match config.sensor {
+            Some(SensorConfig::UltraBorg { channel }) => {
+                let sensor_channel = *Channel::ALL
+                    .get(usize::from(channel).wrapping_sub(1))
+                    .ok_or(ObstacleError::InvalidChannel { channel })?;
+                let mut ultra_borg = ultra_borg::Controller::builder()
+                    .build_with_bus(Box::new(bus.device(ultra_borg::ULTRABORG_SLAVE_ADDR)))?;
+                ObstacleMonitor::spawn(config, move || ultra_borg.get_distance(sensor_channel))?
+            }
+            Some(SensorConfig::HcSr04 {
+                trigger_pin,
+                echo_pin,
+                samples,
+            }) => {
+                let mut sensor = HcSr04::new(trigger_pin, echo_pin)?.samples(samples);
+                ObstacleMonitor::spawn(config, move || sensor.measure())?
+            }
+            None => return Ok(None),
+        };
+        Ok(Some(monitor))
+    }
+
+    pub fn guard(&self) -> ObstacleGuard {
+        self.guard.clone()
+    }
+}
+
+impl Drop for ObstacleMonitor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Obstacle monitor thread panicked");
+            }
+        }
+    }
+}
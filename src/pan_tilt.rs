@@ -0,0 +1,301 @@
+//! Pan and tilt of a camera on two servos, the servo outputs of an
+//! UltraBorg or channels of a PCA9685 (see `vrum::pca9685`), configured in
+//! the `[pan_tilt]` section of the configuration file:
+//!
+//! ```toml
+//! [pan_tilt]
+//! pan_channel = 1
+//! tilt_channel = 2
+//! slew_rate = 120.0
+//!
+//! [pan_tilt.tilt]
+//! min_angle = -30.0
+//! max_angle = 60.0
+//! ```
+//!
+//! Angles are in degrees from straight ahead, the azimuth counter-clockwise
+//! (to the left) positive like the heading of `vrum::kinematics`, and the
+//! elevation up positive. A `PanTiltDriver` moves the servos towards where a
+//! `PanTilt` points at no faster than `slew_rate`, so the camera doesn't
+//! jerk and the mount isn't strained. The stick of `vrum rc` with the `pan`
+//! and `tilt` channels, and the right stick of a gamepad on the page of
+//! `vrum serve`, aim it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PanTiltError {
+    #[error("no servo channel {channel}, the board has channels 1 to {servos}")]
+    InvalidChannel { channel: usize, servos: usize },
+    #[error("aiming the camera needs a [pan_tilt] section in the configuration")]
+    NotConfigured,
+}
+
+/// Servo outputs, e.g. of an UltraBorg or a PCA9685.
+pub trait Servos: Send {
+    /// Number of servo outputs.
+    fn num_servos(&self) -> usize;
+
+    /// Moves servo `index`, counting from 0, to `position` in `[-1, 1]`, 0
+    /// being the centre.
+    fn set_servo(&mut self, index: usize, position: f32) -> Result<(), Error>;
+}
+
+/// The `[pan_tilt]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PanTiltConfig {
+    /// The board the servos are on.
+    pub servos: ServoBoard,
+    /// Servo output turning the camera left and right, from 1.
+    pub pan_channel: usize,
+    /// Servo output turning the camera up and down, from 1.
+    pub tilt_channel: usize,
+    pub pan: ServoAxis,
+    pub tilt: ServoAxis,
+    /// Fastest the servos turn, in degrees per second.
+    pub slew_rate: f32,
+    pub update_interval_ms: u64,
+}
+
+impl Default for PanTiltConfig {
+    fn default() -> Self {
+        PanTiltConfig {
+            servos: ServoBoard::UltraBorg,
+            pan_channel: 1,
+            tilt_channel: 2,
+            pan: ServoAxis::default(),
+            tilt: ServoAxis::default(),
+            slew_rate: 180.0,
+            update_interval_ms: 20,
+        }
+    }
+}
+
+/// Boards with servo outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServoBoard {
+    /// Servo outputs 1 to 4 of an UltraBorg.
+    UltraBorg,
+    /// Channels 1 to 16 of the PCA9685 of the `[pca9685]` section, its
+    /// channels 0 to 15.
+    Pca9685,
+}
+
+/// How one servo turns.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServoAxis {
+    /// Angle at full deflection, position 1 of the servo, in degrees. The
+    /// servo turns as far the other way at -1.
+    pub range: f32,
+    /// Angles beyond these are not pointed at, so the camera doesn't hit
+    /// the robot.
+    pub min_angle: f32,
+    pub max_angle: f32,
+    /// Reverse the servo, for one mounted the other way round.
+    pub inverted: bool,
+}
+
+impl Default for ServoAxis {
+    fn default() -> Self {
+        ServoAxis {
+            range: 90.0,
+            min_angle: -90.0,
+            max_angle: 90.0,
+            inverted: false,
+        }
+    }
+}
+
+impl ServoAxis {
+    /// `angle` within the limits.
+    pub fn clamp(&self, angle: f32) -> f32 {
+        angle.max(self.min_angle).min(self.max_angle)
+    }
+
+    /// The angle for a stick at `value` in `[-1, 1]`, reaching the limits at
+    /// the ends and straight ahead in the centre.
+    pub fn stick_angle(&self, value: f32) -> f32 {
+        let value = value.clamp(-1.0, 1.0);
+        if value >= 0.0 {
+            value * self.max_angle.max(0.0)
+        } else {
+            -value * self.min_angle.min(0.0)
+        }
+    }
+
+    /// Servo position in `[-1, 1]` for `angle`.
+    fn position(&self, angle: f32) -> f32 {
+        let direction = if self.inverted { -1.0 } else { 1.0 };
+        (angle / self.range * direction).clamp(-1.0, 1.0)
+    }
+}
+
+/// Where the camera points and where it is headed, shared between the
+/// `PanTiltDriver` turning the servos and whatever aims.
+#[derive(Clone, Debug)]
+pub struct PanTilt {
+    pan: ServoAxis,
+    tilt: ServoAxis,
+    state: Arc<Mutex<Aim>>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Aim {
+    target: (f32, f32),
+    position: (f32, f32),
+}
+
+impl PanTilt {
+    /// Points at `azimuth` and `elevation`, in degrees, or as close as the
+    /// limits allow.
+    pub fn point_at(&self, azimuth: f32, elevation: f32) {
+        if let Ok(mut aim) = self.state.lock() {
+            aim.target = (self.pan.clamp(azimuth), self.tilt.clamp(elevation));
+        }
+    }
+
+    /// Points where a stick at `pan` and `tilt`, both in `[-1, 1]`, is, see
+    /// `ServoAxis::stick_angle`.
+    pub fn aim(&self, pan: f32, tilt: f32) {
+        self.point_at(self.pan.stick_angle(pan), self.tilt.stick_angle(tilt));
+    }
+
+    /// Points straight ahead.
+    pub fn centre(&self) {
+        self.point_at(0.0, 0.0);
+    }
+
+    /// Azimuth and elevation being pointed at.
+    pub fn target(&self) -> (f32, f32) {
+        self.state.lock().map(|aim| aim.target).unwrap_or_default()
+    }
+
+    /// Azimuth and elevation the servos were last moved to.
+    pub fn position(&self) -> (f32, f32) {
+        self.state
+            .lock()
+            .map(|aim| aim.position)
+            .unwrap_or_default()
+    }
+}
+
+/// Slews the servos towards the target of its `PanTilt` on a background
+/// thread, until dropped. The servos are centred on start.
+pub struct PanTiltDriver {
+    pan_tilt: PanTilt,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PanTiltDriver {
+    pub fn spawn<S: Servos + 'static>(
+        config: &PanTiltConfig,
+        mut servos: S,
+    ) -> Result<Self, Error> {
+        for &channel in [config.pan_channel, config.tilt_channel].iter() {
+            if channel == 0 || channel > servos.num_servos() {
+                return Err(PanTiltError::InvalidChannel {
+                    channel,
+                    servos: servos.num_servos(),
+                }
+                .into());
+            }
+        }
+        let pan_tilt = PanTilt {
+            pan: config.pan,
+            tilt: config.tilt,
+            state: Arc::default(),
+        };
+        let (pan, tilt) = (config.pan_channel - 1, config.tilt_channel - 1);
+        servos.set_servo(pan, config.pan.position(0.0))?;
+        servos.set_servo(tilt, config.tilt.position(0.0))?;
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_pan_tilt = pan_tilt.clone();
+        let thread_running = running.clone();
+        let slew_rate = config.slew_rate.max(0.0);
+        let interval = Duration::from_millis(config.update_interval_ms);
+        let thread = thread::Builder::new()
+            .name("vrum-pan-tilt".into())
+            .spawn(move || {
+                let PanTilt {
+                    pan: pan_axis,
+                    tilt: tilt_axis,
+                    state,
+                } = thread_pan_tilt;
+                let mut last = Instant::now();
+                let mut failing = false;
+                while thread_running.load(Ordering::SeqCst) {
+                    thread::sleep(interval);
+                    let step = slew_rate * last.elapsed().as_secs_f32();
+                    last = Instant::now();
+                    let Aim { target, position } = match state.lock() {
+                        Ok(aim) => *aim,
+                        Err(_) => break,
+                    };
+                    if target == position {
+                        continue;
+                    }
+                    let next = (
+                        slew(position.0, target.0, step),
+                        slew(position.1, target.1, step),
+                    );
+                    let moved = servos
+                        .set_servo(pan, pan_axis.position(next.0))
+                        .and_then(|()| servos.set_servo(tilt, tilt_axis.position(next.1)));
+                    match moved {
+                        Ok(()) => {
+                            failing = false;
+                            if let Ok(mut aim) = state.lock() {
+                                aim.position = next;
+                            }
+                        }
+                        // Once, rather than every update while it fails.
+                        Err(error) if !failing => {
+                            warn!("Could not move the pan and tilt servos: {}", error);
+                            failing = true;
+                        }
+                        Err(_) => {}
+                    }
+                }
+            })?;
+
+        Ok(PanTiltDriver {
+            pan_tilt,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn pan_tilt(&self) -> PanTilt {
+        self.pan_tilt.clone()
+    }
+}
+
+impl Drop for PanTiltDriver {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Pan and tilt thread panicked");
+            }
+        }
+    }
+}
+
+/// `from` moved towards `to` by at most `step`.
+fn slew(from: f32, to: f32, step: f32) -> f32 {
+    if (to - from).abs() <= step {
+        to
+    } else {
+        from + step.copysign(to - from)
+    }
+}
@@ -0,0 +1,294 @@
+//! Driver for the NXP PCA9685, 16 channels of 12-bit PWM on the I2C bus, for
+//! servos, lights and anything else switched or dimmed by a pulse, alongside
+//! the motor board. Configured in the `[pca9685]` section of the
+//! configuration file, with the channels counting from 0:
+//!
+//! ```toml
+//! [pca9685]
+//! frequency = 50.0
+//!
+//! [[pca9685.channels]]
+//! channel = 0
+//! kind = "servo"
+//! min_pulse_us = 900
+//! max_pulse_us = 2100
+//!
+//! [[pca9685.channels]]
+//! channel = 15
+//! kind = "duty"
+//! ```
+//!
+//! Like motors, channels are set to a value with `set_channel`, which each
+//! channel's configuration turns into a pulse: servos take a position in
+//! `[-1, 1]`, and the duty cycle of other channels, e.g. headlights through
+//! a MOSFET, is the value in `[0, 1]`. The servos can aim a `PanTilt`.
+//!
+//! ```no_run
+//! # use vrum::pca9685::{Pca9685, Pca9685Config};
+//! let mut pwm = Pca9685::open("/dev/i2c-1", &Pca9685Config::default())?;
+//! pwm.set_channel(15, 0.5)?;
+//! # Ok::<(), vrum::Error>(())
+//! ```
+//!
+//! Every channel is switched off when the driver is dropped.
+
+use std::thread;
+use std::time::Duration;
+
+use i2cdev::linux::LinuxI2CDevice;
+
+use crate::borg::Bus;
+use crate::error::Error;
+use crate::pan_tilt::Servos;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Pca9685Error {
+    #[error("no PCA9685 channel {channel}, expected 0 to 15")]
+    InvalidChannel { channel: usize },
+}
+
+/// The `[pca9685]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pca9685Config {
+    pub address: u16,
+    /// Of the pulses on every channel, in hertz, 50 for servos.
+    pub frequency: f32,
+    /// Channels not listed are `PwmKind::Duty`.
+    pub channels: Vec<PwmChannelConfig>,
+}
+
+impl Default for Pca9685Config {
+    fn default() -> Self {
+        Pca9685Config {
+            address: PCA9685_ADDR,
+            frequency: 50.0,
+            channels: Vec::new(),
+        }
+    }
+}
+
+/// What a channel drives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PwmKind {
+    /// A servo, or an ESC, positioned in `[-1, 1]` by a pulse from
+    /// `min_pulse_us` to `max_pulse_us`.
+    Servo,
+    /// Lights and other loads, at a duty cycle in `[0, 1]`.
+    Duty,
+}
+
+/// Corrections applied to every value sent to a channel.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PwmChannelConfig {
+    pub channel: usize,
+    #[serde(default = "default_kind")]
+    pub kind: PwmKind,
+    /// Reverse the servo, or switch a load on with a low output.
+    #[serde(default)]
+    pub inverted: bool,
+    /// Pulse of a servo at -1, in microseconds.
+    #[serde(default = "default_min_pulse_us")]
+    pub min_pulse_us: u32,
+    /// Pulse of a servo at 1, in microseconds.
+    #[serde(default = "default_max_pulse_us")]
+    pub max_pulse_us: u32,
+}
+
+impl PwmChannelConfig {
+    pub fn new(channel: usize, kind: PwmKind) -> Self {
+        PwmChannelConfig {
+            channel,
+            kind,
+            inverted: false,
+            min_pulse_us: default_min_pulse_us(),
+            max_pulse_us: default_max_pulse_us(),
+        }
+    }
+}
+
+fn default_kind() -> PwmKind {
+    PwmKind::Duty
+}
+
+fn default_min_pulse_us() -> u32 {
+    1000
+}
+
+fn default_max_pulse_us() -> u32 {
+    2000
+}
+
+pub struct Pca9685 {
+    bus: Box<dyn Bus>,
+    /// Length of a PWM period, in microseconds.
+    period_us: f32,
+    channels: [PwmChannelConfig; NUM_CHANNELS],
+}
+
+impl Pca9685 {
+    pub fn open(bus_path: &str, config: &Pca9685Config) -> Result<Self, Error> {
+        info!(
+            "Initialising PCA9685 at i2c bus {} address 0x{:x}",
+            bus_path, config.address
+        );
+        Pca9685::new(
+            Box::new(LinuxI2CDevice::new(bus_path, config.address)?),
+            config,
+        )
+    }
+
+    /// Sets the frequency of the chip on `bus`, e.g. a device of a
+    /// `BusManager`, with every channel off.
+    pub fn new(bus: Box<dyn Bus>, config: &Pca9685Config) -> Result<Self, Error> {
+        let mut channels = [PwmChannelConfig::new(0, PwmKind::Duty); NUM_CHANNELS];
+        for (index, channel) in channels.iter_mut().enumerate() {
+            channel.channel = index;
+        }
+        for channel in &config.channels {
+            check_channel(channel.channel)?;
+            channels[channel.channel] = *channel;
+        }
+        let prescale = (OSCILLATOR_HZ / (4096.0 * config.frequency)).round() - 1.0;
+        let prescale = prescale.clamp(f32::from(MIN_PRESCALE), 255.0) as u8;
+        let frequency = OSCILLATOR_HZ / (4096.0 * (f32::from(prescale) + 1.0));
+        let mut pwm = Pca9685 {
+            bus,
+            period_us: 1e6 / frequency,
+            channels,
+        };
+        // The prescaler can only be set while asleep.
+        pwm.bus
+            .write(&[REG_MODE1, MODE1_SLEEP | MODE1_AUTO_INCREMENT])?;
+        pwm.bus.write(&[REG_PRESCALE, prescale])?;
+        pwm.bus.write(&[REG_MODE2, MODE2_TOTEM_POLE])?;
+        pwm.all_off()?;
+        pwm.bus.write(&[REG_MODE1, MODE1_AUTO_INCREMENT])?;
+        thread::sleep(OSCILLATOR_STARTUP);
+        pwm.bus
+            .write(&[REG_MODE1, MODE1_AUTO_INCREMENT | MODE1_RESTART])?;
+        info!("PCA9685 running at {:.1}Hz", frequency);
+        Ok(pwm)
+    }
+
+    pub fn num_channels(&self) -> usize {
+        NUM_CHANNELS
+    }
+
+    /// Sets `channel` to `value` as its configuration says: a position in
+    /// `[-1, 1]` for servos, a duty cycle in `[0, 1]` otherwise.
+    pub fn set_channel(&mut self, channel: usize, value: f32) -> Result<(), Error> {
+        check_channel(channel)?;
+        let config = self.channels[channel];
+        match config.kind {
+            PwmKind::Servo => self.set_servo_position(channel, value),
+            PwmKind::Duty if config.inverted => self.set_duty(channel, 1.0 - value.clamp(0.0, 1.0)),
+            PwmKind::Duty => self.set_duty(channel, value),
+        }
+    }
+
+    /// Moves the servo on `channel` to `position` in `[-1, 1]`, between the
+    /// pulses of its configuration.
+    pub fn set_servo_position(&mut self, channel: usize, position: f32) -> Result<(), Error> {
+        check_channel(channel)?;
+        let config = self.channels[channel];
+        let position = if config.inverted { -position } else { position };
+        let fraction = (position.clamp(-1.0, 1.0) + 1.0) / 2.0;
+        let (min, max) = (config.min_pulse_us as f32, config.max_pulse_us as f32);
+        self.set_pulse(channel, min + fraction * (max - min))
+    }
+
+    /// Outputs pulses of `micros` microseconds on `channel`.
+    pub fn set_pulse(&mut self, channel: usize, micros: f32) -> Result<(), Error> {
+        self.set_duty(channel, micros / self.period_us)
+    }
+
+    /// Outputs a duty cycle of `duty` in `[0, 1]` on `channel`, fully on at
+    /// 1 and fully off at 0.
+    pub fn set_duty(&mut self, channel: usize, duty: f32) -> Result<(), Error> {
+        check_channel(channel)?;
+        let duty = if duty.is_nan() {
+            0.0
+        } else {
+            duty.clamp(0.0, 1.0)
+        };
+        let counts = (duty * 4096.0).round() as u16;
+        let (on, off) = match counts {
+            0 => (0, FULL),
+            4096 => (FULL, 0),
+            counts => (0, counts),
+        };
+        let [on_high, on_low] = on.to_be_bytes();
+        let [off_high, off_low] = off.to_be_bytes();
+        self.bus.write(&[
+            REG_LED0 + 4 * channel as u8,
+            on_low,
+            on_high,
+            off_low,
+            off_high,
+        ])
+    }
+
+    /// Switches off `channel`, servos go limp.
+    pub fn off(&mut self, channel: usize) -> Result<(), Error> {
+        self.set_duty(channel, 0.0)
+    }
+
+    pub fn all_off(&mut self) -> Result<(), Error> {
+        self.bus
+            .write(&[REG_ALL_LED_OFF_HIGH, FULL.to_be_bytes()[0]])
+    }
+}
+
+impl Servos for Pca9685 {
+    fn num_servos(&self) -> usize {
+        NUM_CHANNELS
+    }
+
+    fn set_servo(&mut self, index: usize, position: f32) -> Result<(), Error> {
+        self.set_servo_position(index, position)
+    }
+}
+
+impl Drop for Pca9685 {
+    fn drop(&mut self) {
+        info!("Destroying a PCA9685. Ensuring every channel is off...");
+        if let Err(error) = self.all_off() {
+            error!("Could not switch off the PCA9685 channels: {}", error);
+        }
+    }
+}
+
+fn check_channel(channel: usize) -> Result<(), Error> {
+    if channel < NUM_CHANNELS {
+        Ok(())
+    } else {
+        Err(Pca9685Error::InvalidChannel { channel }.into())
+    }
+}
+
+pub const PCA9685_ADDR: u16 = 0x40;
+const NUM_CHANNELS: usize = 16;
+
+const REG_MODE1: u8 = 0x00;
+const REG_MODE2: u8 = 0x01;
+/// `LED0_ON_L`, followed by `ON_H`, `OFF_L` and `OFF_H`, then the same for
+/// the next channels.
+const REG_LED0: u8 = 0x06;
+const REG_ALL_LED_OFF_HIGH: u8 = 0xFD;
+const REG_PRESCALE: u8 = 0xFE;
+
+const MODE1_RESTART: u8 = 0x80;
+const MODE1_AUTO_INCREMENT: u8 = 0x20;
+const MODE1_SLEEP: u8 = 0x10;
+const MODE2_TOTEM_POLE: u8 = 0x04;
+/// The full on or full off bit of the `ON` and `OFF` counts.
+const FULL: u16 = 0x1000;
+
+/// Of the internal oscillator, in hertz.
+const OSCILLATOR_HZ: f32 = 25_000_000.0;
+const OSCILLATOR_STARTUP: Duration = Duration::from_micros(500);
+/// The chip ignores smaller prescalers, about 1.5kHz.
+const MIN_PRESCALE: u8 = 3;
@@ -0,0 +1,407 @@
+//! Driver for the PiBorg PicoBorg Reverse, the two channel motor controller
+//! used by e.g. the DiddyBorg. It speaks the same protocol as the ThunderBorg
+//! with a different command set; the LED is on/off only and there is a
+//! latching emergency power off (EPO) input instead of a battery monitor.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::borg::{
+    self, BoardInfo, BorgCommand, BorgDevice, CommStats, MotorConfig, MotorsConfig, Ping,
+    RecoveryConfig, Response,
+};
+use crate::color::Color;
+use crate::error::Error;
+use crate::motor_driver::{self, MotorDriver};
+
+pub struct ControllerBuilder {
+    bus_path: String,
+    address: u16,
+    recovery: RecoveryConfig,
+    motors: MotorsConfig,
+}
+
+impl Default for ControllerBuilder {
+    fn default() -> Self {
+        ControllerBuilder {
+            bus_path: borg::DEFAULT_I2C_BUS_PATH.into(),
+            address: PICOBORG_REV_SLAVE_ADDR,
+            recovery: RecoveryConfig::default(),
+            motors: MotorsConfig::default(),
+        }
+    }
+}
+
+impl ControllerBuilder {
+    pub fn new() -> Self {
+        ControllerBuilder::default()
+    }
+
+    pub fn bus_path<S: Into<String>>(mut self, bus_path: S) -> Self {
+        self.bus_path = bus_path.into();
+        self
+    }
+
+    pub fn address(mut self, address: u16) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How persistent bus failures are recovered from.
+    pub fn recovery(mut self, config: RecoveryConfig) -> Self {
+        self.recovery = config;
+        self
+    }
+
+    /// `a` configures motor 1 and `b` motor 2.
+    pub fn motors(mut self, config: MotorsConfig) -> Self {
+        self.motors = config;
+        self
+    }
+
+    pub fn build(self) -> Result<Controller, Error> {
+        info!(
+            "Pinging PicoBorg Reverse at i2c bus {} address 0x{:x}",
+            self.bus_path, self.address
+        );
+        let mut controller = Controller {
+            device: BorgDevice::open(&self.bus_path, self.address, I2C_MAX_LEN)?.with_recovery(
+                self.recovery.clone(),
+                Ping {
+                    command: Command::GetId.to_wire(),
+                    id: PICOBORG_REV_ID,
+                },
+            ),
+            motors: self.motors,
+            motor_1_power: 0.0,
+            motor_2_power: 0.0,
+        };
+
+        let response = controller.command_with_response(Command::GetId)?;
+        let id = response.byte(1)?;
+        if id != PICOBORG_REV_ID {
+            return Err(Error::UnexpectedBoardId {
+                board: "PicoBorg Reverse",
+                expected: PICOBORG_REV_ID,
+                id,
+            });
+        }
+        info!("PicoBorg Reverse found.");
+        Ok(controller)
+    }
+}
+
+pub struct Controller {
+    device: BorgDevice,
+    motors: MotorsConfig,
+    motor_1_power: f32,
+    motor_2_power: f32,
+}
+
+impl Controller {
+    /// Opens the PicoBorg Reverse at the default bus and address.
+    pub fn new() -> Result<Self, Error> {
+        ControllerBuilder::new().build()
+    }
+
+    pub fn builder() -> ControllerBuilder {
+        ControllerBuilder::new()
+    }
+
+    /// Bus traffic statistics since the controller was opened, to diagnose
+    /// flaky wiring or bus contention.
+    pub fn comm_stats(&self) -> &CommStats {
+        self.device.comm_stats()
+    }
+
+    pub fn reset_comm_stats(&mut self) {
+        self.device.reset_comm_stats();
+    }
+
+    /// Where the board is and the identifier it reports.
+    pub fn board_info(&mut self) -> Result<BoardInfo, Error> {
+        self.device.board_info("PicoBorg Reverse", &Command::GetId)
+    }
+
+    /// Writes a command the crate doesn't model, e.g. from a newer firmware
+    /// revision.
+    pub fn send_raw(&mut self, command: u8, data: &[u8]) -> Result<(), Error> {
+        self.device.send_raw(command, data)
+    }
+
+    /// Sends a query the crate doesn't model and reads back `read_len`
+    /// bytes, the first one echoing `command`.
+    pub fn query_raw(&mut self, command: u8, read_len: usize) -> Result<Response, Error> {
+        self.device.query_raw(command, read_len)
+    }
+
+    pub fn set_led(&mut self, on: bool) -> Result<(), Error> {
+        self.command(Command::SetLed, &[on_off(on)])
+    }
+
+    pub fn get_led(&mut self) -> Result<bool, Error> {
+        self.get_flag(Command::GetLed)
+    }
+
+    pub fn set_motors(&mut self, power: f32) -> Result<(), Error> {
+        self.set_motor_1(power)?;
+        self.set_motor_2(power)
+    }
+
+    pub fn set_motor_1(&mut self, power: f32) -> Result<(), Error> {
+        // Motor 1 is wired to the board's B channel.
+        self.motor_1_power = self.motor_command(
+            Command::SetMotorBForward,
+            Command::SetMotorBReverse,
+            power,
+            self.motors.a,
+        )?;
+        Ok(())
+    }
+
+    pub fn set_motor_2(&mut self, power: f32) -> Result<(), Error> {
+        self.motor_2_power = self.motor_command(
+            Command::SetMotorAForward,
+            Command::SetMotorAReverse,
+            power,
+            self.motors.b,
+        )?;
+        Ok(())
+    }
+
+    /// Last powers commanded to motors 1 and 2, before any per-motor
+    /// corrections.
+    pub fn motor_powers(&self) -> (f32, f32) {
+        (self.motor_1_power, self.motor_2_power)
+    }
+
+    pub fn get_drive_fault(&mut self) -> Result<bool, Error> {
+        self.get_flag(Command::GetDriveFault)
+    }
+
+    /// True once the EPO input has been broken, the motors stay off until
+    /// `reset_epo()` is called.
+    pub fn get_epo(&mut self) -> Result<bool, Error> {
+        self.get_flag(Command::GetEpo)
+    }
+
+    pub fn reset_epo(&mut self) -> Result<(), Error> {
+        self.command(Command::ResetEpo, &[])
+    }
+
+    /// Ignore the EPO input, for boards without the EPO jumper fitted.
+    pub fn set_epo_ignore(&mut self, ignore: bool) -> Result<(), Error> {
+        self.command(Command::SetEpoIgnore, &[on_off(ignore)])
+    }
+
+    /// Enables the board's own failsafe, switching the motors off if no
+    /// command arrives for a quarter of a second.
+    pub fn set_failsafe(&mut self, enabled: bool) -> Result<(), Error> {
+        self.command(Command::SetFailsafe, &[on_off(enabled)])
+    }
+
+    /// Same as `all_off()`, see `stop_motors()` to keep the LED on.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.all_off()
+    }
+
+    /// Switches off the motors and the LED.
+    pub fn all_off(&mut self) -> Result<(), Error> {
+        self.command(Command::AllOff, &[0])?;
+        self.motor_1_power = 0.0;
+        self.motor_2_power = 0.0;
+        Ok(())
+    }
+
+    /// Sets every motor to zero power, leaving the LED as it is.
+    pub fn stop_motors(&mut self) -> Result<(), Error> {
+        self.command(Command::SetMotorsForward, &[0])?;
+        self.motor_1_power = 0.0;
+        self.motor_2_power = 0.0;
+        Ok(())
+    }
+
+    fn motor_command(
+        &mut self,
+        forward_command: Command,
+        reverse_command: Command,
+        power: f32,
+        config: MotorConfig,
+    ) -> Result<f32, Error> {
+        let power = borg::clamp_motor_power(power);
+        let wire_power = config.to_wire_power(power);
+        let power_bytes = &[borg::motor_power_to_byte(wire_power)?];
+        if wire_power < 0.0 {
+            self.command(reverse_command, power_bytes)?;
+        } else {
+            self.command(forward_command, power_bytes)?;
+        }
+        Ok(power)
+    }
+
+    fn get_flag(&mut self, command: Command) -> Result<bool, Error> {
+        let response = self.command_with_response(command)?;
+        Ok(response.byte(1)? != I2C_VALUE_OFF)
+    }
+
+    fn command_with_response(&mut self, command: Command) -> Result<Response, Error> {
+        self.device.command_with_response(&command)
+    }
+
+    fn command(&mut self, command: Command, data: &[u8]) -> Result<(), Error> {
+        self.device.command(&command, data)
+    }
+}
+
+impl MotorDriver for Controller {
+    fn num_motors(&self) -> usize {
+        2
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        motor_driver::check_motor_index(index, 2)?;
+        if index == 0 {
+            self.set_motor_1(power)
+        } else {
+            self.set_motor_2(power)
+        }
+    }
+
+    fn motor_power(&self, index: usize) -> f32 {
+        match index {
+            0 => self.motor_1_power,
+            1 => self.motor_2_power,
+            _ => 0.0,
+        }
+    }
+
+    /// Motor 1 drives the left side and motor 2 the right.
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        self.set_motor_1(left)?;
+        self.set_motor_2(right)
+    }
+
+    fn stop_all(&mut self) -> Result<(), Error> {
+        self.stop()
+    }
+
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        Ok(Controller::comm_stats(self).clone())
+    }
+
+    /// The board has a single fault flag shared by both motors.
+    fn fault(&mut self, index: usize) -> Result<bool, Error> {
+        motor_driver::check_motor_index(index, 2)?;
+        self.get_drive_fault()
+    }
+
+    /// The LED is on for any colour but `Color::OFF`.
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        self.set_led(color != Color::OFF)
+    }
+}
+
+impl Drop for Controller {
+    fn drop(&mut self) {
+        info!("Destroying a PicoBorg Reverse `Controller`. Ensuring motors are stopped...");
+        if let Err(error) = self.stop() {
+            error!(
+                "Could not run `stop()` when destroying the controller. Error: {}",
+                error
+            );
+            error!("Motors may still be running!");
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum Command {
+    /// Set the LED on or off
+    SetLed,
+    /// Get the LED state
+    GetLed,
+    /// Set motor A PWM rate in a forwards direction
+    SetMotorAForward,
+    /// Set motor A PWM rate in a reverse direction
+    SetMotorAReverse,
+    /// Get motor A direction and PWM rate
+    GetMotorA,
+    /// Set motor B PWM rate in a forwards direction
+    SetMotorBForward,
+    /// Set motor B PWM rate in a reverse direction
+    SetMotorBReverse,
+    /// Get motor B direction and PWM rate
+    GetMotorB,
+    /// Switch everything off
+    AllOff,
+    /// Reset the EPO latch, allowing the motors to move again
+    ResetEpo,
+    /// Get the EPO latch state
+    GetEpo,
+    /// Set whether the EPO input is ignored
+    SetEpoIgnore,
+    /// Get whether the EPO input is ignored
+    GetEpoIgnore,
+    /// Get the drive fault flag, indicates faults such as short-circuits and
+    /// under voltage
+    GetDriveFault,
+    /// Set all motors PWM rate in a forwards direction
+    SetMotorsForward,
+    /// Set all motors PWM rate in a reverse direction
+    SetMotorsReverse,
+    /// Set the failsafe flag, turns the motors off if communication is
+    /// interrupted
+    SetFailsafe,
+    /// Get the failsafe flag
+    GetFailsafe,
+    /// Get the board identifier
+    GetId,
+}
+
+impl Display for Command {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        write!(formatter, "{:?} (0x{:x})", self, self.to_wire())
+    }
+}
+
+impl BorgCommand for Command {
+    #[inline]
+    fn to_wire(&self) -> u8 {
+        match *self {
+            Command::SetLed => 1,
+            Command::GetLed => 2,
+            Command::SetMotorAForward => 3,
+            Command::SetMotorAReverse => 4,
+            Command::GetMotorA => 5,
+            Command::SetMotorBForward => 6,
+            Command::SetMotorBReverse => 7,
+            Command::GetMotorB => 8,
+            Command::AllOff => 9,
+            Command::ResetEpo => 10,
+            Command::GetEpo => 11,
+            Command::SetEpoIgnore => 12,
+            Command::GetEpoIgnore => 13,
+            Command::GetDriveFault => 14,
+            Command::SetMotorsForward => 15,
+            Command::SetMotorsReverse => 16,
+            Command::SetFailsafe => 17,
+            Command::GetFailsafe => 18,
+            Command::GetId => 0x99,
+        }
+    }
+}
+
+#[inline]
+fn on_off(value: bool) -> u8 {
+    if value {
+        I2C_VALUE_ON
+    } else {
+        I2C_VALUE_OFF
+    }
+}
+
+const I2C_VALUE_ON: u8 = 1;
+const I2C_VALUE_OFF: u8 = 0;
+const I2C_MAX_LEN: usize = 4;
+const PICOBORG_REV_ID: u8 = 0x15;
+const PICOBORG_REV_SLAVE_ADDR: u16 = 0x44;
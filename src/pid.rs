@@ -0,0 +1,76 @@
+//! A PID controller for the feedback loops correcting the drive, e.g.
+//! heading hold.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PidConfig {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Bound on the magnitude of the output.
+    pub output_limit: f32,
+    /// Bound on the magnitude of the integral term's contribution to the
+    /// output, so it doesn't wind up while the output saturates.
+    pub integral_limit: f32,
+}
+
+impl Default for PidConfig {
+    fn default() -> Self {
+        PidConfig {
+            kp: 1.0,
+            ki: 0.0,
+            kd: 0.0,
+            output_limit: 1.0,
+            integral_limit: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Pid {
+    config: PidConfig,
+    integral: f32,
+    last_error: Option<f32>,
+}
+
+impl Pid {
+    pub fn new(config: PidConfig) -> Self {
+        Pid {
+            config,
+            integral: 0.0,
+            last_error: None,
+        }
+    }
+
+    pub fn config(&self) -> &PidConfig {
+        &self.config
+    }
+
+    /// Output for `error`, the setpoint minus the measurement, `elapsed`
+    /// after the previous update. The derivative term starts with the
+    /// second update.
+    pub fn update(&mut self, error: f32, elapsed: Duration) -> f32 {
+        let config = &self.config;
+        let dt = elapsed.as_secs_f32();
+        if config.ki != 0.0 {
+            let limit = config.integral_limit.abs() / config.ki.abs();
+            self.integral = (self.integral + error * dt).clamp(-limit, limit);
+        }
+        let derivative = match self.last_error {
+            Some(last_error) if dt > 0.0 => (error - last_error) / dt,
+            _ => 0.0,
+        };
+        self.last_error = Some(error);
+        let output = config.kp * error + config.ki * self.integral + config.kd * derivative;
+        output.clamp(-config.output_limit.abs(), config.output_limit.abs())
+    }
+
+    /// Forgets the integral and the last error, e.g. when the setpoint
+    /// changes or the loop was paused.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_error = None;
+    }
+}
@@ -0,0 +1,825 @@
+//! Motor commands pass through a `Pipeline` of `Stage`s on their way to the
+//! board. Each stage transforms (or rejects) a `DriveCommand`: the safety
+//! checks, the shaping of the requested powers and the per-motor calibration
+//! are all stages, so they can be configured, reordered and tested on their
+//! own.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::arming::{Arming, ArmingError};
+use crate::battery::BatteryGuard;
+use crate::borg::{self, MotorsConfig};
+use crate::bumper::BumperGuard;
+use crate::buzzer::Alerts;
+use crate::clock::{self, Clock};
+use crate::error::Error;
+use crate::estop::EStopLatch;
+use crate::faults::{FaultGuard, FaultLatch};
+use crate::gears::GearSelector;
+use crate::gyro::GyroGuard;
+use crate::obstacle::ObstacleGuard;
+use crate::stall::StallGuard;
+use crate::thermal::ThermalGuard;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error("motor command rejected, the emergency stop is latched")]
+    EStopped,
+    #[error("motor command rejected, the battery is below the cutoff voltage")]
+    BatteryCutoff,
+    #[error("motor command rejected, {cause} latched, clear it with `vrum faults clear`")]
+    FaultLatched { cause: String },
+}
+
+/// Settings for the optional stages, the `[pipeline]` section of the
+/// configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Maximum increase of motor power per second, see `Ramp`. Powers change
+    /// instantly when missing.
+    pub ramp_rate: Option<f32>,
+    /// Maximum absolute motor power, see `Governor`.
+    pub power_limit: Option<f32>,
+    /// Scale factor for the difference between the two sides, see
+    /// `TurnSensitivity`.
+    pub turn_sensitivity: Option<f32>,
+}
+
+/// Powers for motors A and B, in `[-1, 1]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DriveCommand {
+    pub a: f32,
+    pub b: f32,
+}
+
+impl DriveCommand {
+    pub fn new(a: f32, b: f32) -> Self {
+        DriveCommand { a, b }
+    }
+
+    /// Applies `transform` to the power of both motors.
+    pub fn map<F: FnMut(f32) -> f32>(self, mut transform: F) -> Self {
+        DriveCommand {
+            a: transform(self.a),
+            b: transform(self.b),
+        }
+    }
+
+    pub fn is_stop(&self) -> bool {
+        self.a == 0.0 && self.b == 0.0
+    }
+}
+
+/// Where in a `Pipeline` a stage runs. Stages run in phase order, and in the
+/// order they were added within a phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    /// Checks that reject commands outright.
+    Safety,
+    /// Changes to the commanded powers, e.g. ramping.
+    Shaping,
+    /// Caps on the powers, e.g. from faults or obstacles.
+    Limits,
+    /// Per-motor corrections turning commanded powers into the powers written
+    /// to the board.
+    Calibration,
+}
+
+pub trait Stage: Send {
+    /// Identifies the stage, adding a stage replaces any with the same name.
+    fn name(&self) -> &'static str;
+
+    fn phase(&self) -> Phase;
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error>;
+
+    /// Called when the motors were switched off without going through the
+    /// pipeline, so stateful stages can start over.
+    fn reset(&mut self) {}
+}
+
+/// Result of running a command through a `Pipeline`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PipelineOutput {
+    /// Powers after the safety, shaping and limit stages.
+    pub commanded: DriveCommand,
+    /// Powers after calibration, to be written to the board.
+    pub wire: DriveCommand,
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Adds `stage` after the others in its phase, or in place of the stage
+    /// with the same name.
+    pub fn set_stage<S: Stage + 'static>(&mut self, stage: S) {
+        if let Some(existing) = self.stages.iter_mut().find(|s| s.name() == stage.name()) {
+            *existing = Box::new(stage);
+            return;
+        }
+        let position = self
+            .stages
+            .iter()
+            .position(|s| s.phase() > stage.phase())
+            .unwrap_or(self.stages.len());
+        self.stages.insert(position, Box::new(stage));
+    }
+
+    /// Removes the stage called `name`, returning whether there was one.
+    pub fn remove_stage(&mut self, name: &str) -> bool {
+        let len = self.stages.len();
+        self.stages.retain(|stage| stage.name() != name);
+        self.stages.len() != len
+    }
+
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+    }
+
+    /// Runs `command` through every stage, failing with the error of the
+    /// first stage rejecting it. Powers are clamped to `[-1, 1]` on the way
+    /// in and out.
+    pub fn run(&mut self, command: DriveCommand) -> Result<PipelineOutput, Error> {
+        let mut command = command.map(borg::clamp_motor_power);
+        let mut commanded = None;
+        for stage in &mut self.stages {
+            if stage.phase() == Phase::Calibration && commanded.is_none() {
+                commanded = Some(command);
+            }
+            command = stage.apply(command)?.map(borg::clamp_motor_power);
+        }
+        Ok(PipelineOutput {
+            commanded: commanded.unwrap_or(command),
+            wire: command,
+        })
+    }
+}
+
+/// Rejects commands other than stopping while the emergency stop is latched.
+pub struct EStopCheck {
+    latch: EStopLatch,
+}
+
+impl EStopCheck {
+    pub const NAME: &'static str = "estop";
+
+    pub fn new(latch: EStopLatch) -> Self {
+        EStopCheck { latch }
+    }
+}
+
+impl Stage for EStopCheck {
+    fn name(&self) -> &'static str {
+        EStopCheck::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Safety
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        if !command.is_stop() && self.latch.is_latched() {
+            return Err(PipelineError::EStopped.into());
+        }
+        Ok(command)
+    }
+}
+
+/// Rejects commands other than stopping while disarmed, noting whether each
+/// command is a stop so arming can require one.
+pub struct ArmingCheck {
+    arming: Arming,
+}
+
+impl ArmingCheck {
+    pub const NAME: &'static str = "arming";
+
+    pub fn new(arming: Arming) -> Self {
+        ArmingCheck { arming }
+    }
+}
+
+impl Stage for ArmingCheck {
+    fn name(&self) -> &'static str {
+        ArmingCheck::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Safety
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        self.arming.record_command(command.is_stop());
+        if !command.is_stop() && !self.arming.is_armed() {
+            return Err(ArmingError::Disarmed.into());
+        }
+        Ok(command)
+    }
+
+    fn reset(&mut self) {
+        self.arming.record_command(true);
+    }
+}
+
+/// Rejects commands other than stopping while a fault is latched.
+pub struct FaultLatchCheck {
+    latch: FaultLatch,
+}
+
+impl FaultLatchCheck {
+    pub const NAME: &'static str = "fault_latch";
+
+    pub fn new(latch: FaultLatch) -> Self {
+        FaultLatchCheck { latch }
+    }
+}
+
+impl Stage for FaultLatchCheck {
+    fn name(&self) -> &'static str {
+        FaultLatchCheck::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Safety
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        if !command.is_stop() {
+            if let Some(cause) = self.latch.cause() {
+                return Err(PipelineError::FaultLatched { cause }.into());
+            }
+        }
+        Ok(command)
+    }
+}
+
+/// Rejects commands other than stopping while the battery is below its
+/// cutoff voltage.
+pub struct BatteryCutoff {
+    guard: BatteryGuard,
+}
+
+impl BatteryCutoff {
+    pub const NAME: &'static str = "battery_cutoff";
+
+    pub fn new(guard: BatteryGuard) -> Self {
+        BatteryCutoff { guard }
+    }
+}
+
+impl Stage for BatteryCutoff {
+    fn name(&self) -> &'static str {
+        BatteryCutoff::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Safety
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        if !command.is_stop() && self.guard.is_cutoff() {
+            return Err(PipelineError::BatteryCutoff.into());
+        }
+        Ok(command)
+    }
+}
+
+/// Limits how quickly the magnitude of the motor powers grows, to spare the
+/// gearboxes and keep the robot from wheelying. Slowing down is never
+/// delayed. The ramp only progresses as commands come in, so drive loops
+/// have to keep sending them.
+pub struct Ramp {
+    rate: f32,
+    last: DriveCommand,
+    last_at: Option<Instant>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Ramp {
+    pub const NAME: &'static str = "ramp";
+
+    /// `rate` is the maximum increase of power per second.
+    pub fn new(rate: f32) -> Self {
+        Ramp::with_clock(rate, clock::system())
+    }
+
+    /// A ramp timing the commands on `clock`.
+    pub fn with_clock(rate: f32, clock: Arc<dyn Clock>) -> Self {
+        Ramp {
+            rate: rate.max(0.0),
+            last: DriveCommand::default(),
+            last_at: None,
+            clock,
+        }
+    }
+
+    /// Carries on from `powers` rather than from standstill, for replacing a
+    /// ramp while the motors run.
+    pub fn starting_from(mut self, powers: DriveCommand) -> Self {
+        self.last = powers;
+        self.last_at = Some(self.clock.now());
+        self
+    }
+
+    fn step(last: f32, target: f32, max_step: f32) -> f32 {
+        let slowing_down = target.abs() <= last.abs() && target * last >= 0.0;
+        if slowing_down {
+            target
+        } else {
+            // Reversing goes through zero, the drop in power is instant and
+            // only the increase in the new direction is limited.
+            let from = if target * last < 0.0 { 0.0 } else { last };
+            from + (target - from).clamp(-max_step, max_step)
+        }
+    }
+}
+
+impl Stage for Ramp {
+    fn name(&self) -> &'static str {
+        Ramp::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Shaping
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let now = self.clock.now();
+        let elapsed = self
+            .last_at
+            .map_or(RAMP_MAX_INTERVAL, |at| now.duration_since(at))
+            .min(RAMP_MAX_INTERVAL);
+        let max_step = self.rate * elapsed.as_secs_f32();
+        let ramped = DriveCommand {
+            a: Ramp::step(self.last.a, command.a, max_step),
+            b: Ramp::step(self.last.b, command.b, max_step),
+        };
+        self.last = ramped;
+        self.last_at = Some(now);
+        Ok(ramped)
+    }
+
+    fn reset(&mut self) {
+        self.last = DriveCommand::default();
+        self.last_at = None;
+    }
+}
+
+/// Scales the difference between the powers of the two sides, keeping their
+/// mean, so turns are gentler (below 1) or sharper (above 1) for the same
+/// command.
+pub struct TurnSensitivity {
+    sensitivity: f32,
+}
+
+impl TurnSensitivity {
+    pub const NAME: &'static str = "turn_sensitivity";
+
+    pub fn new(sensitivity: f32) -> Self {
+        TurnSensitivity {
+            sensitivity: sensitivity.max(0.0),
+        }
+    }
+}
+
+impl Stage for TurnSensitivity {
+    fn name(&self) -> &'static str {
+        TurnSensitivity::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Shaping
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let mean = (command.a + command.b) / 2.0;
+        Ok(command.map(|power| mean + (power - mean) * self.sensitivity))
+    }
+}
+
+/// Trims the two sides by the correction of a `GyroGuard` while both get
+/// the same power, so the robot drives straight despite mismatched motors.
+/// Any other command disengages the guard.
+pub struct GyroCorrection {
+    guard: GyroGuard,
+}
+
+impl GyroCorrection {
+    pub const NAME: &'static str = "gyro";
+
+    pub fn new(guard: GyroGuard) -> Self {
+        GyroCorrection { guard }
+    }
+}
+
+impl Stage for GyroCorrection {
+    fn name(&self) -> &'static str {
+        GyroCorrection::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Shaping
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let straight = command.a == command.b && !command.is_stop();
+        self.guard.set_engaged(straight);
+        if !straight {
+            return Ok(command);
+        }
+        let correction = self.guard.correction();
+        Ok(DriveCommand::new(
+            command.a - correction,
+            command.b + correction,
+        ))
+    }
+
+    fn reset(&mut self) {
+        self.guard.set_engaged(false);
+    }
+}
+
+/// Caps the absolute power of every motor, e.g. to drive indoors or try out
+/// new code without the robot launching across the room.
+pub struct Governor {
+    limit: f32,
+}
+
+impl Governor {
+    pub const NAME: &'static str = "governor";
+
+    /// `limit` is clamped to `[0, 1]`.
+    pub fn new(limit: f32) -> Self {
+        Governor {
+            limit: borg::clamp_motor_power(limit).max(0.0),
+        }
+    }
+
+    pub fn limit(&self) -> f32 {
+        self.limit
+    }
+}
+
+impl Stage for Governor {
+    fn name(&self) -> &'static str {
+        Governor::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let limit = self.limit;
+        Ok(command.map(|power| power.clamp(-limit, limit)))
+    }
+}
+
+/// Scales the powers by the selected gear, see `vrum::gears`.
+pub struct Gears {
+    selector: GearSelector,
+}
+
+impl Gears {
+    pub const NAME: &'static str = "gears";
+
+    pub fn new(selector: GearSelector) -> Self {
+        Gears { selector }
+    }
+}
+
+impl Stage for Gears {
+    fn name(&self) -> &'static str {
+        Gears::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let scale = self.selector.scale();
+        Ok(command.map(|power| power * scale))
+    }
+}
+
+/// Zeroes the powers driving into a pressed bump switch, see `BumperGuard`.
+pub struct BumperStop {
+    guard: BumperGuard,
+}
+
+impl BumperStop {
+    pub const NAME: &'static str = "bumper";
+
+    pub fn new(guard: BumperGuard) -> Self {
+        BumperStop { guard }
+    }
+}
+
+impl Stage for BumperStop {
+    fn name(&self) -> &'static str {
+        BumperStop::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        Ok(command.map(|power| if self.guard.blocks(power) { 0.0 } else { power }))
+    }
+}
+
+/// Applies the power limit imposed by a `FaultMonitor`.
+pub struct FaultLimit {
+    guard: FaultGuard,
+}
+
+impl FaultLimit {
+    pub const NAME: &'static str = "fault_limit";
+
+    pub fn new(guard: FaultGuard) -> Self {
+        FaultLimit { guard }
+    }
+}
+
+impl Stage for FaultLimit {
+    fn name(&self) -> &'static str {
+        FaultLimit::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let limit = self.guard.power_limit();
+        Ok(command.map(|power| power.clamp(-limit, limit)))
+    }
+}
+
+/// Scales down forward powers when an `ObstacleGuard` reports an object
+/// ahead. Reversing away from it is not limited.
+pub struct ObstacleSlowdown {
+    guard: ObstacleGuard,
+}
+
+impl ObstacleSlowdown {
+    pub const NAME: &'static str = "obstacle";
+
+    pub fn new(guard: ObstacleGuard) -> Self {
+        ObstacleSlowdown { guard }
+    }
+}
+
+impl Stage for ObstacleSlowdown {
+    fn name(&self) -> &'static str {
+        ObstacleSlowdown::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        if command.a <= 0.0 && command.b <= 0.0 {
+            return Ok(command);
+        }
+        let scale = self.guard.forward_scale();
+        Ok(command.map(|power| if power > 0.0 { power * scale } else { power }))
+    }
+}
+
+/// Records the powers commanded for a `StallDetector`, and zeroes those of
+/// the motors it cut.
+pub struct StallCutoff {
+    guard: StallGuard,
+}
+
+impl StallCutoff {
+    pub const NAME: &'static str = "stall";
+
+    pub fn new(guard: StallGuard) -> Self {
+        StallCutoff { guard }
+    }
+}
+
+impl Stage for StallCutoff {
+    fn name(&self) -> &'static str {
+        StallCutoff::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let powers = self.guard.apply(&[command.a, command.b]);
+        Ok(DriveCommand::new(powers[0], powers[1]))
+    }
+
+    fn reset(&mut self) {
+        self.guard.release_all();
+    }
+}
+
+/// Caps the powers while a `ThermalGuard` derates the motors.
+pub struct ThermalDerating {
+    guard: ThermalGuard,
+}
+
+impl ThermalDerating {
+    pub const NAME: &'static str = "thermal";
+
+    pub fn new(guard: ThermalGuard) -> Self {
+        ThermalDerating { guard }
+    }
+}
+
+impl Stage for ThermalDerating {
+    fn name(&self) -> &'static str {
+        ThermalDerating::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        let limit = self.guard.power_limit();
+        Ok(command.map(|power| power.clamp(-limit, limit)))
+    }
+}
+
+/// Sounds the reverse beeper of a `Buzzer` while both sides together drive
+/// backwards, leaving the powers as they are.
+pub struct ReverseAlert {
+    alerts: Alerts,
+}
+
+impl ReverseAlert {
+    pub const NAME: &'static str = "reverse_alert";
+
+    pub fn new(alerts: Alerts) -> Self {
+        ReverseAlert { alerts }
+    }
+}
+
+impl Stage for ReverseAlert {
+    fn name(&self) -> &'static str {
+        ReverseAlert::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Limits
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        // Spinning on the spot isn't reversing.
+        let reversing = (command.a + command.b) / 2.0 < -REVERSE_THRESHOLD;
+        self.alerts.set_reversing(reversing);
+        Ok(command)
+    }
+
+    fn reset(&mut self) {
+        self.alerts.set_reversing(false);
+    }
+}
+
+/// Scales each motor by its `MotorConfig::trim`.
+pub struct Trim {
+    motors: MotorsConfig,
+}
+
+impl Trim {
+    pub const NAME: &'static str = "trim";
+
+    pub fn new(motors: MotorsConfig) -> Self {
+        Trim { motors }
+    }
+}
+
+impl Stage for Trim {
+    fn name(&self) -> &'static str {
+        Trim::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Calibration
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        Ok(DriveCommand {
+            a: self.motors.a.apply_trim(command.a),
+            b: self.motors.b.apply_trim(command.b),
+        })
+    }
+}
+
+/// Zeroes powers within each motor's `MotorConfig::dead_zone` and remaps
+/// the rest above its `MotorConfig::min_power`.
+pub struct DeadZone {
+    motors: MotorsConfig,
+}
+
+impl DeadZone {
+    pub const NAME: &'static str = "dead_zone";
+
+    pub fn new(motors: MotorsConfig) -> Self {
+        DeadZone { motors }
+    }
+}
+
+impl Stage for DeadZone {
+    fn name(&self) -> &'static str {
+        DeadZone::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Calibration
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        Ok(DriveCommand {
+            a: self.motors.a.remap_dead_zone(command.a),
+            b: self.motors.b.remap_dead_zone(command.b),
+        })
+    }
+}
+
+/// Reverses the motors with `MotorConfig::inverted` set.
+pub struct Inversion {
+    motors: MotorsConfig,
+}
+
+impl Inversion {
+    pub const NAME: &'static str = "inversion";
+
+    pub fn new(motors: MotorsConfig) -> Self {
+        Inversion { motors }
+    }
+}
+
+impl Stage for Inversion {
+    fn name(&self) -> &'static str {
+        Inversion::NAME
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Calibration
+    }
+
+    fn apply(&mut self, command: DriveCommand) -> Result<DriveCommand, Error> {
+        Ok(DriveCommand {
+            a: self.motors.a.orient(command.a),
+            b: self.motors.b.orient(command.b),
+        })
+    }
+}
+
+/// The stages every `Controller` starts with: the calibration from `motors`
+/// and those enabled in `config`, timed on `clock`. Safety stages are added
+/// as the guards are attached to the controller.
+pub fn default_pipeline(
+    config: &PipelineConfig,
+    motors: &MotorsConfig,
+    clock: &Arc<dyn Clock>,
+) -> Pipeline {
+    let mut pipeline = Pipeline::new();
+    if let Some(rate) = config.ramp_rate {
+        pipeline.set_stage(Ramp::with_clock(rate, clock.clone()));
+    }
+    if let Some(limit) = config.power_limit {
+        pipeline.set_stage(Governor::new(limit));
+    }
+    if let Some(sensitivity) = config.turn_sensitivity {
+        pipeline.set_stage(TurnSensitivity::new(sensitivity));
+    }
+    pipeline.set_stage(Trim::new(motors.clone()));
+    pipeline.set_stage(DeadZone::new(motors.clone()));
+    pipeline.set_stage(Inversion::new(motors.clone()));
+    pipeline
+}
+
+// Longest time the ramp accounts for between two commands, so the first
+// command after a pause is ramped too.
+const RAMP_MAX_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Mean power of the two sides below which a `ReverseAlert` beeps, so
+/// creeping back while steering doesn't.
+const REVERSE_THRESHOLD: f32 = 0.05;
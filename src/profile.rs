@@ -0,0 +1,46 @@
+use crate::borg::MotorsConfig;
+use crate::pipeline::PipelineConfig;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProfileError {
+    #[error("no driving profile called {name:?} in the configuration")]
+    Unknown { name: String },
+}
+
+/// A named set of driving settings, e.g. "indoor", "race" or "kids", the
+/// `[profiles.<name>]` sections of the configuration file. Settings left out
+/// keep the values from the `[pipeline]` and `[motors]` sections.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub power_limit: Option<f32>,
+    pub ramp_rate: Option<f32>,
+    pub turn_sensitivity: Option<f32>,
+    /// Replaces `MotorConfig::trim` of motor A.
+    pub trim_a: Option<f32>,
+    /// Replaces `MotorConfig::trim` of motor B.
+    pub trim_b: Option<f32>,
+}
+
+impl Profile {
+    /// `base` with the settings of this profile applied on top.
+    pub fn pipeline_config(&self, base: &PipelineConfig) -> PipelineConfig {
+        PipelineConfig {
+            power_limit: self.power_limit.or(base.power_limit),
+            ramp_rate: self.ramp_rate.or(base.ramp_rate),
+            turn_sensitivity: self.turn_sensitivity.or(base.turn_sensitivity),
+        }
+    }
+
+    /// `base` with the trims of this profile applied on top.
+    pub fn motors(&self, base: &MotorsConfig) -> MotorsConfig {
+        let mut motors = base.clone();
+        if let Some(trim) = self.trim_a {
+            motors.a.trim = trim;
+        }
+        if let Some(trim) = self.trim_b {
+            motors.b.trim = trim;
+        }
+        motors
+    }
+}
@@ -0,0 +1,186 @@
+//! Pure pursuit path following: the robot steers along the arc reaching the
+//! point of the path a fixed lookahead distance ahead of it, tracing smooth
+//! curves through a list of waypoints.
+//!
+//! `PurePursuit::update()` turns the current pose, from odometry where the
+//! robot has it, into a velocity command for `DiffDrive::set_velocity()`.
+//! `Motion::follow_path()` does that open loop, dead reckoning the pose
+//! from the commands, `Motion::follow_path_estimated()` closed loop on the
+//! pose from a `PoseEstimator`.
+
+use crate::kinematics::Pose;
+
+/// A point of a path, in metres in the frame of the robot pose.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Waypoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Waypoint {
+    pub fn new(x: f32, y: f32) -> Self {
+        Waypoint { x, y }
+    }
+
+    fn distance_to(&self, pose: &Pose) -> f32 {
+        (self.x - pose.x).hypot(self.y - pose.y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PurePursuitConfig {
+    /// Distance ahead of the robot it steers towards, in metres. Longer
+    /// cuts corners more but oscillates less.
+    pub lookahead: f32,
+    /// Cruising speed in m/s, lowered within the lookahead distance of the
+    /// end of the path.
+    pub speed: f32,
+    /// Distance from the last waypoint at which the path is done, metres.
+    pub goal_tolerance: f32,
+}
+
+impl Default for PurePursuitConfig {
+    fn default() -> Self {
+        PurePursuitConfig {
+            lookahead: 0.3,
+            speed: 0.3,
+            goal_tolerance: 0.05,
+        }
+    }
+}
+
+/// Follows a path through waypoints, in order.
+#[derive(Clone, Debug)]
+pub struct PurePursuit {
+    waypoints: Vec<Waypoint>,
+    config: PurePursuitConfig,
+    /// Index of the segment the lookahead point was last found on, the
+    /// segment from waypoint `segment` to `segment + 1`. The search never
+    /// goes back, so paths crossing themselves are followed in order.
+    segment: usize,
+}
+
+impl PurePursuit {
+    pub fn new(waypoints: Vec<Waypoint>, config: PurePursuitConfig) -> Self {
+        PurePursuit {
+            waypoints,
+            config,
+            segment: 0,
+        }
+    }
+
+    pub fn waypoints(&self) -> &[Waypoint] {
+        &self.waypoints
+    }
+
+    pub fn config(&self) -> &PurePursuitConfig {
+        &self.config
+    }
+
+    /// Starts again from the first waypoint.
+    pub fn reset(&mut self) {
+        self.segment = 0;
+    }
+
+    /// True once the robot at `pose` is within the goal tolerance of the
+    /// last waypoint, or if there are no waypoints.
+    pub fn is_done(&self, pose: &Pose) -> bool {
+        match self.waypoints.last() {
+            Some(goal) => {
+                self.segment + 2 >= self.waypoints.len()
+                    && goal.distance_to(pose) <= self.config.goal_tolerance
+            }
+            None => true,
+        }
+    }
+
+    /// The velocity command `(linear, angular)` in m/s and rad/s steering
+    /// the robot at `pose` along the path, `None` once it is done.
+    pub fn update(&mut self, pose: &Pose) -> Option<(f32, f32)> {
+        if self.is_done(pose) {
+            return None;
+        }
+        let target = self.lookahead_point(pose);
+        let (x, y) = pose.to_local(target.x, target.y);
+        let distance_squared = x * x + y * y;
+        if distance_squared <= f32::EPSILON {
+            return None;
+        }
+        let goal = self.waypoints[self.waypoints.len() - 1];
+        let to_goal = goal.distance_to(pose);
+        let lookahead = self.config.lookahead.max(f32::EPSILON);
+        let speed = if self.segment + 2 >= self.waypoints.len() {
+            self.config.speed * (to_goal / lookahead).min(1.0)
+        } else {
+            self.config.speed
+        };
+        let curvature = 2.0 * y / distance_squared;
+        Some((speed, speed * curvature))
+    }
+
+    /// The furthest point along the path at the lookahead distance from
+    /// `pose`. If the path is out of reach, the start of the current segment
+    /// to get back on it, and within the lookahead of the end, the end.
+    fn lookahead_point(&mut self, pose: &Pose) -> Waypoint {
+        let last = self.waypoints.len() - 1;
+        if last == 0 {
+            return self.waypoints[0];
+        }
+        let mut found = None;
+        for segment in self.segment..last {
+            let (start, end) = (self.waypoints[segment], self.waypoints[segment + 1]);
+            if let Some(point) = circle_intersection(start, end, pose, self.config.lookahead) {
+                found = Some((segment, point));
+            }
+        }
+        match found {
+            Some((segment, point)) => {
+                self.segment = segment;
+                point
+            }
+            None if self.waypoints[last].distance_to(pose) <= self.config.lookahead => {
+                self.segment = last - 1;
+                self.waypoints[last]
+            }
+            None => {
+                let start = self.waypoints[self.segment];
+                let end = self.waypoints[self.segment + 1];
+                if start.distance_to(pose) < self.config.lookahead {
+                    end
+                } else {
+                    start
+                }
+            }
+        }
+    }
+}
+
+/// The point ahead along the segment from `start` to `end` at `radius` from
+/// `pose`, if any.
+fn circle_intersection(
+    start: Waypoint,
+    end: Waypoint,
+    pose: &Pose,
+    radius: f32,
+) -> Option<Waypoint> {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let (fx, fy) = (start.x - pose.x, start.y - pose.y);
+    let a = dx * dx + dy * dy;
+    if a <= f32::EPSILON {
+        return None;
+    }
+    let b = 2.0 * (fx * dx + fy * dy);
+    let c = fx * fx + fy * fy - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // The other root is the intersection behind the robot.
+    let t = (-b + discriminant.sqrt()) / (2.0 * a);
+    if (0.0..=1.0).contains(&t) {
+        Some(Waypoint::new(start.x + t * dx, start.y + t * dy))
+    } else {
+        None
+    }
+}
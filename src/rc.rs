@@ -0,0 +1,853 @@
+//! Hobby RC receivers, so the robot can be driven with a standard RC
+//! transmitter as a backup to WiFi teleop. Two kinds of receiver output are
+//! read, set in the `[rc]` section of the configuration file:
+//!
+//! - SBUS, a serial stream of 16 channels on the Pi's UART. The signal is
+//!   inverted, so it needs an inverter between the receiver and the RX pin
+//!   unless the receiver has an uninverted output. SBUS runs at 100000 baud,
+//!   which the kernel's serial API can't set, so either use a USB adapter
+//!   that can, or set `init_uart_clock=41666667` in `/boot/config.txt` and
+//!   keep `baud_rate` at its default of 115200, so the UART runs at 100000.
+//! - PPM, the pulse train of up to 8 channels on one GPIO pin, timed from its
+//!   rising edges.
+//!
+//! Channels are numbered from 1 like on the transmitter. By default channel
+//! 2 drives forwards and backwards, channel 1 steers and the switch on
+//! channel 5 arms the motors:
+//!
+//! ```toml
+//! [rc]
+//! protocol = "sbus"
+//! device = "/dev/serial0"
+//!
+//! [rc.channels]
+//! throttle = 2
+//! steering = 1
+//! arm = 5
+//! led = 6
+//! ```
+//!
+//! The motors stop when the receiver reports it lost the transmitter, when
+//! it stops sending frames for `failsafe_ms`, and while disarmed. When the
+//! configuration requires arming, see `vrum::arming`, flipping the arm
+//! switch up with the sticks centred arms the motors.
+//!
+//! With a `cruise` channel, flipping its switch up latches the speed, see
+//! `vrum::cruise`, and flipping it down brakes out of it.
+//!
+//! With `gear_up` and `gear_down` channels, pressing their buttons shifts
+//! the gears of `vrum::gears` up and down.
+//!
+//! With `pan` and `tilt` channels, usually the other stick, the camera of
+//! `vrum::pan_tilt` points where the stick is.
+//!
+//! Outputs of `vrum::gpio_outputs` listed in `[rc.channels.aux]`, like
+//! `headlights = 9`, are on while their switch is up.
+//!
+//! With a `record` channel, flipping its switch up starts recording what the
+//! sticks drive into a new file in the `routes` directory, and flipping it
+//! down saves it, to teach the robot a route by driving it once then replay
+//! it with `vrum replay`.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use rppal::uart::{Parity, Uart};
+
+use crate::arming::{Arming, ArmingError};
+use crate::black_box;
+use crate::color::Color;
+use crate::cruise::CruiseControl;
+use crate::error::Error;
+use crate::gears::GearSelector;
+use crate::gpio_outputs::{GpioOutputs, OutputState};
+use crate::latency::LoopTimer;
+use crate::motor_driver::MotorDriver;
+use crate::pan_tilt::PanTilt;
+use crate::recorder::{RecordedCommand, Recorder};
+use crate::shutdown::Shutdown;
+use crate::sticks::StickConfig;
+use crate::telemetry;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RcError {
+    #[error("driving by RC needs an [rc] section in the configuration")]
+    NotConfigured,
+    #[error("RC channel {channel} does not exist, {protocol} has channels 1 to {max}")]
+    InvalidChannel {
+        channel: usize,
+        protocol: RcProtocol,
+        max: usize,
+    },
+}
+
+/// How the receiver sends the channels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RcProtocol {
+    /// Serial, on `device`.
+    #[default]
+    Sbus,
+    /// Pulse position modulation, on the GPIO `pin`.
+    Ppm,
+}
+
+impl RcProtocol {
+    /// The number of channels it carries.
+    pub fn max_channels(self) -> usize {
+        match self {
+            RcProtocol::Sbus => SBUS_CHANNELS,
+            RcProtocol::Ppm => PPM_MAX_CHANNELS,
+        }
+    }
+}
+
+impl Display for RcProtocol {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            RcProtocol::Sbus => "SBUS",
+            RcProtocol::Ppm => "PPM",
+        })
+    }
+}
+
+/// The `[rc]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RcConfig {
+    pub protocol: RcProtocol,
+    /// Serial port of an SBUS receiver.
+    pub device: String,
+    pub baud_rate: u32,
+    /// BCM number of the GPIO pin of a PPM receiver.
+    pub pin: u8,
+    pub channels: RcChannels,
+    /// Sticks closer than this to the centre count as centred.
+    pub deadband: f32,
+    /// Stop when no frame arrives for this long.
+    pub failsafe_ms: u64,
+    /// Where the routes recorded with the `record` switch go.
+    pub routes: PathBuf,
+}
+
+impl Default for RcConfig {
+    fn default() -> Self {
+        RcConfig {
+            protocol: RcProtocol::Sbus,
+            device: "/dev/serial0".into(),
+            baud_rate: 115_200,
+            pin: 18,
+            channels: RcChannels::default(),
+            deadband: 0.05,
+            failsafe_ms: 250,
+            routes: "/var/lib/vrum/routes".into(),
+        }
+    }
+}
+
+/// What each channel controls, numbered from 1.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RcChannels {
+    /// Forwards and backwards.
+    pub throttle: usize,
+    /// Right and left.
+    pub steering: usize,
+    /// A switch, the motors only run with it up. Always armed without one.
+    pub arm: Option<usize>,
+    /// Switches of the outputs of `[gpio_outputs]`, by name.
+    pub aux: BTreeMap<String, usize>,
+    /// A switch latching the speed while up.
+    pub cruise: Option<usize>,
+    /// A button shifting down a gear.
+    pub gear_down: Option<usize>,
+    /// A button shifting up a gear.
+    pub gear_up: Option<usize>,
+    /// A knob or slider picking the colour of the LED.
+    pub led: Option<usize>,
+    /// Left and right of the camera.
+    pub pan: Option<usize>,
+    /// A switch recording the route driven while up.
+    pub record: Option<usize>,
+    /// Up and down of the camera.
+    pub tilt: Option<usize>,
+}
+
+impl Default for RcChannels {
+    fn default() -> Self {
+        RcChannels {
+            throttle: 2,
+            steering: 1,
+            arm: Some(5),
+            aux: BTreeMap::new(),
+            cruise: None,
+            gear_down: None,
+            gear_up: None,
+            led: None,
+            pan: None,
+            record: None,
+            tilt: None,
+        }
+    }
+}
+
+impl RcConfig {
+    /// Checks the channels exist in the protocol.
+    pub fn validate(&self) -> Result<(), Error> {
+        let max = self.protocol.max_channels();
+        let channels = &self.channels;
+        let used = [
+            Some(channels.throttle),
+            Some(channels.steering),
+            channels.arm,
+            channels.cruise,
+            channels.gear_down,
+            channels.gear_up,
+            channels.led,
+            channels.pan,
+            channels.record,
+            channels.tilt,
+        ];
+        for &channel in used.iter().flatten().chain(channels.aux.values()) {
+            if channel == 0 || channel > max {
+                return Err(RcError::InvalidChannel {
+                    channel,
+                    protocol: self.protocol,
+                    max,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// What `frame` asks of the robot, the sticks shaped by `sticks`.
+    /// Missing channels read as centred.
+    pub fn command(&self, frame: &RcFrame, sticks: &StickConfig) -> RcCommand {
+        let stick = |channel| {
+            let value = frame.channel(channel).unwrap_or(0.0);
+            if value.abs() < self.deadband {
+                0.0
+            } else {
+                value
+            }
+        };
+        let (left, right) =
+            sticks.drive(stick(self.channels.throttle), stick(self.channels.steering));
+        let switch =
+            |channel: Option<usize>| channel.is_some_and(|channel| switch_up(frame, channel));
+        RcCommand {
+            left,
+            right,
+            armed: self.channels.arm.is_none() || switch(self.channels.arm),
+            camera: match (self.channels.pan, self.channels.tilt) {
+                (None, None) => None,
+                (pan, tilt) => Some((pan.map_or(0.0, stick), tilt.map_or(0.0, stick))),
+            },
+            cruise: switch(self.channels.cruise),
+            gear_down: switch(self.channels.gear_down),
+            gear_up: switch(self.channels.gear_up),
+            led: self
+                .channels
+                .led
+                .and_then(|led| frame.channel(led))
+                .map(|value| Color::from_hsv(led_hue(value), 1.0, 1.0)),
+            record: switch(self.channels.record),
+        }
+    }
+
+    /// The outputs of the `aux` channels, and whether their switch is up in
+    /// `frame`.
+    pub fn aux_switches(&self, frame: &RcFrame) -> Vec<(&str, bool)> {
+        self.channels
+            .aux
+            .iter()
+            .map(|(name, &channel)| (name.as_str(), switch_up(frame, channel)))
+            .collect()
+    }
+}
+
+/// Whether the switch on `channel` is up in `frame`, down if missing.
+fn switch_up(frame: &RcFrame, channel: usize) -> bool {
+    frame.channel(channel).unwrap_or(-1.0) > 0.5
+}
+
+/// The hue of a knob at `value`, in steps of 15 degrees so the noise on the
+/// channel doesn't keep changing the colour.
+fn led_hue(value: f32) -> f32 {
+    ((value + 1.0) * 12.0).round() * 15.0
+}
+
+/// The channels of one frame from the receiver, each in `[-1, 1]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RcFrame {
+    pub channels: Vec<f32>,
+    /// The receiver lost the transmitter, the channels hold its failsafe
+    /// positions.
+    pub failsafe: bool,
+}
+
+impl RcFrame {
+    /// Channel `number`, from 1.
+    pub fn channel(&self, number: usize) -> Option<f32> {
+        self.channels.get(number.checked_sub(1)?).copied()
+    }
+}
+
+/// What the transmitter asks of the robot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RcCommand {
+    pub left: f32,
+    pub right: f32,
+    pub armed: bool,
+    /// Where the pan and tilt stick is, with either channel.
+    pub camera: Option<(f32, f32)>,
+    /// The cruise switch is up.
+    pub cruise: bool,
+    /// The gear down button is pressed.
+    pub gear_down: bool,
+    /// The gear up button is pressed.
+    pub gear_up: bool,
+    pub led: Option<Color>,
+    /// The record switch is up.
+    pub record: bool,
+}
+
+/// Decodes one SBUS frame, `None` if it is not one.
+pub fn decode_sbus(frame: &[u8]) -> Option<RcFrame> {
+    if frame.len() != SBUS_FRAME_LEN
+        || frame[0] != SBUS_HEADER
+        || !SBUS_FOOTERS.contains(&frame[SBUS_FRAME_LEN - 1])
+    {
+        return None;
+    }
+    // 16 channels of 11 bits, least significant bit first.
+    let data = &frame[1..SBUS_FLAGS];
+    let channels = (0..SBUS_CHANNELS)
+        .map(|channel| {
+            let bit = channel * 11;
+            let bytes = data[bit / 8] as u32
+                | (data[bit / 8 + 1] as u32) << 8
+                | (data.get(bit / 8 + 2).copied().unwrap_or(0) as u32) << 16;
+            let raw = (bytes >> (bit % 8)) & 0x7ff;
+            normalize(raw as f32, SBUS_MIN, SBUS_MAX)
+        })
+        .collect();
+    Some(RcFrame {
+        channels,
+        failsafe: frame[SBUS_FLAGS] & (SBUS_FAILSAFE | SBUS_FRAME_LOST) != 0,
+    })
+}
+
+/// Encodes the first 16 channels of `frame` as SBUS, e.g. to test a decoder.
+pub fn encode_sbus(frame: &RcFrame) -> [u8; SBUS_FRAME_LEN] {
+    let mut encoded = [0; SBUS_FRAME_LEN];
+    encoded[0] = SBUS_HEADER;
+    for (channel, &value) in frame.channels.iter().take(SBUS_CHANNELS).enumerate() {
+        let raw = denormalize(value, SBUS_MIN, SBUS_MAX) as u32 & 0x7ff;
+        let bit = channel * 11;
+        let bytes = raw << (bit % 8);
+        for offset in 0..3 {
+            if let Some(byte) = encoded[1..SBUS_FLAGS].get_mut(bit / 8 + offset) {
+                *byte |= (bytes >> (8 * offset)) as u8;
+            }
+        }
+    }
+    if frame.failsafe {
+        encoded[SBUS_FLAGS] = SBUS_FAILSAFE;
+    }
+    encoded
+}
+
+/// Finds the SBUS frames in a stream of bytes. Noise can pass for a frame,
+/// so a frame is only returned when the previous one ended right before it.
+#[derive(Clone, Debug, Default)]
+pub struct SbusDecoder {
+    buffer: Vec<u8>,
+    synced: bool,
+}
+
+impl SbusDecoder {
+    pub fn new() -> Self {
+        SbusDecoder::default()
+    }
+
+    /// Adds the next byte, returning the frame it completes.
+    pub fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        if self.buffer.is_empty() && byte != SBUS_HEADER {
+            self.synced = false;
+            return None;
+        }
+        self.buffer.push(byte);
+        if self.buffer.len() < SBUS_FRAME_LEN {
+            return None;
+        }
+        match decode_sbus(&self.buffer) {
+            Some(frame) => {
+                self.buffer.clear();
+                Some(frame).filter(|_| std::mem::replace(&mut self.synced, true))
+            }
+            None => {
+                // Out of sync, start again from the next header byte.
+                let next = self.buffer[1..]
+                    .iter()
+                    .position(|&byte| byte == SBUS_HEADER)
+                    .map_or(self.buffer.len(), |position| position + 1);
+                self.buffer.drain(..next);
+                self.synced = false;
+                None
+            }
+        }
+    }
+}
+
+/// Times the channels of a PPM pulse train from its rising edges.
+#[derive(Clone, Debug, Default)]
+pub struct PpmDecoder {
+    last_edge: Option<Duration>,
+    channels: Vec<f32>,
+    synced: bool,
+}
+
+impl PpmDecoder {
+    pub fn new() -> Self {
+        PpmDecoder::default()
+    }
+
+    /// A rising edge at `at`, returning the frame its sync gap completes.
+    pub fn edge(&mut self, at: Duration) -> Option<RcFrame> {
+        let interval = at.checked_sub(self.last_edge.replace(at)?)?;
+        let micros = interval.as_micros() as f32;
+        if interval >= PPM_SYNC_GAP {
+            let channels = std::mem::take(&mut self.channels);
+            let complete = self.synced && channels.len() >= PPM_MIN_CHANNELS;
+            self.synced = true;
+            return if complete {
+                Some(RcFrame {
+                    channels,
+                    failsafe: false,
+                })
+            } else {
+                None
+            };
+        }
+        if !self.synced || !(PPM_MIN_PULSE..=PPM_MAX_PULSE).contains(&micros) {
+            // A glitch, wait for the next sync gap.
+            self.synced = false;
+            self.channels.clear();
+        } else if self.channels.len() < PPM_MAX_CHANNELS {
+            self.channels.push(normalize(micros, 1000.0, 2000.0));
+        }
+        None
+    }
+}
+
+fn normalize(value: f32, min: f32, max: f32) -> f32 {
+    ((value - min) / (max - min) * 2.0 - 1.0).clamp(-1.0, 1.0)
+}
+
+fn denormalize(value: f32, min: f32, max: f32) -> f32 {
+    ((value.clamp(-1.0, 1.0) + 1.0) / 2.0 * (max - min) + min).round()
+}
+
+type Latest = Arc<Mutex<Option<(Instant, RcFrame)>>>;
+
+/// Reads an RC receiver in the background, keeping the latest frame.
+pub struct RcReceiver {
+    latest: Latest,
+    failsafe: Duration,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    _pin: Option<InputPin>,
+    arming: Option<Arming>,
+    sticks: StickConfig,
+    cruise: CruiseControl,
+    gears: Option<GearSelector>,
+    pan_tilt: Option<PanTilt>,
+    outputs: Option<GpioOutputs>,
+}
+
+impl RcReceiver {
+    /// Opens the receiver in `config`.
+    pub fn spawn(config: &RcConfig) -> Result<Self, Error> {
+        config.validate()?;
+        match config.protocol {
+            RcProtocol::Sbus => {
+                info!(
+                    "Reading SBUS on {} at {} baud",
+                    config.device, config.baud_rate
+                );
+                let mut uart =
+                    Uart::with_path(&config.device, config.baud_rate, Parity::Even, 8, 2)?;
+                // Return whatever arrived at least every so often, to notice a stop.
+                uart.set_read_mode(0, READ_TIMEOUT)?;
+                RcReceiver::spawn_sbus_reader(config, move |buffer| Ok(uart.read(buffer)?))
+            }
+            RcProtocol::Ppm => {
+                info!("Reading PPM on GPIO {}", config.pin);
+                let mut pin = Gpio::new()?.get(config.pin)?.into_input();
+                let latest = Latest::default();
+                let callback_latest = latest.clone();
+                let mut decoder = PpmDecoder::new();
+                pin.set_async_interrupt(Trigger::RisingEdge, None, move |event| {
+                    if let Some(frame) = decoder.edge(event.timestamp) {
+                        store(&callback_latest, frame);
+                    }
+                })?;
+                Ok(RcReceiver {
+                    latest,
+                    failsafe: Duration::from_millis(config.failsafe_ms),
+                    running: Arc::new(AtomicBool::new(false)),
+                    thread: None,
+                    _pin: Some(pin),
+                    arming: None,
+                    sticks: StickConfig::default(),
+                    cruise: CruiseControl::new(),
+                    gears: None,
+                    pan_tilt: None,
+                    outputs: None,
+                })
+            }
+        }
+    }
+
+    /// `read` fills a buffer with the next bytes of SBUS, returning how
+    /// many, 0 when nothing arrived for a while.
+    pub fn spawn_sbus_reader<F>(config: &RcConfig, mut read: F) -> Result<Self, Error>
+    where
+        F: FnMut(&mut [u8]) -> Result<usize, Error> + Send + 'static,
+    {
+        let latest = Latest::default();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_latest = latest.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-rc".into())
+            .spawn(move || {
+                let mut decoder = SbusDecoder::new();
+                let mut buffer = [0u8; 64];
+                while thread_running.load(Ordering::SeqCst) {
+                    let len = match read(&mut buffer) {
+                        Ok(len) => len,
+                        Err(error) => {
+                            warn!("Could not read the RC receiver: {}", error);
+                            thread::sleep(READ_TIMEOUT);
+                            continue;
+                        }
+                    };
+                    for &byte in &buffer[..len] {
+                        if let Some(frame) = decoder.push(byte) {
+                            store(&thread_latest, frame);
+                        }
+                    }
+                }
+            })?;
+
+        Ok(RcReceiver {
+            latest,
+            failsafe: Duration::from_millis(config.failsafe_ms),
+            running,
+            thread: Some(thread),
+            _pin: None,
+            arming: None,
+            sticks: StickConfig::default(),
+            cruise: CruiseControl::new(),
+            gears: None,
+            pan_tilt: None,
+            outputs: None,
+        })
+    }
+
+    /// The latest frame, `None` if it is stale or the receiver is in
+    /// failsafe.
+    pub fn frame(&self) -> Option<RcFrame> {
+        self.latest
+            .lock()
+            .ok()?
+            .as_ref()
+            .filter(|(time, frame)| time.elapsed() < self.failsafe && !frame.failsafe)
+            .map(|(_, frame)| frame.clone())
+    }
+
+    /// Arms `arming` when the arm switch is flipped up with the sticks
+    /// centred, and disarms it when the switch is down or the signal lost.
+    pub fn set_arming(&mut self, arming: Arming) {
+        self.arming = Some(arming);
+    }
+
+    /// Shapes the sticks with `sticks`, linear by default.
+    pub fn set_sticks(&mut self, sticks: StickConfig) {
+        self.sticks = sticks;
+    }
+
+    /// Cruise control of the `cruise` switch, e.g. with the guards it
+    /// cancels on.
+    pub fn set_cruise(&mut self, cruise: CruiseControl) {
+        self.cruise = cruise;
+    }
+
+    /// Shifts `gears` with the `gear_up` and `gear_down` buttons.
+    pub fn set_gears(&mut self, gears: GearSelector) {
+        self.gears = Some(gears);
+    }
+
+    /// Aims `pan_tilt` with the `pan` and `tilt` channels.
+    pub fn set_pan_tilt(&mut self, pan_tilt: PanTilt) {
+        self.pan_tilt = Some(pan_tilt);
+    }
+
+    /// Switches `outputs` with the `aux` channels.
+    pub fn set_outputs(&mut self, outputs: GpioOutputs) {
+        self.outputs = Some(outputs);
+    }
+
+    /// Drives `driver` from the transmitter until shut down, stopping the
+    /// motors while the signal is lost or the motors are disarmed, and
+    /// records routes with the record switch.
+    pub fn drive<D: MotorDriver + ?Sized>(
+        &self,
+        config: &RcConfig,
+        driver: &mut D,
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        let mut state = None;
+        let mut switch_up = false;
+        let mut led = None;
+        let mut recording = false;
+        let mut route = None;
+        let mut cruise = self.cruise.clone();
+        let mut cruise_switch = false;
+        let mut gear_buttons = (false, false);
+        let mut aux_switches = BTreeMap::new();
+        let mut timer = LoopTimer::new("RC", DRIVE_PERIOD);
+        let result: Result<(), Error> = loop {
+            timer.tick();
+            let frame = self.frame();
+            let command = frame
+                .as_ref()
+                .map(|frame| config.command(frame, &self.sticks));
+            let flipped_up = !switch_up && command.is_some_and(|command| command.armed);
+            switch_up = command.is_some_and(|command| command.armed);
+            if let Some(ref arming) = self.arming {
+                if !switch_up {
+                    arming.disarm();
+                } else if flipped_up {
+                    let centred =
+                        command.is_some_and(|command| command.left == 0.0 && command.right == 0.0);
+                    let armed = if centred {
+                        arming.arm()
+                    } else {
+                        Err(ArmingError::NotIdle.into())
+                    };
+                    if let Err(error) = armed {
+                        warn!("Not arming, flip the switch again: {}", error);
+                    }
+                }
+            }
+            let armed = switch_up && self.arming.as_ref().is_none_or(Arming::is_armed);
+            let next = match command {
+                None => RcState::SignalLost,
+                Some(_) if !armed => RcState::Disarmed,
+                Some(_) => RcState::Armed,
+            };
+            if state != Some(next) {
+                match next {
+                    RcState::SignalLost => warn!("RC signal lost, stopping"),
+                    RcState::Disarmed => info!("RC disarmed"),
+                    RcState::Armed => info!("RC armed"),
+                }
+                state = Some(next);
+            }
+            // Kept through a loss of signal, during which the route stops.
+            if let Some(command) = command {
+                if command.record && !recording {
+                    route = match RouteRecording::start(&config.routes) {
+                        Ok(route) => Some(route),
+                        Err(error) => {
+                            warn!("Could not record the route: {}", error);
+                            None
+                        }
+                    };
+                } else if !command.record && recording {
+                    if let Some(route) = route.take() {
+                        route.finish();
+                    }
+                }
+                recording = command.record;
+            }
+            let sides = match command {
+                Some(command) if armed => {
+                    if command.cruise && !cruise_switch {
+                        cruise.engage(command.left, command.right);
+                    } else if !command.cruise {
+                        cruise.cancel("braked");
+                    }
+                    cruise.update(command.left, command.right)
+                }
+                _ => {
+                    cruise.cancel("stopped");
+                    (0.0, 0.0)
+                }
+            };
+            if let Some(command) = command {
+                cruise_switch = command.cruise;
+                if let Some(ref gears) = self.gears {
+                    if command.gear_up && !gear_buttons.0 {
+                        gears.shift_up();
+                    }
+                    if command.gear_down && !gear_buttons.1 {
+                        gears.shift_down();
+                    }
+                }
+                gear_buttons = (command.gear_up, command.gear_down);
+                if let (Some(pan_tilt), Some((pan, tilt))) = (&self.pan_tilt, command.camera) {
+                    pan_tilt.aim(pan, tilt);
+                }
+            }
+            if let (Some(outputs), Some(frame)) = (&self.outputs, &frame) {
+                for (name, up) in config.aux_switches(frame) {
+                    if aux_switches.insert(name, up) != Some(up) {
+                        let state = if up {
+                            OutputState::On
+                        } else {
+                            OutputState::Off
+                        };
+                        if let Err(error) = outputs.set(name, state) {
+                            warn!("Could not switch {}: {}", name, error);
+                        }
+                    }
+                }
+            }
+            if let Some(ref mut recorded) = route {
+                if let Err(error) = recorded.record(sides) {
+                    warn!("Could not record the route, stopped recording: {}", error);
+                    route = None;
+                }
+            }
+            let step = driver.set_sides(sides.0, sides.1);
+            let color = command.and_then(|command| command.led);
+            let step = match color {
+                Some(color) if led != Some(color) => step.and_then(|()| {
+                    led = Some(color);
+                    driver.set_led_color(color)
+                }),
+                _ => step,
+            };
+            if let Err(error) = step.and_then(|()| shutdown.sleep(DRIVE_PERIOD)) {
+                break Err(error);
+            }
+        };
+        if let Some(route) = route {
+            route.finish();
+        }
+        let stopped = driver.set_sides(0.0, 0.0);
+        result.and(stopped)
+    }
+}
+
+/// A route being recorded with the record switch.
+struct RouteRecording {
+    recorder: Recorder,
+    path: PathBuf,
+    last: Option<(f32, f32)>,
+}
+
+impl RouteRecording {
+    /// Starts recording to a new file in `dir`.
+    fn start(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "vrum-route-{}.jsonl",
+            black_box::utc_time(telemetry::unix_timestamp())
+        ));
+        Ok(RouteRecording {
+            recorder: Recorder::create(&path)?,
+            path,
+            last: None,
+        })
+    }
+
+    /// Records the powers of the sides, when they change.
+    fn record(&mut self, (left, right): (f32, f32)) -> Result<(), Error> {
+        if self.last != Some((left, right)) {
+            self.recorder
+                .record(&RecordedCommand::SetSides { left, right })?;
+            self.last = Some((left, right));
+        }
+        Ok(())
+    }
+
+    /// Ends the route stopped and saves it.
+    fn finish(mut self) {
+        let finished = self
+            .recorder
+            .record(&RecordedCommand::StopMotors)
+            .and_then(|()| self.recorder.flush());
+        match finished {
+            Ok(()) => info!(
+                "Recorded a route to {}, play it back with `vrum replay`",
+                self.path.display()
+            ),
+            Err(error) => warn!(
+                "Could not save the route {}: {}",
+                self.path.display(),
+                error
+            ),
+        }
+    }
+}
+
+impl Drop for RcReceiver {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("RC receiver thread panicked");
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RcState {
+    SignalLost,
+    Disarmed,
+    Armed,
+}
+
+fn store(latest: &Latest, frame: RcFrame) {
+    // The frame is replaced whole, a poisoned one is still consistent.
+    let mut latest = latest.lock().unwrap_or_else(|p| p.into_inner());
+    *latest = Some((Instant::now(), frame));
+}
+
+pub const SBUS_FRAME_LEN: usize = 25;
+const SBUS_HEADER: u8 = 0x0f;
+/// SBUS2 receivers cycle the footer through telemetry slots.
+const SBUS_FOOTERS: [u8; 5] = [0x00, 0x04, 0x14, 0x24, 0x34];
+const SBUS_CHANNELS: usize = 16;
+/// Index of the flags byte, after the channels.
+const SBUS_FLAGS: usize = 23;
+const SBUS_FRAME_LOST: u8 = 0x04;
+const SBUS_FAILSAFE: u8 = 0x08;
+/// Raw values of the sticks at their ends, 988us and 2012us.
+const SBUS_MIN: f32 = 172.0;
+const SBUS_MAX: f32 = 1811.0;
+const PPM_MIN_CHANNELS: usize = 4;
+const PPM_MAX_CHANNELS: usize = 8;
+/// Pulses are 1000us to 2000us, give or take the trims.
+const PPM_MIN_PULSE: f32 = 800.0;
+const PPM_MAX_PULSE: f32 = 2200.0;
+/// A gap this long separates frames.
+const PPM_SYNC_GAP: Duration = Duration::from_micros(2700);
+const READ_TIMEOUT: Duration = Duration::from_millis(50);
+/// SBUS and PPM frames come every 7ms to 22ms.
+const DRIVE_PERIOD: Duration = Duration::from_millis(20);
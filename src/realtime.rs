@@ -0,0 +1,114 @@
+//! Real-time scheduling for the thread driving the board, so the I2C
+//! transactions, ramping and PID loops keep their timing while the Pi is
+//! busy, e.g. encoding video or serving the dashboard. Configured in the
+//! `[realtime]` section of the configuration file:
+//!
+//! ```toml
+//! [realtime]
+//! priority = 50
+//! lock_memory = true
+//! ```
+//!
+//! Needs root, or the `CAP_SYS_NICE` (and `CAP_IPC_LOCK` to lock memory)
+//! capabilities, e.g. with `AmbientCapabilities=` in the systemd unit.
+
+use std::io;
+use std::os::unix::thread::JoinHandleExt;
+use std::thread::JoinHandle;
+
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RealtimeError {
+    #[error("real-time priority {priority} is not in [{min}, {max}]")]
+    InvalidPriority { priority: i32, min: i32, max: i32 },
+    #[error("cannot schedule with SCHED_FIFO priority {priority}, run as root or with CAP_SYS_NICE: {source}")]
+    Schedule { priority: i32, source: io::Error },
+    #[error("cannot lock memory, run as root or with CAP_IPC_LOCK: {source}")]
+    LockMemory { source: io::Error },
+}
+
+/// The `[realtime]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RealtimeConfig {
+    /// `SCHED_FIFO` priority, from 1 to 99. Kernel threads handling
+    /// interrupts run at 50.
+    pub priority: i32,
+    /// Lock the memory of the process, current and future, so it is never
+    /// paged out.
+    pub lock_memory: bool,
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        RealtimeConfig {
+            priority: 20,
+            lock_memory: true,
+        }
+    }
+}
+
+impl RealtimeConfig {
+    /// Schedules the calling thread with `SCHED_FIFO`, and the threads it
+    /// spawns from then on, and locks memory if configured.
+    pub fn apply(&self) -> Result<(), Error> {
+        // Safe, always succeeds.
+        self.apply_to(unsafe { libc::pthread_self() })
+    }
+
+    /// Schedules `thread` with `SCHED_FIFO`, and locks memory if configured.
+    pub fn apply_to_thread<T>(&self, thread: &JoinHandle<T>) -> Result<(), Error> {
+        self.apply_to(thread.as_pthread_t())
+    }
+
+    fn apply_to(&self, thread: libc::pthread_t) -> Result<(), Error> {
+        // Safe, only reads the constant range of the policy.
+        let (min, max) = unsafe {
+            (
+                libc::sched_get_priority_min(libc::SCHED_FIFO),
+                libc::sched_get_priority_max(libc::SCHED_FIFO),
+            )
+        };
+        if !(min..=max).contains(&self.priority) {
+            return Err(RealtimeError::InvalidPriority {
+                priority: self.priority,
+                min,
+                max,
+            }
+            .into());
+        }
+        let param = libc::sched_param {
+            sched_priority: self.priority,
+        };
+        // Safe, `thread` is alive as the caller holds it and `param` is
+        // valid for the call.
+        let result = unsafe { libc::pthread_setschedparam(thread, libc::SCHED_FIFO, &param) };
+        if result != 0 {
+            return Err(RealtimeError::Schedule {
+                priority: self.priority,
+                source: io::Error::from_raw_os_error(result),
+            }
+            .into());
+        }
+        if self.lock_memory {
+            // Safe, no pointers involved.
+            if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+                return Err(RealtimeError::LockMemory {
+                    source: io::Error::last_os_error(),
+                }
+                .into());
+            }
+        }
+        info!(
+            "Driving with SCHED_FIFO priority {}{}",
+            self.priority,
+            if self.lock_memory {
+                ", memory locked"
+            } else {
+                ""
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::color::Color;
+use crate::error::Error;
+use crate::motor_driver::MotorDriver;
+use crate::shutdown::Shutdown;
+use crate::thunder_borg::Controller;
+
+/// A motor or LED command as issued to the `Controller`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RecordedCommand {
+    SetMotors {
+        power: f32,
+    },
+    SetMotorA {
+        power: f32,
+    },
+    SetMotorB {
+        power: f32,
+    },
+    /// `MotorDriver::set_sides()`, e.g. from `vrum rc`.
+    SetSides {
+        left: f32,
+        right: f32,
+    },
+    SetLed {
+        #[serde(flatten)]
+        color: Color,
+    },
+    /// `Controller::all_off()`.
+    Stop,
+    StopMotors,
+}
+
+impl RecordedCommand {
+    pub fn apply(&self, controller: &mut Controller) -> Result<(), Error> {
+        match *self {
+            RecordedCommand::SetMotors { power } => controller.set_motors(power),
+            RecordedCommand::SetMotorA { power } => controller.set_motor_a(power),
+            RecordedCommand::SetMotorB { power } => controller.set_motor_b(power),
+            RecordedCommand::SetSides { left, right } => controller.set_sides(left, right),
+            RecordedCommand::SetLed { color } => controller.set_led(color),
+            RecordedCommand::Stop => controller.all_off(),
+            RecordedCommand::StopMotors => controller.stop_motors(),
+        }
+    }
+}
+
+/// One line of a recording: a command and when it was issued, relative to
+/// the start of the recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedEntry {
+    pub elapsed_ms: u64,
+    #[serde(flatten)]
+    pub command: RecordedCommand,
+}
+
+/// Appends timestamped commands to a file, one JSON object per line.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        info!("Recording commands to {}", path.as_ref().display());
+        Ok(Recorder {
+            writer: BufWriter::new(File::create(path)?),
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, command: &RecordedCommand) -> Result<(), Error> {
+        let entry = RecordedEntry {
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            command: command.clone(),
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush() {
+            error!("Could not flush recording: {}", error);
+        }
+    }
+}
+
+/// Plays back a recording made with `Recorder`, reproducing the original
+/// timing between commands. Stops early with `ShutdownError::Requested` if
+/// `shutdown` is requested.
+pub fn replay<P: AsRef<Path>>(
+    controller: &mut Controller,
+    path: P,
+    shutdown: &Shutdown,
+) -> Result<(), Error> {
+    info!("Replaying commands from {}", path.as_ref().display());
+    let reader = BufReader::new(File::open(path)?);
+    let started = Instant::now();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: RecordedEntry = serde_json::from_str(&line)?;
+        let due = Duration::from_millis(entry.elapsed_ms);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            shutdown.sleep(wait)?;
+        }
+        debug!("Replaying {:?} at {}ms", entry.command, entry.elapsed_ms);
+        entry.command.apply(controller)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,166 @@
+//! Reloading of the configuration file while vrum runs, on SIGHUP and, with
+//! `watch` in the `[reload]` section of the configuration file, whenever
+//! the file changes:
+//!
+//! ```toml
+//! [reload]
+//! watch = true
+//! poll_interval_ms = 1000
+//! ```
+//!
+//! A `ConfigWatcher` loads the file again on a background thread and hands
+//! the new `Config` to its subscribers. A `Controller` given the updates with
+//! `Controller::set_config_updates` picks up the power limit, ramp rate, turn
+//! sensitivity, profile, trims and dead zones from its next motor command
+//! on, without stopping the motors, and a `BatterySupervisor` its thresholds
+//! and LED flashing. Everything else, e.g. the network frontends, keeps its
+//! settings until vrum restarts. A file that doesn't load is reported and
+//! ignored, the running settings stay as they were.
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use signal_hook::consts::SIGHUP;
+use signal_hook::flag;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::journal;
+
+/// The `[reload]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadConfig {
+    /// Reload when the file changes, not only on SIGHUP.
+    pub watch: bool,
+    /// How often the file is checked for changes.
+    pub poll_interval_ms: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        ReloadConfig {
+            watch: true,
+            poll_interval_ms: 1000,
+        }
+    }
+}
+
+type Subscribers = Arc<Mutex<Vec<Sender<Arc<Config>>>>>;
+
+/// Loads the configuration file again on SIGHUP or when it changes, on a
+/// background thread, until dropped.
+pub struct ConfigWatcher {
+    subscribers: Subscribers,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    /// Watches the file at `path`. `overrides` is applied to every reloaded
+    /// configuration, e.g. to keep settings given on the command line.
+    pub fn spawn<F>(path: &Path, config: &ReloadConfig, overrides: F) -> Result<Self, Error>
+    where
+        F: Fn(&mut Config) + Send + 'static,
+    {
+        let hangup = Arc::new(AtomicBool::new(false));
+        flag::register(SIGHUP, hangup.clone())?;
+        if config.watch {
+            info!("Reloading {} on SIGHUP or when it changes", path.display());
+        } else {
+            info!("Reloading {} on SIGHUP", path.display());
+        }
+        let subscribers: Subscribers = Arc::default();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_subscribers = subscribers.clone();
+        let thread_running = running.clone();
+        let path = path.to_path_buf();
+        let watch = config.watch;
+        let poll_interval = Duration::from_millis(config.poll_interval_ms);
+        let thread = thread::Builder::new()
+            .name("vrum-reload".into())
+            .spawn(move || {
+                let mut last_modified = modified(&path);
+                let mut checked = Instant::now();
+                while thread_running.load(Ordering::SeqCst) {
+                    thread::sleep(SIGNAL_POLL_INTERVAL);
+                    let signalled = hangup.swap(false, Ordering::SeqCst);
+                    let changed = watch && checked.elapsed() >= poll_interval && {
+                        checked = Instant::now();
+                        let now = modified(&path);
+                        let changed = now != last_modified;
+                        last_modified = now;
+                        changed
+                    };
+                    if signalled || changed {
+                        reload(&path, &overrides, &thread_subscribers);
+                    }
+                }
+            })?;
+
+        Ok(ConfigWatcher {
+            subscribers,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns a channel receiving every subsequently reloaded `Config`.
+    pub fn updates(&self) -> Receiver<Arc<Config>> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Configuration watcher thread panicked");
+            }
+        }
+    }
+}
+
+fn reload<F: Fn(&mut Config)>(path: &Path, overrides: &F, subscribers: &Subscribers) {
+    let mut config = match Config::load(path) {
+        Ok(config) => config,
+        Err(error) => {
+            warn!(
+                "Could not reload {}, keeping the running settings: {}",
+                path.display(),
+                error
+            );
+            return;
+        }
+    };
+    overrides(&mut config);
+    info!(
+        message_id = journal::CONFIG_RELOADED,
+        "Reloaded the configuration from {}",
+        path.display()
+    );
+    let config = Arc::new(config);
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.retain(|subscriber| subscriber.send(config.clone()).is_ok());
+    }
+}
+
+/// When the file at `path` was last modified, `None` if that can't be told,
+/// e.g. while it is being replaced.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Longest delay between a SIGHUP and the reload.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(200);
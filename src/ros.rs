@@ -0,0 +1,201 @@
+//! Bridge to a ROS graph through `rosbridge_server`, which exposes topics as
+//! JSON over a WebSocket. This avoids linking against a ROS installation while
+//! still letting standard ROS nodes (`teleop_twist_keyboard`, `move_base`, ...)
+//! drive the robot.
+
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tungstenite::{self, Message, WebSocket};
+
+use crate::error::Error;
+use crate::kinematics::DiffDrive;
+use crate::thunder_borg::Controller;
+
+pub const TOPIC_CMD_VEL: &str = "/cmd_vel";
+pub const TOPIC_DIAGNOSTICS: &str = "/diagnostics";
+
+#[derive(Debug, thiserror::Error)]
+pub enum RosError {
+    #[error("websocket handshake with rosbridge at {url} failed: {reason}")]
+    HandshakeFailed { url: String, reason: String },
+}
+
+#[derive(Clone, Debug)]
+pub struct RosConfig {
+    /// Host running `rosbridge_server`.
+    pub host: String,
+    /// Port of the rosbridge WebSocket, 9090 by default.
+    pub port: u16,
+    /// Interval between messages published on `/diagnostics`.
+    pub diagnostics_interval: Duration,
+    /// Motors are stopped if no `Twist` arrives on `/cmd_vel` within this
+    /// time, matching the behaviour of standard ROS base controllers.
+    pub cmd_vel_timeout: Duration,
+}
+
+impl Default for RosConfig {
+    fn default() -> Self {
+        RosConfig {
+            host: "localhost".into(),
+            port: 9090,
+            diagnostics_interval: Duration::from_secs(1),
+            cmd_vel_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Subscribes to `geometry_msgs/Twist` on `/cmd_vel` and publishes battery and
+/// drive fault status as `diagnostic_msgs/DiagnosticArray` on `/diagnostics`.
+pub struct RosBridge<'a> {
+    controller: &'a mut Controller,
+    drive: DiffDrive,
+    config: RosConfig,
+    socket: WebSocket<TcpStream>,
+}
+
+impl<'a> RosBridge<'a> {
+    pub fn connect(
+        controller: &'a mut Controller,
+        drive: DiffDrive,
+        config: RosConfig,
+    ) -> Result<Self, Error> {
+        let url = format!("ws://{}:{}/", config.host, config.port);
+        info!("Connecting to rosbridge at {}", url);
+        let stream = TcpStream::connect((config.host.as_str(), config.port))?;
+        let (socket, _) = tungstenite::client(url.as_str(), stream).map_err(|error| {
+            RosError::HandshakeFailed {
+                url: url.clone(),
+                reason: error.to_string(),
+            }
+        })?;
+        socket.get_ref().set_read_timeout(Some(ROS_POLL_INTERVAL))?;
+
+        let mut bridge = RosBridge {
+            controller,
+            drive,
+            config,
+            socket,
+        };
+        bridge.send(json!({
+            "op": "subscribe",
+            "topic": TOPIC_CMD_VEL,
+            "type": "geometry_msgs/Twist",
+        }))?;
+        bridge.send(json!({
+            "op": "advertise",
+            "topic": TOPIC_DIAGNOSTICS,
+            "type": "diagnostic_msgs/DiagnosticArray",
+        }))?;
+        Ok(bridge)
+    }
+
+    /// Runs the bridge until rosbridge closes the connection.
+    pub fn run(&mut self) -> Result<(), Error> {
+        let mut last_diagnostics = Instant::now();
+        let mut last_cmd_vel: Option<Instant> = None;
+        loop {
+            match self.socket.read() {
+                Ok(Message::Text(text)) => {
+                    if self.handle_message(text.as_str())? {
+                        last_cmd_vel = Some(Instant::now());
+                    }
+                }
+                Ok(Message::Close(_)) => {
+                    info!("rosbridge closed the connection");
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(ref error))
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut => {}
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(error) => return Err(error.into()),
+            }
+
+            if let Some(received) = last_cmd_vel {
+                if received.elapsed() > self.config.cmd_vel_timeout {
+                    warn!("No {} received recently, stopping motors", TOPIC_CMD_VEL);
+                    self.controller.set_motors(0.0)?;
+                    last_cmd_vel = None;
+                }
+            }
+
+            if last_diagnostics.elapsed() >= self.config.diagnostics_interval {
+                self.publish_diagnostics()?;
+                last_diagnostics = Instant::now();
+            }
+        }
+    }
+
+    /// Returns true if the message was a velocity command that was applied.
+    fn handle_message(&mut self, text: &str) -> Result<bool, Error> {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(error) => {
+                warn!("Ignoring malformed rosbridge message: {}", error);
+                return Ok(false);
+            }
+        };
+        if message["op"] != "publish" || message["topic"] != TOPIC_CMD_VEL {
+            return Ok(false);
+        }
+        let twist = &message["msg"];
+        match (
+            twist["linear"]["x"].as_f64(),
+            twist["angular"]["z"].as_f64(),
+        ) {
+            (Some(linear), Some(angular)) => {
+                debug!("Twist linear.x={} angular.z={}", linear, angular);
+                self.drive
+                    .set_velocity(self.controller, linear as f32, angular as f32)?;
+                Ok(true)
+            }
+            _ => {
+                warn!("Ignoring Twist without linear.x/angular.z: {}", twist);
+                Ok(false)
+            }
+        }
+    }
+
+    fn publish_diagnostics(&mut self) -> Result<(), Error> {
+        let voltage = self.controller.get_battery_voltage()?;
+        let fault_a = self.controller.get_drive_fault_a()?;
+        let fault_b = self.controller.get_drive_fault_b()?;
+        let (level, summary) = if fault_a || fault_b {
+            (DIAGNOSTIC_ERROR, "Drive fault")
+        } else {
+            (DIAGNOSTIC_OK, "OK")
+        };
+        self.send(json!({
+            "op": "publish",
+            "topic": TOPIC_DIAGNOSTICS,
+            "msg": {
+                "status": [{
+                    "level": level,
+                    "name": "vrum: ThunderBorg",
+                    "message": summary,
+                    "hardware_id": "thunderborg",
+                    "values": [
+                        {"key": "Battery voltage", "value": format!("{:.2}", voltage)},
+                        {"key": "Drive fault A", "value": fault_a.to_string()},
+                        {"key": "Drive fault B", "value": fault_b.to_string()},
+                    ],
+                }],
+            },
+        }))
+    }
+
+    fn send(&mut self, message: Value) -> Result<(), Error> {
+        self.socket.send(Message::text(message.to_string()))?;
+        Ok(())
+    }
+}
+
+const ROS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Levels from `diagnostic_msgs/DiagnosticStatus`
+const DIAGNOSTIC_OK: u8 = 0;
+const DIAGNOSTIC_ERROR: u8 = 2;
@@ -0,0 +1,396 @@
+//! Every run of vrum, its motor and LED commands, telemetry samples and
+//! notable events stored in a local SQLite database, to look into past runs
+//! with `vrum runs list`, `vrum runs show <id>` and `vrum runs export <id>`,
+//! or any SQLite client. Configured in the `[run_log]` section of the
+//! configuration file:
+//!
+//! ```toml
+//! [run_log]
+//! path = "/var/lib/vrum/runs.sqlite"
+//! ```
+//!
+//! Commands and samples are stored as JSON, e.g. the battery voltage over a
+//! run is
+//!
+//! ```sql
+//! SELECT timestamp, json_extract(sample, '$.battery_voltage')
+//! FROM samples WHERE run_id = 12;
+//! ```
+
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::black_box::{self, TimedCommand};
+use crate::error::Error;
+use crate::history::{EventHistory, HistoryEvent};
+use crate::telemetry::{self, TelemetrySample};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RunLogError {
+    #[error("no run {id} in the run log")]
+    NoSuchRun { id: i64 },
+}
+
+/// The `[run_log]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunLogConfig {
+    pub path: PathBuf,
+}
+
+impl Default for RunLogConfig {
+    fn default() -> Self {
+        RunLogConfig {
+            path: PathBuf::from("/var/lib/vrum/runs.sqlite"),
+        }
+    }
+}
+
+/// A run and how much of it was stored.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunSummary {
+    pub id: i64,
+    /// Seconds since the UNIX epoch.
+    pub started: f64,
+    /// `None` while running, or if vrum didn't exit cleanly.
+    pub ended: Option<f64>,
+    /// The command line vrum ran with.
+    pub command: String,
+    pub commands: u64,
+    pub samples: u64,
+    pub events: u64,
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{:>5}  {}  ",
+            self.id,
+            black_box::utc_time(self.started)
+        )?;
+        match self.ended {
+            Some(ended) => write!(formatter, "{:>8.1}s", ended - self.started)?,
+            None => write!(formatter, "{:>9}", "unended")?,
+        }
+        write!(
+            formatter,
+            "  {} commands, {} samples, {} events  {}",
+            self.commands, self.samples, self.events, self.command
+        )
+    }
+}
+
+/// A whole run, as written by `vrum runs export`.
+#[derive(Clone, Debug, Serialize)]
+pub struct RunExport {
+    pub run: RunSummary,
+    /// `TimedCommand`s.
+    pub commands: Vec<serde_json::Value>,
+    /// `TelemetrySample`s.
+    pub samples: Vec<serde_json::Value>,
+    pub events: Vec<StoredEvent>,
+}
+
+/// A `HistoryEvent` read back from the run log.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StoredEvent {
+    /// Seconds since the Unix epoch.
+    pub at: f64,
+    pub kind: String,
+    pub level: String,
+    pub message: String,
+}
+
+/// The database of runs.
+pub struct RunLog {
+    connection: Connection,
+}
+
+impl RunLog {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(RunLog { connection })
+    }
+
+    /// Every run, oldest first.
+    pub fn runs(&self) -> Result<Vec<RunSummary>, Error> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("{} ORDER BY runs.id", SUMMARY_QUERY))?;
+        let runs = statement
+            .query_map([], summary)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(runs)
+    }
+
+    pub fn run(&self, id: i64) -> Result<RunSummary, Error> {
+        self.connection
+            .query_row(
+                &format!("{} WHERE runs.id = ?1", SUMMARY_QUERY),
+                [id],
+                summary,
+            )
+            .optional()?
+            .ok_or_else(|| RunLogError::NoSuchRun { id }.into())
+    }
+
+    /// The events of run `id`, oldest first.
+    pub fn events(&self, id: i64) -> Result<Vec<StoredEvent>, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT at, kind, level, message FROM events WHERE run_id = ?1 ORDER BY at")?;
+        let events = statement
+            .query_map([id], |row| {
+                Ok(StoredEvent {
+                    at: row.get(0)?,
+                    kind: row.get(1)?,
+                    level: row.get(2)?,
+                    message: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(events)
+    }
+
+    pub fn export(&self, id: i64) -> Result<RunExport, Error> {
+        Ok(RunExport {
+            run: self.run(id)?,
+            commands: self.json_rows(
+                "SELECT timestamp, command FROM commands WHERE run_id = ?1 ORDER BY timestamp",
+                id,
+            )?,
+            samples: self.json_rows(
+                "SELECT timestamp, sample FROM samples WHERE run_id = ?1 ORDER BY timestamp",
+                id,
+            )?,
+            events: self.events(id)?,
+        })
+    }
+
+    /// The JSON objects of `query`, with the timestamp of their row.
+    fn json_rows(&self, query: &str, id: i64) -> Result<Vec<serde_json::Value>, Error> {
+        let mut statement = self.connection.prepare(query)?;
+        let rows = statement
+            .query_map([id], |row| {
+                Ok((row.get::<_, f64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(timestamp, json)| {
+                let mut value: serde_json::Value = serde_json::from_str(&json)?;
+                if let Some(object) = value.as_object_mut() {
+                    object.insert("timestamp".into(), timestamp.into());
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+
+    fn start_run(&self, command: &str) -> Result<i64, Error> {
+        self.connection.execute(
+            "INSERT INTO runs (started, command) VALUES (?1, ?2)",
+            params![telemetry::unix_timestamp(), command],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    fn end_run(&self, id: i64) -> Result<(), Error> {
+        self.connection.execute(
+            "UPDATE runs SET ended = ?1 WHERE id = ?2",
+            params![telemetry::unix_timestamp(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Stores `entries` of run `id` in one transaction, as a commit per row
+    /// is slow on an SD card.
+    fn store(&mut self, id: i64, entries: &[Entry]) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+        for entry in entries {
+            match entry {
+                Entry::Command(command) => transaction.execute(
+                    "INSERT INTO commands (run_id, timestamp, command) VALUES (?1, ?2, ?3)",
+                    params![
+                        id,
+                        command.timestamp,
+                        serde_json::to_string(&command.command)?
+                    ],
+                )?,
+                Entry::Sample(sample) => transaction.execute(
+                    "INSERT INTO samples (run_id, timestamp, sample) VALUES (?1, ?2, ?3)",
+                    params![id, sample.timestamp, serde_json::to_string(sample)?],
+                )?,
+                Entry::Event(event) => transaction.execute(
+                    "INSERT INTO events (run_id, at, kind, level, message) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![id, event.at, event.kind, event.level, event.message],
+                )?,
+            };
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Stores a run in the `RunLog` on a background thread, from its start to
+/// when it is dropped.
+pub struct RunRecorder {
+    id: i64,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RunRecorder {
+    /// Starts run `command`, storing the `commands` of the controller, e.g.
+    /// from `Controller::commands`, the telemetry `samples`, e.g. from
+    /// `TelemetryPoller::samples`, and the notable events of
+    /// `vrum::history`.
+    pub fn start(
+        config: &RunLogConfig,
+        command: &str,
+        commands: Receiver<TimedCommand>,
+        samples: Option<Receiver<TelemetrySample>>,
+    ) -> Result<Self, Error> {
+        let mut log = RunLog::open(&config.path)?;
+        let id = log.start_run(command)?;
+        info!("Storing run {} in {}", id, config.path.display());
+        let started = telemetry::unix_timestamp();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-run-log".into())
+            .spawn(move || {
+                let mut events_after = started;
+                let mut entries = Vec::new();
+                loop {
+                    let running = thread_running.load(Ordering::SeqCst);
+                    let deadline = Instant::now() + RUN_LOG_INTERVAL;
+                    while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                        match commands.recv_timeout(wait) {
+                            Ok(command) => entries.push(Entry::Command(command)),
+                            Err(RecvTimeoutError::Timeout) => break,
+                            // The controller is gone, there won't be commands.
+                            Err(RecvTimeoutError::Disconnected) => {
+                                thread::sleep(wait);
+                                break;
+                            }
+                        }
+                    }
+                    if let Some(ref samples) = samples {
+                        entries.extend(samples.try_iter().map(Entry::Sample));
+                    }
+                    for event in EventHistory::global().events() {
+                        if event.at > events_after {
+                            events_after = event.at;
+                            entries.push(Entry::Event(event));
+                        }
+                    }
+                    if let Err(error) = log.store(id, &entries) {
+                        error!(
+                            "Could not store {} run log entries: {}",
+                            entries.len(),
+                            error
+                        );
+                    }
+                    entries.clear();
+                    if !running {
+                        break;
+                    }
+                }
+                if let Err(error) = log.end_run(id) {
+                    error!("Could not end run {}: {}", id, error);
+                }
+            })?;
+
+        Ok(RunRecorder {
+            id,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl Drop for RunRecorder {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Run log thread panicked");
+            }
+        }
+    }
+}
+
+enum Entry {
+    Command(TimedCommand),
+    Sample(TelemetrySample),
+    Event(HistoryEvent),
+}
+
+fn summary(row: &rusqlite::Row) -> rusqlite::Result<RunSummary> {
+    Ok(RunSummary {
+        id: row.get(0)?,
+        started: row.get(1)?,
+        ended: row.get(2)?,
+        command: row.get(3)?,
+        commands: row.get(4)?,
+        samples: row.get(5)?,
+        events: row.get(6)?,
+    })
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started REAL NOT NULL,
+    ended REAL,
+    command TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS commands (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    timestamp REAL NOT NULL,
+    command TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS samples (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    timestamp REAL NOT NULL,
+    sample TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS events (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    at REAL NOT NULL,
+    kind TEXT NOT NULL,
+    level TEXT NOT NULL,
+    message TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS commands_run ON commands (run_id);
+CREATE INDEX IF NOT EXISTS samples_run ON samples (run_id);
+CREATE INDEX IF NOT EXISTS events_run ON events (run_id);
+";
+
+const SUMMARY_QUERY: &str = "
+SELECT runs.id, runs.started, runs.ended, runs.command,
+    (SELECT COUNT(*) FROM commands WHERE run_id = runs.id),
+    (SELECT COUNT(*) FROM samples WHERE run_id = runs.id),
+    (SELECT COUNT(*) FROM events WHERE run_id = runs.id)
+FROM runs";
+
+/// How often entries are stored.
+const RUN_LOG_INTERVAL: Duration = Duration::from_secs(1);
@@ -0,0 +1,208 @@
+//! Motion sequences written in [Rhai](https://rhai.rs), e.g.
+//!
+//! ```text
+//! led(0, 255, 0);
+//! for i in 0..2 {
+//!     drive(0.5);
+//!     sleep_ms(1800);
+//!     turn(90);
+//! }
+//! stop();
+//! ```
+//!
+//! Available functions:
+//!
+//! * `drive(power)`: set both motors to `power` in `[-1, 1]`
+//! * `set_motor_a(power)`, `set_motor_b(power)`: set a single motor
+//! * `velocity(linear, angular)`: drive at `linear` m/s and `angular` rad/s
+//! * `arc(speed, radius)`: drive at `speed` m/s along a circle of `radius`
+//!   metres, turning left for a positive radius and right for a negative one
+//! * `turn(degrees)`: timed turn in place, counter-clockwise for positive angles
+//! * `led(red, green, blue)`: set the LED colour, channels in `[0, 255]`
+//! * `led(color)`: set the LED colour from a name or `"#rrggbb"`
+//! * `stop()`: switch off the motors and LED
+//! * `stop_motors()`: switch off the motors, leaving the LED on
+//! * `power_limit(limit)`: cap the power of both motors to `limit` in `[0, 1]`
+//! * `sleep_ms(milliseconds)`: wait, keeping the current motor powers
+//! * `battery_voltage()`: read the battery voltage in volts
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+use rhai::{Engine, EvalAltResult, FLOAT, INT};
+
+use crate::color::Color;
+use crate::error::Error;
+use crate::kinematics::DiffDrive;
+use crate::motion::Motion;
+use crate::shutdown::Shutdown;
+use crate::thunder_borg::Controller;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptError {
+    #[error("script failed: {reason}")]
+    Failed { reason: String },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScriptConfig {
+    pub drive: DiffDrive,
+    /// Motor power used by `turn()`.
+    pub turn_power: f32,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Runs the script at `path`, the motors are stopped when the controller is
+/// dropped at the end of the script. The script is aborted with
+/// `ShutdownError::Requested` if `shutdown` is requested.
+pub fn run_file<P: AsRef<Path>>(
+    controller: Controller,
+    config: ScriptConfig,
+    path: P,
+    shutdown: &Shutdown,
+) -> Result<(), Error> {
+    info!("Running script {}", path.as_ref().display());
+    let engine = build_engine(Rc::new(RefCell::new(controller)), config, shutdown);
+    let result = engine.run_file(path.as_ref().to_path_buf());
+    shutdown.check()?;
+    result.map_err(|error| ScriptError::Failed {
+        reason: error.to_string(),
+    })?;
+    Ok(())
+}
+
+fn build_engine(
+    controller: Rc<RefCell<Controller>>,
+    config: ScriptConfig,
+    shutdown: &Shutdown,
+) -> Engine {
+    let mut engine = Engine::new();
+
+    let stopping = shutdown.clone();
+    engine.on_progress(move |_| {
+        if stopping.is_requested() {
+            Some("shutdown requested".into())
+        } else {
+            None
+        }
+    });
+
+    let shared = controller.clone();
+    engine.register_fn("drive", move |power: FLOAT| -> ScriptResult<()> {
+        to_script(shared.borrow_mut().set_motors(power as f32))
+    });
+    let shared = controller.clone();
+    engine.register_fn("set_motor_a", move |power: FLOAT| -> ScriptResult<()> {
+        to_script(shared.borrow_mut().set_motor_a(power as f32))
+    });
+    let shared = controller.clone();
+    engine.register_fn("set_motor_b", move |power: FLOAT| -> ScriptResult<()> {
+        to_script(shared.borrow_mut().set_motor_b(power as f32))
+    });
+    let shared = controller.clone();
+    engine.register_fn(
+        "velocity",
+        move |linear: FLOAT, angular: FLOAT| -> ScriptResult<()> {
+            let mut controller = shared.borrow_mut();
+            to_script(
+                config
+                    .drive
+                    .set_velocity(&mut *controller, linear as f32, angular as f32),
+            )
+        },
+    );
+    let shared = controller.clone();
+    engine.register_fn(
+        "arc",
+        move |speed: FLOAT, radius: FLOAT| -> ScriptResult<()> {
+            let mut controller = shared.borrow_mut();
+            to_script(
+                config
+                    .drive
+                    .drive_arc(&mut *controller, speed as f32, radius as f32),
+            )
+        },
+    );
+    let (shared, stopping) = (controller.clone(), shutdown.clone());
+    engine.register_fn("turn", move |degrees: FLOAT| -> ScriptResult<()> {
+        to_script(turn(
+            &mut shared.borrow_mut(),
+            config,
+            &stopping,
+            degrees as f32,
+        ))
+    });
+    let (shared, stopping) = (controller.clone(), shutdown.clone());
+    engine.register_fn("turn", move |degrees: INT| -> ScriptResult<()> {
+        to_script(turn(
+            &mut shared.borrow_mut(),
+            config,
+            &stopping,
+            degrees as f32,
+        ))
+    });
+    let shared = controller.clone();
+    engine.register_fn(
+        "led",
+        move |red: INT, green: INT, blue: INT| -> ScriptResult<()> {
+            to_script(shared.borrow_mut().set_led(Color::rgb(
+                to_channel(red),
+                to_channel(green),
+                to_channel(blue),
+            )))
+        },
+    );
+    let shared = controller.clone();
+    engine.register_fn("led", move |color: &str| -> ScriptResult<()> {
+        let color = to_script(color.parse::<Color>().map_err(Error::from))?;
+        to_script(shared.borrow_mut().set_led(color))
+    });
+    let shared = controller.clone();
+    engine.register_fn("stop", move || -> ScriptResult<()> {
+        to_script(shared.borrow_mut().stop())
+    });
+    let shared = controller.clone();
+    engine.register_fn("stop_motors", move || -> ScriptResult<()> {
+        to_script(shared.borrow_mut().stop_motors())
+    });
+    let shared = controller.clone();
+    engine.register_fn("power_limit", move |limit: FLOAT| {
+        shared.borrow_mut().set_power_limit(limit as f32);
+    });
+    let shared = controller;
+    engine.register_fn("battery_voltage", move || -> ScriptResult<FLOAT> {
+        to_script(shared.borrow_mut().get_battery_voltage()).map(FLOAT::from)
+    });
+    let stopping = shutdown.clone();
+    engine.register_fn("sleep_ms", move |milliseconds: INT| -> ScriptResult<()> {
+        to_script(stopping.sleep(Duration::from_millis(milliseconds.max(0) as u64)))
+    });
+
+    engine.on_print(|text| info!("[script] {}", text));
+    engine
+}
+
+/// Turns in place with `Motion::spin()`.
+fn turn(
+    controller: &mut Controller,
+    config: ScriptConfig,
+    shutdown: &Shutdown,
+    degrees: f32,
+) -> Result<(), Error> {
+    Motion::new(controller, shutdown)
+        .drive_model(config.drive)
+        .turn_power(config.turn_power)
+        .spin(degrees)
+}
+
+fn to_script<T>(result: Result<T, Error>) -> ScriptResult<T> {
+    result.map_err(|error| error.to_string().into())
+}
+
+#[inline]
+fn to_channel(value: INT) -> u8 {
+    value.clamp(0, INT::from(u8::MAX)) as u8
+}
@@ -0,0 +1,10 @@
+//! Drivers for sensors that aren't PiBorg boards, sharing the I2C bus with
+//! them.
+
+pub mod ads1115;
+pub mod gps;
+pub mod imu;
+pub mod ina219;
+pub mod line;
+pub mod temperature;
+pub mod ultrasonic;
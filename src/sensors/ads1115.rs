@@ -0,0 +1,90 @@
+//! Driver for the TI ADS1115, a four channel 16-bit ADC on the I2C bus, for
+//! analog sensors the Pi cannot read on its own.
+//!
+//! ```no_run
+//! # use vrum::sensors::ads1115::Ads1115;
+//! let mut adc = Ads1115::open("/dev/i2c-1", 0x48)?;
+//! println!("{:.3}V", adc.voltage(0)?);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use i2cdev::linux::LinuxI2CDevice;
+
+use crate::borg::{read_registers, Bus};
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Ads1115Error {
+    #[error("no ADS1115 channel {channel}, expected 0 to 3")]
+    InvalidChannel { channel: u8 },
+    #[error("ADS1115 conversion did not complete")]
+    ConversionTimeout,
+}
+
+pub const DEFAULT_ADDRESS: u16 = 0x48;
+
+pub struct Ads1115 {
+    bus: Box<dyn Bus>,
+}
+
+impl Ads1115 {
+    pub fn open(bus_path: &str, address: u16) -> Result<Self, Error> {
+        info!(
+            "Initialising ADS1115 at i2c bus {} address 0x{:x}",
+            bus_path, address
+        );
+        Ok(Ads1115::new(Box::new(LinuxI2CDevice::new(
+            bus_path, address,
+        )?)))
+    }
+
+    pub fn new(bus: Box<dyn Bus>) -> Self {
+        Ads1115 { bus }
+    }
+
+    /// Voltage on input `channel` (0 to 3) against ground, from a single
+    /// conversion in the ±4.096V range.
+    pub fn voltage(&mut self, channel: u8) -> Result<f32, Error> {
+        if channel > 3 {
+            return Err(Ads1115Error::InvalidChannel { channel }.into());
+        }
+        let config = CONFIG_START
+            | (u16::from(MUX_SINGLE_ENDED + channel) << 12)
+            | CONFIG_4V096_SINGLE_SHOT_860SPS;
+        let [high, low] = config.to_be_bytes();
+        self.bus.write(&[REG_CONFIG, high, low])?;
+
+        let started = Instant::now();
+        thread::sleep(CONVERSION_TIME);
+        while self.read_u16(REG_CONFIG)? & CONFIG_START == 0 {
+            if started.elapsed() > CONVERSION_TIMEOUT {
+                return Err(Ads1115Error::ConversionTimeout.into());
+            }
+            thread::sleep(Duration::from_micros(200));
+        }
+        let raw = self.read_u16(REG_CONVERSION)? as i16;
+        Ok(f32::from(raw) * VOLTS_PER_COUNT)
+    }
+
+    fn read_u16(&mut self, register: u8) -> Result<u16, Error> {
+        let mut data = [0u8; 2];
+        read_registers(&mut *self.bus, register, &mut data)?;
+        Ok(u16::from_be_bytes(data))
+    }
+}
+
+const REG_CONVERSION: u8 = 0x00;
+const REG_CONFIG: u8 = 0x01;
+/// Starts a conversion when written, reads 1 once it completed.
+const CONFIG_START: u16 = 0x8000;
+/// MUX setting for input 0 against ground, the other inputs follow.
+const MUX_SINGLE_ENDED: u8 = 0b100;
+/// ±4.096V range, single-shot mode, 860 samples per second, comparator off.
+const CONFIG_4V096_SINGLE_SHOT_860SPS: u16 = 0x03E3;
+const VOLTS_PER_COUNT: f32 = 4.096 / 32768.0;
+/// A conversion takes about 1.2ms at 860 samples per second.
+const CONVERSION_TIME: Duration = Duration::from_micros(1200);
+const CONVERSION_TIMEOUT: Duration = Duration::from_millis(20);
@@ -0,0 +1,391 @@
+//! GPS receivers talking NMEA 0183 over a serial port, e.g. u-blox NEO-6M
+//! modules on the Pi's UART. The GGA and RMC sentences are decoded into a
+//! `Fix`, other sentences are ignored.
+//!
+//! ```no_run
+//! # use vrum::sensors::gps::{GpsConfig, GpsReceiver};
+//! let gps = GpsReceiver::spawn(&GpsConfig::default())?;
+//! if let Some(fix) = gps.fix() {
+//!     println!("{:.6}, {:.6}", fix.position.latitude, fix.position.longitude);
+//! }
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rppal::uart::{Parity, Uart};
+
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GpsError {
+    #[error("NMEA sentence {sentence:?} fails its checksum")]
+    InvalidChecksum { sentence: String },
+    #[error("malformed NMEA sentence {sentence:?}")]
+    Malformed { sentence: String },
+}
+
+/// The `[gps]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpsConfig {
+    /// Serial port the receiver is wired to.
+    pub device: String,
+    pub baud_rate: u32,
+    /// A fix older than this is not trusted.
+    pub stale_after_ms: u64,
+}
+
+impl Default for GpsConfig {
+    fn default() -> Self {
+        GpsConfig {
+            device: "/dev/serial0".into(),
+            baud_rate: 9600,
+            stale_after_ms: 2000,
+        }
+    }
+}
+
+/// A point on the WGS 84 ellipsoid, in degrees, positive north and east.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Position {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Position {
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Great-circle distance to `other`, in metres.
+    pub fn distance_to(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+
+    /// Offset from `origin` in metres, `(east, north)`, on a plane tangent
+    /// at `origin`. Good to a few centimetres over a few kilometres.
+    pub fn offset_from(&self, origin: &Position) -> (f64, f64) {
+        let east = (self.longitude - origin.longitude).to_radians()
+            * origin.latitude.to_radians().cos()
+            * EARTH_RADIUS;
+        let north = (self.latitude - origin.latitude).to_radians() * EARTH_RADIUS;
+        (east, north)
+    }
+
+    /// Initial bearing of the great circle to `other`, in degrees clockwise
+    /// from true north in `[0, 360)`.
+    pub fn bearing_to(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let y = dlon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+}
+
+/// The latest position fix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fix {
+    /// When the fix was received.
+    pub time: Instant,
+    pub position: Position,
+    /// Above mean sea level, in metres.
+    pub altitude: Option<f32>,
+    pub satellites: u8,
+    /// Horizontal dilution of precision, lower is better.
+    pub hdop: Option<f32>,
+    /// Speed over ground, in metres per second.
+    pub speed: f32,
+    /// Course over ground, in degrees clockwise from true north. Unreliable
+    /// at walking pace.
+    pub course: Option<f32>,
+}
+
+/// The parts of a fix carried by one sentence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sentence {
+    /// GGA, the fix data. `position` is `None` without a fix.
+    Gga {
+        position: Option<Position>,
+        altitude: Option<f32>,
+        satellites: u8,
+        hdop: Option<f32>,
+    },
+    /// RMC, the recommended minimum data. `position` is `None` while the
+    /// receiver reports it invalid.
+    Rmc {
+        position: Option<Position>,
+        /// In metres per second.
+        speed: Option<f32>,
+        course: Option<f32>,
+    },
+}
+
+/// Parses one NMEA sentence, `None` for sentence types other than GGA and
+/// RMC. The checksum is checked when present.
+pub fn parse_sentence(line: &str) -> Result<Option<Sentence>, Error> {
+    let line = line.trim();
+    let malformed = || GpsError::Malformed {
+        sentence: line.to_owned(),
+    };
+    let body = line.strip_prefix('$').ok_or_else(malformed)?;
+    let body = match body.split_once('*') {
+        Some((body, checksum)) => {
+            let expected = u8::from_str_radix(checksum, 16).map_err(|_| malformed())?;
+            if body.bytes().fold(0, |sum, byte| sum ^ byte) != expected {
+                return Err(GpsError::InvalidChecksum {
+                    sentence: line.to_owned(),
+                }
+                .into());
+            }
+            body
+        }
+        None => body,
+    };
+    let fields: Vec<&str> = body.split(',').collect();
+    // The address is a two letter talker, e.g. GP or GN, and the type.
+    let kind = fields[0].get(2..).ok_or_else(malformed)?;
+    let field = |index: usize| fields.get(index).copied().unwrap_or("");
+    let number = |index: usize| field(index).parse::<f32>().ok();
+
+    Ok(match kind {
+        "GGA" => {
+            let quality = field(6).parse::<u8>().map_err(|_| malformed())?;
+            Some(Sentence::Gga {
+                position: if quality > 0 {
+                    parse_position(field(2), field(3), field(4), field(5))
+                } else {
+                    None
+                },
+                altitude: number(9),
+                satellites: field(7).parse().unwrap_or(0),
+                hdop: number(8),
+            })
+        }
+        "RMC" => Some(Sentence::Rmc {
+            position: if field(2) == "A" {
+                parse_position(field(3), field(4), field(5), field(6))
+            } else {
+                None
+            },
+            speed: number(7).map(|knots| knots * METRES_PER_SECOND_PER_KNOT),
+            course: number(8),
+        }),
+        _ => None,
+    })
+}
+
+/// Parses NMEA `ddmm.mmmm` latitude and `dddmm.mmmm` longitude fields with
+/// their hemispheres.
+fn parse_position(lat: &str, north_south: &str, lon: &str, east_west: &str) -> Option<Position> {
+    let degrees = |field: &str, degree_digits: usize| -> Option<f64> {
+        let whole = field.get(..degree_digits)?.parse::<f64>().ok()?;
+        let minutes = field.get(degree_digits..)?.parse::<f64>().ok()?;
+        Some(whole + minutes / 60.0)
+    };
+    let latitude = match north_south {
+        "N" => degrees(lat, 2)?,
+        "S" => -degrees(lat, 2)?,
+        _ => return None,
+    };
+    let longitude = match east_west {
+        "E" => degrees(lon, 3)?,
+        "W" => -degrees(lon, 3)?,
+        _ => return None,
+    };
+    Some(Position::new(latitude, longitude))
+}
+
+/// Puts the GGA and RMC sentences from a receiver together into fixes.
+#[derive(Clone, Debug, Default)]
+pub struct NmeaDecoder {
+    fix: Option<Fix>,
+}
+
+impl NmeaDecoder {
+    pub fn new() -> Self {
+        NmeaDecoder::default()
+    }
+
+    /// Decodes one line, updating the fix.
+    pub fn decode_line(&mut self, line: &str) -> Result<(), Error> {
+        let now = Instant::now();
+        match parse_sentence(line)? {
+            Some(Sentence::Gga {
+                position: Some(position),
+                altitude,
+                satellites,
+                hdop,
+            }) => {
+                let (speed, course) = self.fix.map_or((0.0, None), |fix| (fix.speed, fix.course));
+                self.fix = Some(Fix {
+                    time: now,
+                    position,
+                    altitude,
+                    satellites,
+                    hdop,
+                    speed,
+                    course,
+                });
+            }
+            Some(Sentence::Rmc {
+                position: Some(position),
+                speed,
+                course,
+            }) => {
+                let fix = self.fix.get_or_insert(Fix {
+                    time: now,
+                    position,
+                    altitude: None,
+                    satellites: 0,
+                    hdop: None,
+                    speed: 0.0,
+                    course: None,
+                });
+                fix.time = now;
+                fix.position = position;
+                fix.speed = speed.unwrap_or(0.0);
+                fix.course = course;
+            }
+            Some(Sentence::Gga { position: None, .. })
+            | Some(Sentence::Rmc { position: None, .. }) => self.lose_fix(),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn lose_fix(&mut self) {
+        if self.fix.take().is_some() {
+            warn!("GPS fix lost");
+        }
+    }
+
+    /// The last fix, `None` if the receiver has none.
+    pub fn fix(&self) -> Option<Fix> {
+        self.fix
+    }
+}
+
+/// Reads a GPS receiver on a background thread, keeping the latest fix.
+pub struct GpsReceiver {
+    fix: Arc<Mutex<Option<Fix>>>,
+    stale_after: Duration,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GpsReceiver {
+    /// Opens the serial port in `config`.
+    pub fn spawn(config: &GpsConfig) -> Result<Self, Error> {
+        info!(
+            "Reading GPS on {} at {} baud",
+            config.device, config.baud_rate
+        );
+        let mut uart = Uart::with_path(&config.device, config.baud_rate, Parity::None, 8, 1)?;
+        // Return whatever arrived at least every so often, to notice a stop.
+        uart.set_read_mode(0, READ_TIMEOUT)?;
+        GpsReceiver::spawn_reader(config, move |buffer| Ok(uart.read(buffer)?))
+    }
+
+    /// `read` fills a buffer with the next bytes of NMEA, returning how
+    /// many, 0 when nothing arrived for a while.
+    pub fn spawn_reader<F>(config: &GpsConfig, mut read: F) -> Result<Self, Error>
+    where
+        F: FnMut(&mut [u8]) -> Result<usize, Error> + Send + 'static,
+    {
+        let fix = Arc::new(Mutex::new(None));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_fix = fix.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-gps".into())
+            .spawn(move || {
+                let mut decoder = NmeaDecoder::new();
+                let mut line = Vec::new();
+                let mut buffer = [0u8; 256];
+                while thread_running.load(Ordering::SeqCst) {
+                    let len = match read(&mut buffer) {
+                        Ok(len) => len,
+                        Err(error) => {
+                            warn!("Could not read the GPS: {}", error);
+                            thread::sleep(READ_TIMEOUT);
+                            continue;
+                        }
+                    };
+                    for &byte in &buffer[..len] {
+                        if byte != b'\n' {
+                            line.push(byte);
+                            continue;
+                        }
+                        let decoded = String::from_utf8_lossy(&line);
+                        if let Err(error) = decoder.decode_line(&decoded) {
+                            debug!("Skipping NMEA sentence: {}", error);
+                        }
+                        line.clear();
+                        if let Ok(mut fix) = thread_fix.lock() {
+                            *fix = decoder.fix();
+                        }
+                    }
+                    // Noise on an unconnected port never ends a line.
+                    if line.len() > MAX_SENTENCE_LEN {
+                        line.clear();
+                    }
+                }
+            })?;
+
+        Ok(GpsReceiver {
+            fix,
+            stale_after: Duration::from_millis(config.stale_after_ms),
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// The latest fix, `None` without one or if it is stale.
+    pub fn fix(&self) -> Option<Fix> {
+        self.fix
+            .lock()
+            .ok()
+            .and_then(|fix| *fix)
+            .filter(|fix| fix.time.elapsed() < self.stale_after)
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        self.fix().map(|fix| fix.position)
+    }
+
+    /// Speed over ground in metres per second.
+    pub fn speed(&self) -> Option<f32> {
+        self.fix().map(|fix| fix.speed)
+    }
+}
+
+impl Drop for GpsReceiver {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("GPS thread panicked");
+            }
+        }
+    }
+}
+
+/// Mean radius, in metres.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+const METRES_PER_SECOND_PER_KNOT: f32 = 1852.0 / 3600.0;
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+/// NMEA sentences are at most 82 characters.
+const MAX_SENTENCE_LEN: usize = 128;
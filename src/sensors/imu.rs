@@ -0,0 +1,437 @@
+//! Inertial measurement units on the I2C bus: the InvenSense MPU-6050
+//! (accelerometer and gyro) and the Bosch BNO055 (accelerometer, gyro and
+//! magnetometer with on-chip sensor fusion).
+//!
+//! Both are read through the `Imu` trait. An `ImuStream` samples one on a
+//! background thread for consumers such as the gyro correction, heading
+//! hold and odometry. Axes are those of the chip, which is assumed mounted
+//! flat with x forward and z up.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use i2cdev::linux::LinuxI2CDevice;
+
+use crate::borg::{read_registers, write_register, Bus};
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+
+/// Which IMU to open, and where.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ImuConfig {
+    Mpu6050 {
+        #[serde(default = "default_mpu6050_address")]
+        address: u16,
+    },
+    Bno055 {
+        #[serde(default = "default_bno055_address")]
+        address: u16,
+        #[serde(default)]
+        mode: Bno055Mode,
+    },
+}
+
+impl Default for ImuConfig {
+    fn default() -> Self {
+        ImuConfig::Mpu6050 {
+            address: MPU6050_ADDR,
+        }
+    }
+}
+
+fn default_mpu6050_address() -> u16 {
+    MPU6050_ADDR
+}
+
+fn default_bno055_address() -> u16 {
+    BNO055_ADDR
+}
+
+/// Opens the IMU in `config` on `bus`.
+pub fn open(config: &ImuConfig, bus: &BusManager) -> Result<Box<dyn Imu>, Error> {
+    Ok(match *config {
+        ImuConfig::Mpu6050 { address } => Box::new(Mpu6050::new(Box::new(bus.device(address)))?),
+        ImuConfig::Bno055 { address, mode } => {
+            Box::new(Bno055::new(Box::new(bus.device(address)), mode)?)
+        }
+    })
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Vector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vector3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Vector3 { x, y, z }
+    }
+}
+
+/// Absolute orientation, in degrees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Orientation {
+    /// Clockwise from magnetic north in `[0, 360)`, or from the heading at
+    /// startup without a magnetometer.
+    pub heading: f32,
+    /// Positive right side down.
+    pub roll: f32,
+    /// Positive nose up.
+    pub pitch: f32,
+}
+
+/// One reading of every output of an IMU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuSample {
+    pub time: Instant,
+    /// In g, including gravity.
+    pub acceleration: Vector3,
+    /// In degrees per second, counter-clockwise around each axis.
+    pub angular_velocity: Vector3,
+    /// `None` unless the IMU fuses its sensors on chip.
+    pub orientation: Option<Orientation>,
+}
+
+pub trait Imu: Send {
+    fn acceleration(&mut self) -> Result<Vector3, Error>;
+
+    fn angular_velocity(&mut self) -> Result<Vector3, Error>;
+
+    /// `None` for IMUs without sensor fusion.
+    fn orientation(&mut self) -> Result<Option<Orientation>, Error> {
+        Ok(None)
+    }
+
+    /// Reads every output, in one bus transfer where the chip allows.
+    fn sample(&mut self) -> Result<ImuSample, Error> {
+        Ok(ImuSample {
+            time: Instant::now(),
+            acceleration: self.acceleration()?,
+            angular_velocity: self.angular_velocity()?,
+            orientation: self.orientation()?,
+        })
+    }
+}
+
+impl<I: Imu + ?Sized> Imu for Box<I> {
+    fn acceleration(&mut self) -> Result<Vector3, Error> {
+        (**self).acceleration()
+    }
+
+    fn angular_velocity(&mut self) -> Result<Vector3, Error> {
+        (**self).angular_velocity()
+    }
+
+    fn orientation(&mut self) -> Result<Option<Orientation>, Error> {
+        (**self).orientation()
+    }
+
+    fn sample(&mut self) -> Result<ImuSample, Error> {
+        (**self).sample()
+    }
+}
+
+/// InvenSense MPU-6050, configured for ±2g and ±250°/s.
+pub struct Mpu6050 {
+    bus: Box<dyn Bus>,
+}
+
+impl Mpu6050 {
+    pub fn open(bus_path: &str, address: u16) -> Result<Self, Error> {
+        info!(
+            "Initialising MPU-6050 at i2c bus {} address 0x{:x}",
+            bus_path, address
+        );
+        Mpu6050::new(Box::new(LinuxI2CDevice::new(bus_path, address)?))
+    }
+
+    /// Checks the chip answers on `bus` and wakes it up.
+    pub fn new(mut bus: Box<dyn Bus>) -> Result<Self, Error> {
+        let mut id = [0u8];
+        read_registers(&mut *bus, MPU_WHO_AM_I, &mut id)?;
+        if id[0] != MPU6050_ID {
+            return Err(Error::UnexpectedBoardId {
+                board: "MPU-6050",
+                expected: MPU6050_ID,
+                id: id[0],
+            });
+        }
+        write_register(&mut *bus, MPU_PWR_MGMT_1, MPU_CLOCK_GYRO_X)?;
+        write_register(&mut *bus, MPU_CONFIG, MPU_DLPF_44HZ)?;
+        write_register(&mut *bus, MPU_GYRO_CONFIG, MPU_GYRO_250DPS)?;
+        write_register(&mut *bus, MPU_ACCEL_CONFIG, MPU_ACCEL_2G)?;
+        Ok(Mpu6050 { bus })
+    }
+
+    fn read_vector(&mut self, register: u8, counts_per_unit: f32) -> Result<Vector3, Error> {
+        let mut data = [0u8; 6];
+        read_registers(&mut *self.bus, register, &mut data)?;
+        Ok(vector_be(&data, counts_per_unit))
+    }
+}
+
+impl Imu for Mpu6050 {
+    fn acceleration(&mut self) -> Result<Vector3, Error> {
+        self.read_vector(MPU_ACCEL_XOUT_H, MPU_COUNTS_PER_G)
+    }
+
+    fn angular_velocity(&mut self) -> Result<Vector3, Error> {
+        self.read_vector(MPU_GYRO_XOUT_H, MPU_COUNTS_PER_DPS)
+    }
+
+    fn sample(&mut self) -> Result<ImuSample, Error> {
+        // Acceleration, temperature and angular velocity, big endian.
+        let mut data = [0u8; 14];
+        read_registers(&mut *self.bus, MPU_ACCEL_XOUT_H, &mut data)?;
+        Ok(ImuSample {
+            time: Instant::now(),
+            acceleration: vector_be(&data[0..6], MPU_COUNTS_PER_G),
+            angular_velocity: vector_be(&data[8..14], MPU_COUNTS_PER_DPS),
+            orientation: None,
+        })
+    }
+}
+
+/// Sensor fusion mode of a BNO055.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bno055Mode {
+    /// Accelerometer and gyro only, the heading being relative to startup.
+    /// Unaffected by magnetic interference from the motors.
+    #[default]
+    Imu,
+    /// Accelerometer, gyro and magnetometer, the heading being from
+    /// magnetic north.
+    Ndof,
+}
+
+/// Bosch BNO055 absolute orientation sensor.
+pub struct Bno055 {
+    bus: Box<dyn Bus>,
+}
+
+impl Bno055 {
+    pub fn open(bus_path: &str, address: u16, mode: Bno055Mode) -> Result<Self, Error> {
+        info!(
+            "Initialising BNO055 at i2c bus {} address 0x{:x}",
+            bus_path, address
+        );
+        Bno055::new(Box::new(LinuxI2CDevice::new(bus_path, address)?), mode)
+    }
+
+    /// Checks the chip answers on `bus` and starts sensor fusion in `mode`.
+    pub fn new(mut bus: Box<dyn Bus>, mode: Bno055Mode) -> Result<Self, Error> {
+        let mut id = [0u8];
+        read_registers(&mut *bus, BNO_CHIP_ID, &mut id)?;
+        if id[0] != BNO055_ID {
+            return Err(Error::UnexpectedBoardId {
+                board: "BNO055",
+                expected: BNO055_ID,
+                id: id[0],
+            });
+        }
+        let mode = match mode {
+            Bno055Mode::Imu => BNO_MODE_IMU,
+            Bno055Mode::Ndof => BNO_MODE_NDOF,
+        };
+        // Units can only be changed in config mode.
+        write_register(&mut *bus, BNO_OPR_MODE, BNO_MODE_CONFIG)?;
+        thread::sleep(BNO_MODE_SWITCH);
+        write_register(&mut *bus, BNO_UNIT_SEL, BNO_UNITS_DEFAULT)?;
+        write_register(&mut *bus, BNO_OPR_MODE, mode)?;
+        thread::sleep(BNO_MODE_SWITCH);
+        Ok(Bno055 { bus })
+    }
+
+    fn read_vector(&mut self, register: u8, counts_per_unit: f32) -> Result<Vector3, Error> {
+        let mut data = [0u8; 6];
+        read_registers(&mut *self.bus, register, &mut data)?;
+        Ok(vector_le(&data, counts_per_unit))
+    }
+}
+
+impl Imu for Bno055 {
+    fn acceleration(&mut self) -> Result<Vector3, Error> {
+        self.read_vector(BNO_ACC_DATA_X_LSB, BNO_COUNTS_PER_G)
+    }
+
+    fn angular_velocity(&mut self) -> Result<Vector3, Error> {
+        self.read_vector(BNO_GYR_DATA_X_LSB, BNO_COUNTS_PER_DPS)
+    }
+
+    fn orientation(&mut self) -> Result<Option<Orientation>, Error> {
+        let mut data = [0u8; 6];
+        read_registers(&mut *self.bus, BNO_EUL_HEADING_LSB, &mut data)?;
+        Ok(Some(orientation(&data)))
+    }
+
+    fn sample(&mut self) -> Result<ImuSample, Error> {
+        // Acceleration, magnetic field, angular velocity and Euler angles,
+        // little endian.
+        let mut data = [0u8; 24];
+        read_registers(&mut *self.bus, BNO_ACC_DATA_X_LSB, &mut data)?;
+        Ok(ImuSample {
+            time: Instant::now(),
+            acceleration: vector_le(&data[0..6], BNO_COUNTS_PER_G),
+            angular_velocity: vector_le(&data[12..18], BNO_COUNTS_PER_DPS),
+            orientation: Some(orientation(&data[18..24])),
+        })
+    }
+}
+
+/// Samples an IMU on a background thread, keeping the latest sample and
+/// sending every sample to subscribers.
+pub struct ImuStream {
+    latest: Arc<Mutex<Option<ImuSample>>>,
+    subscribers: Arc<Mutex<Vec<Sender<ImuSample>>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ImuStream {
+    pub fn spawn<I>(mut imu: I, rate_hz: f32) -> Result<Self, Error>
+    where
+        I: Imu + 'static,
+    {
+        let latest: Arc<Mutex<Option<ImuSample>>> = Arc::default();
+        let subscribers: Arc<Mutex<Vec<Sender<ImuSample>>>> = Arc::default();
+        let running = Arc::new(AtomicBool::new(true));
+        let period = Duration::from_secs_f32(1.0 / rate_hz.max(1.0));
+
+        let thread_latest = latest.clone();
+        let thread_subscribers = subscribers.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-imu".into())
+            .spawn(move || {
+                while thread_running.load(Ordering::SeqCst) {
+                    match imu.sample() {
+                        Ok(sample) => {
+                            if let Ok(mut latest) = thread_latest.lock() {
+                                *latest = Some(sample);
+                            }
+                            if let Ok(mut subscribers) = thread_subscribers.lock() {
+                                subscribers.retain(|subscriber| subscriber.send(sample).is_ok());
+                            }
+                        }
+                        Err(error) => warn!("Could not read the IMU: {}", error),
+                    }
+                    thread::sleep(period);
+                }
+            })?;
+
+        Ok(ImuStream {
+            latest,
+            subscribers,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Most recent sample, `None` until the first one is read.
+    pub fn latest(&self) -> Option<ImuSample> {
+        self.latest.lock().ok().and_then(|latest| *latest)
+    }
+
+    pub fn acceleration(&self) -> Option<Vector3> {
+        self.latest().map(|sample| sample.acceleration)
+    }
+
+    pub fn angular_velocity(&self) -> Option<Vector3> {
+        self.latest().map(|sample| sample.angular_velocity)
+    }
+
+    pub fn orientation(&self) -> Option<Orientation> {
+        self.latest().and_then(|sample| sample.orientation)
+    }
+
+    /// Returns a channel receiving every subsequent sample.
+    pub fn samples(&self) -> Receiver<ImuSample> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+}
+
+impl Drop for ImuStream {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("IMU thread panicked");
+            }
+        }
+    }
+}
+
+fn vector_be(data: &[u8], counts_per_unit: f32) -> Vector3 {
+    let axis = |index: usize| {
+        f32::from(i16::from_be_bytes([data[index], data[index + 1]])) / counts_per_unit
+    };
+    Vector3::new(axis(0), axis(2), axis(4))
+}
+
+fn vector_le(data: &[u8], counts_per_unit: f32) -> Vector3 {
+    let axis = |index: usize| {
+        f32::from(i16::from_le_bytes([data[index], data[index + 1]])) / counts_per_unit
+    };
+    Vector3::new(axis(0), axis(2), axis(4))
+}
+
+/// Euler angles as heading, roll and pitch, in the BNO055's Windows
+/// orientation where pitch is positive nose down.
+fn orientation(data: &[u8]) -> Orientation {
+    let angles = vector_le(data, BNO_COUNTS_PER_DEGREE);
+    Orientation {
+        heading: angles.x.rem_euclid(360.0),
+        roll: angles.y,
+        pitch: -angles.z,
+    }
+}
+
+const MPU6050_ADDR: u16 = 0x68;
+pub const BNO055_ADDR: u16 = 0x28;
+
+// MPU-6050 registers and values
+const MPU6050_ID: u8 = 0x68;
+const MPU_CONFIG: u8 = 0x1A;
+const MPU_GYRO_CONFIG: u8 = 0x1B;
+const MPU_ACCEL_CONFIG: u8 = 0x1C;
+const MPU_ACCEL_XOUT_H: u8 = 0x3B;
+const MPU_GYRO_XOUT_H: u8 = 0x43;
+const MPU_PWR_MGMT_1: u8 = 0x6B;
+const MPU_WHO_AM_I: u8 = 0x75;
+const MPU_CLOCK_GYRO_X: u8 = 0x01;
+const MPU_DLPF_44HZ: u8 = 0x03;
+const MPU_GYRO_250DPS: u8 = 0x00;
+const MPU_ACCEL_2G: u8 = 0x00;
+const MPU_COUNTS_PER_G: f32 = 16384.0;
+const MPU_COUNTS_PER_DPS: f32 = 131.0;
+
+// BNO055 registers and values
+const BNO055_ID: u8 = 0xA0;
+const BNO_CHIP_ID: u8 = 0x00;
+const BNO_ACC_DATA_X_LSB: u8 = 0x08;
+const BNO_GYR_DATA_X_LSB: u8 = 0x14;
+const BNO_EUL_HEADING_LSB: u8 = 0x1A;
+const BNO_UNIT_SEL: u8 = 0x3B;
+const BNO_OPR_MODE: u8 = 0x3D;
+const BNO_MODE_CONFIG: u8 = 0x00;
+const BNO_MODE_IMU: u8 = 0x08;
+const BNO_MODE_NDOF: u8 = 0x0C;
+// m/s², degrees per second, degrees and Windows orientation.
+const BNO_UNITS_DEFAULT: u8 = 0x00;
+// 100 counts per m/s².
+const BNO_COUNTS_PER_G: f32 = 100.0 * 9.806_65;
+const BNO_COUNTS_PER_DPS: f32 = 16.0;
+const BNO_COUNTS_PER_DEGREE: f32 = 16.0;
+// Operating mode changes take up to 19ms.
+const BNO_MODE_SWITCH: Duration = Duration::from_millis(25);
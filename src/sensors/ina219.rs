@@ -0,0 +1,181 @@
+//! Driver for the TI INA219 current and power monitor, measuring the pack
+//! current through a shunt resistor on the high side of the battery.
+//!
+//! ```no_run
+//! # use vrum::sensors::ina219::{Ina219, Ina219Config};
+//! let mut sensor = Ina219::open("/dev/i2c-1", &Ina219Config::default())?;
+//! let reading = sensor.read()?;
+//! println!("{:.2}A, {:.1}W", reading.current, reading.power);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::time::Instant;
+
+use i2cdev::linux::LinuxI2CDevice;
+
+use crate::borg::{read_registers, Bus};
+use crate::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ina219Config {
+    pub address: u16,
+    /// Resistance of the shunt, in ohms. Breakout boards usually have 0.1.
+    pub shunt_ohms: f32,
+    /// Largest current expected, in amps, setting the resolution. Up to
+    /// 3.2A with a 0.1 ohm shunt.
+    pub max_current: f32,
+}
+
+impl Default for Ina219Config {
+    fn default() -> Self {
+        Ina219Config {
+            address: INA219_ADDR,
+            shunt_ohms: 0.1,
+            max_current: 3.2,
+        }
+    }
+}
+
+/// One reading of the pack.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct PowerReading {
+    /// Voltage on the load side of the shunt, in volts.
+    pub voltage: f32,
+    /// In amps, positive when the pack discharges.
+    pub current: f32,
+    /// In watts.
+    pub power: f32,
+}
+
+pub struct Ina219 {
+    bus: Box<dyn Bus>,
+    /// Amps per count of the current register.
+    current_lsb: f32,
+}
+
+impl Ina219 {
+    pub fn open(bus_path: &str, config: &Ina219Config) -> Result<Self, Error> {
+        info!(
+            "Initialising INA219 at i2c bus {} address 0x{:x}",
+            bus_path, config.address
+        );
+        Ina219::new(
+            Box::new(LinuxI2CDevice::new(bus_path, config.address)?),
+            config,
+        )
+    }
+
+    /// Configures the chip on `bus` for a 32V bus and continuous, averaged
+    /// conversions, and calibrates it for the shunt.
+    pub fn new(bus: Box<dyn Bus>, config: &Ina219Config) -> Result<Self, Error> {
+        let current_lsb = config.max_current / 32768.0;
+        // The lowest bit of the calibration register is unused.
+        let calibration = (CALIBRATION_SCALE / (current_lsb * config.shunt_ohms))
+            .min(f32::from(u16::MAX)) as u16
+            & !1;
+        let mut sensor = Ina219 { bus, current_lsb };
+        sensor.write_u16(REG_CONFIG, CONFIG_32V_320MV_12BIT_CONTINUOUS)?;
+        sensor.write_u16(REG_CALIBRATION, calibration)?;
+        Ok(sensor)
+    }
+
+    /// Voltage on the load side of the shunt, in volts.
+    pub fn bus_voltage(&mut self) -> Result<f32, Error> {
+        let raw = self.read_u16(REG_BUS_VOLTAGE)?;
+        Ok(f32::from(raw >> 3) * BUS_VOLTAGE_LSB)
+    }
+
+    /// Pack current in amps.
+    pub fn current(&mut self) -> Result<f32, Error> {
+        let raw = self.read_u16(REG_CURRENT)? as i16;
+        Ok(f32::from(raw) * self.current_lsb)
+    }
+
+    /// Power drawn in watts.
+    pub fn power(&mut self) -> Result<f32, Error> {
+        let raw = self.read_u16(REG_POWER)?;
+        Ok(f32::from(raw) * self.current_lsb * POWER_LSB_FACTOR)
+    }
+
+    pub fn read(&mut self) -> Result<PowerReading, Error> {
+        Ok(PowerReading {
+            voltage: self.bus_voltage()?,
+            current: self.current()?,
+            power: self.power()?,
+        })
+    }
+
+    fn read_u16(&mut self, register: u8) -> Result<u16, Error> {
+        let mut data = [0u8; 2];
+        read_registers(&mut *self.bus, register, &mut data)?;
+        Ok(u16::from_be_bytes(data))
+    }
+
+    fn write_u16(&mut self, register: u8, value: u16) -> Result<(), Error> {
+        let [high, low] = value.to_be_bytes();
+        self.bus.write(&[register, high, low])
+    }
+}
+
+/// Integrates power readings into the energy and charge used, e.g. over a
+/// run.
+#[derive(Clone, Debug, Default)]
+pub struct EnergyMeter {
+    /// In joules.
+    energy: f64,
+    /// In coulombs.
+    charge: f64,
+    peak_current: f32,
+    last: Option<(Instant, PowerReading)>,
+}
+
+impl EnergyMeter {
+    pub fn new() -> Self {
+        EnergyMeter::default()
+    }
+
+    /// Adds the energy used since the previous reading, taking the mean of
+    /// the two.
+    pub fn update(&mut self, reading: PowerReading) {
+        let now = Instant::now();
+        if let Some((at, previous)) = self.last {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            self.energy += f64::from(previous.power + reading.power) / 2.0 * elapsed;
+            self.charge += f64::from(previous.current + reading.current) / 2.0 * elapsed;
+        }
+        self.peak_current = self.peak_current.max(reading.current);
+        self.last = Some((now, reading));
+    }
+
+    pub fn energy_wh(&self) -> f32 {
+        (self.energy / 3600.0) as f32
+    }
+
+    pub fn charge_mah(&self) -> f32 {
+        (self.charge / 3.6) as f32
+    }
+
+    /// Highest current seen, in amps.
+    pub fn peak_current(&self) -> f32 {
+        self.peak_current
+    }
+
+    pub fn reset(&mut self) {
+        *self = EnergyMeter::default();
+    }
+}
+
+const INA219_ADDR: u16 = 0x40;
+
+// INA219 registers and values
+const REG_CONFIG: u8 = 0x00;
+const REG_BUS_VOLTAGE: u8 = 0x02;
+const REG_POWER: u8 = 0x03;
+const REG_CURRENT: u8 = 0x04;
+const REG_CALIBRATION: u8 = 0x05;
+// 32V bus range, ±320mV shunt range, 12 bit conversions of both, continuous.
+const CONFIG_32V_320MV_12BIT_CONTINUOUS: u16 = 0x399F;
+const CALIBRATION_SCALE: f32 = 0.04096;
+const BUS_VOLTAGE_LSB: f32 = 0.004;
+const POWER_LSB_FACTOR: f32 = 20.0;
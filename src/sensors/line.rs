@@ -0,0 +1,163 @@
+//! Reflectance sensors looking down at the floor, in a row across the
+//! robot, for following a dark line on a light floor. Digital modules (e.g.
+//! TCRT5000 boards with a comparator) are read from GPIO pins, analog ones
+//! through an ADS1115.
+
+use rppal::gpio::{Gpio, InputPin};
+
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+use crate::sensors::ads1115::{self, Ads1115};
+
+/// Which sensors to read, ordered from left to right.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LineSensorConfig {
+    /// Digital outputs on GPIO pins, BCM numbers.
+    Gpio {
+        pins: Vec<u8>,
+        /// True if an output reads low over the line.
+        #[serde(default)]
+        active_low: bool,
+    },
+    /// Analog outputs on inputs of an ADS1115.
+    Ads1115 {
+        #[serde(default = "default_ads1115_address")]
+        address: u16,
+        channels: Vec<u8>,
+        /// Voltage read over the line.
+        #[serde(default = "default_line_voltage")]
+        line_voltage: f32,
+        /// Voltage read over the bare floor.
+        #[serde(default)]
+        floor_voltage: f32,
+    },
+}
+
+fn default_ads1115_address() -> u16 {
+    ads1115::DEFAULT_ADDRESS
+}
+
+fn default_line_voltage() -> f32 {
+    3.3
+}
+
+/// Opens the sensors in `config`, `bus` being the I2C bus of an ADS1115.
+pub fn open(config: &LineSensorConfig, bus: &BusManager) -> Result<Box<dyn LineSensor>, Error> {
+    Ok(match *config {
+        LineSensorConfig::Gpio {
+            ref pins,
+            active_low,
+        } => Box::new(GpioLineSensor::new(pins, active_low)?),
+        LineSensorConfig::Ads1115 {
+            address,
+            ref channels,
+            line_voltage,
+            floor_voltage,
+        } => Box::new(AnalogLineSensor::new(
+            Ads1115::new(Box::new(bus.device(address))),
+            channels.clone(),
+            floor_voltage,
+            line_voltage,
+        )),
+    })
+}
+
+pub trait LineSensor: Send {
+    /// How much each sensor sees the line, from left to right, 0 over the
+    /// floor to 1 over the line.
+    fn read(&mut self) -> Result<Vec<f32>, Error>;
+}
+
+impl<S: LineSensor + ?Sized> LineSensor for Box<S> {
+    fn read(&mut self) -> Result<Vec<f32>, Error> {
+        (**self).read()
+    }
+}
+
+pub struct GpioLineSensor {
+    pins: Vec<InputPin>,
+    active_low: bool,
+}
+
+impl GpioLineSensor {
+    pub fn new(pins: &[u8], active_low: bool) -> Result<Self, Error> {
+        info!("Reading line sensors on GPIO {:?}", pins);
+        let gpio = Gpio::new()?;
+        let pins = pins
+            .iter()
+            .map(|&pin| Ok(gpio.get(pin)?.into_input()))
+            .collect::<Result<_, Error>>()?;
+        Ok(GpioLineSensor { pins, active_low })
+    }
+}
+
+impl LineSensor for GpioLineSensor {
+    fn read(&mut self) -> Result<Vec<f32>, Error> {
+        Ok(self
+            .pins
+            .iter()
+            .map(|pin| {
+                if pin.is_high() != self.active_low {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect())
+    }
+}
+
+pub struct AnalogLineSensor {
+    adc: Ads1115,
+    channels: Vec<u8>,
+    floor_voltage: f32,
+    line_voltage: f32,
+}
+
+impl AnalogLineSensor {
+    /// Readings are scaled linearly from 0 at `floor_voltage` to 1 at
+    /// `line_voltage`.
+    pub fn new(adc: Ads1115, channels: Vec<u8>, floor_voltage: f32, line_voltage: f32) -> Self {
+        AnalogLineSensor {
+            adc,
+            channels,
+            floor_voltage,
+            line_voltage,
+        }
+    }
+}
+
+impl LineSensor for AnalogLineSensor {
+    fn read(&mut self) -> Result<Vec<f32>, Error> {
+        let (floor, span) = (self.floor_voltage, self.line_voltage - self.floor_voltage);
+        let adc = &mut self.adc;
+        self.channels
+            .iter()
+            .map(|&channel| {
+                let voltage = adc.voltage(channel)?;
+                Ok(((voltage - floor) / span).clamp(0.0, 1.0))
+            })
+            .collect()
+    }
+}
+
+/// Position of the line under a row of evenly spaced sensors, from -1 under
+/// the leftmost to 1 under the rightmost, the readings' weighted mean.
+/// `None` when no sensor sees the line.
+pub fn line_position(readings: &[f32]) -> Option<f32> {
+    let total: f32 = readings.iter().sum();
+    if total <= f32::EPSILON {
+        return None;
+    }
+    if readings.len() == 1 {
+        return Some(0.0);
+    }
+    let step = 2.0 / (readings.len() - 1) as f32;
+    let weighted: f32 = readings
+        .iter()
+        .enumerate()
+        .map(|(index, reading)| (index as f32 * step - 1.0) * reading)
+        .sum();
+    Some(weighted / total)
+}
@@ -0,0 +1,174 @@
+//! Temperature sensors: the Pi's own SoC, a DS18B20 on the 1-Wire bus
+//! (`dtoverlay=w1-gpio` in `/boot/config.txt`) and a TMP36 read through an
+//! ADS1115, all in degrees Celsius.
+//!
+//! ```no_run
+//! # use vrum::sensors::temperature::{self, Ds18b20, TemperatureSensor};
+//! let mut motors = Ds18b20::find()?;
+//! println!(
+//!     "SoC {:.1}°C, motors {:.1}°C",
+//!     temperature::soc_temperature()?,
+//!     motors.temperature()?
+//! );
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::sensors::ads1115::Ads1115;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TemperatureError {
+    #[error("no DS18B20 found in {W1_DEVICES}, is the w1-gpio overlay loaded?")]
+    NoDs18b20,
+    #[error("could not make sense of the temperature in {}", path.display())]
+    InvalidReading { path: PathBuf },
+    #[error("DS18B20 {device} failed its CRC check")]
+    CrcMismatch { device: String },
+}
+
+/// A sensor reporting a temperature.
+pub trait TemperatureSensor: Send {
+    /// In degrees Celsius.
+    fn temperature(&mut self) -> Result<f32, Error>;
+}
+
+/// Temperature of the Pi's SoC, in degrees Celsius, as the firmware
+/// throttling it sees it.
+pub fn soc_temperature() -> Result<f32, Error> {
+    let path = Path::new(SOC_THERMAL_ZONE);
+    let millis = fs::read_to_string(path)?;
+    millis
+        .trim()
+        .parse::<f32>()
+        .map(|millis| millis / 1000.0)
+        .map_err(|_| {
+            TemperatureError::InvalidReading {
+                path: path.to_path_buf(),
+            }
+            .into()
+        })
+}
+
+/// The SoC of the Pi as a `TemperatureSensor`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SocSensor;
+
+impl TemperatureSensor for SocSensor {
+    fn temperature(&mut self) -> Result<f32, Error> {
+        soc_temperature()
+    }
+}
+
+/// A DS18B20 digital thermometer on the 1-Wire bus, read through the
+/// kernel's `w1_therm` driver. A conversion takes up to 750ms.
+#[derive(Clone, Debug)]
+pub struct Ds18b20 {
+    device: String,
+    path: PathBuf,
+}
+
+impl Ds18b20 {
+    /// The sensor with 1-Wire id `device`, e.g. `28-0316a2794cff`.
+    pub fn open(device: &str) -> Self {
+        Ds18b20 {
+            device: device.into(),
+            path: Path::new(W1_DEVICES).join(device).join("w1_slave"),
+        }
+    }
+
+    /// The first DS18B20 on the bus, for robots with only one.
+    pub fn find() -> Result<Self, Error> {
+        let mut devices = fs::read_dir(W1_DEVICES)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with(DS18B20_FAMILY))
+            .collect::<Vec<_>>();
+        devices.sort();
+        let device = devices
+            .into_iter()
+            .next()
+            .ok_or(TemperatureError::NoDs18b20)?;
+        info!("Found DS18B20 {}", device);
+        Ok(Ds18b20::open(&device))
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+}
+
+impl TemperatureSensor for Ds18b20 {
+    fn temperature(&mut self) -> Result<f32, Error> {
+        let contents = fs::read_to_string(&self.path)?;
+        parse_w1_slave(&contents).ok_or_else(|| {
+            if contents
+                .lines()
+                .next()
+                .is_some_and(|line| !line.ends_with("YES"))
+            {
+                TemperatureError::CrcMismatch {
+                    device: self.device.clone(),
+                }
+                .into()
+            } else {
+                TemperatureError::InvalidReading {
+                    path: self.path.clone(),
+                }
+                .into()
+            }
+        })
+    }
+}
+
+/// The temperature in the `w1_slave` file of a DS18B20, e.g.
+///
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+///
+/// `None` if the CRC check failed or there is no temperature.
+pub fn parse_w1_slave(contents: &str) -> Option<f32> {
+    let mut lines = contents.lines();
+    if !lines.next()?.trim_end().ends_with("YES") {
+        return None;
+    }
+    let (_, millis) = lines.next()?.split_once("t=")?;
+    millis
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .map(|millis| millis / 1000.0)
+}
+
+/// A TMP36 analog thermometer on an input of an ADS1115.
+pub struct Tmp36 {
+    adc: Ads1115,
+    channel: u8,
+}
+
+impl Tmp36 {
+    pub fn new(adc: Ads1115, channel: u8) -> Self {
+        Tmp36 { adc, channel }
+    }
+}
+
+impl TemperatureSensor for Tmp36 {
+    fn temperature(&mut self) -> Result<f32, Error> {
+        Ok(tmp36_temperature(self.adc.voltage(self.channel)?))
+    }
+}
+
+/// Temperature for an output of `volts` of a TMP36, 0.5V at 0°C and 10mV a
+/// degree.
+pub fn tmp36_temperature(volts: f32) -> f32 {
+    (volts - 0.5) * 100.0
+}
+
+const SOC_THERMAL_ZONE: &str = "/sys/class/thermal/thermal_zone0/temp";
+const W1_DEVICES: &str = "/sys/bus/w1/devices";
+/// 1-Wire family code of the DS18B20, prefixing the ids of the devices.
+const DS18B20_FAMILY: &str = "28-";
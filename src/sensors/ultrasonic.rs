@@ -0,0 +1,158 @@
+//! HC-SR04 ultrasonic distance sensor wired straight to the GPIO header, for
+//! the obstacle guard without an UltraBorg, or for mapping.
+//!
+//! The sensor answers a 10µs trigger pulse with an echo pulse as long as the
+//! sound takes to get back. The echo is timed by polling the pin from a busy
+//! loop: a microsecond is 0.17mm, so scheduling jitter is what limits the
+//! accuracy, and taking the median of a few pings removes the outliers it
+//! causes. The echo pin outputs 5V and has to go through a voltage divider.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Level, OutputPin};
+
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UltrasonicError {
+    #[error("the HC-SR04 did not respond to the trigger pulse")]
+    NoResponse,
+    #[error("the HC-SR04 echo pin is stuck high")]
+    EchoStuck,
+}
+
+/// A sensor measuring the distance to the nearest object ahead.
+pub trait DistanceSensor {
+    /// In metres, `None` if nothing is in range.
+    fn distance(&mut self) -> Result<Option<f32>, Error>;
+}
+
+pub struct HcSr04 {
+    trigger: OutputPin,
+    echo: InputPin,
+    speed_of_sound: f32,
+    max_range: f32,
+    samples: usize,
+    last_ping: Option<Instant>,
+}
+
+impl HcSr04 {
+    /// BCM pin numbers.
+    pub fn new(trigger_pin: u8, echo_pin: u8) -> Result<Self, Error> {
+        let gpio = Gpio::new()?;
+        let mut trigger = gpio.get(trigger_pin)?.into_output();
+        trigger.set_low();
+        let echo = gpio.get(echo_pin)?.into_input();
+        Ok(HcSr04 {
+            trigger,
+            echo,
+            speed_of_sound: speed_of_sound(DEFAULT_TEMPERATURE),
+            max_range: MAX_RANGE,
+            samples: 1,
+            last_ping: None,
+        })
+    }
+
+    /// Air temperature in °C, the speed of sound changing by about 0.2% per
+    /// degree. 20°C by default.
+    pub fn temperature(mut self, celsius: f32) -> Self {
+        self.speed_of_sound = speed_of_sound(celsius);
+        self
+    }
+
+    /// Distances beyond this, in metres, read as nothing in range. At most
+    /// 4m, the range of the sensor.
+    pub fn max_range(mut self, metres: f32) -> Self {
+        self.max_range = metres.clamp(0.0, MAX_RANGE);
+        self
+    }
+
+    /// Number of pings each `measure()` takes the median of, 1 by default.
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Distance to the nearest object in metres, `None` if most pings found
+    /// nothing within range. Fails if the sensor doesn't respond at all.
+    pub fn measure(&mut self) -> Result<Option<f32>, Error> {
+        let mut distances = Vec::with_capacity(self.samples);
+        let mut misses = 0;
+        for _ in 0..self.samples {
+            match self.ping()? {
+                Some(distance) => distances.push(distance),
+                None => misses += 1,
+            }
+        }
+        if misses >= distances.len() && misses > 0 {
+            return Ok(None);
+        }
+        distances.sort_by(|a, b| a.total_cmp(b));
+        Ok(Some(distances[distances.len() / 2]))
+    }
+
+    fn ping(&mut self) -> Result<Option<f32>, Error> {
+        // Echoes of the previous ping could be taken for this one's.
+        if let Some(last_ping) = self.last_ping {
+            if let Some(wait) = PING_INTERVAL.checked_sub(last_ping.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        // Without an echo the sensor holds the pin high for about 38ms.
+        if self.wait_for(Level::Low, NO_ECHO_PULSE).is_none() {
+            return Err(UltrasonicError::EchoStuck.into());
+        }
+        self.last_ping = Some(Instant::now());
+        self.trigger.set_high();
+        spin(TRIGGER_PULSE);
+        self.trigger.set_low();
+
+        let echo_start = self
+            .wait_for(Level::High, ECHO_START_TIMEOUT)
+            .ok_or(UltrasonicError::NoResponse)?;
+        let max_echo = Duration::from_secs_f32(2.0 * self.max_range / self.speed_of_sound);
+        Ok(self.wait_for(Level::Low, max_echo).map(|echo_end| {
+            let echo_time = echo_end.duration_since(echo_start);
+            echo_time.as_secs_f32() * self.speed_of_sound / 2.0
+        }))
+    }
+
+    /// Busy waits for the echo pin to reach `level`, sleeping would make the
+    /// timing too coarse.
+    fn wait_for(&self, level: Level, timeout: Duration) -> Option<Instant> {
+        let started = Instant::now();
+        while self.echo.read() != level {
+            if started.elapsed() > timeout {
+                return None;
+            }
+        }
+        Some(Instant::now())
+    }
+}
+
+impl DistanceSensor for HcSr04 {
+    fn distance(&mut self) -> Result<Option<f32>, Error> {
+        self.measure()
+    }
+}
+
+/// In metres per second, in dry air.
+fn speed_of_sound(celsius: f32) -> f32 {
+    331.3 + 0.606 * celsius
+}
+
+/// Sleeping for microseconds can take much longer.
+fn spin(duration: Duration) {
+    let started = Instant::now();
+    while started.elapsed() < duration {}
+}
+
+const TRIGGER_PULSE: Duration = Duration::from_micros(10);
+// The echo starts about 0.5ms after the trigger, once the burst is sent.
+const ECHO_START_TIMEOUT: Duration = Duration::from_millis(5);
+const NO_ECHO_PULSE: Duration = Duration::from_millis(50);
+// The datasheet asks for at least 60ms between pings.
+const PING_INTERVAL: Duration = Duration::from_millis(60);
+const MAX_RANGE: f32 = 4.0;
+const DEFAULT_TEMPERATURE: f32 = 20.0;
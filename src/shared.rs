@@ -0,0 +1,347 @@
+use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::borg::CommStats;
+use crate::color::Color;
+use crate::error::Error;
+use crate::motor_driver::MotorDriver;
+use crate::profile::Profile;
+use crate::realtime::RealtimeConfig;
+use crate::thunder_borg::Controller;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SharedControllerError {
+    #[error("the controller worker thread is no longer running")]
+    WorkerStopped,
+    #[error("drive command dropped, it waited {waited_ms}ms in the queue")]
+    Stale { waited_ms: u128 },
+    #[error("drive command dropped, a stop was requested")]
+    Preempted,
+}
+
+#[derive(Clone, Debug)]
+pub struct SharedControllerConfig {
+    /// Drive commands waiting longer than this for the bus are dropped
+    /// instead of being executed late.
+    pub max_drive_age: Duration,
+    /// Real-time scheduling of the worker thread, the one driving the board.
+    pub realtime: Option<RealtimeConfig>,
+}
+
+impl Default for SharedControllerConfig {
+    fn default() -> Self {
+        SharedControllerConfig {
+            max_drive_age: Duration::from_millis(200),
+            realtime: None,
+        }
+    }
+}
+
+/// How a request is scheduled by the worker thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Run in order, e.g. LED commands and readings.
+    Normal,
+    /// Run in order, but dropped when stale or when a stop is requested.
+    Drive,
+    /// Run before anything else queued, cancelling the drive commands
+    /// queued before it.
+    Stop,
+}
+
+/// Called with the controller, or with the reason the request is dropped.
+type Job = Box<dyn FnOnce(Result<&mut Controller, Error>) + Send>;
+
+struct Queued {
+    priority: Priority,
+    queued_at: Instant,
+    job: Job,
+}
+
+#[derive(Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    jobs: VecDeque<Queued>,
+    closed: bool,
+}
+
+/// Handle to a `Controller` owned by a worker thread, which runs the
+/// requests of every handle one at a time so access to the I2C bus is
+/// serialized. Handles can be cloned and shared between threads, e.g. by a
+/// teleop loop, a watchdog and telemetry. The controller is dropped, and the
+/// motors stopped, once the last handle is gone.
+///
+/// Stops jump the queue, and drive commands that would run late are dropped,
+/// see `Priority`.
+#[derive(Clone)]
+pub struct SharedController {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    queue: Arc<Queue>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SharedController {
+    pub fn spawn(config: SharedControllerConfig, controller: Controller) -> Result<Self, Error> {
+        let queue = Arc::new(Queue::default());
+        let worker_queue = queue.clone();
+        let max_drive_age = config.max_drive_age;
+        let thread = thread::Builder::new()
+            .name("vrum-controller".into())
+            .spawn(move || run_worker(controller, &worker_queue, max_drive_age))?;
+        let shared = SharedController {
+            inner: Arc::new(Inner {
+                queue,
+                thread: Some(thread),
+            }),
+        };
+        if let (Some(realtime), Some(thread)) = (config.realtime, &shared.inner.thread) {
+            realtime.apply_to_thread(thread)?;
+        }
+        Ok(shared)
+    }
+
+    /// Runs `action` on the worker thread and waits for its result.
+    pub fn call<T, F>(&self, action: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Controller) -> T + Send + 'static,
+    {
+        self.call_with_priority(Priority::Normal, action)
+    }
+
+    pub fn call_with_priority<T, F>(&self, priority: Priority, action: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Controller) -> T + Send + 'static,
+    {
+        let (reply, result) = mpsc::channel();
+        let job: Job = Box::new(move |controller| {
+            // The caller may have given up waiting, nothing to do then.
+            let _ = reply.send(controller.map(action));
+        });
+        self.inner.queue.push(Queued {
+            priority,
+            queued_at: Instant::now(),
+            job,
+        })?;
+        result
+            .recv()
+            .map_err(|_| SharedControllerError::WorkerStopped)?
+    }
+
+    pub fn set_motors(&self, power: f32) -> Result<(), Error> {
+        self.call_with_priority(Priority::Drive, move |controller| {
+            controller.set_motors(power)
+        })?
+    }
+
+    pub fn set_motor_a(&self, power: f32) -> Result<(), Error> {
+        self.call_with_priority(Priority::Drive, move |controller| {
+            controller.set_motor_a(power)
+        })?
+    }
+
+    pub fn set_motor_b(&self, power: f32) -> Result<(), Error> {
+        self.call_with_priority(Priority::Drive, move |controller| {
+            controller.set_motor_b(power)
+        })?
+    }
+
+    pub fn motor_powers(&self) -> Result<(f32, f32), Error> {
+        self.call(|controller| controller.motor_powers())
+    }
+
+    pub fn stop(&self) -> Result<(), Error> {
+        self.call_with_priority(Priority::Stop, |controller| controller.stop())?
+    }
+
+    pub fn stop_motors(&self) -> Result<(), Error> {
+        self.call_with_priority(Priority::Stop, |controller| controller.stop_motors())?
+    }
+
+    pub fn all_off(&self) -> Result<(), Error> {
+        self.call_with_priority(Priority::Stop, |controller| controller.all_off())?
+    }
+
+    pub fn set_led(&self, color: Color) -> Result<(), Error> {
+        self.call(move |controller| controller.set_led(color))?
+    }
+
+    pub fn get_battery_voltage(&self) -> Result<f32, Error> {
+        self.call(|controller| controller.get_battery_voltage())?
+    }
+
+    pub fn get_drive_fault_a(&self) -> Result<bool, Error> {
+        self.call(|controller| controller.get_drive_fault_a())?
+    }
+
+    pub fn get_drive_fault_b(&self) -> Result<bool, Error> {
+        self.call(|controller| controller.get_drive_fault_b())?
+    }
+
+    pub fn set_power_limit(&self, limit: f32) -> Result<(), Error> {
+        self.call(move |controller| controller.set_power_limit(limit))
+    }
+
+    pub fn apply_profile(&self, profile: Profile) -> Result<(), Error> {
+        self.call(move |controller| controller.apply_profile(&profile))
+    }
+
+    pub fn is_estopped(&self) -> Result<bool, Error> {
+        self.call(|controller| controller.is_estopped())
+    }
+}
+
+/// Lets the safety monitors share the main controller instead of opening
+/// a second handle to the board.
+impl MotorDriver for SharedController {
+    fn num_motors(&self) -> usize {
+        2
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        self.call_with_priority(Priority::Drive, move |controller| {
+            controller.set_motor(index, power)
+        })?
+    }
+
+    /// 0 if the worker is gone.
+    fn motor_power(&self, index: usize) -> f32 {
+        self.call(move |controller| controller.motor_power(index))
+            .unwrap_or(0.0)
+    }
+
+    fn motor_readback(&mut self, index: usize) -> Result<f32, Error> {
+        self.call(move |controller| controller.motor_readback(index))?
+    }
+
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        self.call_with_priority(Priority::Drive, move |controller| {
+            controller.set_sides(left, right)
+        })?
+    }
+
+    fn stop_all(&mut self) -> Result<(), Error> {
+        self.stop()
+    }
+
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        self.call(|controller| controller.comm_stats().clone())
+    }
+
+    fn fault(&mut self, index: usize) -> Result<bool, Error> {
+        self.call(move |controller| controller.fault(index))?
+    }
+
+    fn battery_voltage(&mut self) -> Result<f32, Error> {
+        self.call(|controller| controller.battery_voltage())?
+    }
+
+    fn battery_percent(&mut self) -> Result<f32, Error> {
+        self.call(|controller| controller.battery_percent())?
+    }
+
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        self.set_led(color)
+    }
+}
+
+impl Queue {
+    fn push(&self, queued: Queued) -> Result<(), Error> {
+        let preempted = {
+            let mut state = self
+                .state
+                .lock()
+                .map_err(|_| SharedControllerError::WorkerStopped)?;
+            if state.closed {
+                return Err(SharedControllerError::WorkerStopped.into());
+            }
+            let preempted = if queued.priority == Priority::Stop {
+                let (drives, others) = state
+                    .jobs
+                    .drain(..)
+                    .partition(|job| job.priority == Priority::Drive);
+                state.jobs = others;
+                drives
+            } else {
+                VecDeque::new()
+            };
+            state.jobs.push_back(queued);
+            preempted
+        };
+        self.available.notify_one();
+        for queued in preempted {
+            (queued.job)(Err(SharedControllerError::Preempted.into()));
+        }
+        Ok(())
+    }
+
+    /// Waits for the next job, stops first. `None` once the queue is closed.
+    fn pop(&self) -> Option<Queued> {
+        let mut state = self.state.lock().ok()?;
+        loop {
+            let stop = state
+                .jobs
+                .iter()
+                .position(|queued| queued.priority == Priority::Stop);
+            let next = match stop {
+                Some(index) => state.jobs.remove(index),
+                None => state.jobs.pop_front(),
+            };
+            if next.is_some() {
+                return next;
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.available.wait(state).ok()?;
+        }
+    }
+
+    fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+        }
+        self.available.notify_all();
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Every handle is gone so nobody is waiting on a request, the worker
+        // exits once it has run the jobs already queued.
+        self.queue.close();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Controller worker thread panicked");
+            }
+        }
+    }
+}
+
+fn run_worker(mut controller: Controller, queue: &Queue, max_drive_age: Duration) {
+    while let Some(queued) = queue.pop() {
+        let waited = queued.queued_at.elapsed();
+        if queued.priority == Priority::Drive && waited > max_drive_age {
+            debug!("Dropping a drive command queued {:?} ago", waited);
+            (queued.job)(Err(SharedControllerError::Stale {
+                waited_ms: waited.as_millis(),
+            }
+            .into()));
+        } else {
+            (queued.job)(Ok(&mut controller));
+        }
+    }
+    info!("Last controller handle dropped, stopping the worker");
+}
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use signal_hook::consts::TERM_SIGNALS;
+use signal_hook::flag;
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ShutdownError {
+    #[error("interrupted by a shutdown request")]
+    Requested,
+}
+
+/// Shutdown request raised by SIGINT or SIGTERM.
+///
+/// The default action of both signals kills the process without running
+/// `Drop`, leaving the motors at their last power until the firmware
+/// failsafe kicks in. Once the handlers are installed, long running
+/// operations check the request and bail out with `ShutdownError::Requested`
+/// instead, so the controller and loggers are dropped on the way out: the
+/// motors are stopped, LED effects turned off and files flushed.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    /// Requests a shutdown on SIGINT and SIGTERM. A second signal terminates
+    /// the process straight away, in case the first one is not acted upon.
+    pub fn install_signal_handlers(&self) -> Result<(), Error> {
+        for &signal in TERM_SIGNALS {
+            flag::register_conditional_shutdown(signal, 1, self.requested.clone())?;
+            flag::register(signal, self.requested.clone())?;
+        }
+        Ok(())
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// `ShutdownError::Requested` if a shutdown was requested.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_requested() {
+            Err(ShutdownError::Requested.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sleeps for `duration`, cut short by a shutdown request.
+    pub fn sleep(&self, duration: Duration) -> Result<(), Error> {
+        self.sleep_on(&SystemClock, duration)
+    }
+
+    /// Sleeps for `duration` on `clock`, cut short by a shutdown request.
+    pub fn sleep_on<C: Clock + ?Sized>(&self, clock: &C, duration: Duration) -> Result<(), Error> {
+        let deadline = clock.now() + duration;
+        loop {
+            self.check()?;
+            let remaining = deadline.saturating_duration_since(clock.now());
+            if remaining == Duration::default() {
+                return Ok(());
+            }
+            clock.sleep(remaining.min(SHUTDOWN_POLL_INTERVAL));
+        }
+    }
+}
+
+/// Longest delay between a signal and a sleeping operation noticing it.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
@@ -0,0 +1,348 @@
+//! A physics-lite simulation of a two-wheeled robot, to develop teleop,
+//! missions and PID tuning off the robot. Where `SimulatedBoard` emulates
+//! the ThunderBorg firmware, a `SimRobot` models what the motors do: each
+//! side accelerates with the mass of the robot towards a speed set by its
+//! power and the battery voltage, the pose follows the differential drive
+//! kinematics, and the battery drains and sags under load.
+//!
+//! Time is virtual: it only moves when `step()` is called, so runs are
+//! deterministic and as fast as the host allows. A `SimRobot::with_clock()`
+//! robot instead keeps up with a `Clock`, scaled by `time_scale`: the wall
+//! clock for `SimRobot::realtime()`, or a `ManualClock` shared with the code
+//! driving it.
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use vrum::motor_driver::MotorDriver;
+//! # use vrum::sim::{SimConfig, SimRobot};
+//! let mut robot = SimRobot::new(SimConfig::default());
+//! robot.set_sides(1.0, 1.0)?;
+//! robot.step(Duration::from_secs(2));
+//! assert!(robot.state().pose.x > 1.0);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::color::Color;
+use crate::error::Error;
+use crate::kinematics::{DiffDrive, Pose};
+use crate::motor_driver::{self, MotorDriver};
+use crate::simulator::SimulatedBoard;
+
+/// The `[sim]` section of the configuration file.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    /// In kilograms.
+    pub mass: f32,
+    /// Wheel speed at full power on a full battery, in metres per second.
+    pub max_speed: f32,
+    /// Force of one side at full power from rest, in newtons.
+    pub motor_force: f32,
+    /// Distance between the left and right wheels, in metres.
+    pub wheel_base: f32,
+    pub full_voltage: f32,
+    pub empty_voltage: f32,
+    pub battery_capacity_mah: f32,
+    /// Internal resistance of the pack, in ohms, causing the voltage sag.
+    pub internal_resistance: f32,
+    /// Current drawn by one motor at full power, in amps.
+    pub motor_current: f32,
+    /// Virtual seconds per second of the clock of a `with_clock()` robot.
+    pub time_scale: f32,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            mass: 1.5,
+            max_speed: 1.0,
+            motor_force: 6.0,
+            wheel_base: 0.2,
+            full_voltage: 12.6,
+            empty_voltage: 9.9,
+            battery_capacity_mah: 2200.0,
+            internal_resistance: 0.2,
+            motor_current: 2.5,
+            time_scale: 1.0,
+        }
+    }
+}
+
+/// Everything about the simulated robot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RobotState {
+    /// Virtual time since the start of the simulation.
+    pub time: Duration,
+    pub pose: Pose,
+    /// Ground speeds of the left and right wheels, in metres per second.
+    pub left_speed: f32,
+    pub right_speed: f32,
+    /// Powers of motors A (left) and B (right), in `[-1, 1]`.
+    pub powers: [f32; 2],
+    /// Battery voltage under the current load.
+    pub battery_voltage: f32,
+    /// In `[0, 1]`.
+    pub state_of_charge: f32,
+    pub led: Color,
+}
+
+/// Handle to a simulated robot, cheap to clone.
+#[derive(Clone)]
+pub struct SimRobot {
+    world: Arc<Mutex<World>>,
+}
+
+struct World {
+    config: SimConfig,
+    drive: DiffDrive,
+    state: RobotState,
+    /// The clock of a `with_clock()` robot and its time the state was last
+    /// advanced to.
+    synced: Option<(Arc<dyn Clock>, Instant)>,
+}
+
+impl SimRobot {
+    /// A robot at rest at the origin with a full battery, on virtual time
+    /// moved by `step()`.
+    pub fn new(config: SimConfig) -> Self {
+        let world = World {
+            drive: DiffDrive::new(config.wheel_base, config.max_speed),
+            state: RobotState {
+                time: Duration::default(),
+                pose: Pose::default(),
+                left_speed: 0.0,
+                right_speed: 0.0,
+                powers: [0.0; 2],
+                battery_voltage: config.full_voltage,
+                state_of_charge: 1.0,
+                led: Color::OFF,
+            },
+            config,
+            synced: None,
+        };
+        SimRobot {
+            world: Arc::new(Mutex::new(world)),
+        }
+    }
+
+    /// A robot whose virtual time follows the wall clock, scaled by
+    /// `config.time_scale`.
+    pub fn realtime(config: SimConfig) -> Self {
+        SimRobot::with_clock(config, clock::system())
+    }
+
+    /// A robot whose virtual time follows `clock`, scaled by
+    /// `config.time_scale`.
+    pub fn with_clock(config: SimConfig, clock: Arc<dyn Clock>) -> Self {
+        let robot = SimRobot::new(config);
+        let now = clock.now();
+        robot.lock().synced = Some((clock, now));
+        robot
+    }
+
+    pub fn config(&self) -> SimConfig {
+        self.lock().config
+    }
+
+    pub fn state(&self) -> RobotState {
+        self.lock().state
+    }
+
+    /// Moves the robot to `pose`, at rest.
+    pub fn place(&self, pose: Pose) {
+        let mut world = self.lock();
+        world.state.pose = pose;
+        world.state.left_speed = 0.0;
+        world.state.right_speed = 0.0;
+    }
+
+    /// Advances virtual time by `duration`.
+    pub fn step(&self, duration: Duration) {
+        self.lock().advance(duration);
+    }
+
+    /// Drives the robot from the motor powers of `board`, every `period` of
+    /// wall clock time, stepping it unless it follows a clock, and feeding the battery voltage back to
+    /// the board, so a `Controller` on the board's bus drives the robot.
+    pub fn drive_board(&self, board: SimulatedBoard, period: Duration) -> Result<SimLink, Error> {
+        let running = Arc::new(AtomicBool::new(true));
+        let robot = self.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-sim".into())
+            .spawn(move || {
+                let mut last = Instant::now();
+                while thread_running.load(Ordering::SeqCst) {
+                    thread::sleep(period);
+                    let now = Instant::now();
+                    let board_state = board.state();
+                    let voltage = {
+                        let mut world = robot.lock();
+                        world.state.powers = [board_state.motor_a, board_state.motor_b];
+                        world.state.led = board_state.led;
+                        if world.synced.is_none() {
+                            let elapsed = (now - last).mul_f32(world.config.time_scale);
+                            world.advance(elapsed);
+                        }
+                        world.state.battery_voltage
+                    };
+                    last = now;
+                    // The board adds its own sag on top of the battery
+                    // voltage, the robot has modelled it already.
+                    board.update(|state| {
+                        state.battery_voltage = voltage;
+                        state.load_sag = 0.0;
+                    });
+                }
+            })?;
+        Ok(SimLink {
+            robot: self.clone(),
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Locks the world, first advancing a clocked robot to the present.
+    fn lock(&self) -> MutexGuard<'_, World> {
+        // The world is consistent between steps, a panic mid-step at worst
+        // loses that step.
+        let mut world = self.world.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some((clock, synced_at)) = world.synced.take() {
+            let now = clock.now();
+            let elapsed = now
+                .saturating_duration_since(synced_at)
+                .mul_f32(world.config.time_scale);
+            world.synced = Some((clock, now));
+            world.advance(elapsed);
+        }
+        world
+    }
+}
+
+impl World {
+    fn advance(&mut self, duration: Duration) {
+        let mut remaining = duration;
+        while remaining > Duration::default() {
+            let dt = remaining.min(MAX_STEP);
+            self.integrate(dt.as_secs_f32());
+            remaining -= dt;
+        }
+        self.state.time += duration;
+    }
+
+    fn integrate(&mut self, dt: f32) {
+        let config = self.config;
+        let state = &mut self.state;
+
+        let load = state.powers[0].abs() + state.powers[1].abs();
+        let current = config.motor_current * load;
+        let open_circuit = config.empty_voltage
+            + (config.full_voltage - config.empty_voltage) * state.state_of_charge;
+        state.battery_voltage = (open_circuit - config.internal_resistance * current).max(0.0);
+        let capacity = config.battery_capacity_mah / 1000.0 * 3600.0;
+        state.state_of_charge = (state.state_of_charge - current * dt / capacity).max(0.0);
+
+        // Each side carries half the mass. The force drops linearly with
+        // speed, like a DC motor's, to zero at the speed the power and
+        // voltage allow.
+        let supply = state.battery_voltage / config.full_voltage;
+        let side_mass = config.mass / 2.0;
+        let accelerate = |speed: f32, power: f32| {
+            let force = config.motor_force * (power * supply - speed / config.max_speed);
+            speed + force / side_mass * dt
+        };
+        let (left, right) = (
+            accelerate(state.left_speed, state.powers[0]),
+            accelerate(state.right_speed, state.powers[1]),
+        );
+        let (linear, angular) = self.drive.body_velocity(
+            (state.left_speed + left) / 2.0,
+            (state.right_speed + right) / 2.0,
+        );
+        state.left_speed = left;
+        state.right_speed = right;
+        state.pose = state
+            .pose
+            .advance(linear, angular, Duration::from_secs_f32(dt));
+    }
+}
+
+impl MotorDriver for SimRobot {
+    fn num_motors(&self) -> usize {
+        2
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        motor_driver::check_motor_index(index, 2)?;
+        self.lock().state.powers[index] = power.clamp(-1.0, 1.0);
+        Ok(())
+    }
+
+    fn motor_power(&self, index: usize) -> f32 {
+        self.lock().state.powers.get(index).copied().unwrap_or(0.0)
+    }
+
+    /// Motor A drives the left side and motor B the right.
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        self.lock().state.powers = [left.clamp(-1.0, 1.0), right.clamp(-1.0, 1.0)];
+        Ok(())
+    }
+
+    fn stop_all(&mut self) -> Result<(), Error> {
+        self.set_sides(0.0, 0.0)
+    }
+
+    fn fault(&mut self, index: usize) -> Result<bool, Error> {
+        motor_driver::check_motor_index(index, 2)?;
+        Ok(false)
+    }
+
+    fn battery_voltage(&mut self) -> Result<f32, Error> {
+        Ok(self.lock().state.battery_voltage)
+    }
+
+    fn battery_percent(&mut self) -> Result<f32, Error> {
+        Ok(self.lock().state.state_of_charge * 100.0)
+    }
+
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        self.lock().state.led = color;
+        Ok(())
+    }
+}
+
+/// Keeps a `SimRobot` following a `SimulatedBoard`, until dropped.
+pub struct SimLink {
+    robot: SimRobot,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for SimLink {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Simulation thread panicked");
+            }
+        }
+        let state = self.robot.state();
+        info!(
+            "Simulated robot at ({:.2}, {:.2}) heading {:.0}° after {:.1}s, battery {:.2}V",
+            state.pose.x,
+            state.pose.y,
+            state.pose.heading.to_degrees(),
+            state.time.as_secs_f32(),
+            state.battery_voltage
+        );
+    }
+}
+
+/// Longest integration step, longer steps are split.
+const MAX_STEP: Duration = Duration::from_millis(5);
@@ -0,0 +1,259 @@
+//! A ThunderBorg simulated in software, to run the crate in CI or on a
+//! development machine without a robot:
+//!
+//! ```
+//! # use vrum::simulator::SimulatedBoard;
+//! # use vrum::thunder_borg::ControllerBuilder;
+//! let board = SimulatedBoard::new();
+//! let mut controller = ControllerBuilder::new().build_with_bus(board.bus())?;
+//! controller.set_motors(1.0)?;
+//! assert_eq!(board.state().motor_a, 1.0);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::borg::{self, Bus};
+use crate::bus_manager::{AddressedBus, BusManager};
+use crate::color::Color;
+use crate::error::Error;
+use crate::thunder_borg::{Command, VoltageCalibration, THUNDERBORG_ID, THUNDERBORG_SLAVE_ADDR};
+
+/// What the simulated firmware holds, as last set over the bus or by the
+/// test driving the simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardState {
+    /// Motor powers in `[-1, 1]`, at the 8 bit resolution of the firmware.
+    pub motor_a: f32,
+    pub motor_b: f32,
+    pub led: Color,
+    /// Battery voltage with the motors off.
+    pub battery_voltage: f32,
+    /// Voltage drop at full power on both motors, mimicking the sag of a
+    /// real battery under load.
+    pub load_sag: f32,
+    pub fault_a: bool,
+    pub fault_b: bool,
+}
+
+impl Default for BoardState {
+    fn default() -> Self {
+        BoardState {
+            motor_a: 0.0,
+            motor_b: 0.0,
+            led: Color::OFF,
+            battery_voltage: 12.0,
+            load_sag: 0.5,
+            fault_a: false,
+            fault_b: false,
+        }
+    }
+}
+
+impl BoardState {
+    /// The voltage the board measures, lowered by the current load.
+    pub fn loaded_voltage(&self) -> f32 {
+        let load = (self.motor_a.abs() + self.motor_b.abs()) / 2.0;
+        self.battery_voltage - self.load_sag * load
+    }
+
+    /// Runs a command like the firmware does, returning the response to
+    /// send if it is a query.
+    fn apply(&mut self, wire: u8, data: &[u8]) -> Vec<u8> {
+        let byte = |index: usize| data.get(index).cloned().unwrap_or(0);
+        let power = |sign: f32| sign * borg::byte_to_motor_power(byte(0));
+        let mut response = vec![wire];
+        let command = match Command::from_wire(wire) {
+            Some(command) => command,
+            None => return response,
+        };
+        match command {
+            Command::SetLed => self.led = Color::rgb(byte(0), byte(1), byte(2)),
+            Command::GetLed => response.extend(&[self.led.red, self.led.green, self.led.blue]),
+            Command::SetMotorAForward => self.motor_a = power(1.0),
+            Command::SetMotorAReverse => self.motor_a = power(-1.0),
+            Command::SetMotorBForward => self.motor_b = power(1.0),
+            Command::SetMotorBReverse => self.motor_b = power(-1.0),
+            Command::SetMotorsForward => {
+                self.motor_a = power(1.0);
+                self.motor_b = power(1.0);
+            }
+            Command::SetMotorsReverse => {
+                self.motor_a = power(-1.0);
+                self.motor_b = power(-1.0);
+            }
+            Command::GetMotorA => response.extend(&motor_readback(self.motor_a)),
+            Command::GetMotorB => response.extend(&motor_readback(self.motor_b)),
+            Command::AllOff => {
+                self.motor_a = 0.0;
+                self.motor_b = 0.0;
+                self.led = Color::OFF;
+            }
+            Command::GetDriveFaultFlagA => response.push(self.fault_a as u8),
+            Command::GetDriveFaultFlagB => response.push(self.fault_b as u8),
+            Command::GetBatteryVoltage => {
+                let calibration = VoltageCalibration::default();
+                let fraction = self.loaded_voltage() / calibration.pin_max;
+                let raw = (fraction.clamp(0.0, 1.0) * ANALOG_MAX).round() as u16;
+                response.extend(&raw.to_be_bytes());
+            }
+            Command::GetId => response.push(THUNDERBORG_ID),
+        }
+        response
+    }
+}
+
+/// Handle to a simulated board, cheap to clone. `bus()` connects a
+/// controller to it while the handle inspects and changes the board state,
+/// or injects bus errors.
+#[derive(Clone, Default)]
+pub struct SimulatedBoard {
+    inner: Arc<Mutex<Simulation>>,
+}
+
+#[derive(Default)]
+struct Simulation {
+    state: BoardState,
+    /// Response to the last query, read back by the next `read()`.
+    response: Vec<u8>,
+    /// Number of upcoming responses not echoing the command byte.
+    garbage_responses: u32,
+    /// Number of upcoming writes failing with a bus error.
+    failing_writes: u32,
+    /// Every write, command byte first.
+    writes: Vec<Vec<u8>>,
+}
+
+impl SimulatedBoard {
+    pub fn new() -> Self {
+        SimulatedBoard::default()
+    }
+
+    pub fn with_state(state: BoardState) -> Self {
+        let board = SimulatedBoard::new();
+        board.lock().state = state;
+        board
+    }
+
+    /// A `Bus` to the board, for `ControllerBuilder::build_with_bus`.
+    pub fn bus(&self) -> Box<dyn Bus> {
+        Box::new(self.clone())
+    }
+
+    /// An I2C bus with the board at the ThunderBorg address, where no other
+    /// device answers.
+    pub fn bus_manager(&self) -> BusManager {
+        BusManager::new(Box::new(SimulatedI2c {
+            board: self.clone(),
+            address: None,
+        }))
+    }
+
+    pub fn state(&self) -> BoardState {
+        self.lock().state.clone()
+    }
+
+    /// Changes the board state, e.g. to raise a drive fault or drain the
+    /// battery.
+    pub fn update<F: FnOnce(&mut BoardState)>(&self, change: F) {
+        change(&mut self.lock().state);
+    }
+
+    /// Makes the next `count` responses start with a byte other than the
+    /// command, as happens on a noisy bus, so the query is retried.
+    pub fn inject_garbage(&self, count: u32) {
+        self.lock().garbage_responses += count;
+    }
+
+    /// Makes the next `count` writes fail with a bus error.
+    pub fn fail_writes(&self, count: u32) {
+        self.lock().failing_writes += count;
+    }
+
+    /// Every command written so far, command byte first.
+    pub fn writes(&self) -> Vec<Vec<u8>> {
+        self.lock().writes.clone()
+    }
+
+    pub fn clear_writes(&self) {
+        self.lock().writes.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Simulation> {
+        // The simulation holds no invariants a panicking test could break.
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Bus for SimulatedBoard {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut simulation = self.lock();
+        if simulation.failing_writes > 0 {
+            simulation.failing_writes -= 1;
+            return Err(Error::Io(std::io::Error::other("simulated bus error")));
+        }
+        simulation.writes.push(bytes.to_vec());
+        let (&command, data) = match bytes.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        simulation.response = simulation.state.apply(command, data);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut simulation = self.lock();
+        buffer.iter_mut().for_each(|byte| *byte = 0);
+        let length = buffer.len().min(simulation.response.len());
+        buffer[..length].copy_from_slice(&simulation.response[..length]);
+        if simulation.garbage_responses > 0 && !buffer.is_empty() {
+            simulation.garbage_responses -= 1;
+            buffer[0] = !buffer[0];
+        }
+        Ok(())
+    }
+}
+
+/// See `SimulatedBoard::bus_manager`.
+struct SimulatedI2c {
+    board: SimulatedBoard,
+    address: Option<u16>,
+}
+
+impl SimulatedI2c {
+    fn board(&mut self) -> Result<&mut SimulatedBoard, Error> {
+        match self.address {
+            Some(THUNDERBORG_SLAVE_ADDR) => Ok(&mut self.board),
+            address => Err(Error::Io(std::io::Error::other(format!(
+                "no simulated device at address 0x{:x}",
+                address.unwrap_or_default()
+            )))),
+        }
+    }
+}
+
+impl AddressedBus for SimulatedI2c {
+    fn set_address(&mut self, address: u16) -> Result<(), Error> {
+        self.address = Some(address);
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        Bus::write(self.board()?, bytes)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        Bus::read(self.board()?, buffer)
+    }
+}
+
+/// Direction and duty cycle, as the firmware reports a motor.
+fn motor_readback(power: f32) -> [u8; 2] {
+    let direction = if power < 0.0 { 2 } else { 1 };
+    let duty = borg::motor_power_to_byte(borg::clamp_motor_power(power)).unwrap_or(0);
+    [direction, duty]
+}
+
+const ANALOG_MAX: f32 = 0x3FF as f32;
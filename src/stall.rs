@@ -0,0 +1,423 @@
+//! Stall detection. The drive fault flags only catch electrical faults; a
+//! motor held still by an obstacle or a jammed gearbox draws its stall
+//! current until something burns out. A `StallDetector` flags a motor as
+//! stalled when it has been commanded a high power for a while but its
+//! encoder reads about zero speed, the pack current spikes or its fault
+//! flag is raised, and optionally cuts its power.
+//!
+//! The powers commanded, and the cut, go through the `StallCutoff` stage of
+//! the drive pipeline, attached with `Controller::set_stall_guard`. A cut
+//! motor stays off until commanded below the power threshold or in the
+//! other direction.
+
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::motor_driver::MotorDriver;
+
+/// The `[stall]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StallConfig {
+    /// Motors commanded at least this absolute power are checked.
+    pub power_threshold: f32,
+    /// Encoder speeds below this, in the units of the encoders, count as
+    /// not turning.
+    pub speed_threshold: f32,
+    /// Pack currents from this many amps up count as a spike.
+    pub current_threshold: f32,
+    /// Whether a drive fault flag raised under high power counts.
+    pub use_fault_flags: bool,
+    /// How long the signs have to last for a stall, so starting from rest
+    /// isn't one.
+    pub stall_time_ms: u64,
+    pub poll_interval_ms: u64,
+    /// Cut the power of a stalled motor, otherwise only log and send a
+    /// `StallEvent`.
+    pub cut_power: bool,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        StallConfig {
+            power_threshold: 0.5,
+            speed_threshold: 0.05,
+            current_threshold: 2.5,
+            use_fault_flags: true,
+            stall_time_ms: 500,
+            poll_interval_ms: 100,
+            cut_power: true,
+        }
+    }
+}
+
+/// What gave a stall away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StallCause {
+    /// The encoder read about zero speed.
+    Encoder,
+    /// The pack current spiked.
+    Current,
+    /// The drive fault flag of the motor was raised.
+    DriveFault,
+}
+
+impl Display for StallCause {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            StallCause::Encoder => "encoder reads no motion",
+            StallCause::Current => "current spike",
+            StallCause::DriveFault => "drive fault",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StallEvent {
+    /// A motor, identified by its index, stalled.
+    Stalled { motor: usize, cause: StallCause },
+    /// A stalled motor is no longer commanded a high power.
+    Cleared(usize),
+    /// A sensor could not be read.
+    ReadFailed,
+}
+
+type Reader<T> = Box<dyn FnMut() -> Result<T, Error> + Send>;
+
+/// Optional inputs of a `StallDetector`, besides the fault flags.
+#[derive(Default)]
+pub struct StallSensors {
+    encoders: Option<Reader<Vec<f32>>>,
+    current: Option<Reader<f32>>,
+}
+
+impl StallSensors {
+    pub fn new() -> Self {
+        StallSensors::default()
+    }
+
+    /// `read` returns the speed of every motor, by index, in any unit.
+    pub fn encoders<F>(mut self, read: F) -> Self
+    where
+        F: FnMut() -> Result<Vec<f32>, Error> + Send + 'static,
+    {
+        self.encoders = Some(Box::new(read));
+        self
+    }
+
+    /// `read` returns the pack current in amps, e.g. from an `Ina219`.
+    pub fn current<F>(mut self, read: F) -> Self
+    where
+        F: FnMut() -> Result<f32, Error> + Send + 'static,
+    {
+        self.current = Some(Box::new(read));
+        self
+    }
+}
+
+/// The powers commanded and the motors cut, shared between a
+/// `StallDetector` and the `StallCutoff` stage.
+#[derive(Clone)]
+pub struct StallGuard {
+    state: Arc<Mutex<GuardState>>,
+    power_threshold: f32,
+}
+
+#[derive(Default)]
+struct GuardState {
+    commanded: Vec<f32>,
+    /// Direction of the power cut, by motor, `None` if not cut.
+    cut: Vec<Option<f32>>,
+}
+
+impl StallGuard {
+    fn new(power_threshold: f32) -> Self {
+        StallGuard {
+            state: Arc::default(),
+            power_threshold: power_threshold.abs(),
+        }
+    }
+
+    /// Last powers commanded, before the cut.
+    pub fn commanded(&self) -> Vec<f32> {
+        self.lock().commanded.clone()
+    }
+
+    pub fn is_cut(&self, motor: usize) -> bool {
+        self.lock().cut.get(motor).is_some_and(Option::is_some)
+    }
+
+    /// Records the powers commanded and returns them with those of the cut
+    /// motors zeroed. Releases the cut of motors commanded below the power
+    /// threshold or in the other direction.
+    pub(crate) fn apply(&self, powers: &[f32]) -> Vec<f32> {
+        let mut state = self.lock();
+        state.commanded = powers.to_vec();
+        state.cut.resize(powers.len(), None);
+        let threshold = self.power_threshold;
+        powers
+            .iter()
+            .zip(state.cut.iter_mut())
+            .map(|(&power, cut)| match *cut {
+                Some(direction) if power.abs() >= threshold && power.signum() == direction => 0.0,
+                Some(_) => {
+                    *cut = None;
+                    power
+                }
+                None => power,
+            })
+            .collect()
+    }
+
+    /// Releases every cut, e.g. when the motors are stopped.
+    pub(crate) fn release_all(&self) {
+        let mut state = self.lock();
+        state.commanded.iter_mut().for_each(|power| *power = 0.0);
+        state.cut.iter_mut().for_each(|cut| *cut = None);
+    }
+
+    fn cut(&self, motor: usize) {
+        let mut state = self.lock();
+        let direction = state
+            .commanded
+            .get(motor)
+            .map_or(0.0, |power| power.signum());
+        if let Some(cut) = state.cut.get_mut(motor) {
+            *cut = Some(direction);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, GuardState> {
+        // Plain values, nothing a panic could leave half updated.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Watches for stalls on a background thread.
+pub struct StallDetector {
+    guard: StallGuard,
+    subscribers: Arc<Mutex<Vec<Sender<StallEvent>>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StallDetector {
+    /// `controller` is a dedicated handle used to read the fault flags and
+    /// stop stalled motors right away.
+    pub fn spawn<D>(
+        config: StallConfig,
+        sensors: StallSensors,
+        mut controller: D,
+    ) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        if sensors.encoders.is_none() && sensors.current.is_none() && !config.use_fault_flags {
+            warn!(
+                "Stall detection has nothing to go on, no encoders, current sensor or fault flags"
+            );
+        }
+        let guard = StallGuard::new(config.power_threshold);
+        let subscribers: Arc<Mutex<Vec<Sender<StallEvent>>>> = Arc::default();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_guard = guard.clone();
+        let thread_subscribers = subscribers.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-stall".into())
+            .spawn(move || {
+                let mut check = StallCheck::new(&config, controller.num_motors());
+                let mut sensors = sensors;
+                while thread_running.load(Ordering::SeqCst) {
+                    let readings = read_signs(&mut sensors, &mut controller, &config);
+                    let events = match readings {
+                        Ok(signs) => check.update(&thread_guard, &signs, Instant::now()),
+                        Err(error) => {
+                            warn!("Could not read the stall sensors: {}", error);
+                            vec![StallEvent::ReadFailed]
+                        }
+                    };
+                    for event in events {
+                        react(&mut controller, &config, &thread_guard, event);
+                        broadcast(&thread_subscribers, event);
+                    }
+                    thread::sleep(Duration::from_millis(config.poll_interval_ms));
+                }
+            })?;
+
+        Ok(StallDetector {
+            guard,
+            subscribers,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn guard(&self) -> StallGuard {
+        self.guard.clone()
+    }
+
+    /// Returns a channel receiving every subsequent `StallEvent`.
+    pub fn events(&self) -> Receiver<StallEvent> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+}
+
+impl Drop for StallDetector {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Stall detector thread panicked");
+            }
+        }
+    }
+}
+
+/// One reading of every input, missing ones being `None`.
+#[derive(Default)]
+struct Signs {
+    speeds: Option<Vec<f32>>,
+    current: Option<f32>,
+    faults: Option<Vec<bool>>,
+}
+
+fn read_signs<D: MotorDriver>(
+    sensors: &mut StallSensors,
+    controller: &mut D,
+    config: &StallConfig,
+) -> Result<Signs, Error> {
+    Ok(Signs {
+        speeds: sensors.encoders.as_mut().map(|read| read()).transpose()?,
+        current: sensors.current.as_mut().map(|read| read()).transpose()?,
+        faults: if config.use_fault_flags {
+            Some(
+                (0..controller.num_motors())
+                    .map(|motor| controller.fault(motor))
+                    .collect::<Result<_, _>>()?,
+            )
+        } else {
+            None
+        },
+    })
+}
+
+/// Tracks, by motor, since when it has shown signs of a stall.
+struct StallCheck {
+    config: StallConfig,
+    suspect_since: Vec<Option<Instant>>,
+    stalled: Vec<bool>,
+}
+
+impl StallCheck {
+    fn new(config: &StallConfig, num_motors: usize) -> Self {
+        StallCheck {
+            config: config.clone(),
+            suspect_since: vec![None; num_motors],
+            stalled: vec![false; num_motors],
+        }
+    }
+
+    fn update(&mut self, guard: &StallGuard, signs: &Signs, now: Instant) -> Vec<StallEvent> {
+        let commanded = guard.commanded();
+        let stall_time = Duration::from_millis(self.config.stall_time_ms);
+        let mut events = Vec::new();
+        for motor in 0..self.stalled.len() {
+            let power = commanded.get(motor).copied().unwrap_or(0.0);
+            if self.stalled[motor] {
+                let over = if self.config.cut_power {
+                    !guard.is_cut(motor)
+                } else {
+                    power.abs() < self.config.power_threshold || self.cause(motor, signs).is_none()
+                };
+                if over {
+                    info!(motor, "Motor {} no longer stalled", motor);
+                    self.stalled[motor] = false;
+                    events.push(StallEvent::Cleared(motor));
+                }
+                continue;
+            }
+            let cause = if power.abs() >= self.config.power_threshold {
+                self.cause(motor, signs)
+            } else {
+                None
+            };
+            let cause = match cause {
+                Some(cause) => cause,
+                None => {
+                    self.suspect_since[motor] = None;
+                    continue;
+                }
+            };
+            let since = *self.suspect_since[motor].get_or_insert(now);
+            if now.duration_since(since) >= stall_time {
+                error!(motor, "Motor {} stalled: {}", motor, cause);
+                self.stalled[motor] = true;
+                self.suspect_since[motor] = None;
+                events.push(StallEvent::Stalled { motor, cause });
+            }
+        }
+        events
+    }
+
+    fn cause(&self, motor: usize, signs: &Signs) -> Option<StallCause> {
+        let not_turning = signs
+            .speeds
+            .as_ref()
+            .and_then(|speeds| speeds.get(motor))
+            .is_some_and(|speed| speed.abs() < self.config.speed_threshold);
+        let faulted = signs
+            .faults
+            .as_ref()
+            .and_then(|faults| faults.get(motor))
+            .is_some_and(|&fault| fault);
+        let current_spike = signs
+            .current
+            .is_some_and(|current| current >= self.config.current_threshold);
+        if not_turning {
+            Some(StallCause::Encoder)
+        } else if faulted {
+            Some(StallCause::DriveFault)
+        } else if current_spike && signs.speeds.is_none() {
+            // With encoders, a motor that turns isn't the one drawing it.
+            Some(StallCause::Current)
+        } else {
+            None
+        }
+    }
+}
+
+fn react<D: MotorDriver>(
+    controller: &mut D,
+    config: &StallConfig,
+    guard: &StallGuard,
+    event: StallEvent,
+) {
+    if let StallEvent::Stalled { motor, .. } = event {
+        if config.cut_power {
+            warn!("Cutting the power of stalled motor {}", motor);
+            guard.cut(motor);
+            if let Err(error) = controller.set_motor(motor, 0.0) {
+                error!("Could not stop stalled motor {}: {}", motor, error);
+            }
+        }
+    }
+}
+
+fn broadcast(subscribers: &Mutex<Vec<Sender<StallEvent>>>, event: StallEvent) {
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
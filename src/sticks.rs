@@ -0,0 +1,127 @@
+//! Response curves of the sticks driving the robot, the transmitter of
+//! `vrum rc` and the joystick of `vrum serve`, so it isn't twitchy around the
+//! centre and at speed. Configured in the `[sticks]` section of the
+//! configuration file, each axis with a dead zone and an expo curve:
+//!
+//! ```toml
+//! [sticks]
+//! reverse_scale = 0.6
+//! turn_reduction = 0.5
+//!
+//! [sticks.throttle]
+//! expo = 0.3
+//!
+//! [sticks.steering]
+//! dead_zone = 0.05
+//! expo = 0.5
+//! ```
+//!
+//! The shaped throttle and steering are then mixed into side powers as in
+//! arcade drive:
+//!
+//! ```
+//! # use vrum::sticks::{AxisCurve, StickConfig};
+//! let sticks = StickConfig {
+//!     throttle: AxisCurve { dead_zone: 0.0, expo: 1.0 },
+//!     reverse_scale: 0.5,
+//!     ..StickConfig::default()
+//! };
+//! assert_eq!(sticks.shape(0.5, 0.0), (0.125, 0.0));
+//! assert_eq!(sticks.drive(-1.0, 0.0), (-0.5, -0.5));
+//! ```
+//!
+//! The defaults leave the sticks linear.
+
+/// The shape of one axis of a stick.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AxisCurve {
+    /// Deflections up to this read as centred, the rest of the travel is
+    /// stretched to still reach full scale.
+    pub dead_zone: f32,
+    /// From 0, linear, to 1, cubic: how much finer the control is around
+    /// the centre, at the expense of the ends.
+    pub expo: f32,
+}
+
+impl Default for AxisCurve {
+    fn default() -> Self {
+        AxisCurve {
+            dead_zone: 0.0,
+            expo: 0.0,
+        }
+    }
+}
+
+impl AxisCurve {
+    /// The shaped `value`, both in `[-1, 1]`.
+    pub fn apply(&self, value: f32) -> f32 {
+        let value = value.clamp(-1.0, 1.0);
+        let dead_zone = self.dead_zone.clamp(0.0, MAX_DEAD_ZONE);
+        let magnitude = ((value.abs() - dead_zone) / (1.0 - dead_zone)).max(0.0);
+        let expo = self.expo.clamp(0.0, 1.0);
+        ((1.0 - expo) * magnitude + expo * magnitude.powi(3)).copysign(value)
+    }
+}
+
+/// The `[sticks]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StickConfig {
+    /// Forwards positive.
+    pub throttle: AxisCurve,
+    /// Right positive.
+    pub steering: AxisCurve,
+    /// Power at full throttle forwards.
+    pub forward_scale: f32,
+    /// Power at full throttle backwards, often lower as reversing is harder
+    /// to control.
+    pub reverse_scale: f32,
+    /// How much of the steering is taken away at full throttle, less at
+    /// lower speeds, so turns at speed are gentler than on the spot.
+    pub turn_reduction: f32,
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        StickConfig {
+            throttle: AxisCurve::default(),
+            steering: AxisCurve::default(),
+            forward_scale: 1.0,
+            reverse_scale: 1.0,
+            turn_reduction: 0.0,
+        }
+    }
+}
+
+impl StickConfig {
+    /// The shaped `throttle` and `steering`, all in `[-1, 1]`.
+    pub fn shape(&self, throttle: f32, steering: f32) -> (f32, f32) {
+        let throttle = self.throttle.apply(throttle);
+        let scale = if throttle >= 0.0 {
+            self.forward_scale
+        } else {
+            self.reverse_scale
+        };
+        let throttle = throttle * scale.clamp(0.0, 1.0);
+        let reduction = self.turn_reduction.clamp(0.0, 1.0) * throttle.abs();
+        (throttle, self.steering.apply(steering) * (1.0 - reduction))
+    }
+
+    /// Left and right side powers for the sticks, shaped then mixed.
+    pub fn drive(&self, throttle: f32, steering: f32) -> (f32, f32) {
+        let (throttle, steering) = self.shape(throttle, steering);
+        mix(throttle, steering)
+    }
+}
+
+/// Arcade mixing of the sticks into side powers, scaled down together when
+/// one would exceed full power so turns keep their shape.
+pub fn mix(throttle: f32, steering: f32) -> (f32, f32) {
+    let (left, right) = (throttle + steering, throttle - steering);
+    let scale = left.abs().max(right.abs()).max(1.0);
+    (left / scale, right / scale)
+}
+
+/// So there is some travel left out of the dead zone.
+const MAX_DEAD_ZONE: f32 = 0.95;
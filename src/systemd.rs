@@ -0,0 +1,143 @@
+//! Supervision by systemd: readiness and watchdog notifications for a
+//! `Type=notify` service, and the unit file installed by
+//! `vrum install-service`.
+
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+/// Sends `state`, e.g. `"READY=1"`, to the service manager. Returns false
+/// without doing anything when not running under systemd.
+pub fn notify(state: &str) -> Result<bool, Error> {
+    let socket_path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let socket_path = socket_path.to_string_lossy();
+    let address = match socket_path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&*socket_path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(true)
+}
+
+/// Watchdog timeout set by `WatchdogSec=` in the unit file, if the watchdog
+/// is enabled for this process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    if let Some(pid) = env::var_os("WATCHDOG_PID") {
+        if pid.to_string_lossy().parse::<u32>().ok()? != process::id() {
+            return None;
+        }
+    }
+    let microseconds = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(microseconds))
+}
+
+/// Tells systemd the service is ready, then pings its watchdog from a
+/// background thread at half the timeout. Systemd restarts the service if
+/// the process hangs badly enough to stop the pings. Reports the service as
+/// stopping when dropped.
+pub struct SystemdNotifier {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SystemdNotifier {
+    pub fn start() -> Result<Self, Error> {
+        if !notify("READY=1")? {
+            warn!("Not started by systemd, NOTIFY_SOCKET is not set");
+        }
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = match watchdog_timeout() {
+            Some(timeout) => {
+                info!("Pinging the systemd watchdog, timeout {:?}", timeout);
+                let interval = timeout / 2;
+                let thread_running = running.clone();
+                Some(
+                    thread::Builder::new()
+                        .name("vrum-systemd".into())
+                        .spawn(move || ping_watchdog(&thread_running, interval))?,
+                )
+            }
+            None => None,
+        };
+        Ok(SystemdNotifier { running, thread })
+    }
+}
+
+impl Drop for SystemdNotifier {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Systemd watchdog thread panicked");
+            }
+        }
+        if let Err(error) = notify("STOPPING=1") {
+            warn!("Could not notify systemd: {}", error);
+        }
+    }
+}
+
+fn ping_watchdog(running: &AtomicBool, interval: Duration) {
+    let mut last_ping = None;
+    while running.load(Ordering::SeqCst) {
+        if last_ping.is_none_or(|at: Instant| at.elapsed() >= interval) {
+            if let Err(error) = notify("WATCHDOG=1") {
+                warn!("Could not ping the systemd watchdog: {}", error);
+            }
+            last_ping = Some(Instant::now());
+        }
+        thread::sleep(interval.min(WATCHDOG_POLL_INTERVAL));
+    }
+}
+
+/// Unit file running `executable` with `arguments` as a `Type=notify`
+/// service, restarted by systemd if it exits or stops pinging the watchdog.
+pub fn unit_file(executable: &Path, arguments: &[String], watchdog: Duration) -> String {
+    let mut command = vec![quote(&executable.display().to_string()), "--systemd".into()];
+    command.extend(arguments.iter().map(|argument| quote(argument)));
+    format!(
+        "[Unit]\n\
+         Description=vrum robot control\n\
+         After=network.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={}\n\
+         ExecReload=/bin/kill -HUP $MAINPID\n\
+         WatchdogSec={}\n\
+         Restart=on-failure\n\
+         KillSignal=SIGTERM\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        command.join(" "),
+        watchdog.as_secs().max(1)
+    )
+}
+
+/// Quotes `argument` for `ExecStart=` if it contains whitespace or quotes.
+fn quote(argument: &str) -> String {
+    if argument.is_empty() || argument.contains(|c: char| c.is_whitespace() || c == '"') {
+        format!(
+            "\"{}\"",
+            argument.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+    } else {
+        argument.into()
+    }
+}
+
+/// Longest delay before a dropped `SystemdNotifier` stops its thread.
+const WATCHDOG_POLL_INTERVAL: Duration = Duration::from_millis(100);
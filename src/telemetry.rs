@@ -0,0 +1,495 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::estimation::{PoseEstimate, PoseHandle};
+use crate::motor_driver::{MotorDriver, MotorDriverError};
+use crate::sensors::ina219::{EnergyMeter, Ina219, PowerReading};
+use crate::thermal::ThermalGuard;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TelemetrySample {
+    /// Seconds since the UNIX epoch.
+    pub timestamp: f64,
+    pub battery_voltage: f32,
+    /// Estimated state of charge, in `[0, 100]`.
+    pub battery_percent: f32,
+    pub drive_fault_a: bool,
+    pub drive_fault_b: bool,
+    /// Last power commanded to motor A, in `[-1, 1]`.
+    pub motor_a_power: f32,
+    /// Last power commanded to motor B, in `[-1, 1]`.
+    pub motor_b_power: f32,
+    /// Bus retries and failures since the board was opened.
+    pub i2c_retries: u64,
+    pub i2c_failures: u64,
+    /// Mean round trip time of bus commands, in milliseconds.
+    pub i2c_latency_ms: f32,
+    /// Pack current in amps, from a current sensor if there is one.
+    pub pack_current: Option<f32>,
+    /// Power drawn from the pack in watts.
+    pub pack_power: Option<f32>,
+    /// Energy drawn since logging started, in watt hours.
+    pub energy_used_wh: Option<f32>,
+    /// Estimated pose, in metres and radians, from a `PoseEstimator` if
+    /// there is one.
+    pub pose_x: Option<f32>,
+    pub pose_y: Option<f32>,
+    pub pose_heading: Option<f32>,
+    /// Standard deviation of the estimated position, in metres.
+    pub position_std: Option<f32>,
+    /// Temperature of the Pi's SoC, in degrees Celsius, from a
+    /// `ThermalMonitor` if there is one.
+    pub soc_temperature: Option<f32>,
+    /// Temperature near the motor driver, in degrees Celsius.
+    pub motor_temperature: Option<f32>,
+}
+
+impl TelemetrySample {
+    /// Reads a sample from a two motor board with fault flags and a battery
+    /// monitor, like the ThunderBorg.
+    pub fn read<D: MotorDriver + ?Sized>(controller: &mut D) -> Result<Self, Error> {
+        let comm = controller.comm_stats()?.total();
+        Ok(TelemetrySample {
+            timestamp: unix_timestamp(),
+            battery_voltage: controller.battery_voltage()?,
+            battery_percent: controller.battery_percent()?,
+            drive_fault_a: controller.fault(0)?,
+            drive_fault_b: controller.fault(1)?,
+            motor_a_power: controller.motor_power(0),
+            motor_b_power: controller.motor_power(1),
+            i2c_retries: comm.retries,
+            i2c_failures: comm.failures,
+            i2c_latency_ms: comm.mean_latency().as_secs_f32() * 1000.0,
+            pack_current: None,
+            pack_power: None,
+            energy_used_wh: None,
+            pose_x: None,
+            pose_y: None,
+            pose_heading: None,
+            position_std: None,
+            soc_temperature: None,
+            motor_temperature: None,
+        })
+    }
+
+    /// Adds a reading of the pack current sensor.
+    pub fn with_power(mut self, reading: &PowerReading, energy: &EnergyMeter) -> Self {
+        self.pack_current = Some(reading.current);
+        self.pack_power = Some(reading.power);
+        self.energy_used_wh = Some(energy.energy_wh());
+        self
+    }
+
+    /// Adds a pose estimate.
+    pub fn with_pose(mut self, estimate: &PoseEstimate) -> Self {
+        self.pose_x = Some(estimate.pose.x);
+        self.pose_y = Some(estimate.pose.y);
+        self.pose_heading = Some(estimate.pose.heading);
+        self.position_std = Some(estimate.position_std());
+        self
+    }
+
+    /// Adds the temperatures read by a `ThermalMonitor`.
+    pub fn with_temperatures(mut self, thermal: &ThermalGuard) -> Self {
+        self.soc_temperature = thermal.soc_temperature();
+        self.motor_temperature = thermal.motor_temperature();
+        self
+    }
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writeln!(
+            writer,
+            "{:.3},{:.3},{:.1},{},{},{:.3},{:.3},{},{},{:.3},{},{},{},{},{},{},{},{},{}",
+            self.timestamp,
+            self.battery_voltage,
+            self.battery_percent,
+            self.drive_fault_a,
+            self.drive_fault_b,
+            self.motor_a_power,
+            self.motor_b_power,
+            self.i2c_retries,
+            self.i2c_failures,
+            self.i2c_latency_ms,
+            optional_csv(self.pack_current, 3),
+            optional_csv(self.pack_power, 2),
+            optional_csv(self.energy_used_wh, 4),
+            optional_csv(self.pose_x, 3),
+            optional_csv(self.pose_y, 3),
+            optional_csv(self.pose_heading, 4),
+            optional_csv(self.position_std, 3),
+            optional_csv(self.soc_temperature, 1),
+            optional_csv(self.motor_temperature, 1)
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TelemetryFormat {
+    /// One JSON object per line.
+    Json,
+    /// Comma separated values with a header row.
+    Csv,
+}
+
+#[derive(Clone, Debug)]
+pub struct TelemetryConfig {
+    pub path: PathBuf,
+    pub format: TelemetryFormat,
+    pub sample_interval: Duration,
+    /// The file is rotated once it grows past this size.
+    pub max_file_bytes: u64,
+    /// Number of rotated files (`<path>.1`, `<path>.2`, ...) kept around.
+    pub max_rotated_files: usize,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            path: PathBuf::from("vrum-telemetry.jsonl"),
+            format: TelemetryFormat::Json,
+            sample_interval: Duration::from_millis(500),
+            max_file_bytes: 8 * 1024 * 1024,
+            max_rotated_files: 4,
+        }
+    }
+}
+
+/// Writes timestamped `TelemetrySample`s to disk, rotating the output file
+/// when it reaches the configured size.
+pub struct TelemetryLogger {
+    config: TelemetryConfig,
+    writer: BufWriter<File>,
+    bytes_written: u64,
+    last_sample: Option<Instant>,
+    power_sensor: Option<Ina219>,
+    energy: EnergyMeter,
+    pose: Option<PoseHandle>,
+    thermal: Option<ThermalGuard>,
+}
+
+impl TelemetryLogger {
+    pub fn new(config: TelemetryConfig) -> Result<Self, Error> {
+        info!(
+            "Logging telemetry every {:?} to {}",
+            config.sample_interval,
+            config.path.display()
+        );
+        let (writer, bytes_written) = open_log(&config.path, config.format)?;
+        Ok(TelemetryLogger {
+            config,
+            writer,
+            bytes_written,
+            last_sample: None,
+            power_sensor: None,
+            energy: EnergyMeter::new(),
+            pose: None,
+            thermal: None,
+        })
+    }
+
+    /// Adds the pack current and power read from `sensor` to every sample,
+    /// and the energy used since.
+    pub fn set_power_sensor(&mut self, sensor: Ina219) {
+        self.power_sensor = Some(sensor);
+        self.energy.reset();
+    }
+
+    /// Adds the latest estimate of the `PoseEstimator` behind `pose` to
+    /// every sample.
+    pub fn set_pose_source(&mut self, pose: PoseHandle) {
+        self.pose = Some(pose);
+    }
+
+    /// Adds the temperatures read by the `ThermalMonitor` behind `thermal`
+    /// to every sample.
+    pub fn set_thermal_source(&mut self, thermal: ThermalGuard) {
+        self.thermal = Some(thermal);
+    }
+
+    /// Energy and charge drawn while logging, with a power sensor.
+    pub fn energy(&self) -> &EnergyMeter {
+        &self.energy
+    }
+
+    /// Reads and records a sample if at least `sample_interval` has passed
+    /// since the previous one. Meant to be called from the control loop.
+    pub fn sample_if_due<D: MotorDriver + ?Sized>(
+        &mut self,
+        controller: &mut D,
+    ) -> Result<(), Error> {
+        let due = self
+            .last_sample
+            .is_none_or(|last| last.elapsed() >= self.config.sample_interval);
+        if due {
+            self.last_sample = Some(Instant::now());
+            let mut sample = TelemetrySample::read(controller)?;
+            if let Some(ref mut sensor) = self.power_sensor {
+                let reading = sensor.read()?;
+                self.energy.update(reading);
+                sample = sample.with_power(&reading, &self.energy);
+            }
+            if let Some(ref pose) = self.pose {
+                sample = sample.with_pose(&pose.latest());
+            }
+            if let Some(ref thermal) = self.thermal {
+                sample = sample.with_temperatures(thermal);
+            }
+            self.record(&sample)?;
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, sample: &TelemetrySample) -> Result<(), Error> {
+        let mut line = Vec::new();
+        match self.config.format {
+            TelemetryFormat::Json => {
+                serde_json::to_writer(&mut line, sample)?;
+                line.push(b'\n');
+            }
+            TelemetryFormat::Csv => sample.write_csv(&mut line)?,
+        }
+        self.writer.write_all(&line)?;
+        self.bytes_written += line.len() as u64;
+        if self.bytes_written >= self.config.max_file_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        let path = &self.config.path;
+        debug!("Rotating telemetry file {}", path.display());
+        if self.config.max_rotated_files == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for index in (1..self.config.max_rotated_files).rev() {
+                let from = rotated_path(path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(path, index + 1))?;
+                }
+            }
+            fs::rename(path, rotated_path(path, 1))?;
+        }
+        let (writer, bytes_written) = open_log(path, self.config.format)?;
+        self.writer = writer;
+        self.bytes_written = bytes_written;
+        Ok(())
+    }
+}
+
+impl Drop for TelemetryLogger {
+    fn drop(&mut self) {
+        if self.power_sensor.is_some() {
+            info!(
+                "Used {:.2}Wh ({:.0}mAh, peak {:.2}A)",
+                self.energy.energy_wh(),
+                self.energy.charge_mah(),
+                self.energy.peak_current()
+            );
+        }
+        if let Err(error) = self.flush() {
+            error!("Could not flush telemetry file: {}", error);
+        }
+    }
+}
+
+/// The `[telemetry]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryPollerConfig {
+    pub interval_ms: u64,
+}
+
+impl Default for TelemetryPollerConfig {
+    fn default() -> Self {
+        TelemetryPollerConfig { interval_ms: 100 }
+    }
+}
+
+/// The latest sample of a `TelemetryPoller`, for consumers which only need
+/// the current state rather than every sample.
+#[derive(Clone, Default)]
+pub struct TelemetryHandle {
+    latest: Arc<Mutex<Option<TelemetrySample>>>,
+}
+
+impl TelemetryHandle {
+    /// `None` until the first sample is read.
+    pub fn latest(&self) -> Option<TelemetrySample> {
+        // The sample is only ever replaced whole, a panic can't leave it
+        // half written.
+        self.latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn publish(&self, sample: TelemetrySample) {
+        *self
+            .latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(sample);
+    }
+}
+
+/// Reads the battery, the fault flags and the motor powers read back from
+/// the board on a background thread, and shares the samples with every
+/// consumer, so a logger, a dashboard and a state of charge estimator don't
+/// each poll the bus.
+pub struct TelemetryPoller {
+    handle: TelemetryHandle,
+    subscribers: Arc<Mutex<Vec<Sender<TelemetrySample>>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TelemetryPoller {
+    /// `controller` is a dedicated handle used by the poller thread to read
+    /// the board. The temperatures of `thermal` are added to the samples.
+    pub fn spawn<D>(
+        config: TelemetryPollerConfig,
+        mut controller: D,
+        thermal: Option<ThermalGuard>,
+    ) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        info!("Polling telemetry every {}ms", config.interval_ms);
+        let handle = TelemetryHandle::default();
+        let subscribers: Arc<Mutex<Vec<Sender<TelemetrySample>>>> = Arc::default();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_handle = handle.clone();
+        let thread_subscribers = subscribers.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-telemetry".into())
+            .spawn(move || {
+                while thread_running.load(Ordering::SeqCst) {
+                    let polled = poll(&mut controller).map(|sample| match thermal {
+                        Some(ref thermal) => sample.with_temperatures(thermal),
+                        None => sample,
+                    });
+                    match polled {
+                        Ok(sample) => {
+                            thread_handle.publish(sample.clone());
+                            if let Ok(mut subscribers) = thread_subscribers.lock() {
+                                subscribers
+                                    .retain(|subscriber| subscriber.send(sample.clone()).is_ok());
+                            }
+                        }
+                        Err(error) => debug!("Could not poll telemetry: {}", error),
+                    }
+                    thread::sleep(Duration::from_millis(config.interval_ms));
+                }
+            })?;
+
+        Ok(TelemetryPoller {
+            handle,
+            subscribers,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn handle(&self) -> TelemetryHandle {
+        self.handle.clone()
+    }
+
+    /// The latest sample, `None` until the first one is read.
+    pub fn latest(&self) -> Option<TelemetrySample> {
+        self.handle.latest()
+    }
+
+    /// Returns a channel receiving every subsequent sample.
+    pub fn samples(&self) -> Receiver<TelemetrySample> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+}
+
+impl Drop for TelemetryPoller {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Telemetry poller thread panicked");
+            }
+        }
+    }
+}
+
+/// A sample with the motor powers read back from the board, when it
+/// supports it, as the poller's handle doesn't see the commands.
+fn poll<D: MotorDriver>(controller: &mut D) -> Result<TelemetrySample, Error> {
+    let mut sample = TelemetrySample::read(controller)?;
+    if let Some(powers) = readback(controller)? {
+        (sample.motor_a_power, sample.motor_b_power) = powers;
+    }
+    Ok(sample)
+}
+
+/// Powers of motors A and B read back from the board, `None` if it can't.
+fn readback<D: MotorDriver>(controller: &mut D) -> Result<Option<(f32, f32)>, Error> {
+    let a = match controller.motor_readback(0) {
+        Ok(a) => a,
+        Err(Error::MotorDriver(MotorDriverError::Unsupported { .. })) => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    Ok(Some((a, controller.motor_readback(1)?)))
+}
+
+fn open_log(path: &Path, format: TelemetryFormat) -> Result<(BufWriter<File>, u64), Error> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut bytes_written = file.metadata()?.len();
+    let mut writer = BufWriter::new(file);
+    if format == TelemetryFormat::Csv && bytes_written == 0 {
+        writer.write_all(CSV_HEADER.as_bytes())?;
+        bytes_written += CSV_HEADER.len() as u64;
+    }
+    Ok((writer, bytes_written))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Empty for a missing value.
+fn optional_csv(value: Option<f32>, precision: usize) -> String {
+    value.map_or(String::new(), |value| format!("{:.*}", precision, value))
+}
+
+pub(crate) fn unix_timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// The name of this machine, e.g. for mDNS, certificates and metrics.
+pub(crate) fn host_name() -> Option<String> {
+    let host = fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+    Some(host.trim().to_string()).filter(|host| !host.is_empty())
+}
+
+const CSV_HEADER: &str =
+    "timestamp,battery_voltage,battery_percent,drive_fault_a,drive_fault_b,motor_a_power,motor_b_power,i2c_retries,i2c_failures,i2c_latency_ms,pack_current,pack_power,energy_used_wh,pose_x,pose_y,pose_heading,position_std,soc_temperature,motor_temperature\n";
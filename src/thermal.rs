@@ -0,0 +1,333 @@
+//! Temperature monitoring and thermal derating, configured in the
+//! `[thermal]` section of the configuration file:
+//!
+//! ```toml
+//! [thermal]
+//! hysteresis = 5.0
+//!
+//! [thermal.motor_sensor]
+//! kind = "ds18b20"
+//!
+//! [[thermal.derating]]
+//! sensor = "motor"
+//! above = 60.0
+//! power_limit = 0.6
+//!
+//! [[thermal.derating]]
+//! sensor = "soc"
+//! above = 80.0
+//! power_limit = 0.5
+//! ```
+//!
+//! A `ThermalMonitor` reads the SoC of the Pi, and a sensor near the motor
+//! driver if there is one (see `vrum::sensors::temperature`), on a background
+//! thread. While a temperature is above the `above` of a derating step the
+//! motor power is capped at its `power_limit`, the lowest of the steps
+//! crossed, so the robot slows down before the driver trips a thermal fault.
+//! A step is released once its temperature falls `hysteresis` degrees below
+//! it. The temperatures are added to the telemetry samples.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::bus_manager::BusManager;
+use crate::error::Error;
+use crate::journal;
+use crate::sensors::ads1115::{self, Ads1115};
+use crate::sensors::temperature::{Ds18b20, SocSensor, TemperatureSensor, Tmp36};
+
+/// The `[thermal]` section of the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThermalConfig {
+    /// Read the temperature of the Pi's SoC.
+    pub soc: bool,
+    /// Sensor near the motor driver, if any.
+    pub motor_sensor: Option<MotorSensorConfig>,
+    /// Steps capping the motor power, see `DeratingStep`.
+    pub derating: Vec<DeratingStep>,
+    /// Degrees below its `above` a temperature falls to release a step.
+    pub hysteresis: f32,
+    pub poll_interval_ms: u64,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        ThermalConfig {
+            soc: true,
+            motor_sensor: None,
+            derating: Vec::new(),
+            hysteresis: 5.0,
+            poll_interval_ms: 2000,
+        }
+    }
+}
+
+/// Sensors near the motor driver.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MotorSensorConfig {
+    /// A DS18B20 on the 1-Wire bus, the first one found without a `device`
+    /// id.
+    Ds18b20 {
+        #[serde(default)]
+        device: Option<String>,
+    },
+    /// A TMP36 on input `channel` of an ADS1115.
+    Tmp36 {
+        #[serde(default = "default_adc_address")]
+        address: u16,
+        channel: u8,
+    },
+}
+
+fn default_adc_address() -> u16 {
+    ads1115::DEFAULT_ADDRESS
+}
+
+/// Which temperature a `DeratingStep` watches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThermalSensor {
+    Soc,
+    Motor,
+}
+
+/// Caps the motor power at `power_limit` while `sensor` is above `above`
+/// degrees Celsius.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeratingStep {
+    pub sensor: ThermalSensor,
+    pub above: f32,
+    pub power_limit: f32,
+}
+
+/// The power cap of the derating steps crossed by the temperatures, with
+/// hysteresis.
+#[derive(Clone, Debug)]
+pub struct Derating {
+    steps: Vec<DeratingStep>,
+    hysteresis: f32,
+    active: Vec<bool>,
+}
+
+impl Derating {
+    pub fn new(steps: &[DeratingStep], hysteresis: f32) -> Self {
+        Derating {
+            steps: steps.to_vec(),
+            hysteresis: hysteresis.max(0.0),
+            active: vec![false; steps.len()],
+        }
+    }
+
+    /// Updates the steps crossed with the latest temperatures and returns
+    /// the power cap, 1.0 when unrestricted. A missing temperature leaves
+    /// its steps as they were.
+    pub fn update(&mut self, soc: Option<f32>, motor: Option<f32>) -> f32 {
+        for (step, active) in self.steps.iter().zip(&mut self.active) {
+            let temperature = match step.sensor {
+                ThermalSensor::Soc => soc,
+                ThermalSensor::Motor => motor,
+            };
+            if let Some(temperature) = temperature {
+                *active = if *active {
+                    temperature > step.above - self.hysteresis
+                } else {
+                    temperature >= step.above
+                };
+            }
+        }
+        self.power_limit()
+    }
+
+    pub fn power_limit(&self) -> f32 {
+        self.steps
+            .iter()
+            .zip(&self.active)
+            .filter(|(_, &active)| active)
+            .map(|(step, _)| step.power_limit.clamp(0.0, 1.0))
+            .fold(1.0, f32::min)
+    }
+}
+
+/// Shared view of the temperatures and the power cap, attached to a
+/// `Controller` with `Controller::set_thermal_guard`.
+#[derive(Clone, Debug)]
+pub struct ThermalGuard {
+    soc: Arc<AtomicU32>,
+    motor: Arc<AtomicU32>,
+    power_limit: Arc<AtomicU32>,
+}
+
+impl ThermalGuard {
+    fn new() -> Self {
+        ThermalGuard {
+            soc: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            motor: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            power_limit: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+        }
+    }
+
+    /// Latest temperature of the SoC, in degrees Celsius, `None` until read.
+    pub fn soc_temperature(&self) -> Option<f32> {
+        load_temperature(&self.soc)
+    }
+
+    /// Latest temperature near the motor driver, in degrees Celsius, `None`
+    /// until read or without a sensor.
+    pub fn motor_temperature(&self) -> Option<f32> {
+        load_temperature(&self.motor)
+    }
+
+    /// Maximum absolute motor power currently allowed, 1.0 when unrestricted.
+    pub fn power_limit(&self) -> f32 {
+        f32::from_bits(self.power_limit.load(Ordering::SeqCst))
+    }
+
+    pub fn is_derated(&self) -> bool {
+        self.power_limit() < 1.0
+    }
+}
+
+fn load_temperature(temperature: &AtomicU32) -> Option<f32> {
+    Some(f32::from_bits(temperature.load(Ordering::SeqCst))).filter(|t| !t.is_nan())
+}
+
+/// Reads the temperatures on a background thread, updating the power cap of
+/// its `ThermalGuard`, until dropped.
+pub struct ThermalMonitor {
+    guard: ThermalGuard,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThermalMonitor {
+    /// Opens the sensors of `config`, a TMP36 on `bus`.
+    pub fn spawn(config: ThermalConfig, bus: &BusManager) -> Result<Self, Error> {
+        let mut soc_sensor: Option<Box<dyn TemperatureSensor>> = if config.soc {
+            Some(Box::new(SocSensor))
+        } else {
+            None
+        };
+        let mut motor_sensor: Option<Box<dyn TemperatureSensor>> = match config.motor_sensor {
+            Some(MotorSensorConfig::Ds18b20 {
+                device: Some(ref device),
+            }) => Some(Box::new(Ds18b20::open(device))),
+            Some(MotorSensorConfig::Ds18b20 { device: None }) => Some(Box::new(Ds18b20::find()?)),
+            Some(MotorSensorConfig::Tmp36 { address, channel }) => Some(Box::new(Tmp36::new(
+                Ads1115::new(Box::new(bus.device(address))),
+                channel,
+            ))),
+            None => None,
+        };
+        info!(
+            "Monitoring temperatures every {}ms",
+            config.poll_interval_ms
+        );
+        let guard = ThermalGuard::new();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_guard = guard.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-thermal".into())
+            .spawn(move || {
+                let mut derating = Derating::new(&config.derating, config.hysteresis);
+                let mut failing = [false; 2];
+                while thread_running.load(Ordering::SeqCst) {
+                    let soc = read(&mut soc_sensor, "SoC", &mut failing[0]);
+                    let motor = read(&mut motor_sensor, "motor", &mut failing[1]);
+                    store_temperature(&thread_guard.soc, soc);
+                    store_temperature(&thread_guard.motor, motor);
+                    let previous = thread_guard.power_limit();
+                    let limit = derating.update(soc, motor);
+                    if limit != previous {
+                        log_derating(limit, soc, motor);
+                        thread_guard
+                            .power_limit
+                            .store(limit.to_bits(), Ordering::SeqCst);
+                    }
+                    thread::sleep(Duration::from_millis(config.poll_interval_ms));
+                }
+            })?;
+
+        Ok(ThermalMonitor {
+            guard,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn guard(&self) -> ThermalGuard {
+        self.guard.clone()
+    }
+}
+
+impl Drop for ThermalMonitor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Thermal monitor thread panicked");
+            }
+        }
+    }
+}
+
+/// The temperature of `sensor`, warning the first time it can't be read.
+fn read(
+    sensor: &mut Option<Box<dyn TemperatureSensor>>,
+    name: &str,
+    failing: &mut bool,
+) -> Option<f32> {
+    match sensor.as_mut()?.temperature() {
+        Ok(temperature) => {
+            *failing = false;
+            Some(temperature)
+        }
+        Err(error) => {
+            if !*failing {
+                warn!("Could not read the {} temperature: {}", name, error);
+                *failing = true;
+            }
+            None
+        }
+    }
+}
+
+/// Keeps the last reading when `temperature` is missing, as the derating
+/// does.
+fn store_temperature(slot: &AtomicU32, temperature: Option<f32>) {
+    if let Some(temperature) = temperature {
+        slot.store(temperature.to_bits(), Ordering::SeqCst);
+    }
+}
+
+fn log_derating(limit: f32, soc: Option<f32>, motor: Option<f32>) {
+    let temperatures = format!(
+        "SoC {}, motor {}",
+        format_temperature(soc),
+        format_temperature(motor)
+    );
+    if limit < 1.0 {
+        warn!(
+            message_id = journal::THERMAL_DERATED,
+            power_limit = limit,
+            "Too hot ({}), limiting motor power to {:.2}",
+            temperatures,
+            limit
+        );
+    } else {
+        info!(
+            message_id = journal::THERMAL_RECOVERED,
+            "Cooled down ({}), motor power no longer limited", temperatures
+        );
+    }
+}
+
+fn format_temperature(temperature: Option<f32>) -> String {
+    temperature.map_or("unknown".into(), |t| format!("{:.1}°C", t))
+}
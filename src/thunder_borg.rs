@@ -1,124 +1,1103 @@
-use i2cdev::core::*;
-use i2cdev::linux::LinuxI2CDevice;
-use failure::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use arrayvec::ArrayVec;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Fail)]
-enum ControllerError {
-    #[fail(display = "error while running command {}", command)] CommandError { command: Command },
+use crate::arming::Arming;
+use crate::battery::{BatteryGuard, BatterySoc, SocConfig, VoltageFilter, VoltageFilterConfig};
+use crate::black_box::{BlackBox, TimedCommand};
+use crate::borg::{
+    self, BoardInfo, BorgCommand, BorgDevice, Bus, CommStats, Ping, RecoveryConfig, Response,
+};
+pub use crate::borg::{MotorConfig, MotorsConfig};
+use crate::bumper::BumperGuard;
+use crate::buzzer::Alerts;
+use crate::clock::{self, Clock};
+use crate::color::Color;
+use crate::config::Config;
+use crate::energy::EnergyReport;
+use crate::error::Error;
+use crate::estop::EStopLatch;
+use crate::faults::{FaultError, FaultGuard, FaultLatch};
+use crate::gears::GearSelector;
+use crate::gyro::GyroGuard;
+use crate::history::{EventHistory, HistoryEvent};
+use crate::led::{Effect, LedAnimator};
+use crate::motor_driver::{self, MotorDriver};
+use crate::obstacle::ObstacleGuard;
+use crate::pipeline::{
+    self, ArmingCheck, BatteryCutoff, BumperStop, DeadZone, DriveCommand, EStopCheck,
+    FaultLatchCheck, FaultLimit, Gears, Governor, GyroCorrection, ObstacleSlowdown, Pipeline,
+    PipelineConfig, Ramp, ReverseAlert, StallCutoff, ThermalDerating, Trim, TurnSensitivity,
+};
+use crate::profile::Profile;
+use crate::recorder::{RecordedCommand, Recorder};
+use crate::stall::StallGuard;
+use crate::telemetry;
+use crate::thermal::ThermalGuard;
+use crate::watchdog::WatchdogFeeder;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ControllerError {
+    #[error("no plausible battery voltage reading, last read {voltage:.2}V")]
+    ImplausibleVoltage { voltage: f32 },
 }
 
-pub struct Controller {
-    dev: LinuxI2CDevice,
+/// Conversion of the battery monitoring pin reading to volts. The analog
+/// front end differs slightly from board to board, see
+/// `vrum calibrate battery`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoltageCalibration {
+    /// Voltage corresponding to a full scale reading of the monitoring pin.
+    pub pin_max: f32,
+    /// Offset added to every reading.
+    pub correction: f32,
 }
 
-impl Controller {
-    pub fn new() -> Result<Self, Error> {
+impl Default for VoltageCalibration {
+    fn default() -> Self {
+        VoltageCalibration {
+            pin_max: VOLTAGE_PIN_MAX,
+            correction: VOLTAGE_PIN_CORRECTION,
+        }
+    }
+}
+
+impl VoltageCalibration {
+    /// Returns a calibration with the `correction` adjusted so `reading`,
+    /// taken with this calibration, would read as `measured` instead (e.g.
+    /// the voltage measured across the battery with a multimeter).
+    pub fn corrected(self, reading: f32, measured: f32) -> Self {
+        VoltageCalibration {
+            correction: self.correction + measured - reading,
+            ..self
+        }
+    }
+
+    /// The battery voltage of a raw 10 bit reading of the monitoring pin.
+    #[inline]
+    pub fn to_volts(self, raw_voltage: u16) -> f32 {
+        f32::from(raw_voltage) / COMMAND_ANALOG_MAX * self.pin_max + self.correction
+    }
+}
+
+/// What a `Controller` does to the board when dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    /// Set both motors to zero power, leaving the LED as it is.
+    StopMotors,
+    /// Switch off the motors and the LED, like `stop()`.
+    #[default]
+    AllOff,
+    /// Leave the board alone, e.g. to hand it over to a restarted daemon
+    /// while the firmware failsafe holds the motors in check.
+    LeaveRunning,
+}
+
+pub struct ControllerBuilder {
+    bus_path: String,
+    address: u16,
+    recovery: RecoveryConfig,
+    motors: MotorsConfig,
+    voltage_calibration: VoltageCalibration,
+    battery_soc: SocConfig,
+    voltage_filter: VoltageFilterConfig,
+    pipeline: PipelineConfig,
+    profile: Option<Profile>,
+    refresh_interval: Duration,
+    drop_policy: DropPolicy,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ControllerBuilder {
+    fn default() -> Self {
+        ControllerBuilder {
+            bus_path: borg::DEFAULT_I2C_BUS_PATH.into(),
+            address: THUNDERBORG_SLAVE_ADDR,
+            recovery: RecoveryConfig::default(),
+            motors: MotorsConfig::default(),
+            voltage_calibration: VoltageCalibration::default(),
+            battery_soc: SocConfig::default(),
+            voltage_filter: VoltageFilterConfig::default(),
+            pipeline: PipelineConfig::default(),
+            profile: None,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            drop_policy: DropPolicy::default(),
+            clock: clock::system(),
+        }
+    }
+}
+
+impl ControllerBuilder {
+    pub fn new() -> Self {
+        ControllerBuilder::default()
+    }
+
+    pub fn bus_path<S: Into<String>>(mut self, bus_path: S) -> Self {
+        self.bus_path = bus_path.into();
+        self
+    }
+
+    pub fn address(mut self, address: u16) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How persistent bus failures are recovered from.
+    pub fn recovery(mut self, config: RecoveryConfig) -> Self {
+        self.recovery = config;
+        self
+    }
+
+    pub fn motor_a(mut self, config: MotorConfig) -> Self {
+        self.motors.a = config;
+        self
+    }
+
+    pub fn motor_b(mut self, config: MotorConfig) -> Self {
+        self.motors.b = config;
+        self
+    }
+
+    pub fn motors(mut self, config: MotorsConfig) -> Self {
+        self.motors = config;
+        self
+    }
+
+    pub fn voltage_calibration(mut self, calibration: VoltageCalibration) -> Self {
+        self.voltage_calibration = calibration;
+        self
+    }
+
+    pub fn battery_soc(mut self, config: SocConfig) -> Self {
+        self.battery_soc = config;
+        self
+    }
+
+    pub fn voltage_filter(mut self, config: VoltageFilterConfig) -> Self {
+        self.voltage_filter = config;
+        self
+    }
+
+    pub fn pipeline(mut self, config: PipelineConfig) -> Self {
+        self.pipeline = config;
+        self
+    }
+
+    /// Driving profile applied on top of the `pipeline` and `motors`
+    /// settings, see `Controller::apply_profile`.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Motor powers and LED colours identical to the last ones written are
+    /// only written again once `interval` has passed, to cut down bus
+    /// traffic from high rate control loops. Keep it below the firmware
+    /// failsafe timeout when that is enabled; zero writes every command.
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// What the controller does to the board when dropped, `AllOff` by
+    /// default. Secondary handles used by background threads (e.g. the LED
+    /// animator) leave the board running.
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Clock timing the `Ramp` stage, the wall clock by default.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn build(self) -> Result<Controller, Error> {
         info!(
             "Pinging ThunderBorg at i2c bus {} address 0x{:x}",
-            1, THUNDERBORG_SLAVE_ADDR
+            self.bus_path, self.address
         );
+        let device = BorgDevice::open(&self.bus_path, self.address, I2C_MAX_LEN)?;
+        self.build_on(device)
+    }
+
+    /// Builds a controller talking to the board over `bus` rather than the
+    /// Linux I2C bus, e.g. to a `SimulatedBoard`.
+    pub fn build_with_bus(self, bus: Box<dyn Bus>) -> Result<Controller, Error> {
+        self.build_on(BorgDevice::new(bus, I2C_MAX_LEN))
+    }
+
+    fn build_on(self, device: BorgDevice) -> Result<Controller, Error> {
         let mut controller = Controller {
-            dev: LinuxI2CDevice::new("/dev/i2c-1", THUNDERBORG_SLAVE_ADDR)?,
+            device: device.with_recovery(
+                self.recovery.clone(),
+                Ping {
+                    command: Command::GetId.to_wire(),
+                    id: THUNDERBORG_ID,
+                },
+            ),
+            drop_policy: self.drop_policy,
+            pipeline: pipeline::default_pipeline(&self.pipeline, &self.motors, &self.clock),
+            power_limit: self
+                .pipeline
+                .power_limit
+                .map_or(1.0, |limit| limit.clamp(0.0, 1.0)),
+            pipeline_config: self.pipeline,
+            motors: self.motors,
+            refresh_interval: self.refresh_interval,
+            written_a: None,
+            written_b: None,
+            written_led: None,
+            voltage_calibration: self.voltage_calibration,
+            battery_soc: BatterySoc::new(self.battery_soc),
+            voltage_filter: VoltageFilter::new(self.voltage_filter),
+            motor_a_power: 0.0,
+            motor_b_power: 0.0,
+            requested: DriveCommand::default(),
+            recorder: None,
+            black_box: None,
+            command_subscribers: Vec::new(),
+            watchdog: None,
+            estop: None,
+            arming: None,
+            battery: None,
+            faults: None,
+            fault_latch: None,
+            led_effect: None,
+            config_updates: None,
+            clock: self.clock,
         };
 
-        let response = controller.command_with_response(Command::GetId)?;
-        if response[1] == THUNDERBORG_ID {
-            info!("ThunderBorg chip found. ");
-        } else {
-            panic!("Found chip with different id");
+        let id = controller.command_with_response(Command::GetId)?.byte(1)?;
+        if id != THUNDERBORG_ID {
+            return Err(Error::UnexpectedBoardId {
+                board: "ThunderBorg",
+                expected: THUNDERBORG_ID,
+                id,
+            });
+        }
+        info!("ThunderBorg chip found. ");
+        if let Some(ref profile) = self.profile {
+            controller.apply_profile(profile);
         }
         Ok(controller)
     }
+}
+
+/// The state of the board and the fault latch, see `Controller::status`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ControllerStatus {
+    pub board: BoardInfo,
+    pub battery_voltage: f32,
+    /// Estimated state of charge, in `[0, 100]`.
+    pub battery_percent: f32,
+    /// Drive fault flags of motors A and B.
+    pub faults: [bool; 2],
+    /// Powers of motors A and B read back from the board, whichever process
+    /// commanded them.
+    pub powers: [f32; 2],
+    /// What latched the fault blocking motor commands, if any.
+    pub fault_latched: Option<String>,
+    /// Energy used by the running or the last run, with an `[energy]`
+    /// section, see `vrum::energy::read_report`.
+    pub energy: Option<EnergyReport>,
+}
+
+impl Display for ControllerStatus {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        let fault = |faulted: bool| if faulted { "FAULT" } else { "ok" };
+        writeln!(formatter, "{}", self.board)?;
+        writeln!(
+            formatter,
+            "Battery {:.2}V ({:.0}%)",
+            self.battery_voltage, self.battery_percent
+        )?;
+        write!(
+            formatter,
+            "Motor A {:+.2} {} | Motor B {:+.2} {}",
+            self.powers[0],
+            fault(self.faults[0]),
+            self.powers[1],
+            fault(self.faults[1])
+        )?;
+        if let Some(ref cause) = self.fault_latched {
+            write!(formatter, "\nFault latched: {}", cause)?;
+        }
+        if let Some(ref energy) = self.energy {
+            write!(formatter, "\n{}", energy)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Controller {
+    device: BorgDevice,
+    drop_policy: DropPolicy,
+    pipeline: Pipeline,
+    power_limit: f32,
+    pipeline_config: PipelineConfig,
+    motors: MotorsConfig,
+    refresh_interval: Duration,
+    written_a: Option<Written<f32>>,
+    written_b: Option<Written<f32>>,
+    written_led: Option<Written<Color>>,
+    voltage_calibration: VoltageCalibration,
+    battery_soc: BatterySoc,
+    voltage_filter: VoltageFilter,
+    motor_a_power: f32,
+    motor_b_power: f32,
+    /// Last command run through the pipeline, as requested.
+    requested: DriveCommand,
+    recorder: Option<Recorder>,
+    black_box: Option<BlackBox>,
+    command_subscribers: Vec<Sender<TimedCommand>>,
+    watchdog: Option<WatchdogFeeder>,
+    estop: Option<EStopLatch>,
+    arming: Option<Arming>,
+    battery: Option<BatteryGuard>,
+    faults: Option<FaultGuard>,
+    fault_latch: Option<FaultLatch>,
+    led_effect: Option<LedAnimator>,
+    config_updates: Option<Receiver<Arc<Config>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Controller {
+    /// Opens the ThunderBorg at the default bus and address.
+    pub fn new() -> Result<Self, Error> {
+        ControllerBuilder::new().build()
+    }
+
+    pub fn builder() -> ControllerBuilder {
+        ControllerBuilder::new()
+    }
+
+    /// Bus traffic statistics since the controller was opened, to diagnose
+    /// flaky wiring or bus contention.
+    pub fn comm_stats(&self) -> &CommStats {
+        self.device.comm_stats()
+    }
+
+    pub fn reset_comm_stats(&mut self) {
+        self.device.reset_comm_stats();
+    }
+
+    /// The latest notable events of the process, oldest first, see
+    /// `vrum::history`.
+    pub fn events(&self) -> Vec<HistoryEvent> {
+        EventHistory::global().events()
+    }
+
+    /// Where the board is and the identifier it reports.
+    pub fn board_info(&mut self) -> Result<BoardInfo, Error> {
+        self.device.board_info("ThunderBorg", &Command::GetId)
+    }
+
+    /// Reads the board for a snapshot of its state, e.g. for `vrum status`.
+    pub fn status(&mut self) -> Result<ControllerStatus, Error> {
+        let battery_voltage = self.get_battery_voltage()?;
+        let (a, b) = self.read_motor_powers()?;
+        Ok(ControllerStatus {
+            board: self.board_info()?,
+            battery_voltage,
+            battery_percent: self.estimate_battery_percent(battery_voltage),
+            faults: [self.get_drive_fault_a()?, self.get_drive_fault_b()?],
+            powers: [a, b],
+            fault_latched: self.fault_latch.as_ref().and_then(FaultLatch::cause),
+            energy: None,
+        })
+    }
+
+    /// Writes a command the crate doesn't model, e.g. from a newer firmware
+    /// revision, bypassing the drive pipeline. Forgets the powers and LED
+    /// colour last written, in case the command changed them.
+    pub fn send_raw(&mut self, command: u8, data: &[u8]) -> Result<(), Error> {
+        self.device.send_raw(command, data)?;
+        self.written_a = None;
+        self.written_b = None;
+        self.written_led = None;
+        self.feed_watchdog();
+        Ok(())
+    }
+
+    /// Sends a query the crate doesn't model and reads back `read_len`
+    /// bytes, the first one echoing `command`.
+    pub fn query_raw(&mut self, command: u8, read_len: usize) -> Result<Response, Error> {
+        self.device.query_raw(command, read_len)
+    }
+
+    /// Feeds `watchdog` every time a command is written to the board.
+    pub fn set_watchdog(&mut self, watchdog: WatchdogFeeder) {
+        self.watchdog = Some(watchdog);
+    }
+
+    /// Rejects motor commands other than stopping with
+    /// `PipelineError::EStopped` while `estop` is latched.
+    pub fn set_estop(&mut self, estop: EStopLatch) {
+        self.pipeline.set_stage(EStopCheck::new(estop.clone()));
+        if let Some(ref arming) = self.arming {
+            arming.watch_estop(estop.clone());
+        }
+        self.estop = Some(estop);
+    }
+
+    pub fn is_estopped(&self) -> bool {
+        self.estop.as_ref().is_some_and(EStopLatch::is_latched)
+    }
+
+    /// Allows motor commands again after an emergency stop. Fails if the
+    /// e-stop button is still pressed.
+    pub fn reset_estop(&mut self) -> Result<(), Error> {
+        match self.estop {
+            Some(ref estop) => estop.reset(),
+            None => Ok(()),
+        }
+    }
+
+    /// Rejects motor commands other than stops with `ArmingError::Disarmed`
+    /// unless `arming` is armed. The e-stop and the fault guard set on the
+    /// controller disarm it.
+    pub fn set_arming(&mut self, arming: Arming) {
+        if let Some(ref estop) = self.estop {
+            arming.watch_estop(estop.clone());
+        }
+        if let Some(ref faults) = self.faults {
+            arming.watch_faults(faults.clone());
+        }
+        self.pipeline.set_stage(ArmingCheck::new(arming.clone()));
+        self.arming = Some(arming);
+    }
+
+    /// Arms the motors, if arming is required. See `vrum::arming`.
+    pub fn arm(&mut self) -> Result<(), Error> {
+        match self.arming {
+            Some(ref arming) => arming.arm(),
+            None => Ok(()),
+        }
+    }
+
+    /// Disarms the motors, stopping them, if arming is required.
+    pub fn disarm(&mut self) -> Result<(), Error> {
+        match self.arming {
+            Some(ref arming) => {
+                arming.disarm();
+                self.set_motors(0.0)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Always true when arming isn't required.
+    pub fn is_armed(&self) -> bool {
+        self.arming.as_ref().is_none_or(Arming::is_armed)
+    }
+
+    /// The arming state, `None` when arming isn't required.
+    pub fn arming(&self) -> Option<&Arming> {
+        self.arming.as_ref()
+    }
+
+    /// Rejects motor commands with `PipelineError::BatteryCutoff` while the
+    /// battery is below its cutoff voltage.
+    pub fn set_battery_guard(&mut self, battery: BatteryGuard) {
+        self.pipeline.set_stage(BatteryCutoff::new(battery.clone()));
+        self.battery = Some(battery);
+    }
+
+    /// Zeroes motor commands driving into a pressed bump switch.
+    pub fn set_bumper_guard(&mut self, bumper: BumperGuard) {
+        self.pipeline.set_stage(BumperStop::new(bumper));
+    }
+
+    /// Applies the power limit imposed by a `FaultMonitor` to motor commands,
+    /// and disarms the motors on drive faults if arming is required.
+    pub fn set_fault_guard(&mut self, faults: FaultGuard) {
+        if let Some(ref arming) = self.arming {
+            arming.watch_faults(faults.clone());
+        }
+        self.pipeline.set_stage(FaultLimit::new(faults.clone()));
+        self.faults = Some(faults);
+    }
+
+    /// Rejects motor commands with `PipelineError::FaultLatched` while
+    /// `latch` is latched.
+    pub fn set_fault_latch(&mut self, latch: FaultLatch) {
+        self.pipeline.set_stage(FaultLatchCheck::new(latch.clone()));
+        self.fault_latch = Some(latch);
+    }
+
+    /// The latch of drive faults and battery cutoffs, if any.
+    pub fn fault_latch(&self) -> Option<&FaultLatch> {
+        self.fault_latch.as_ref()
+    }
+
+    /// Allows motor commands again after a latched fault. Fails if a drive
+    /// fault is still raised, read from the board, or the battery is still
+    /// below its cutoff voltage.
+    pub fn clear_faults(&mut self) -> Result<(), Error> {
+        if self.get_drive_fault_a()? || self.get_drive_fault_b()? {
+            return Err(FaultError::StillFaulted.into());
+        }
+        if self.battery.as_ref().is_some_and(BatteryGuard::is_cutoff) {
+            return Err(FaultError::BatteryCutoff.into());
+        }
+        match self.fault_latch {
+            Some(ref latch) => latch.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Scales down forward motor commands when `obstacle` reports an object
+    /// ahead.
+    pub fn set_obstacle_guard(&mut self, obstacle: ObstacleGuard) {
+        self.pipeline.set_stage(ObstacleSlowdown::new(obstacle));
+    }
+
+    /// Scales motor commands by the gear selected in `gears`.
+    pub fn set_gears(&mut self, gears: GearSelector) {
+        self.pipeline.set_stage(Gears::new(gears));
+    }
+
+    /// Sounds the reverse beeper of `alerts` while driving backwards.
+    pub fn set_buzzer(&mut self, alerts: Alerts) {
+        self.pipeline.set_stage(ReverseAlert::new(alerts));
+    }
+
+    pub fn set_gyro_guard(&mut self, gyro: GyroGuard) {
+        self.pipeline.set_stage(GyroCorrection::new(gyro));
+    }
+
+    pub fn set_stall_guard(&mut self, stall: StallGuard) {
+        self.pipeline.set_stage(StallCutoff::new(stall));
+    }
+
+    /// Caps motor commands while `thermal` reports the robot too hot.
+    pub fn set_thermal_guard(&mut self, thermal: ThermalGuard) {
+        self.pipeline.set_stage(ThermalDerating::new(thermal));
+    }
+
+    /// Caps the absolute power of both motors to `limit` in `[0, 1]`, from
+    /// the next motor command on. A limit of 1 removes the cap.
+    pub fn set_power_limit(&mut self, limit: f32) {
+        let governor = Governor::new(limit);
+        self.power_limit = governor.limit();
+        if self.power_limit < 1.0 {
+            info!("Limiting motor power to {:.2}", self.power_limit);
+            self.pipeline.set_stage(governor);
+        } else {
+            info!("Motor power no longer limited");
+            self.pipeline.remove_stage(Governor::NAME);
+        }
+    }
+
+    pub fn power_limit(&self) -> f32 {
+        self.power_limit
+    }
+
+    /// Switches to the driving settings of `profile`, applied on top of the
+    /// `pipeline` and `motors` settings the controller was built with, so
+    /// settings of a previous profile don't linger. The ramp carries on from
+    /// the current powers, switching profiles on the move doesn't jerk the
+    /// motors back to a standstill.
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        let config = profile.pipeline_config(&self.pipeline_config);
+        match config.ramp_rate {
+            Some(rate) => {
+                let (a, b) = self.motor_powers();
+                self.pipeline.set_stage(
+                    Ramp::with_clock(rate, self.clock.clone())
+                        .starting_from(DriveCommand::new(a, b)),
+                )
+            }
+            None => {
+                self.pipeline.remove_stage(Ramp::NAME);
+            }
+        }
+        match config.turn_sensitivity {
+            Some(sensitivity) => self.pipeline.set_stage(TurnSensitivity::new(sensitivity)),
+            None => {
+                self.pipeline.remove_stage(TurnSensitivity::NAME);
+            }
+        }
+        self.set_power_limit(config.power_limit.unwrap_or(1.0));
+        self.pipeline
+            .set_stage(Trim::new(profile.motors(&self.motors)));
+    }
+
+    /// Picks up the driving settings of every reloaded `Config` from the
+    /// next motor command on, see `reload`.
+    pub fn set_config_updates(&mut self, updates: Receiver<Arc<Config>>) {
+        self.config_updates = Some(updates);
+    }
+
+    /// Switches to the `pipeline` and `motors` settings and the selected
+    /// profile of `config` without stopping the motors, see `apply_profile`.
+    /// The inversion of the motors is left as it
+    /// was, flipping it would reverse a moving robot.
+    pub fn reload(&mut self, config: &Config) {
+        let profile = match config.profile {
+            Some(ref name) => match config.find_profile(name) {
+                Ok(profile) => profile.clone(),
+                Err(error) => {
+                    warn!("Not reloading the driving settings: {}", error);
+                    return;
+                }
+            },
+            None => Profile::default(),
+        };
+        let mut motors = config.motors.clone();
+        motors.a.inverted = self.motors.a.inverted;
+        motors.b.inverted = self.motors.b.inverted;
+        self.pipeline_config = config.pipeline.clone();
+        self.motors = motors;
+        self.apply_profile(&profile);
+        self.pipeline
+            .set_stage(DeadZone::new(profile.motors(&self.motors)));
+    }
+
+    /// The stages motor commands go through before being written to the
+    /// board, to add custom stages or remove default ones.
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    /// Starts recording every motor and LED command to `path`, replacing any
+    /// recording already in progress. See `recorder::replay`.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), Error> {
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Keeps every motor and LED command in `black_box` too, see
+    /// `vrum::black_box`.
+    pub fn set_black_box(&mut self, black_box: BlackBox) {
+        self.black_box = Some(black_box);
+    }
+
+    /// Returns a channel receiving every subsequent motor and LED command,
+    /// e.g. to store them with `vrum::run_log`.
+    pub fn commands(&mut self) -> Receiver<TimedCommand> {
+        let (sender, receiver) = mpsc::channel();
+        self.command_subscribers.push(sender);
+        receiver
+    }
+
+    /// Plays `effect` on the LED in the background, over the bus of this
+    /// controller, replacing any effect already running. The effect ends
+    /// when the controller is dropped, on `stop()`, `set_led()` or
+    /// `stop_led_effect()`, or when the emergency stop is latched.
+    pub fn led_effect(&mut self, effect: Effect) -> Result<(), Error> {
+        self.led_effect = None;
+        self.written_led = None;
+        // Recovering the shared bus is left to this controller.
+        let led_controller = ControllerBuilder::new()
+            .voltage_calibration(self.voltage_calibration)
+            .recovery(RecoveryConfig {
+                enabled: false,
+                ..RecoveryConfig::default()
+            })
+            .drop_policy(DropPolicy::LeaveRunning)
+            .build_on(self.device.share())?;
+        self.led_effect = Some(LedAnimator::spawn(
+            effect,
+            led_controller,
+            self.estop.clone(),
+        )?);
+        Ok(())
+    }
+
+    pub fn stop_led_effect(&mut self) {
+        self.led_effect = None;
+    }
 
-    pub fn set_led(&mut self, red: u8, green: u8, blue: u8) -> Result<(), Error> {
-        self.command(Command::SetLed, &[red, green, blue])
+    pub fn set_led(&mut self, color: Color) -> Result<(), Error> {
+        self.led_effect = None;
+        if self.is_written(self.written_led, color) {
+            self.feed_watchdog();
+        } else {
+            self.command(Command::SetLed, &[color.red, color.green, color.blue])?;
+            self.written_led = Some(Written::now(color));
+        }
+        self.record(RecordedCommand::SetLed { color });
+        Ok(())
     }
 
     pub fn set_motors(&mut self, power: f32) -> Result<(), Error> {
-        self.motor_command(Command::SetMotorsForward, Command::SetMotorsReverse, power)
+        self.drive(DriveCommand::new(power, power), None)?;
+        self.record(RecordedCommand::SetMotors { power });
+        Ok(())
     }
 
+    /// Drives motor A alone, motor B keeping the power last requested for
+    /// it.
     pub fn set_motor_a(&mut self, power: f32) -> Result<(), Error> {
-        self.motor_command(Command::SetMotorAForward, Command::SetMotorAReverse, power)
+        self.drive(DriveCommand::new(power, self.requested.b), Some(Motor::A))?;
+        self.record(RecordedCommand::SetMotorA { power });
+        Ok(())
     }
 
+    /// Drives motor B alone, motor A keeping the power last requested for
+    /// it.
     pub fn set_motor_b(&mut self, power: f32) -> Result<(), Error> {
-        self.motor_command(Command::SetMotorBForward, Command::SetMotorBReverse, power)
+        self.drive(DriveCommand::new(self.requested.a, power), Some(Motor::B))?;
+        self.record(RecordedCommand::SetMotorB { power });
+        Ok(())
+    }
+
+    /// Runs the last requested powers through the pipeline again, for
+    /// background loops correcting them over time, see `GyroMonitor`. Not
+    /// recorded, and doesn't feed the watchdog so it keeps tracking the
+    /// application.
+    pub fn refresh_drive(&mut self) -> Result<(), Error> {
+        let watchdog = self.watchdog.take();
+        let result = self.drive(self.requested, None);
+        self.watchdog = watchdog;
+        result
+    }
+
+    /// Last powers successfully commanded to motors A and B, as they came
+    /// out of the pipeline before calibration.
+    pub fn motor_powers(&self) -> (f32, f32) {
+        (self.motor_a_power, self.motor_b_power)
+    }
+
+    /// Powers of motors A and B read back from the board. Unlike
+    /// `motor_powers()` they include commands sent by other processes, and
+    /// the inversion of the motors.
+    pub fn read_motor_powers(&mut self) -> Result<(f32, f32), Error> {
+        let a = self.read_motor_power(Command::GetMotorA)?;
+        let b = self.read_motor_power(Command::GetMotorB)?;
+        Ok((a, b))
     }
 
     pub fn get_drive_fault_a(&mut self) -> Result<bool, Error> {
         let response = self.command_with_response(Command::GetDriveFaultFlagA)?;
-        Ok(response[1] != I2C_VALUE_OFF)
+        Ok(response.byte(1)? != I2C_VALUE_OFF)
     }
 
     pub fn get_drive_fault_b(&mut self) -> Result<bool, Error> {
         let response = self.command_with_response(Command::GetDriveFaultFlagB)?;
-        Ok(response[1] != I2C_VALUE_OFF)
+        Ok(response.byte(1)? != I2C_VALUE_OFF)
     }
 
+    /// Same as `all_off()`, see `stop_motors()` to keep the LED on.
     pub fn stop(&mut self) -> Result<(), Error> {
-        self.command(Command::AllOff, &[0])
+        self.all_off()
+    }
+
+    /// Switches off the motors and the LED, ending any LED effect.
+    pub fn all_off(&mut self) -> Result<(), Error> {
+        self.led_effect = None;
+        self.command(Command::AllOff, &[0])?;
+        self.written_a = None;
+        self.written_b = None;
+        self.written_led = None;
+        self.pipeline.reset();
+        self.requested = DriveCommand::default();
+        self.motor_a_power = 0.0;
+        self.motor_b_power = 0.0;
+        self.record(RecordedCommand::Stop);
+        Ok(())
+    }
+
+    /// Sets both motors to zero power, leaving the LED as it is. Bypasses
+    /// the drive pipeline so it works while e.g. the emergency stop is
+    /// latched.
+    pub fn stop_motors(&mut self) -> Result<(), Error> {
+        self.command(Command::SetMotorsForward, &[0])?;
+        self.written_a = None;
+        self.written_b = None;
+        self.pipeline.reset();
+        self.requested = DriveCommand::default();
+        self.motor_a_power = 0.0;
+        self.motor_b_power = 0.0;
+        self.record(RecordedCommand::StopMotors);
+        Ok(())
     }
 
     pub fn get_battery_voltage(&mut self) -> Result<f32, Error> {
-        let voltage_bytes = self.command_with_response(Command::GetBatteryVoltage)?;
-        let raw_voltage = ((voltage_bytes[1] as u16) << 8) + (voltage_bytes[2] as u16);
-        Ok((raw_voltage as f32) / COMMAND_ANALOG_MAX * VOLTAGE_PIN_MAX + VOLTAGE_PIN_CORRECTION)
+        let response = self.command_with_response(Command::GetBatteryVoltage)?;
+        let raw_voltage = response.u16_be(1)?;
+        let voltage = self.voltage_calibration.to_volts(raw_voltage);
+        debug!(
+            raw_voltage,
+            battery_voltage = voltage,
+            "Read battery voltage"
+        );
+        Ok(voltage)
+    }
+
+    /// Reads the battery voltage and returns it smoothed over successive
+    /// calls, with bogus ADC samples and outliers discarded. Fails with
+    /// `ControllerError::ImplausibleVoltage` until a plausible reading is seen.
+    pub fn get_battery_voltage_filtered(&mut self) -> Result<f32, Error> {
+        let voltage = self.get_battery_voltage()?;
+        match self.voltage_filter.update(voltage) {
+            Some(filtered) => Ok(filtered),
+            None => Err(ControllerError::ImplausibleVoltage { voltage }.into()),
+        }
+    }
+
+    /// Reads the battery voltage and returns the estimated state of charge,
+    /// in `[0, 100]`. The estimate is smoothed over successive calls.
+    pub fn battery_percent(&mut self) -> Result<f32, Error> {
+        let voltage = self.get_battery_voltage()?;
+        Ok(self.estimate_battery_percent(voltage))
+    }
+
+    /// Updates the state of charge estimate with a voltage just read.
+    pub(crate) fn estimate_battery_percent(&mut self, voltage: f32) -> f32 {
+        let load = (self.motor_a_power.abs() + self.motor_b_power.abs()) / 2.0;
+        self.battery_soc.update(voltage, load)
+    }
+
+    /// A failing recording should not stop the robot from being driven, so
+    /// errors are logged and the recording is abandoned.
+    fn record(&mut self, command: RecordedCommand) {
+        if let Some(ref black_box) = self.black_box {
+            black_box.record_command(&command, (self.motor_a_power, self.motor_b_power));
+        }
+        if !self.command_subscribers.is_empty() {
+            let timed = TimedCommand {
+                timestamp: telemetry::unix_timestamp(),
+                command: command.clone(),
+            };
+            self.command_subscribers
+                .retain(|subscriber| subscriber.send(timed.clone()).is_ok());
+        }
+        if let Some(ref mut recorder) = self.recorder {
+            if let Err(error) = recorder.record(&command) {
+                error!(
+                    "Could not record {:?}, stopping recording: {}",
+                    command, error
+                );
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// Runs `command` through the pipeline and writes the result for
+    /// `motor`, or for both motors if `None`.
+    fn drive(&mut self, command: DriveCommand, motor: Option<Motor>) -> Result<(), Error> {
+        // Rejected before the pipeline, where a NaN would stick in the state
+        // of e.g. the ramp.
+        if let Some(&power) = [command.a, command.b].iter().find(|p| !p.is_finite()) {
+            return Err(Error::InvalidMotorPower { power });
+        }
+        if let Some(config) = self
+            .config_updates
+            .as_ref()
+            .and_then(|updates| updates.try_iter().last())
+        {
+            self.reload(&config);
+        }
+        let output = self.pipeline.run(command)?;
+        let (commanded, wire) = (output.commanded, output.wire);
+        debug!(
+            power_a = commanded.a,
+            power_b = commanded.b,
+            wire_a = wire.a,
+            wire_b = wire.b,
+            "Driving motors"
+        );
+        let write_a = motor != Some(Motor::B) && !self.is_written(self.written_a, wire.a);
+        let write_b = motor != Some(Motor::A) && !self.is_written(self.written_b, wire.b);
+        if write_a && write_b && wire.a == wire.b {
+            self.motor_command(Command::SetMotorsForward, Command::SetMotorsReverse, wire.a)?;
+        } else {
+            if write_a {
+                self.motor_command(Command::SetMotorAForward, Command::SetMotorAReverse, wire.a)?;
+            }
+            if write_b {
+                self.motor_command(Command::SetMotorBForward, Command::SetMotorBReverse, wire.b)?;
+            }
+        }
+        if write_a {
+            self.written_a = Some(Written::now(wire.a));
+        }
+        if write_b {
+            self.written_b = Some(Written::now(wire.b));
+        }
+        if !write_a && !write_b {
+            self.feed_watchdog();
+        }
+        self.requested = command;
+        if motor != Some(Motor::B) {
+            self.motor_a_power = commanded.a;
+        }
+        if motor != Some(Motor::A) {
+            self.motor_b_power = commanded.b;
+        }
+        Ok(())
     }
 
     fn motor_command(
         &mut self,
         forward_command: Command,
         reverse_command: Command,
-        power: f32,
+        wire_power: f32,
     ) -> Result<(), Error> {
-        let power = clamp_motor_power(power);
-        let power_bytes = &[motor_power_to_byte(power)];
-        if power < 0.0 {
-            self.command(reverse_command, power_bytes)?;
+        let power_bytes = &[borg::motor_power_to_byte(wire_power)?];
+        if wire_power < 0.0 {
+            self.command(reverse_command, power_bytes)
         } else {
-            self.command(forward_command, power_bytes)?;
+            self.command(forward_command, power_bytes)
         }
-        Ok(())
     }
 
-    fn command_with_response(&mut self, command: Command) -> Result<I2CResponse, Error> {
-        let mut attempt = I2C_COMMAND_NUM_ATTEMPTS;
-        while attempt > 0 {
-            debug!("Writing command {} to i2c bus", command);
-            let wire_command = command.to_wire();
-            self.dev.smbus_write_byte(wire_command)?;
-
-            let mut response = [0u8; I2C_MAX_LEN];
-            self.dev.read(&mut response)?;
-            debug!("Read bytes from i2c bus: {:?}", response);
-            if response[0] != wire_command {
-                attempt -= 1;
-                info!("Retrying (read {})", response[0]);
-            } else {
-                return Ok(response);
-            }
+    /// True if `value` was written recently enough not to need writing again.
+    fn is_written<T: PartialEq>(&self, written: Option<Written<T>>, value: T) -> bool {
+        written.is_some_and(|written| {
+            written.value == value && written.at.elapsed() < self.refresh_interval
+        })
+    }
+
+    /// Skipped writes still count as activity, the control loop is alive.
+    fn feed_watchdog(&self) {
+        if let Some(ref watchdog) = self.watchdog {
+            watchdog.feed();
         }
-        error!("Failed to run command {}", command);
-        Err((ControllerError::CommandError { command }).into())
+    }
+
+    fn read_motor_power(&mut self, command: Command) -> Result<f32, Error> {
+        let response = self.command_with_response(command)?;
+        let power = borg::byte_to_motor_power(response.byte(2)?);
+        if response.byte(1)? == I2C_VALUE_REVERSE {
+            Ok(-power)
+        } else {
+            Ok(power)
+        }
+    }
+
+    fn command_with_response(&mut self, command: Command) -> Result<Response, Error> {
+        self.device.command_with_response(&command)
     }
 
     fn command(&mut self, command: Command, data: &[u8]) -> Result<(), Error> {
-        debug!("Writing command {} {:?} to bus", command, data);
-        let mut command_bytes = ArrayVec::<[u8; I2C_MAX_LEN]>::new();
-        command_bytes.push(command.to_wire());
-        command_bytes.extend(data.iter().map(|x| *x));
-        self.dev.write(&command_bytes)?;
+        self.device.command(&command, data)?;
+        self.feed_watchdog();
         Ok(())
     }
 }
 
+/// A value written to the board and when.
+#[derive(Clone, Copy)]
+struct Written<T> {
+    value: T,
+    at: Instant,
+}
+
+impl<T> Written<T> {
+    fn now(value: T) -> Self {
+        Written {
+            value,
+            at: Instant::now(),
+        }
+    }
+}
+
+/// The two motor channels of the ThunderBorg.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motor {
+    A,
+    B,
+}
+
+impl MotorDriver for Controller {
+    fn num_motors(&self) -> usize {
+        2
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        motor_driver::check_motor_index(index, 2)?;
+        if index == 0 {
+            self.set_motor_a(power)
+        } else {
+            self.set_motor_b(power)
+        }
+    }
+
+    fn motor_power(&self, index: usize) -> f32 {
+        match index {
+            0 => self.motor_a_power,
+            1 => self.motor_b_power,
+            _ => 0.0,
+        }
+    }
+
+    fn motor_readback(&mut self, index: usize) -> Result<f32, Error> {
+        motor_driver::check_motor_index(index, 2)?;
+        if index == 0 {
+            self.read_motor_power(Command::GetMotorA)
+        } else {
+            self.read_motor_power(Command::GetMotorB)
+        }
+    }
+
+    /// Motor A drives the left side and motor B the right, both in one
+    /// pass through the pipeline.
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        self.drive(DriveCommand::new(left, right), None)?;
+        self.record(RecordedCommand::SetSides { left, right });
+        Ok(())
+    }
+
+    fn stop_all(&mut self) -> Result<(), Error> {
+        self.stop()
+    }
+
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        Ok(Controller::comm_stats(self).clone())
+    }
+
+    fn fault(&mut self, index: usize) -> Result<bool, Error> {
+        motor_driver::check_motor_index(index, 2)?;
+        if index == 0 {
+            self.get_drive_fault_a()
+        } else {
+            self.get_drive_fault_b()
+        }
+    }
+
+    fn battery_voltage(&mut self) -> Result<f32, Error> {
+        self.get_battery_voltage_filtered()
+    }
+
+    fn battery_percent(&mut self) -> Result<f32, Error> {
+        Controller::battery_percent(self)
+    }
+
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        self.set_led(color)
+    }
+}
+
 impl Drop for Controller {
     fn drop(&mut self) {
-        info!("Destroying a ThunderBorg `Controller`. Ensuring engines are stopped...");
-        if let Err(error) = self.stop() {
+        let result = match self.drop_policy {
+            DropPolicy::LeaveRunning => return,
+            DropPolicy::StopMotors => {
+                info!("Destroying a ThunderBorg `Controller`. Stopping the motors...");
+                self.stop_motors()
+            }
+            DropPolicy::AllOff => {
+                info!("Destroying a ThunderBorg `Controller`. Ensuring engines are stopped...");
+                self.stop()
+            }
+        };
+        if let Err(error) = result {
             error!(
                 "Could not run `stop()` when destroying the controller. Error: {}",
                 error
@@ -128,8 +1107,9 @@ impl Drop for Controller {
     }
 }
 
-#[derive(Debug)]
-enum Command {
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
     /// Set the colour of the ThunderBorg LED
     SetLed,
     /// Get the colour of the ThunderBorg LED
@@ -164,6 +1144,31 @@ enum Command {
     GetId,
 }
 
+impl Command {
+    /// The command sent as `wire` on the bus, if the crate models it.
+    pub fn from_wire(wire: u8) -> Option<Command> {
+        let command = match wire {
+            1 => Command::SetLed,
+            2 => Command::GetLed,
+            8 => Command::SetMotorAForward,
+            9 => Command::SetMotorAReverse,
+            10 => Command::GetMotorA,
+            11 => Command::SetMotorBForward,
+            12 => Command::SetMotorBReverse,
+            13 => Command::GetMotorB,
+            14 => Command::AllOff,
+            15 => Command::GetDriveFaultFlagA,
+            16 => Command::GetDriveFaultFlagB,
+            17 => Command::SetMotorsForward,
+            18 => Command::SetMotorsReverse,
+            21 => Command::GetBatteryVoltage,
+            0x99 => Command::GetId,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
 impl Display for Command {
     fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
         let pretty_name = match *self {
@@ -187,7 +1192,7 @@ impl Display for Command {
     }
 }
 
-impl Command {
+impl BorgCommand for Command {
     #[inline]
     fn to_wire(&self) -> u8 {
         match *self {
@@ -208,33 +1213,30 @@ impl Command {
             Command::GetId => 0x99,
         }
     }
-}
-
-type I2CResponse = [u8; I2C_MAX_LEN];
 
-#[inline]
-fn clamp_motor_power(value: f32) -> f32 {
-    if value > 1.0 {
-        1.0
-    } else if value < -1.0 {
-        -1.0
-    } else {
-        value
+    /// The command byte followed by the value, rather than the 6 bytes
+    /// read for commands without one, so a field beyond the value is a
+    /// `ResponseTooShort` error.
+    fn response_len(&self) -> Option<usize> {
+        match *self {
+            Command::GetLed => Some(4),
+            Command::GetMotorA | Command::GetMotorB => Some(3),
+            Command::GetDriveFaultFlagA | Command::GetDriveFaultFlagB => Some(2),
+            Command::GetBatteryVoltage => Some(3),
+            Command::GetId => Some(2),
+            _ => None,
+        }
     }
 }
 
-#[inline]
-fn motor_power_to_byte(value: f32) -> u8 {
-    assert!(-1.0 <= value && value <= 1.0);
-    (value.abs() * 255.0) as u8
-}
-
-const I2C_VALUE_ON: u8 = 1; // I2C value representing on
 const I2C_VALUE_OFF: u8 = 0; // I2C value representing off
-const I2C_COMMAND_NUM_ATTEMPTS: usize = 3;
+const I2C_VALUE_REVERSE: u8 = 2; // Direction of a motor read back running in reverse
 const I2C_MAX_LEN: usize = 6;
-const THUNDERBORG_ID: u8 = 0x15;
-const THUNDERBORG_SLAVE_ADDR: u16 = 0x15;
+pub(crate) const THUNDERBORG_ID: u8 = 0x15;
+pub const THUNDERBORG_SLAVE_ADDR: u16 = 0x15;
+
+// Well below the quarter of a second of the firmware failsafe.
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
 
 // Maximum value for analog readings
 const COMMAND_ANALOG_MAX: f32 = 0x3FF as f32;
@@ -244,3 +1246,111 @@ const VOLTAGE_PIN_MAX: f32 = 36.3;
 
 // Correction value for the analog voltage monitoring pin
 const VOLTAGE_PIN_CORRECTION: f32 = 0.0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::simulator::SimulatedBoard;
+
+    #[test]
+    fn reload_carries_on_ramping_from_the_current_powers() {
+        let clock = ManualClock::new();
+        let board = SimulatedBoard::new();
+        let mut config = Config::default();
+        config.pipeline.ramp_rate = Some(1.0);
+        let mut controller = ControllerBuilder::new()
+            .pipeline(config.pipeline.clone())
+            .refresh_interval(Duration::default())
+            .clock(Arc::new(clock.clone()))
+            .build_with_bus(board.bus())
+            .expect("the simulated board answers");
+
+        controller.set_motors(0.0).unwrap();
+        for _ in 0..5 {
+            clock.advance(Duration::from_millis(50));
+            controller.set_motors(1.0).unwrap();
+        }
+        config.pipeline.ramp_rate = Some(2.0);
+        controller.reload(&config);
+        clock.advance(Duration::from_millis(50));
+        controller.set_motors(1.0).unwrap();
+        assert!((board.state().motor_a - 0.35).abs() < 1e-3);
+    }
+
+    #[test]
+    fn switching_profiles_carries_on_ramping_from_the_current_powers() {
+        let clock = ManualClock::new();
+        let board = SimulatedBoard::new();
+        let mut controller = ControllerBuilder::new()
+            .pipeline(PipelineConfig {
+                ramp_rate: Some(1.0),
+                ..PipelineConfig::default()
+            })
+            .refresh_interval(Duration::default())
+            .clock(Arc::new(clock.clone()))
+            .build_with_bus(board.bus())
+            .expect("the simulated board answers");
+
+        controller.set_motors(0.0).unwrap();
+        for _ in 0..5 {
+            clock.advance(Duration::from_millis(50));
+            controller.set_motors(1.0).unwrap();
+        }
+        controller.apply_profile(&Profile {
+            ramp_rate: Some(2.0),
+            ..Profile::default()
+        });
+        clock.advance(Duration::from_millis(50));
+        controller.set_motors(1.0).unwrap();
+        assert!((board.state().motor_a - 0.35).abs() < 1e-3);
+    }
+
+    #[test]
+    fn single_motor_commands_shape_the_other_side_once() {
+        let board = SimulatedBoard::new();
+        let mut controller = ControllerBuilder::new()
+            .pipeline(PipelineConfig {
+                turn_sensitivity: Some(0.5),
+                ..PipelineConfig::default()
+            })
+            .refresh_interval(Duration::default())
+            .build_with_bus(board.bus())
+            .expect("the simulated board answers");
+
+        for _ in 0..5 {
+            controller.set_motor_a(1.0).unwrap();
+            controller.set_motor_b(-1.0).unwrap();
+        }
+        assert_eq!(controller.motor_powers(), (0.5, -0.5));
+        let state = board.state();
+        assert!((state.motor_a - 0.5).abs() <= 1.0 / 255.0);
+        assert!((state.motor_b + 0.5).abs() <= 1.0 / 255.0);
+    }
+
+    #[test]
+    fn sides_go_through_the_pipeline_together() {
+        let config = PipelineConfig {
+            turn_sensitivity: Some(0.5),
+            ..PipelineConfig::default()
+        };
+        let board = SimulatedBoard::new();
+        let mut controller = ControllerBuilder::new()
+            .pipeline(config.clone())
+            .build_with_bus(board.bus())
+            .expect("the simulated board answers");
+        let mut pipeline =
+            pipeline::default_pipeline(&config, &MotorsConfig::default(), &clock::system());
+
+        controller.set_sides(1.0, -1.0).unwrap();
+        let expected = pipeline
+            .run(DriveCommand::new(1.0, -1.0))
+            .unwrap()
+            .commanded;
+        assert_eq!(
+            controller.motor_powers(),
+            (expected.a, expected.b),
+            "shaped once, from the whole command"
+        );
+    }
+}
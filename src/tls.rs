@@ -0,0 +1,144 @@
+//! TLS for the web dashboard, so teleoperation over an untrusted network
+//! isn't plaintext. The server loads a PEM certificate chain and private key
+//! from the `[tls]` section of the configuration file:
+//!
+//! ```toml
+//! [tls]
+//! cert = "/var/lib/vrum/cert.pem"
+//! key = "/var/lib/vrum/key.pem"
+//! ```
+//!
+//! If neither file exists, a self-signed certificate for the host name is
+//! generated on first start and kept there. Browsers warn about it until it
+//! is accepted, once per device.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+
+use crate::error::Error;
+use crate::telemetry::host_name;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("cannot read {}: {message}", path.display())]
+    Pem { path: PathBuf, message: String },
+    #[error("{} has no certificate", path.display())]
+    NoCertificate { path: PathBuf },
+    #[error("{} is missing", path.display())]
+    Missing { path: PathBuf },
+    #[error("cannot generate a self-signed certificate: {0}")]
+    Generate(#[from] rcgen::Error),
+    #[error("TLS error: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+/// The `[tls]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain, the server's certificate first.
+    pub cert: PathBuf,
+    /// PEM private key of the certificate.
+    pub key: PathBuf,
+    /// Generate a self-signed certificate if neither file exists.
+    #[serde(default = "default_self_signed")]
+    pub self_signed: bool,
+    /// Names and addresses the generated certificate is for, besides
+    /// `localhost` and the host name.
+    #[serde(default)]
+    pub names: Vec<String>,
+}
+
+impl TlsConfig {
+    /// The rustls configuration of a server presenting the certificate,
+    /// generating it first if needed.
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>, Error> {
+        if self.self_signed && !self.cert.exists() && !self.key.exists() {
+            self.generate_self_signed()?;
+        }
+        let certs = read_pem(&self.cert, CertificateDer::pem_file_iter(&self.cert))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| pem_error(&self.cert, error))?;
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificate {
+                path: self.cert.clone(),
+            }
+            .into());
+        }
+        let key = read_pem(&self.key, PrivateKeyDer::from_pem_file(&self.key))?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::from)?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(TlsError::from)?;
+        Ok(Arc::new(config))
+    }
+
+    fn generate_self_signed(&self) -> Result<(), Error> {
+        let mut names = vec!["localhost".to_string()];
+        if let Some(host) = host_name() {
+            names.push(format!("{}.local", host));
+            names.push(host);
+        }
+        names.extend(self.names.iter().cloned());
+        let certified =
+            rcgen::generate_simple_self_signed(names.clone()).map_err(TlsError::from)?;
+        write_new(&self.cert, certified.cert.pem().as_bytes(), 0o644)?;
+        write_new(
+            &self.key,
+            certified.key_pair.serialize_pem().as_bytes(),
+            0o600,
+        )?;
+        warn!(
+            "Generated a self-signed certificate for {} in {}, browsers will warn about it",
+            names.join(", "),
+            self.cert.display()
+        );
+        Ok(())
+    }
+}
+
+fn default_self_signed() -> bool {
+    true
+}
+
+fn read_pem<T>(path: &Path, pem: Result<T, rustls::pki_types::pem::Error>) -> Result<T, Error> {
+    match pem {
+        Ok(pem) => Ok(pem),
+        Err(_) if !path.exists() => Err(TlsError::Missing {
+            path: path.to_owned(),
+        }
+        .into()),
+        Err(error) => Err(pem_error(path, error).into()),
+    }
+}
+
+fn pem_error(path: &Path, error: rustls::pki_types::pem::Error) -> TlsError {
+    TlsError::Pem {
+        path: path.to_owned(),
+        message: error.to_string(),
+    }
+}
+
+/// Writes a file that must not exist yet, with the permissions `mode`.
+fn write_new(path: &Path, contents: &[u8], mode: u32) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)?;
+    file.write_all(contents)?;
+    Ok(())
+}
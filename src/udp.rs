@@ -0,0 +1,403 @@
+//! Low latency teleop over UDP. Every packet carries the whole command, so a
+//! lost one is simply superseded by the next and late ones are dropped:
+//! unlike over TCP, a retransmission never holds up the commands behind it,
+//! which over Wi-Fi makes for jerky control.
+//!
+//! A packet is 11 bytes, big-endian, followed by the token when the receiver
+//! requires one, see `vrum::auth`:
+//!
+//! | bytes | field                                                  |
+//! |-------|--------------------------------------------------------|
+//! | 0     | magic, `b'V'`                                          |
+//! | 1     | protocol version, 1                                    |
+//! | 2-5   | sequence number, incremented by the sender, wrapping   |
+//! | 6-7   | left power, `i16` scaled so 32767 is full power        |
+//! | 8-9   | right power, likewise                                  |
+//! | 10    | flags, `FLAG_STOP` or `FLAG_HEARTBEAT`                 |
+//! | 11-   | token, at most `MAX_TOKEN_LEN` bytes                   |
+//!
+//! The receiver stops the motors when no packet arrives for the heartbeat
+//! timeout while they run, see `vrum::heartbeat`. A heartbeat packet keeps
+//! them running without changing the powers. A sender silent for longer than
+//! the timeout starts its sequence afresh, e.g. after a restart.
+//!
+//! ```
+//! # use vrum::udp::Packet;
+//! let packet = Packet::drive(7, 1.0, -1.0);
+//! assert_eq!(Packet::decode(&packet.encode())?, packet);
+//! # Ok::<(), vrum::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::auth::{self, Token};
+use crate::error::Error;
+use crate::heartbeat::{DeadMan, DeadManConfig};
+use crate::motor_driver::MotorDriver;
+use crate::shutdown::Shutdown;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UdpError {
+    #[error(
+        "UDP packet of {len} bytes, expected {PACKET_LEN} and a token of at most {MAX_TOKEN_LEN}"
+    )]
+    WrongLength { len: usize },
+    #[error("UDP packet without the vrum magic byte")]
+    BadMagic,
+    #[error("UDP protocol version {version} is not supported, expected {VERSION}")]
+    UnsupportedVersion { version: u8 },
+    #[error("UDP packet with unknown flags {flags:#04x}")]
+    UnknownFlags { flags: u8 },
+}
+
+pub const PACKET_LEN: usize = 11;
+pub const MAX_TOKEN_LEN: usize = 64;
+pub const MAGIC: u8 = b'V';
+pub const VERSION: u8 = 1;
+/// Stop the motors, ignoring the powers.
+pub const FLAG_STOP: u8 = 0x01;
+/// Keep the motors running as they are, ignoring the powers.
+pub const FLAG_HEARTBEAT: u8 = 0x02;
+
+#[derive(Clone, Debug)]
+pub struct UdpConfig {
+    pub listen: SocketAddr,
+    pub heartbeat: DeadManConfig,
+    /// Packets without it are ignored.
+    pub token: Option<Token>,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            listen: SocketAddr::from(([0, 0, 0, 0], 9000)),
+            heartbeat: DeadManConfig::default(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Packet {
+    pub seq: u32,
+    /// Powers of the left and right sides, in `[-1, 1]`.
+    pub left: f32,
+    pub right: f32,
+    pub flags: u8,
+}
+
+impl Packet {
+    pub fn drive(seq: u32, left: f32, right: f32) -> Self {
+        Packet {
+            seq,
+            left,
+            right,
+            flags: 0,
+        }
+    }
+
+    pub fn stop(seq: u32) -> Self {
+        Packet {
+            seq,
+            left: 0.0,
+            right: 0.0,
+            flags: FLAG_STOP,
+        }
+    }
+
+    pub fn heartbeat(seq: u32) -> Self {
+        Packet {
+            seq,
+            left: 0.0,
+            right: 0.0,
+            flags: FLAG_HEARTBEAT,
+        }
+    }
+
+    pub fn is_stop(&self) -> bool {
+        self.flags & FLAG_STOP != 0
+    }
+
+    pub fn is_heartbeat(&self) -> bool {
+        self.flags & FLAG_HEARTBEAT != 0 && !self.is_stop()
+    }
+
+    /// Powers are clamped to `[-1, 1]` and quantized to 1/32767.
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        let mut bytes = [0; PACKET_LEN];
+        bytes[0] = MAGIC;
+        bytes[1] = VERSION;
+        bytes[2..6].copy_from_slice(&self.seq.to_be_bytes());
+        bytes[6..8].copy_from_slice(&power_to_wire(self.left).to_be_bytes());
+        bytes[8..10].copy_from_slice(&power_to_wire(self.right).to_be_bytes());
+        bytes[10] = self.flags;
+        bytes
+    }
+
+    /// The packet followed by `token`.
+    pub fn encode_with_token(&self, token: &Token) -> Vec<u8> {
+        let mut bytes = self.encode().to_vec();
+        bytes.extend_from_slice(token.as_bytes());
+        bytes
+    }
+
+    /// Decodes the packet at the start of `bytes`, see `token()` for the
+    /// rest.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < PACKET_LEN || bytes.len() > PACKET_LEN + MAX_TOKEN_LEN {
+            return Err(UdpError::WrongLength { len: bytes.len() }.into());
+        }
+        if bytes[0] != MAGIC {
+            return Err(UdpError::BadMagic.into());
+        }
+        if bytes[1] != VERSION {
+            return Err(UdpError::UnsupportedVersion { version: bytes[1] }.into());
+        }
+        let flags = bytes[10];
+        if flags & !(FLAG_STOP | FLAG_HEARTBEAT) != 0 {
+            return Err(UdpError::UnknownFlags { flags }.into());
+        }
+        let power = |at: usize| wire_to_power(i16::from_be_bytes([bytes[at], bytes[at + 1]]));
+        Ok(Packet {
+            seq: u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            left: power(6),
+            right: power(8),
+            flags,
+        })
+    }
+
+    /// The token following the packet in `bytes`, if any.
+    pub fn token(bytes: &[u8]) -> Option<&[u8]> {
+        bytes.get(PACKET_LEN..).filter(|token| !token.is_empty())
+    }
+}
+
+/// Packets received by a `UdpReceiver`, to gauge the quality of the link.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct UdpStats {
+    /// Packets acted upon.
+    pub received: u64,
+    /// Packets that never arrived, from the gaps in the sequence numbers.
+    pub lost: u64,
+    /// Packets older than one already acted upon, dropped.
+    pub late: u64,
+    /// Packets that could not be decoded.
+    pub malformed: u64,
+    /// Packets without the token, ignored.
+    pub unauthenticated: u64,
+    /// Drive packets ignored as the dead man latched or the robot refused
+    /// them, e.g. while e-stopped.
+    pub ignored: u64,
+    /// Times the motors were stopped as the packets stopped arriving.
+    pub timeouts: u64,
+}
+
+/// Drives the motors from the packets received on a UDP socket.
+pub struct UdpReceiver<'a, D: MotorDriver + ?Sized> {
+    driver: &'a mut D,
+    socket: UdpSocket,
+    config: UdpConfig,
+    /// Last sequence number acted upon from each sender, and when.
+    senders: HashMap<SocketAddr, (u32, Instant)>,
+    dead_man: DeadMan,
+    stats: UdpStats,
+}
+
+impl<'a, D: MotorDriver + ?Sized> UdpReceiver<'a, D> {
+    pub fn bind(driver: &'a mut D, config: UdpConfig) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(config.listen)?;
+        socket.set_read_timeout(Some(UDP_POLL_INTERVAL))?;
+        info!(
+            "Listening for UDP drive commands on {}",
+            socket.local_addr()?
+        );
+        Ok(UdpReceiver {
+            driver,
+            socket,
+            dead_man: DeadMan::new(config.heartbeat),
+            config,
+            senders: HashMap::new(),
+            stats: UdpStats::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    pub fn stats(&self) -> UdpStats {
+        self.stats
+    }
+
+    /// Receives packets until a shutdown is requested, stopping the motors
+    /// on the way out.
+    pub fn run(&mut self, shutdown: &Shutdown) -> Result<(), Error> {
+        let result = self.receive_until(shutdown);
+        info!("UDP teleop ended: {:?}", self.stats);
+        result.and(self.driver.set_sides(0.0, 0.0))
+    }
+
+    /// Waits up to the poll interval for a packet and acts on it, then
+    /// stops the motors if the packets stopped arriving.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        let mut buffer = [0; PACKET_LEN + MAX_TOKEN_LEN + 1];
+        match self.socket.recv_from(&mut buffer) {
+            Ok((len, sender)) => match Packet::decode(&buffer[..len]) {
+                Ok(_)
+                    if !auth::is_authorized(
+                        self.config.token.as_ref(),
+                        Packet::token(&buffer[..len]),
+                    ) =>
+                {
+                    self.stats.unauthenticated += 1;
+                    debug!("Ignoring UDP packet from {} without the token", sender);
+                }
+                Ok(packet) => self.handle(sender, packet)?,
+                Err(error) => {
+                    self.stats.malformed += 1;
+                    debug!("Ignoring UDP packet from {}: {}", sender, error);
+                }
+            },
+            Err(ref error)
+                if error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut => {
+            }
+            Err(error) => return Err(error.into()),
+        }
+        if self.dead_man.expired() {
+            warn!(
+                "No UDP packet for {:?}, stopping the motors",
+                self.config.heartbeat.timeout
+            );
+            self.stats.timeouts += 1;
+            self.driver.set_sides(0.0, 0.0)?;
+        }
+        Ok(())
+    }
+
+    fn receive_until(&mut self, shutdown: &Shutdown) -> Result<(), Error> {
+        while !shutdown.is_requested() {
+            self.poll()?;
+        }
+        shutdown.check()
+    }
+
+    fn handle(&mut self, sender: SocketAddr, packet: Packet) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some(&(last_seq, last_at)) = self.senders.get(&sender) {
+            // Serial number arithmetic, so the sequence can wrap around.
+            let ahead = packet.seq.wrapping_sub(last_seq) as i32;
+            let restarted = now - last_at > self.config.heartbeat.timeout;
+            if ahead <= 0 && !restarted {
+                self.stats.late += 1;
+                return Ok(());
+            }
+            if ahead > 1 && !restarted {
+                self.stats.lost += (ahead - 1) as u64;
+            }
+        }
+        self.senders.insert(sender, (packet.seq, now));
+        self.stats.received += 1;
+        if packet.is_heartbeat() {
+            self.dead_man.heartbeat();
+            return Ok(());
+        }
+        let (left, right) = if packet.is_stop() {
+            (0.0, 0.0)
+        } else {
+            (packet.left, packet.right)
+        };
+        match self.dead_man.drive(left, right) {
+            Ok(()) => match self.driver.set_sides(left, right) {
+                // Refused by the robot, e.g. while e-stopped, rather than
+                // failed: the next packet may well go through.
+                Err(
+                    error @ (Error::Arming(_)
+                    | Error::Pipeline(_)
+                    | Error::InvalidMotorPower { .. }),
+                ) => {
+                    self.stats.ignored += 1;
+                    debug!("Ignoring UDP packet from {}: {}", sender, error);
+                    Ok(())
+                }
+                result => result,
+            },
+            Err(error) => {
+                self.stats.ignored += 1;
+                debug!("Ignoring UDP packet from {}: {}", sender, error);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Sends drive commands to a `UdpReceiver`, numbering them.
+pub struct UdpSender {
+    socket: UdpSocket,
+    seq: u32,
+    token: Option<Token>,
+}
+
+impl UdpSender {
+    pub fn connect<A: ToSocketAddrs>(receiver: A) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+        socket.connect(receiver)?;
+        Ok(UdpSender {
+            socket,
+            seq: 0,
+            token: None,
+        })
+    }
+
+    /// Sends `token` with every packet, for a receiver requiring it.
+    pub fn with_token(mut self, token: Token) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Keep sending, or send heartbeats, faster than the receiver's
+    /// timeout while driving.
+    pub fn drive(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        let packet = Packet::drive(self.next_seq(), left, right);
+        self.send(&packet)
+    }
+
+    pub fn stop(&mut self) -> Result<(), Error> {
+        let packet = Packet::stop(self.next_seq());
+        self.send(&packet)
+    }
+
+    /// Keeps the motors running at the last powers sent.
+    pub fn heartbeat(&mut self) -> Result<(), Error> {
+        let packet = Packet::heartbeat(self.next_seq());
+        self.send(&packet)
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        self.seq = self.seq.wrapping_add(1);
+        self.seq
+    }
+
+    fn send(&self, packet: &Packet) -> Result<(), Error> {
+        match self.token {
+            Some(ref token) => self.socket.send(&packet.encode_with_token(token))?,
+            None => self.socket.send(&packet.encode())?,
+        };
+        Ok(())
+    }
+}
+
+fn power_to_wire(power: f32) -> i16 {
+    (power.clamp(-1.0, 1.0) * WIRE_FULL_POWER).round() as i16
+}
+
+fn wire_to_power(wire: i16) -> f32 {
+    (f32::from(wire) / WIRE_FULL_POWER).clamp(-1.0, 1.0)
+}
+
+const WIRE_FULL_POWER: f32 = i16::MAX as f32;
+
+/// How often the receiver checks the timeout and the shutdown request.
+const UDP_POLL_INTERVAL: Duration = Duration::from_millis(20);
@@ -0,0 +1,287 @@
+//! Driver for the PiBorg UltraBorg: four servo outputs and four HC-SR04
+//! ultrasonic distance sensor inputs, on the same I2C bus as the ThunderBorg.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::borg::{
+    self, BoardInfo, BorgCommand, BorgDevice, Bus, CommStats, Ping, RecoveryConfig, Response,
+};
+use crate::error::Error;
+use crate::pan_tilt::{PanTiltError, Servos};
+
+/// One of the four servo outputs or ultrasonic inputs, as labelled on the
+/// board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    One,
+    Two,
+    Three,
+    Four,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [Channel::One, Channel::Two, Channel::Three, Channel::Four];
+
+    #[inline]
+    fn index(self) -> usize {
+        match self {
+            Channel::One => 0,
+            Channel::Two => 1,
+            Channel::Three => 2,
+            Channel::Four => 3,
+        }
+    }
+}
+
+pub struct ControllerBuilder {
+    bus_path: String,
+    address: u16,
+    recovery: RecoveryConfig,
+}
+
+impl Default for ControllerBuilder {
+    fn default() -> Self {
+        ControllerBuilder {
+            bus_path: borg::DEFAULT_I2C_BUS_PATH.into(),
+            address: ULTRABORG_SLAVE_ADDR,
+            recovery: RecoveryConfig::default(),
+        }
+    }
+}
+
+impl ControllerBuilder {
+    pub fn new() -> Self {
+        ControllerBuilder::default()
+    }
+
+    pub fn bus_path<S: Into<String>>(mut self, bus_path: S) -> Self {
+        self.bus_path = bus_path.into();
+        self
+    }
+
+    pub fn address(mut self, address: u16) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How persistent bus failures are recovered from.
+    pub fn recovery(mut self, config: RecoveryConfig) -> Self {
+        self.recovery = config;
+        self
+    }
+
+    /// Opens the board and reads the servo limits stored on it.
+    pub fn build(self) -> Result<Controller, Error> {
+        info!(
+            "Pinging UltraBorg at i2c bus {} address 0x{:x}",
+            self.bus_path, self.address
+        );
+        let device = BorgDevice::open(&self.bus_path, self.address, I2C_MAX_LEN)?;
+        self.build_on(device)
+    }
+
+    /// Builds a controller talking to the board over `bus` rather than the
+    /// Linux I2C bus, e.g. a device of a `BusManager`.
+    pub fn build_with_bus(self, bus: Box<dyn Bus>) -> Result<Controller, Error> {
+        self.build_on(BorgDevice::new(bus, I2C_MAX_LEN))
+    }
+
+    fn build_on(self, device: BorgDevice) -> Result<Controller, Error> {
+        let mut controller = Controller {
+            device: device.with_recovery(
+                self.recovery.clone(),
+                Ping {
+                    command: Command::GetId.to_wire(),
+                    id: ULTRABORG_ID,
+                },
+            ),
+            servo_limits: [(PWM_DEFAULT_MIN, PWM_DEFAULT_MAX); 4],
+        };
+
+        let response = controller.command_with_response(Command::GetId)?;
+        let id = response.byte(1)?;
+        if id != ULTRABORG_ID {
+            return Err(Error::UnexpectedBoardId {
+                board: "UltraBorg",
+                expected: ULTRABORG_ID,
+                id,
+            });
+        }
+        info!("UltraBorg found.");
+        for channel in Channel::ALL.iter().cloned() {
+            let minimum = controller.get_u16(Command::GetPwmMin(channel))?;
+            let maximum = controller.get_u16(Command::GetPwmMax(channel))?;
+            controller.servo_limits[channel.index()] = (minimum, maximum);
+        }
+        Ok(controller)
+    }
+}
+
+pub struct Controller {
+    device: BorgDevice,
+    /// PWM duty cycle limits of each servo, position -1 maps to the minimum
+    /// and +1 to the maximum.
+    servo_limits: [(u16, u16); 4],
+}
+
+impl Controller {
+    /// Opens the UltraBorg at the default bus and address.
+    pub fn new() -> Result<Self, Error> {
+        ControllerBuilder::new().build()
+    }
+
+    pub fn builder() -> ControllerBuilder {
+        ControllerBuilder::new()
+    }
+
+    /// Bus traffic statistics since the controller was opened, to diagnose
+    /// flaky wiring or bus contention.
+    pub fn comm_stats(&self) -> &CommStats {
+        self.device.comm_stats()
+    }
+
+    pub fn reset_comm_stats(&mut self) {
+        self.device.reset_comm_stats();
+    }
+
+    /// Where the board is and the identifier it reports.
+    pub fn board_info(&mut self) -> Result<BoardInfo, Error> {
+        self.device.board_info("UltraBorg", &Command::GetId)
+    }
+
+    /// Writes a command the crate doesn't model, e.g. from a newer firmware
+    /// revision.
+    pub fn send_raw(&mut self, command: u8, data: &[u8]) -> Result<(), Error> {
+        self.device.send_raw(command, data)
+    }
+
+    /// Sends a query the crate doesn't model and reads back `read_len`
+    /// bytes, the first one echoing `command`.
+    pub fn query_raw(&mut self, command: u8, read_len: usize) -> Result<Response, Error> {
+        self.device.query_raw(command, read_len)
+    }
+
+    /// Moves a servo to `position` in `[-1, 1]`, 0 being the centre.
+    pub fn set_servo_position(&mut self, channel: Channel, position: f32) -> Result<(), Error> {
+        let (minimum, maximum) = self.servo_limits[channel.index()];
+        let fraction = (position.clamp(-1.0, 1.0) + 1.0) / 2.0;
+        let duty = minimum + (fraction * f32::from(maximum.saturating_sub(minimum))) as u16;
+        self.command(Command::SetPwm(channel), &duty.to_be_bytes())
+    }
+
+    /// Current position of a servo, in `[-1, 1]`.
+    pub fn get_servo_position(&mut self, channel: Channel) -> Result<f32, Error> {
+        let (minimum, maximum) = self.servo_limits[channel.index()];
+        let duty = self.get_u16(Command::GetPwm(channel))?;
+        let range = f32::from(maximum.saturating_sub(minimum)).max(1.0);
+        Ok((f32::from(duty.saturating_sub(minimum)) / range * 2.0 - 1.0).clamp(-1.0, 1.0))
+    }
+
+    /// PWM duty cycle limits of a servo, stored on the board.
+    pub fn servo_limits(&self, channel: Channel) -> (u16, u16) {
+        self.servo_limits[channel.index()]
+    }
+
+    /// Filtered distance measured by an ultrasonic sensor, in metres. `None`
+    /// if there is no echo, i.e. nothing in range or no sensor connected.
+    pub fn get_distance(&mut self, channel: Channel) -> Result<Option<f32>, Error> {
+        self.distance(Command::GetFilteredTime(channel))
+    }
+
+    /// Like `get_distance` but without the board's filtering, noisier but
+    /// more responsive.
+    pub fn get_raw_distance(&mut self, channel: Channel) -> Result<Option<f32>, Error> {
+        self.distance(Command::GetTime(channel))
+    }
+
+    fn distance(&mut self, command: Command) -> Result<Option<f32>, Error> {
+        let echo_time = self.get_u16(command)?;
+        if echo_time == USM_NO_ECHO {
+            return Ok(None);
+        }
+        Ok(Some(f32::from(echo_time) * USM_US_TO_M))
+    }
+
+    fn get_u16(&mut self, command: Command) -> Result<u16, Error> {
+        let response = self.command_with_response(command)?;
+        response.u16_be(1)
+    }
+
+    fn command_with_response(&mut self, command: Command) -> Result<Response, Error> {
+        self.device.command_with_response(&command)
+    }
+
+    fn command(&mut self, command: Command, data: &[u8]) -> Result<(), Error> {
+        self.device.command(&command, data)
+    }
+}
+
+impl Servos for Controller {
+    fn num_servos(&self) -> usize {
+        Channel::ALL.len()
+    }
+
+    fn set_servo(&mut self, index: usize, position: f32) -> Result<(), Error> {
+        let channel = *Channel::ALL
+            .get(index)
+            .ok_or(PanTiltError::InvalidChannel {
+                channel: index + 1,
+                servos: Channel::ALL.len(),
+            })?;
+        self.set_servo_position(channel, position)
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    /// Get the raw echo time of an ultrasonic sensor, in microseconds
+    GetTime(Channel),
+    /// Get the filtered echo time of an ultrasonic sensor, in microseconds
+    GetFilteredTime(Channel),
+    /// Set the PWM duty cycle of a servo
+    SetPwm(Channel),
+    /// Get the PWM duty cycle of a servo
+    GetPwm(Channel),
+    /// Get the minimum PWM duty cycle of a servo
+    GetPwmMin(Channel),
+    /// Get the maximum PWM duty cycle of a servo
+    GetPwmMax(Channel),
+    /// Get the board identifier
+    GetId,
+}
+
+impl Display for Command {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        write!(formatter, "{:?} (0x{:x})", self, self.to_wire())
+    }
+}
+
+impl BorgCommand for Command {
+    #[inline]
+    fn to_wire(&self) -> u8 {
+        let offset = |channel: Channel, stride: usize| (channel.index() * stride) as u8;
+        match *self {
+            Command::GetTime(channel) => 1 + offset(channel, 1),
+            Command::SetPwm(channel) => 5 + offset(channel, 2),
+            Command::GetPwm(channel) => 6 + offset(channel, 2),
+            Command::GetPwmMin(channel) => 17 + offset(channel, 3),
+            Command::GetPwmMax(channel) => 18 + offset(channel, 3),
+            Command::GetFilteredTime(channel) => 41 + offset(channel, 1),
+            Command::GetId => 0x99,
+        }
+    }
+}
+
+const I2C_MAX_LEN: usize = 4;
+const ULTRABORG_ID: u8 = 0x36;
+pub const ULTRABORG_SLAVE_ADDR: u16 = 0x36;
+
+// Servo limits used until the ones stored on the board have been read
+const PWM_DEFAULT_MIN: u16 = 2000;
+const PWM_DEFAULT_MAX: u16 = 4000;
+
+// Echo time reported when no echo was received
+const USM_NO_ECHO: u16 = 65535;
+
+// Echo time in microseconds to distance in metres, half the speed of sound
+const USM_US_TO_M: f32 = 0.000_171_5;
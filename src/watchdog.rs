@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::error::Error;
+use crate::journal;
+use crate::motor_driver::MotorDriver;
+
+/// Stops the motors if the application goes quiet for longer than a timeout.
+///
+/// The watchdog thread calls its stop action when neither `feed()` nor a
+/// command sent through a `Controller` with an attached `WatchdogFeeder` has
+/// happened within `timeout`. This guards against panics, deadlocks or hung
+/// network clients leaving the motors running, independently of the
+/// firmware's own failsafe.
+///
+/// The stop action should not share a lock with the code it is guarding: a
+/// dedicated board handle (see `Watchdog::with_controller`) keeps working
+/// even if the application deadlocks while holding its own.
+pub struct Watchdog {
+    state: Arc<WatchdogState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Cheap handle for feeding a `Watchdog` from other threads or from a
+/// `Controller`, see `Controller::set_watchdog`.
+#[derive(Clone)]
+pub struct WatchdogFeeder {
+    state: Arc<WatchdogState>,
+}
+
+struct WatchdogState {
+    last_feed: Mutex<Instant>,
+    clock: Arc<dyn Clock>,
+    running: AtomicBool,
+    tripped: AtomicBool,
+}
+
+impl Watchdog {
+    pub fn spawn<F>(timeout: Duration, stop: F) -> Result<Self, Error>
+    where
+        F: FnMut() -> Result<(), Error> + Send + 'static,
+    {
+        Watchdog::spawn_with_clock(timeout, clock::system(), stop)
+    }
+
+    /// Spawns a watchdog measuring the time since the last feed on `clock`.
+    /// It still polls on the wall clock, every `timeout / 4` within
+    /// [5ms, 100ms].
+    pub fn spawn_with_clock<F>(
+        timeout: Duration,
+        clock: Arc<dyn Clock>,
+        mut stop: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnMut() -> Result<(), Error> + Send + 'static,
+    {
+        let state = Arc::new(WatchdogState {
+            last_feed: Mutex::new(clock.now()),
+            clock,
+            running: AtomicBool::new(true),
+            tripped: AtomicBool::new(false),
+        });
+        let poll_interval = (timeout / 4).clamp(WATCHDOG_MIN_POLL, WATCHDOG_MAX_POLL);
+        let thread_state = state.clone();
+        let thread = thread::Builder::new()
+            .name("vrum-watchdog".into())
+            .spawn(move || {
+                while thread_state.running.load(Ordering::SeqCst) {
+                    thread::sleep(poll_interval);
+                    let since_feed = thread_state.since_last_feed();
+                    if since_feed < timeout || thread_state.tripped.load(Ordering::SeqCst) {
+                        continue;
+                    }
+                    error!(
+                        message_id = journal::WATCHDOG_TRIPPED,
+                        "Watchdog not fed for {:?} (timeout {:?}), stopping motors",
+                        since_feed,
+                        timeout
+                    );
+                    match stop() {
+                        Ok(()) => thread_state.tripped.store(true, Ordering::SeqCst),
+                        Err(error) => error!("Watchdog could not stop the motors: {}", error),
+                    }
+                }
+            })?;
+        info!("Watchdog started with a timeout of {:?}", timeout);
+        Ok(Watchdog {
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    /// Spawns a watchdog that owns a dedicated handle to the board, used only
+    /// to stop the motors when the watchdog trips.
+    pub fn with_controller<D>(mut controller: D, timeout: Duration) -> Result<Self, Error>
+    where
+        D: MotorDriver + Send + 'static,
+    {
+        Watchdog::spawn(timeout, move || controller.stop_all())
+    }
+
+    pub fn feeder(&self) -> WatchdogFeeder {
+        WatchdogFeeder {
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn feed(&self) {
+        self.state.feed();
+    }
+
+    /// True if the watchdog has stopped the motors and has not been fed since.
+    pub fn is_tripped(&self) -> bool {
+        self.state.tripped.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.state.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Watchdog thread panicked");
+            }
+        }
+    }
+}
+
+impl WatchdogFeeder {
+    pub fn feed(&self) {
+        self.state.feed();
+    }
+}
+
+impl WatchdogState {
+    fn feed(&self) {
+        if let Ok(mut last_feed) = self.last_feed.lock() {
+            *last_feed = self.clock.now();
+        }
+        if self.tripped.swap(false, Ordering::SeqCst) {
+            info!("Watchdog fed again, re-arming");
+        }
+    }
+
+    fn since_last_feed(&self) -> Duration {
+        self.last_feed
+            .lock()
+            .map(|last_feed| self.clock.elapsed(*last_feed))
+            .unwrap_or_default()
+    }
+}
+
+const WATCHDOG_MIN_POLL: Duration = Duration::from_millis(5);
+const WATCHDOG_MAX_POLL: Duration = Duration::from_millis(100);
@@ -0,0 +1,135 @@
+//! Outdoor waypoint navigation: drives to a latitude and longitude, taking
+//! the bearing from the GPS fix and holding it on the compass with a
+//! `HeadingHold`. Consumer GPS is good to a few metres, so waypoints suit
+//! open ground rather than tight spaces.
+
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::heading::{Compass, CompassConfig, HeadingHold};
+use crate::latency::LoopTimer;
+use crate::motor_driver::MotorDriver;
+use crate::sensors::gps::{GpsReceiver, Position};
+use crate::shutdown::Shutdown;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WaypointError {
+    #[error("waypoint navigation needs a [gps] section in the configuration")]
+    NotConfigured,
+    #[error("no GPS fix for {ms}ms")]
+    NoFix { ms: u64 },
+}
+
+/// The `[waypoint]` section of the configuration file.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WaypointConfig {
+    pub compass: CompassConfig,
+    pub power: f32,
+    /// A waypoint is reached within this many metres.
+    pub arrival_radius: f32,
+    /// Magnetic declination at the site, in degrees east of true north.
+    /// Bearings from the GPS are true, the compass reads magnetic.
+    pub declination: f32,
+    /// How long to wait for a fix, with the motors stopped, before giving
+    /// up.
+    pub fix_timeout_ms: u64,
+    pub period_ms: u64,
+}
+
+impl Default for WaypointConfig {
+    fn default() -> Self {
+        WaypointConfig {
+            compass: CompassConfig::default(),
+            power: 0.5,
+            arrival_radius: 3.0,
+            declination: 0.0,
+            fix_timeout_ms: 5000,
+            period_ms: 100,
+        }
+    }
+}
+
+pub struct WaypointNavigator<'a, C: Compass> {
+    gps: &'a GpsReceiver,
+    hold: HeadingHold<C>,
+    config: WaypointConfig,
+}
+
+impl<'a, C: Compass> WaypointNavigator<'a, C> {
+    pub fn new(gps: &'a GpsReceiver, hold: HeadingHold<C>, config: WaypointConfig) -> Self {
+        WaypointNavigator { gps, hold, config }
+    }
+
+    /// Drives to `target` and stops there. A shutdown request stops the
+    /// motors and ends it early, as does losing the fix for
+    /// `fix_timeout_ms`.
+    pub fn go_to<D: MotorDriver + ?Sized>(
+        &mut self,
+        driver: &mut D,
+        target: Position,
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        info!("Driving to {:.6}, {:.6}", target.latitude, target.longitude);
+        self.hold.reset();
+        let result = self.drive_to(driver, target, shutdown);
+        let stopped = driver.set_sides(0.0, 0.0);
+        result.and(stopped)
+    }
+
+    /// Drives through `route` in order, stopping at the end.
+    pub fn follow_route<D: MotorDriver + ?Sized>(
+        &mut self,
+        driver: &mut D,
+        route: &[Position],
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        route
+            .iter()
+            .try_for_each(|&waypoint| self.go_to(driver, waypoint, shutdown))
+    }
+
+    fn drive_to<D: MotorDriver + ?Sized>(
+        &mut self,
+        driver: &mut D,
+        target: Position,
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        let period = Duration::from_millis(self.config.period_ms.max(1));
+        let fix_timeout = Duration::from_millis(self.config.fix_timeout_ms);
+        let mut last_fix = Instant::now();
+        let mut timer = LoopTimer::new("waypoint", period);
+        loop {
+            timer.tick();
+            match self.gps.position() {
+                Some(position) => {
+                    last_fix = Instant::now();
+                    let distance = position.distance_to(&target);
+                    if distance <= f64::from(self.config.arrival_radius) {
+                        info!("Reached waypoint, {:.1}m away", distance);
+                        return Ok(());
+                    }
+                    let bearing = position.bearing_to(&target) as f32 - self.config.declination;
+                    debug!(distance, bearing, "Driving to waypoint");
+                    self.hold.track_heading(
+                        driver,
+                        self.config.power,
+                        bearing.rem_euclid(360.0),
+                    )?;
+                }
+                None => {
+                    let waited = last_fix.elapsed();
+                    if waited >= fix_timeout {
+                        return Err(WaypointError::NoFix {
+                            ms: waited.as_millis() as u64,
+                        }
+                        .into());
+                    }
+                    driver.set_sides(0.0, 0.0)?;
+                    self.hold.reset();
+                }
+            }
+            shutdown.sleep(period)?;
+        }
+    }
+}
@@ -0,0 +1,732 @@
+//! Zero-install control from a phone: a small HTTP server serving a single
+//! page with a touch joystick, a battery gauge, fault lights and an
+//! emergency-stop button, which talks to the robot over a WebSocket.
+//!
+//! The WebSocket, at `/ws`, carries JSON messages. Clients send
+//!
+//! - `{"type": "drive", "left": 0.5, "right": 0.3}`, powers in `[-1, 1]`,
+//! - `{"type": "joystick", "throttle": 0.5, "steering": -0.2}`, the stick of
+//!   the page in `[-1, 1]`, forwards and right positive, shaped with the
+//!   `sticks` curves, see `vrum::sticks`, and mixed into side powers,
+//! - `{"type": "camera", "pan": 0.5, "tilt": 0.0}`, aiming the camera with a
+//!   stick in `[-1, 1]`, left and up positive, see `vrum::pan_tilt`. The page
+//!   sends the right stick of a gamepad,
+//! - `{"type": "aux", "name": "headlights", "state": "on"}`, switching or
+//!   dimming an output of `[gpio_outputs]`, see `vrum::gpio_outputs`,
+//! - `{"type": "stop"}`,
+//! - `{"type": "estop"}`, latching the emergency stop,
+//! - `{"type": "reset_estop"}`,
+//! - `{"type": "arm"}` and `{"type": "disarm"}`, when the configuration
+//!   requires arming, see `vrum::arming`,
+//! - `{"type": "clear_faults"}`, allowing motor commands again after a
+//!   latched drive fault or battery cutoff, see `FaultLatch`,
+//! - `{"type": "heartbeat"}`, keeping the motors running as they are,
+//! - `{"type": "take_control"}` and `{"type": "release"}`, as every client
+//!   has to be in control to drive, aim the camera, switch outputs, arm,
+//!   reset the emergency stop or clear faults, see `vrum::arbiter`. Anyone
+//!   can stop, e-stop and disarm,
+//! - `{"type": "auth", "token": "..."}`, when a token is required to send
+//!   the other commands, see `vrum::auth`. It can also be given when
+//!   connecting, as in `/ws?token=...`, and the page passes on its own
+//!   `?token=...`,
+//!
+//! and receive `{"type": "status", ...}` (see `Status`) every
+//! `status_interval`, and `{"type": "error", "message": ...}` when a command
+//! fails. With `encoding = Encoding::Cbor` the server sends the same
+//! messages CBOR encoded in binary frames. Either way clients may send text
+//! frames of JSON or binary frames of CBOR. A client that sends neither
+//! drive commands nor heartbeats for the heartbeat timeout, e.g. a phone
+//! that lost Wi-Fi, has its motors stopped, as does one that disconnects,
+//! see `vrum::heartbeat`.
+//!
+//! `GET /events` returns the latest notable events as a JSON array, see
+//! `vrum::history`.
+//!
+//! With a certificate, see `vrum::tls`, the page and the WebSocket are
+//! served over HTTPS only.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use tungstenite::{Message, WebSocket};
+
+use crate::arbiter::{Arbiter, ArbiterClient, ArbitrationError, ControlPriority, Holder};
+use crate::arming::Arming;
+use crate::auth::{self, AuthError, Token};
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::estop::EStopLatch;
+use crate::faults::FaultLatch;
+use crate::gpio_outputs::{AuxError, GpioOutputs, OutputState};
+use crate::heartbeat::{DeadMan, DeadManConfig};
+use crate::motor_driver::MotorDriver;
+use crate::pan_tilt::{PanTilt, PanTiltError};
+use crate::shutdown::Shutdown;
+use crate::sticks::StickConfig;
+use crate::thunder_borg::Controller;
+use crate::tls::{TlsConfig, TlsError};
+
+/// The page served at `/`.
+pub const INDEX_HTML: &str = include_str!("web/index.html");
+
+#[derive(Clone, Debug)]
+pub struct WebConfig {
+    pub listen: SocketAddr,
+    pub status_interval: Duration,
+    pub heartbeat: DeadManConfig,
+    /// Priority of every web client when taking control.
+    pub priority: ControlPriority,
+    /// Clients without it only get the status.
+    pub token: Option<Token>,
+    /// Serve over HTTPS with this certificate.
+    pub tls: Option<TlsConfig>,
+    /// Encoding of the messages sent to clients.
+    pub encoding: Encoding,
+    /// Response curves of `joystick` commands.
+    pub sticks: StickConfig,
+    /// Aimed by `camera` commands.
+    pub pan_tilt: Option<PanTilt>,
+    /// Switched by `aux` commands.
+    pub outputs: Option<GpioOutputs>,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig {
+            listen: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            status_interval: Duration::from_millis(500),
+            heartbeat: DeadManConfig::default(),
+            priority: ControlPriority::Teleop,
+            token: None,
+            tls: None,
+            encoding: Encoding::Json,
+            sticks: StickConfig::default(),
+            pan_tilt: None,
+            outputs: None,
+        }
+    }
+}
+
+/// What clients are told about the robot.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Status {
+    pub battery_voltage: f32,
+    pub battery_percent: f32,
+    /// Drive fault flags of motors A and B.
+    pub faults: [bool; 2],
+    /// Powers of motors A (left) and B (right).
+    pub powers: [f32; 2],
+    pub estopped: bool,
+    /// Whether the motors are armed, `None` when arming isn't required.
+    pub armed: Option<bool>,
+    /// What latched the fault blocking motor commands, if any.
+    pub fault_latched: Option<String>,
+    /// The client in control, if any.
+    pub holder: Option<Holder>,
+    /// Whether the client receiving the status is in control.
+    pub in_control: bool,
+    /// Whether the client receiving the status may send commands.
+    pub authenticated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Drive { left: f32, right: f32 },
+    Joystick { throttle: f32, steering: f32 },
+    Camera { pan: f32, tilt: f32 },
+    Aux { name: String, state: OutputState },
+    Stop,
+    Estop,
+    ResetEstop,
+    Arm,
+    Disarm,
+    ClearFaults,
+    Heartbeat,
+    TakeControl,
+    Release,
+    Auth { token: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Status(&'a Status),
+    Error { message: String },
+}
+
+/// Serves the dashboard and its WebSocket, each client on its own thread.
+pub struct WebServer {
+    listener: TcpListener,
+    tls: Option<Arc<ServerConfig>>,
+    arbiter: Arbiter,
+    estop: EStopLatch,
+    config: WebConfig,
+}
+
+impl WebServer {
+    /// Listens on `config.listen`, each client competing for control through
+    /// `arbiter`. The e-stop button latches `estop`, which should be the
+    /// latch set on the controller.
+    pub fn bind(config: WebConfig, arbiter: Arbiter, estop: EStopLatch) -> Result<Self, Error> {
+        let tls = config
+            .tls
+            .as_ref()
+            .map(TlsConfig::server_config)
+            .transpose()?;
+        let listener = TcpListener::bind(config.listen)?;
+        // Polled, so a shutdown request is noticed between connections.
+        listener.set_nonblocking(true)?;
+        info!(
+            "Web dashboard listening on {}://{}",
+            if tls.is_some() { "https" } else { "http" },
+            listener.local_addr()?
+        );
+        Ok(WebServer {
+            listener,
+            tls,
+            arbiter,
+            estop,
+            config,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves clients until a shutdown is requested.
+    pub fn run(&self, shutdown: &Shutdown) -> Result<(), Error> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => self.spawn_client(stream, peer, shutdown)?,
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
+                    shutdown.sleep(ACCEPT_POLL_INTERVAL)?;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    fn spawn_client(
+        &self,
+        stream: TcpStream,
+        peer: SocketAddr,
+        shutdown: &Shutdown,
+    ) -> Result<(), Error> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        let stream = match self.tls {
+            Some(ref tls) => {
+                let connection = ServerConnection::new(tls.clone()).map_err(TlsError::from)?;
+                Stream::Tls(Box::new(StreamOwned::new(connection, stream)))
+            }
+            None => Stream::Plain(stream),
+        };
+        let client = Client {
+            arbiter: self.arbiter.clone(),
+            estop: self.estop.clone(),
+            config: self.config.clone(),
+            shutdown: shutdown.clone(),
+        };
+        thread::Builder::new()
+            .name("vrum-web-client".into())
+            .spawn(move || {
+                if let Err(error) = client.serve(stream) {
+                    warn!("Web client {} failed: {}", peer, error);
+                }
+            })?;
+        Ok(())
+    }
+}
+
+struct Client {
+    arbiter: Arbiter,
+    estop: EStopLatch,
+    config: WebConfig,
+    shutdown: Shutdown,
+}
+
+impl Client {
+    fn serve(&self, mut stream: Stream) -> Result<(), Error> {
+        let head = read_head(&mut stream)?;
+        let target = request_target(&head).unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        match path {
+            "/ws" => {
+                let token = query_param(query, "token");
+                // The handshake reads the request again.
+                let replay = Replay {
+                    head: io::Cursor::new(head),
+                    stream,
+                };
+                let socket = tungstenite::accept(replay)
+                    .map_err(|error| io::Error::new(ErrorKind::InvalidData, error.to_string()))?;
+                socket
+                    .get_ref()
+                    .stream
+                    .tcp()
+                    .set_read_timeout(Some(WEBSOCKET_POLL_INTERVAL))?;
+                self.run_socket(socket, token)
+            }
+            "/" | "/index.html" => respond(
+                &mut stream,
+                "200 OK",
+                "text/html; charset=utf-8",
+                INDEX_HTML,
+            ),
+            "/events" => {
+                let events = self
+                    .arbiter
+                    .controller()
+                    .call(|controller| controller.events())?;
+                respond(
+                    &mut stream,
+                    "200 OK",
+                    "application/json",
+                    &serde_json::to_string(&events)?,
+                )
+            }
+            _ => respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+        }
+    }
+
+    fn run_socket(
+        &self,
+        mut socket: WebSocket<Replay>,
+        token: Option<String>,
+    ) -> Result<(), Error> {
+        let peer = socket.get_ref().stream.tcp().peer_addr()?;
+        info!("Web client {} connected", peer);
+        let mut session = Session {
+            dead_man: DeadMan::new(self.config.heartbeat),
+            control: self
+                .arbiter
+                .client(format!("web client {}", peer), self.config.priority),
+            authenticated: auth::is_authorized(
+                self.config.token.as_ref(),
+                token.as_ref().map(|token| token.as_bytes()),
+            ),
+        };
+        if !session.authenticated {
+            info!(
+                "Web client {} is not authenticated, sending it the status only",
+                peer
+            );
+        }
+        let result = self.exchange(&mut socket, peer, &mut session);
+        info!("Web client {} disconnected", peer);
+        result.and(session.control.release())
+    }
+
+    fn exchange(
+        &self,
+        socket: &mut WebSocket<Replay>,
+        peer: SocketAddr,
+        session: &mut Session,
+    ) -> Result<(), Error> {
+        let mut last_status = None::<Instant>;
+        while !self.shutdown.is_requested() {
+            let received = match socket.read() {
+                Ok(Message::Text(text)) => Some(Encoding::Json.decode(text.as_bytes())),
+                Ok(Message::Binary(bytes)) => Some(Encoding::Cbor.decode(&bytes)),
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => None,
+                Err(tungstenite::Error::Io(ref error))
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut =>
+                {
+                    None
+                }
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(error) => return Err(error.into()),
+            };
+            if let Some(message) = received {
+                if let Err(error) = message.and_then(|message| self.handle(message, session)) {
+                    let message = ServerMessage::Error {
+                        message: error.to_string(),
+                    };
+                    socket.send(self.encode(&message)?)?;
+                }
+            }
+
+            if session.dead_man.expired() {
+                warn!("Web client {} went quiet, stopping the motors", peer);
+                session.control.stop_motors()?;
+            }
+            if last_status.is_none_or(|at| at.elapsed() >= self.config.status_interval) {
+                let status = self.status(session)?;
+                socket.send(self.encode(&ServerMessage::Status(&status))?)?;
+                last_status = Some(Instant::now());
+            }
+        }
+        Ok(())
+    }
+
+    fn encode(&self, message: &ServerMessage) -> Result<Message, Error> {
+        match self.config.encoding {
+            Encoding::Json => Ok(Message::text(serde_json::to_string(message)?)),
+            Encoding::Cbor => Ok(Message::binary(Encoding::Cbor.encode(message)?)),
+        }
+    }
+
+    fn handle(&self, message: ClientMessage, session: &mut Session) -> Result<(), Error> {
+        if let ClientMessage::Auth { ref token } = message {
+            session.authenticated =
+                auth::is_authorized(self.config.token.as_ref(), Some(token.as_bytes()));
+            if !session.authenticated {
+                return Err(AuthError::InvalidToken.into());
+            }
+            return Ok(());
+        }
+        if !session.authenticated {
+            return Err(AuthError::Unauthenticated.into());
+        }
+        let Session {
+            dead_man, control, ..
+        } = session;
+        match message {
+            ClientMessage::Drive { left, right } => drive(dead_man, control, left, right),
+            ClientMessage::Joystick { throttle, steering } => {
+                let (left, right) = self.config.sticks.drive(throttle, steering);
+                drive(dead_man, control, left, right)
+            }
+            ClientMessage::Camera { pan, tilt } => {
+                let pan_tilt = self
+                    .config
+                    .pan_tilt
+                    .as_ref()
+                    .ok_or(PanTiltError::NotConfigured)?;
+                if !control.has_control() {
+                    return Err(ArbitrationError::NotInControl.into());
+                }
+                pan_tilt.aim(pan, tilt);
+                Ok(())
+            }
+            ClientMessage::Aux { name, state } => {
+                let outputs = self
+                    .config
+                    .outputs
+                    .as_ref()
+                    .ok_or_else(|| AuxError::UnknownOutput { name: name.clone() })?;
+                if !control.has_control() {
+                    return Err(ArbitrationError::NotInControl.into());
+                }
+                outputs.set(&name, state)?;
+                Ok(())
+            }
+            ClientMessage::Stop => {
+                dead_man.stopped();
+                control.stop_motors()
+            }
+            ClientMessage::Estop => {
+                dead_man.stopped();
+                self.estop.trigger();
+                self.arbiter.controller().stop_motors()
+            }
+            ClientMessage::ResetEstop => {
+                if !control.has_control() {
+                    return Err(ArbitrationError::NotInControl.into());
+                }
+                self.estop.reset()
+            }
+            ClientMessage::Arm => {
+                if !control.has_control() {
+                    return Err(ArbitrationError::NotInControl.into());
+                }
+                self.arbiter.controller().call(Controller::arm)?
+            }
+            ClientMessage::Disarm => {
+                dead_man.stopped();
+                self.arbiter.controller().call(Controller::disarm)?
+            }
+            ClientMessage::ClearFaults => {
+                if !control.has_control() {
+                    return Err(ArbitrationError::NotInControl.into());
+                }
+                self.arbiter.controller().call(Controller::clear_faults)?
+            }
+            ClientMessage::Heartbeat => {
+                dead_man.heartbeat();
+                Ok(())
+            }
+            ClientMessage::TakeControl => control.take_control(),
+            ClientMessage::Release => {
+                dead_man.stopped();
+                control.release()
+            }
+            ClientMessage::Auth { .. } => Ok(()),
+        }
+    }
+
+    fn status(&self, session: &Session) -> Result<Status, Error> {
+        let estopped = self.estop.is_latched();
+        let holder = self.arbiter.holder();
+        let in_control = session.control.has_control();
+        let authenticated = session.authenticated;
+        self.arbiter.controller().call(move |controller| {
+            let (a, b) = controller.motor_powers();
+            Ok(Status {
+                battery_voltage: controller.get_battery_voltage()?,
+                battery_percent: controller.battery_percent()?,
+                faults: [
+                    controller.get_drive_fault_a()?,
+                    controller.get_drive_fault_b()?,
+                ],
+                powers: [a, b],
+                estopped,
+                armed: controller.arming().map(Arming::is_armed),
+                fault_latched: controller.fault_latch().and_then(FaultLatch::cause),
+                holder,
+                in_control,
+                authenticated,
+            })
+        })?
+    }
+}
+
+/// Drives the sides for a client, which has to be in control.
+fn drive(
+    dead_man: &mut DeadMan,
+    control: &mut ArbiterClient,
+    left: f32,
+    right: f32,
+) -> Result<(), Error> {
+    if !control.has_control() {
+        return Err(ArbitrationError::NotInControl.into());
+    }
+    let (left, right) = (left.clamp(-1.0, 1.0), right.clamp(-1.0, 1.0));
+    dead_man.drive(left, right)?;
+    control.set_sides(left, right)
+}
+
+/// What the server keeps of one WebSocket client.
+struct Session {
+    dead_man: DeadMan,
+    control: ArbiterClient,
+    authenticated: bool,
+}
+
+/// A client connection, encrypted when the server has a certificate.
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Stream {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    /// Flushes the response, telling a TLS client it is complete.
+    fn finish(&mut self) -> io::Result<()> {
+        if let Stream::Tls(stream) = self {
+            stream.conn.send_close_notify();
+        }
+        self.flush()
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buffer),
+            Stream::Tls(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(bytes),
+            Stream::Tls(stream) => stream.write(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A stream whose request head was already read, reading it again first.
+struct Replay {
+    head: io::Cursor<Vec<u8>>,
+    stream: Stream,
+}
+
+impl Read for Replay {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.head.read(buffer)? {
+            0 => self.stream.read(buffer),
+            read => Ok(read),
+        }
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.stream.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Reads the request line and headers, up to the blank line after them.
+fn read_head(stream: &mut Stream) -> Result<Vec<u8>, Error> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_LEN {
+            return Err(
+                io::Error::new(ErrorKind::InvalidData, "HTTP request head too long").into(),
+            );
+        }
+        match stream.read(&mut buffer)? {
+            0 => break,
+            read => head.extend_from_slice(&buffer[..read]),
+        }
+    }
+    Ok(head)
+}
+
+/// The target of a GET request, the path and query.
+fn request_target(head: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(head);
+    let line = head.split("\r\n").next()?;
+    let mut parts = line.split(' ');
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.into()),
+        _ => None,
+    }
+}
+
+/// The value of `name` in a URL query, percent-decoded.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    let value = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))?;
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            (b'+', _) => {
+                bytes.push(b' ');
+                rest = tail;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn respond(stream: &mut Stream, status: &str, content_type: &str, body: &str) -> Result<(), Error> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(stream.finish()?)
+}
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longer request heads are refused.
+const MAX_HEAD_LEN: usize = 8 * 1024;
+/// How often a client thread checks the heartbeat timeout and status
+/// interval.
+const WEBSOCKET_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{SharedController, SharedControllerConfig};
+    use crate::simulator::SimulatedBoard;
+    use crate::thunder_borg::ControllerBuilder;
+
+    #[test]
+    fn arming_and_resets_need_control() {
+        let board = SimulatedBoard::new();
+        let mut controller = ControllerBuilder::new()
+            .refresh_interval(Duration::default())
+            .build_with_bus(board.bus())
+            .unwrap();
+        let estop = EStopLatch::new();
+        let faults = FaultLatch::new();
+        controller.set_arming(Arming::new());
+        controller.set_estop(estop.clone());
+        controller.set_fault_latch(faults.clone());
+        let controller =
+            SharedController::spawn(SharedControllerConfig::default(), controller).unwrap();
+        let arbiter = Arbiter::new(controller);
+        let client = Client {
+            arbiter: arbiter.clone(),
+            estop: estop.clone(),
+            config: WebConfig::default(),
+            shutdown: Shutdown::new(),
+        };
+        let mut session = Session {
+            dead_man: DeadMan::new(DeadManConfig::default()),
+            control: arbiter.client("phone", ControlPriority::Teleop),
+            authenticated: true,
+        };
+        let mut holder = arbiter.client("gamepad", ControlPriority::Operator);
+        holder.take_control().unwrap();
+        estop.trigger();
+        faults.latch("drive fault");
+
+        for message in [
+            ClientMessage::ResetEstop,
+            ClientMessage::Arm,
+            ClientMessage::ClearFaults,
+        ] {
+            assert!(matches!(
+                client.handle(message, &mut session),
+                Err(Error::Arbitration(ArbitrationError::NotInControl))
+            ));
+        }
+        assert!(estop.is_latched());
+        assert!(faults.is_latched());
+
+        holder.release().unwrap();
+        session.control.take_control().unwrap();
+        client
+            .handle(ClientMessage::ResetEstop, &mut session)
+            .unwrap();
+        client
+            .handle(ClientMessage::ClearFaults, &mut session)
+            .unwrap();
+        client.handle(ClientMessage::Arm, &mut session).unwrap();
+        assert!(!estop.is_latched());
+        assert!(!faults.is_latched());
+        assert!(arbiter
+            .controller()
+            .call(|controller| controller.is_armed())
+            .unwrap());
+    }
+}
@@ -0,0 +1,249 @@
+//! Driver for the PiBorg XLoBorg: an MMA8453Q three axis accelerometer and
+//! a MAG3110 three axis magnetometer. Unlike the other PiBorg boards these
+//! are plain register based chips, each at its own I2C address.
+
+use std::f32::consts::PI;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use i2cdev::linux::LinuxI2CDevice;
+
+use crate::borg::{self, read_registers, write_register, Bus};
+use crate::error::Error;
+
+/// Offsets subtracted from the raw sensor readings, see
+/// `Controller::calibrate_accelerometer` and `Controller::calibrate_compass`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calibration {
+    /// Accelerometer reading when level and at rest, minus gravity, in g.
+    pub accelerometer_offset: [f32; 3],
+    /// Hard iron offset of the magnetometer, in raw counts.
+    pub compass_offset: [f32; 3],
+    /// Angle between magnetic and true north at the robot's location, in
+    /// degrees, positive to the east.
+    pub declination: f32,
+}
+
+/// Acceleration in g along the board's axes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Acceleration {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Acceleration {
+    /// Rotation around the y axis in degrees, positive nose up. Only
+    /// meaningful when the robot isn't accelerating.
+    pub fn pitch(&self) -> f32 {
+        (-self.x).atan2((self.y * self.y + self.z * self.z).sqrt()) * 180.0 / PI
+    }
+
+    /// Rotation around the x axis in degrees, positive right side down.
+    pub fn roll(&self) -> f32 {
+        self.y.atan2(self.z) * 180.0 / PI
+    }
+}
+
+pub struct ControllerBuilder {
+    bus_path: String,
+    calibration: Calibration,
+}
+
+impl Default for ControllerBuilder {
+    fn default() -> Self {
+        ControllerBuilder {
+            bus_path: borg::DEFAULT_I2C_BUS_PATH.into(),
+            calibration: Calibration::default(),
+        }
+    }
+}
+
+impl ControllerBuilder {
+    pub fn new() -> Self {
+        ControllerBuilder::default()
+    }
+
+    pub fn bus_path<S: Into<String>>(mut self, bus_path: S) -> Self {
+        self.bus_path = bus_path.into();
+        self
+    }
+
+    pub fn calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    /// Opens both chips and switches them to active mode.
+    pub fn build(self) -> Result<Controller, Error> {
+        info!("Initialising XLoBorg at i2c bus {}", self.bus_path);
+        let accelerometer = LinuxI2CDevice::new(&self.bus_path, ACCELEROMETER_ADDR)?;
+        let compass = LinuxI2CDevice::new(&self.bus_path, COMPASS_ADDR)?;
+        self.build_with_buses(Box::new(accelerometer), Box::new(compass))
+    }
+
+    /// Builds a controller talking to the chips over `accelerometer` and
+    /// `compass` rather than the Linux I2C bus, e.g. devices of a
+    /// `BusManager` at `ACCELEROMETER_ADDR` and `COMPASS_ADDR`.
+    pub fn build_with_buses(
+        self,
+        accelerometer: Box<dyn Bus>,
+        compass: Box<dyn Bus>,
+    ) -> Result<Controller, Error> {
+        let mut controller = Controller {
+            accelerometer,
+            compass,
+            calibration: self.calibration,
+        };
+        controller.init_accelerometer()?;
+        controller.init_compass()?;
+        Ok(controller)
+    }
+}
+
+pub struct Controller {
+    accelerometer: Box<dyn Bus>,
+    compass: Box<dyn Bus>,
+    calibration: Calibration,
+}
+
+impl Controller {
+    /// Opens the XLoBorg on the default bus.
+    pub fn new() -> Result<Self, Error> {
+        ControllerBuilder::new().build()
+    }
+
+    pub fn builder() -> ControllerBuilder {
+        ControllerBuilder::new()
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Calibrated acceleration, in g.
+    pub fn get_acceleration(&mut self) -> Result<Acceleration, Error> {
+        let [x, y, z] = self.read_raw_acceleration()?;
+        let offset = self.calibration.accelerometer_offset;
+        Ok(Acceleration {
+            x: x - offset[0],
+            y: y - offset[1],
+            z: z - offset[2],
+        })
+    }
+
+    /// Calibrated magnetic field along the board's axes, in raw counts.
+    pub fn get_magnetic_field(&mut self) -> Result<[f32; 3], Error> {
+        let raw = self.read_raw_magnetic_field()?;
+        let offset = self.calibration.compass_offset;
+        Ok([raw[0] - offset[0], raw[1] - offset[1], raw[2] - offset[2]])
+    }
+
+    /// Heading in degrees clockwise from true north, in `[0, 360)`. Assumes
+    /// the board is mounted flat.
+    pub fn get_heading(&mut self) -> Result<f32, Error> {
+        let [x, y, _] = self.get_magnetic_field()?;
+        let heading = (-y).atan2(x) * 180.0 / PI + self.calibration.declination;
+        Ok(heading.rem_euclid(360.0))
+    }
+
+    /// Measures the accelerometer offset, the robot has to be level and at
+    /// rest while `samples` readings are averaged.
+    pub fn calibrate_accelerometer(&mut self, samples: u32) -> Result<Calibration, Error> {
+        let samples = samples.max(1);
+        let mut total = [0.0f32; 3];
+        for _ in 0..samples {
+            let reading = self.read_raw_acceleration()?;
+            for (sum, value) in total.iter_mut().zip(reading.iter()) {
+                *sum += value;
+            }
+            thread::sleep(SAMPLE_INTERVAL);
+        }
+        let mean = total.map(|sum| sum / samples as f32);
+        self.calibration.accelerometer_offset = [mean[0], mean[1], mean[2] - 1.0];
+        info!(
+            "Accelerometer offset: {:?}",
+            self.calibration.accelerometer_offset
+        );
+        Ok(self.calibration.clone())
+    }
+
+    /// Measures the hard iron offset of the magnetometer. Rotate the robot
+    /// through a few full turns during `duration`; the offset is the centre
+    /// of the range of readings seen.
+    pub fn calibrate_compass(&mut self, duration: Duration) -> Result<Calibration, Error> {
+        let started = Instant::now();
+        let mut minimum = [f32::MAX; 3];
+        let mut maximum = [f32::MIN; 3];
+        while started.elapsed() < duration {
+            let reading = self.read_raw_magnetic_field()?;
+            for axis in 0..3 {
+                minimum[axis] = minimum[axis].min(reading[axis]);
+                maximum[axis] = maximum[axis].max(reading[axis]);
+            }
+            thread::sleep(SAMPLE_INTERVAL);
+        }
+        for axis in 0..3 {
+            self.calibration.compass_offset[axis] = (minimum[axis] + maximum[axis]) / 2.0;
+        }
+        info!("Compass offset: {:?}", self.calibration.compass_offset);
+        Ok(self.calibration.clone())
+    }
+
+    fn init_accelerometer(&mut self) -> Result<(), Error> {
+        // Registers can only be changed in standby.
+        write_register(&mut *self.accelerometer, MMA_CTRL_REG1, 0)?;
+        write_register(&mut *self.accelerometer, MMA_XYZ_DATA_CFG, MMA_RANGE_2G)?;
+        write_register(
+            &mut *self.accelerometer,
+            MMA_CTRL_REG1,
+            MMA_FAST_READ | MMA_ACTIVE,
+        )
+    }
+
+    fn init_compass(&mut self) -> Result<(), Error> {
+        write_register(&mut *self.compass, MAG_CTRL_REG2, MAG_AUTO_RESET)?;
+        write_register(&mut *self.compass, MAG_CTRL_REG1, MAG_ACTIVE)
+    }
+
+    fn read_raw_acceleration(&mut self) -> Result<[f32; 3], Error> {
+        // Status followed by the 8 bit fast read X, Y and Z values.
+        let mut data = [0u8; 4];
+        read_registers(&mut *self.accelerometer, MMA_STATUS, &mut data)?;
+        let to_g = |value: u8| f32::from(value as i8) / MMA_COUNTS_PER_G;
+        Ok([to_g(data[1]), to_g(data[2]), to_g(data[3])])
+    }
+
+    fn read_raw_magnetic_field(&mut self) -> Result<[f32; 3], Error> {
+        // Status followed by big endian X, Y and Z values.
+        let mut data = [0u8; 7];
+        read_registers(&mut *self.compass, MAG_DR_STATUS, &mut data)?;
+        let axis = |index: usize| f32::from(i16::from_be_bytes([data[index], data[index + 1]]));
+        Ok([axis(1), axis(3), axis(5)])
+    }
+}
+
+pub const ACCELEROMETER_ADDR: u16 = 0x1C;
+pub const COMPASS_ADDR: u16 = 0x0E;
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+
+// MMA8453Q registers and values
+const MMA_STATUS: u8 = 0x00;
+const MMA_XYZ_DATA_CFG: u8 = 0x0E;
+const MMA_CTRL_REG1: u8 = 0x2A;
+const MMA_RANGE_2G: u8 = 0x00;
+const MMA_FAST_READ: u8 = 1 << 1;
+const MMA_ACTIVE: u8 = 1 << 0;
+const MMA_COUNTS_PER_G: f32 = 64.0;
+
+// MAG3110 registers and values
+const MAG_DR_STATUS: u8 = 0x00;
+const MAG_CTRL_REG1: u8 = 0x10;
+const MAG_CTRL_REG2: u8 = 0x11;
+const MAG_ACTIVE: u8 = 1 << 0;
+const MAG_AUTO_RESET: u8 = 1 << 7;
@@ -0,0 +1,420 @@
+//! Driver for the PiBorg ZeroBorg: four motor channels, an infrared remote
+//! receiver and two analog inputs.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::borg::{
+    self, BoardInfo, BorgCommand, BorgDevice, CommStats, MotorConfig, Ping, RecoveryConfig,
+    Response,
+};
+use crate::color::Color;
+use crate::error::Error;
+use crate::motor_driver::{self, MotorDriver};
+
+pub struct ControllerBuilder {
+    bus_path: String,
+    address: u16,
+    recovery: RecoveryConfig,
+    motors: [MotorConfig; NUM_MOTORS],
+}
+
+impl Default for ControllerBuilder {
+    fn default() -> Self {
+        ControllerBuilder {
+            bus_path: borg::DEFAULT_I2C_BUS_PATH.into(),
+            address: ZEROBORG_SLAVE_ADDR,
+            recovery: RecoveryConfig::default(),
+            motors: [MotorConfig::default(); NUM_MOTORS],
+        }
+    }
+}
+
+impl ControllerBuilder {
+    pub fn new() -> Self {
+        ControllerBuilder::default()
+    }
+
+    pub fn bus_path<S: Into<String>>(mut self, bus_path: S) -> Self {
+        self.bus_path = bus_path.into();
+        self
+    }
+
+    pub fn address(mut self, address: u16) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How persistent bus failures are recovered from.
+    pub fn recovery(mut self, config: RecoveryConfig) -> Self {
+        self.recovery = config;
+        self
+    }
+
+    /// Corrections for motor `index`, counting from 0. Panics if `index` is
+    /// not a valid motor.
+    pub fn motor(mut self, index: usize, config: MotorConfig) -> Self {
+        self.motors[index] = config;
+        self
+    }
+
+    pub fn build(self) -> Result<Controller, Error> {
+        info!(
+            "Pinging ZeroBorg at i2c bus {} address 0x{:x}",
+            self.bus_path, self.address
+        );
+        let mut controller = Controller {
+            device: BorgDevice::open(&self.bus_path, self.address, I2C_NORM_LEN)?.with_recovery(
+                self.recovery.clone(),
+                Ping {
+                    command: Command::GetId.to_wire(),
+                    id: ZEROBORG_ID,
+                },
+            ),
+            motors: self.motors,
+            motor_powers: [0.0; NUM_MOTORS],
+        };
+
+        let response = controller.command_with_response(Command::GetId)?;
+        let id = response.byte(1)?;
+        if id != ZEROBORG_ID {
+            return Err(Error::UnexpectedBoardId {
+                board: "ZeroBorg",
+                expected: ZEROBORG_ID,
+                id,
+            });
+        }
+        info!("ZeroBorg found.");
+        Ok(controller)
+    }
+}
+
+pub struct Controller {
+    device: BorgDevice,
+    motors: [MotorConfig; NUM_MOTORS],
+    motor_powers: [f32; NUM_MOTORS],
+}
+
+impl Controller {
+    /// Opens the ZeroBorg at the default bus and address.
+    pub fn new() -> Result<Self, Error> {
+        ControllerBuilder::new().build()
+    }
+
+    pub fn builder() -> ControllerBuilder {
+        ControllerBuilder::new()
+    }
+
+    /// Bus traffic statistics since the controller was opened, to diagnose
+    /// flaky wiring or bus contention.
+    pub fn comm_stats(&self) -> &CommStats {
+        self.device.comm_stats()
+    }
+
+    pub fn reset_comm_stats(&mut self) {
+        self.device.reset_comm_stats();
+    }
+
+    /// Where the board is and the identifier it reports.
+    pub fn board_info(&mut self) -> Result<BoardInfo, Error> {
+        self.device.board_info("ZeroBorg", &Command::GetId)
+    }
+
+    /// Writes a command the crate doesn't model, e.g. from a newer firmware
+    /// revision.
+    pub fn send_raw(&mut self, command: u8, data: &[u8]) -> Result<(), Error> {
+        self.device.send_raw(command, data)
+    }
+
+    /// Sends a query the crate doesn't model and reads back `read_len`
+    /// bytes, the first one echoing `command`.
+    pub fn query_raw(&mut self, command: u8, read_len: usize) -> Result<Response, Error> {
+        self.device.query_raw(command, read_len)
+    }
+
+    pub fn set_led(&mut self, on: bool) -> Result<(), Error> {
+        self.command(Command::SetLed, &[on_off(on)])
+    }
+
+    pub fn get_led(&mut self) -> Result<bool, Error> {
+        self.get_flag(Command::GetLed)
+    }
+
+    /// Sets motor `index` (0 to 3, motors 1 to 4 on the board) to `power`
+    /// in `[-1, 1]`.
+    pub fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        motor_driver::check_motor_index(index, NUM_MOTORS)?;
+        let power = borg::clamp_motor_power(power);
+        let wire_power = self.motors[index].to_wire_power(power);
+        let power_bytes = &[borg::motor_power_to_byte(wire_power)?];
+        if wire_power < 0.0 {
+            self.command(Command::SetMotorReverse(index), power_bytes)?;
+        } else {
+            self.command(Command::SetMotorForward(index), power_bytes)?;
+        }
+        self.motor_powers[index] = power;
+        Ok(())
+    }
+
+    pub fn set_motors(&mut self, power: f32) -> Result<(), Error> {
+        for index in 0..NUM_MOTORS {
+            self.set_motor(index, power)?;
+        }
+        Ok(())
+    }
+
+    /// Last powers commanded to each motor, before any per-motor corrections.
+    pub fn motor_powers(&self) -> [f32; NUM_MOTORS] {
+        self.motor_powers
+    }
+
+    /// Same as `all_off()`, see `stop_motors()` to keep the LED on.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.all_off()
+    }
+
+    /// Switches off the motors and the LED.
+    pub fn all_off(&mut self) -> Result<(), Error> {
+        self.command(Command::AllOff, &[0])?;
+        self.motor_powers = [0.0; NUM_MOTORS];
+        Ok(())
+    }
+
+    /// Sets every motor to zero power, leaving the LED as it is.
+    pub fn stop_motors(&mut self) -> Result<(), Error> {
+        self.command(Command::SetMotorsForward, &[0])?;
+        self.motor_powers = [0.0; NUM_MOTORS];
+        Ok(())
+    }
+
+    /// True once the EPO input has been broken, the motors stay off until
+    /// `reset_epo()` is called.
+    pub fn get_epo(&mut self) -> Result<bool, Error> {
+        self.get_flag(Command::GetEpo)
+    }
+
+    pub fn reset_epo(&mut self) -> Result<(), Error> {
+        self.command(Command::ResetEpo, &[])
+    }
+
+    /// Ignore the EPO input, for boards without the EPO jumper fitted.
+    pub fn set_epo_ignore(&mut self, ignore: bool) -> Result<(), Error> {
+        self.command(Command::SetEpoIgnore, &[on_off(ignore)])
+    }
+
+    /// Enables the board's own failsafe, switching the motors off if no
+    /// command arrives for a quarter of a second.
+    pub fn set_failsafe(&mut self, enabled: bool) -> Result<(), Error> {
+        self.command(Command::SetFailsafe, &[on_off(enabled)])
+    }
+
+    /// True if an IR message arrived since the last call to
+    /// `get_ir_message()`.
+    pub fn has_new_ir_message(&mut self) -> Result<bool, Error> {
+        self.get_flag(Command::GetNewIr)
+    }
+
+    /// The last IR remote message received, as raw bytes.
+    pub fn get_ir_message(&mut self) -> Result<Vec<u8>, Error> {
+        let response = self.command_with_response(Command::GetLastIr)?;
+        Ok(response.data().to_vec())
+    }
+
+    /// Flash the LED whenever an IR message is received.
+    pub fn set_led_ir(&mut self, enabled: bool) -> Result<(), Error> {
+        self.command(Command::SetLedIr, &[on_off(enabled)])
+    }
+
+    /// Voltage on analog input 1 or 2, in volts.
+    pub fn get_analog(&mut self, input: AnalogInput) -> Result<f32, Error> {
+        let command = match input {
+            AnalogInput::One => Command::GetAnalog1,
+            AnalogInput::Two => Command::GetAnalog2,
+        };
+        let response = self.command_with_response(command)?;
+        let raw = response.u16_be(1)?;
+        Ok(f32::from(raw) / COMMAND_ANALOG_MAX * ANALOG_PIN_MAX)
+    }
+
+    fn get_flag(&mut self, command: Command) -> Result<bool, Error> {
+        let response = self.command_with_response(command)?;
+        Ok(response.byte(1)? != I2C_VALUE_OFF)
+    }
+
+    fn command_with_response(&mut self, command: Command) -> Result<Response, Error> {
+        self.device.command_with_response(&command)
+    }
+
+    fn command(&mut self, command: Command, data: &[u8]) -> Result<(), Error> {
+        self.device.command(&command, data)
+    }
+}
+
+impl MotorDriver for Controller {
+    fn num_motors(&self) -> usize {
+        NUM_MOTORS
+    }
+
+    fn set_motor(&mut self, index: usize, power: f32) -> Result<(), Error> {
+        Controller::set_motor(self, index, power)
+    }
+
+    fn motor_power(&self, index: usize) -> f32 {
+        self.motor_powers.get(index).cloned().unwrap_or(0.0)
+    }
+
+    /// Motors 1 and 2 drive the right side and motors 3 and 4 the left, as
+    /// in PiBorg's examples. Use `MotorConfig::inverted` for motors wired
+    /// backwards.
+    fn set_sides(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        self.set_motor(0, right)?;
+        self.set_motor(1, right)?;
+        self.set_motor(2, left)?;
+        self.set_motor(3, left)
+    }
+
+    fn stop_all(&mut self) -> Result<(), Error> {
+        self.stop()
+    }
+
+    fn comm_stats(&self) -> Result<CommStats, Error> {
+        Ok(Controller::comm_stats(self).clone())
+    }
+
+    /// The LED is on for any colour but `Color::OFF`.
+    fn set_led_color(&mut self, color: Color) -> Result<(), Error> {
+        self.set_led(color != Color::OFF)
+    }
+}
+
+impl Drop for Controller {
+    fn drop(&mut self) {
+        info!("Destroying a ZeroBorg `Controller`. Ensuring motors are stopped...");
+        if let Err(error) = self.stop() {
+            error!(
+                "Could not run `stop()` when destroying the controller. Error: {}",
+                error
+            );
+            error!("Motors may still be running!");
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnalogInput {
+    One,
+    Two,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum Command {
+    /// Set the LED on or off
+    SetLed,
+    /// Get the LED state
+    GetLed,
+    /// Set a motor's PWM rate in a forwards direction, motors counted from 0
+    SetMotorForward(usize),
+    /// Set a motor's PWM rate in a reverse direction, motors counted from 0
+    SetMotorReverse(usize),
+    /// Get a motor's direction and PWM rate, motors counted from 0
+    GetMotor(usize),
+    /// Switch everything off
+    AllOff,
+    /// Set all motors PWM rate in a forwards direction
+    SetMotorsForward,
+    /// Set all motors PWM rate in a reverse direction
+    SetMotorsReverse,
+    /// Set the failsafe flag, turns the motors off if communication is
+    /// interrupted
+    SetFailsafe,
+    /// Get the failsafe flag
+    GetFailsafe,
+    /// Reset the EPO latch, allowing the motors to move again
+    ResetEpo,
+    /// Get the EPO latch state
+    GetEpo,
+    /// Set whether the EPO input is ignored
+    SetEpoIgnore,
+    /// Get whether the EPO input is ignored
+    GetEpoIgnore,
+    /// Get whether an IR message arrived since the last one was read
+    GetNewIr,
+    /// Get the last IR message received
+    GetLastIr,
+    /// Set whether the LED flashes on IR messages
+    SetLedIr,
+    /// Get whether the LED flashes on IR messages
+    GetLedIr,
+    /// Get the reading of analog input 1
+    GetAnalog1,
+    /// Get the reading of analog input 2
+    GetAnalog2,
+    /// Get the board identifier
+    GetId,
+}
+
+impl Display for Command {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        write!(formatter, "{:?} (0x{:x})", self, self.to_wire())
+    }
+}
+
+impl BorgCommand for Command {
+    #[inline]
+    fn to_wire(&self) -> u8 {
+        match *self {
+            Command::SetLed => 1,
+            Command::GetLed => 2,
+            Command::SetMotorForward(index) => 3 + 3 * index as u8,
+            Command::SetMotorReverse(index) => 4 + 3 * index as u8,
+            Command::GetMotor(index) => 5 + 3 * index as u8,
+            Command::AllOff => 15,
+            Command::SetMotorsForward => 16,
+            Command::SetMotorsReverse => 17,
+            Command::SetFailsafe => 18,
+            Command::GetFailsafe => 19,
+            Command::ResetEpo => 20,
+            Command::GetEpo => 21,
+            Command::SetEpoIgnore => 22,
+            Command::GetEpoIgnore => 23,
+            Command::GetNewIr => 24,
+            Command::GetLastIr => 25,
+            Command::SetLedIr => 26,
+            Command::GetLedIr => 27,
+            Command::GetAnalog1 => 28,
+            Command::GetAnalog2 => 29,
+            Command::GetId => 0x99,
+        }
+    }
+
+    fn response_len(&self) -> Option<usize> {
+        match *self {
+            Command::GetLastIr => Some(I2C_LONG_LEN),
+            _ => None,
+        }
+    }
+}
+
+#[inline]
+fn on_off(value: bool) -> u8 {
+    if value {
+        I2C_VALUE_ON
+    } else {
+        I2C_VALUE_OFF
+    }
+}
+
+pub const NUM_MOTORS: usize = 4;
+
+const I2C_VALUE_ON: u8 = 1;
+const I2C_VALUE_OFF: u8 = 0;
+const I2C_NORM_LEN: usize = 4;
+const I2C_LONG_LEN: usize = 24;
+const ZEROBORG_ID: u8 = 0x40;
+const ZEROBORG_SLAVE_ADDR: u16 = 0x40;
+
+// Maximum value for analog readings
+const COMMAND_ANALOG_MAX: f32 = 0x3FF as f32;
+
+// Voltage of a full scale analog reading
+const ANALOG_PIN_MAX: f32 = 3.3;
@@ -0,0 +1,70 @@
+//! Drive sources sharing a `SharedController` through an `Arbiter`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use vrum::arbiter::{Arbiter, ClaimingClient, ControlPriority};
+use vrum::clock::ManualClock;
+use vrum::motor_driver::MotorDriver;
+use vrum::shared::{SharedController, SharedControllerConfig};
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::ControllerBuilder;
+
+fn arbiter(board: &SimulatedBoard) -> Arbiter {
+    let controller = ControllerBuilder::new()
+        .refresh_interval(Duration::default())
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers");
+    Arbiter::new(SharedController::spawn(SharedControllerConfig::default(), controller).unwrap())
+}
+
+#[test]
+fn operator_takes_over_and_hands_back() {
+    let board = SimulatedBoard::new();
+    let arbiter = arbiter(&board);
+    let clock = ManualClock::new();
+    let mut rc = ClaimingClient::with_clock(
+        arbiter.client("RC transmitter", ControlPriority::Operator),
+        Duration::from_secs(2),
+        Arc::new(clock.clone()),
+    );
+    let mut phone = arbiter.client("phone", ControlPriority::Teleop);
+    phone.take_control().unwrap();
+    phone.set_sides(0.25, 0.25).unwrap();
+
+    // Resting sticks leave the phone driving.
+    rc.set_sides(0.0, 0.0).unwrap();
+    assert!(phone.has_control());
+    assert!(board.state().motor_a > 0.0);
+
+    rc.set_sides(-0.5, -0.5).unwrap();
+    assert!(rc.client().has_control());
+    assert!(board.state().motor_a < 0.0);
+    assert!(phone.take_control().is_err());
+
+    rc.set_sides(0.0, 0.0).unwrap();
+    assert_eq!(board.state().motor_a, 0.0);
+    clock.advance(Duration::from_secs(1));
+    rc.set_sides(0.0, 0.0).unwrap();
+    assert!(rc.client().has_control());
+    clock.advance(Duration::from_secs(1));
+    rc.set_sides(0.0, 0.0).unwrap();
+    assert!(!rc.client().has_control());
+    phone.take_control().unwrap();
+}
+
+#[test]
+fn lower_priority_commands_are_dropped() {
+    let board = SimulatedBoard::new();
+    let arbiter = arbiter(&board);
+    let mut planner = ClaimingClient::new(
+        arbiter.client("planner", ControlPriority::Autonomy),
+        Duration::from_secs(2),
+    );
+    let mut phone = arbiter.client("phone", ControlPriority::Teleop);
+    phone.take_control().unwrap();
+
+    planner.set_sides(0.5, 0.5).unwrap();
+    assert!(phone.has_control());
+    assert_eq!(board.state().motor_a, 0.0);
+}
@@ -0,0 +1,30 @@
+//! Arming of a `Controller` on a `SimulatedBoard`.
+
+use std::time::Duration;
+
+use vrum::arming::Arming;
+use vrum::estop::EStopLatch;
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::ControllerBuilder;
+
+#[test]
+fn disarming_while_estopped_stops_the_motors() {
+    let board = SimulatedBoard::new();
+    let mut controller = ControllerBuilder::new()
+        .refresh_interval(Duration::default())
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers");
+    let estop = EStopLatch::new();
+    controller.set_estop(estop.clone());
+    controller.set_arming(Arming::new());
+    controller.arm().unwrap();
+    controller.set_motors(0.5).unwrap();
+
+    estop.trigger();
+    assert!(controller.set_motors(0.5).is_err());
+    controller.disarm().unwrap();
+
+    assert!(!controller.is_armed());
+    assert_eq!(board.state().motor_a, 0.0);
+    assert_eq!(board.state().motor_b, 0.0);
+}
@@ -0,0 +1,70 @@
+//! Devices sharing one bus through a `BusManager`.
+
+use std::thread;
+
+use vrum::borg::Bus;
+use vrum::bus_manager::{AddressedBus, BusManager};
+use vrum::Error;
+
+/// Answers a read with the address and first byte of the write before it,
+/// wherever that write went, so a transfer slipping in between a query and
+/// its read takes the response.
+#[derive(Default)]
+struct Echo {
+    address: u16,
+    response: [u8; 2],
+}
+
+impl AddressedBus for Echo {
+    fn set_address(&mut self, address: u16) -> Result<(), Error> {
+        self.address = address;
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.response = [self.address as u8, bytes[0]];
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        buffer.copy_from_slice(&self.response);
+        Ok(())
+    }
+}
+
+#[test]
+fn interleaved_transfers_reach_their_device() {
+    let bus = BusManager::new(Box::<Echo>::default());
+    let threads: Vec<_> = [0x15, 0x68]
+        .iter()
+        .map(|&address| {
+            let mut device = bus.device(address);
+            thread::spawn(move || {
+                for query in 0..10_000u32 {
+                    let query = query as u8;
+                    let mut response = [0u8; 2];
+                    device.write_read(&[query], &mut response).unwrap();
+                    assert_eq!(response, [address as u8, query]);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn each_device_selects_its_address() {
+    let bus = BusManager::new(Box::<Echo>::default());
+    let mut a = bus.device(0x15);
+    let mut b = bus.device(0x68);
+
+    let mut response = [0u8; 2];
+    a.write_read(&[1], &mut response).unwrap();
+    assert_eq!(response, [0x15, 1]);
+    b.write_read(&[2], &mut response).unwrap();
+    assert_eq!(response, [0x68, 2]);
+    a.write_read(&[3], &mut response).unwrap();
+    assert_eq!(response, [0x15, 3]);
+}
@@ -0,0 +1,83 @@
+//! Time-dependent behaviour on a `ManualClock`, without waiting.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vrum::clock::ManualClock;
+use vrum::kinematics::DiffDrive;
+use vrum::motion::Motion;
+use vrum::pipeline::PipelineConfig;
+use vrum::shutdown::Shutdown;
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::ControllerBuilder;
+use vrum::watchdog::Watchdog;
+
+#[test]
+fn ramp_follows_the_clock() {
+    let clock = ManualClock::new();
+    let board = SimulatedBoard::new();
+    let mut controller = ControllerBuilder::new()
+        .pipeline(PipelineConfig {
+            ramp_rate: Some(1.0),
+            ..PipelineConfig::default()
+        })
+        .refresh_interval(Duration::default())
+        .clock(Arc::new(clock.clone()))
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers");
+
+    controller.set_motors(0.0).unwrap();
+    for _ in 0..5 {
+        clock.advance(Duration::from_millis(50));
+        controller.set_motors(1.0).unwrap();
+    }
+    assert!((board.state().motor_a - 0.25).abs() < 1e-3);
+}
+
+#[test]
+fn watchdog_trips_when_the_clock_passes_the_timeout() {
+    let clock = ManualClock::new();
+    let stopped = Arc::new(AtomicBool::new(false));
+    let watchdog_stopped = stopped.clone();
+    let watchdog =
+        Watchdog::spawn_with_clock(Duration::from_secs(1), Arc::new(clock.clone()), move || {
+            watchdog_stopped.store(true, Ordering::SeqCst);
+            Ok(())
+        })
+        .unwrap();
+
+    // A few polls on the wall clock, the manual one hasn't moved.
+    thread::sleep(Duration::from_millis(50));
+    assert!(!watchdog.is_tripped());
+
+    clock.advance(Duration::from_secs(1));
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !watchdog.is_tripped() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(watchdog.is_tripped());
+    assert!(stopped.load(Ordering::SeqCst));
+}
+
+#[test]
+fn motion_sleeps_on_the_clock() {
+    let clock = ManualClock::new();
+    let board = SimulatedBoard::new();
+    let mut controller = ControllerBuilder::new()
+        .build_with_bus(board.bus())
+        .unwrap();
+    let shutdown = Shutdown::new();
+    let started = Instant::now();
+    let mut motion = Motion::new(&mut controller, &shutdown)
+        .drive_model(DiffDrive::new(0.15, 1.0))
+        .clock(Arc::new(clock.clone()));
+
+    motion.drive_for(0.5, Duration::from_secs(60)).unwrap();
+    motion.spin(90.0).unwrap();
+
+    assert!(clock.total() >= Duration::from_secs(60));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(board.state().motor_a, 0.0);
+}
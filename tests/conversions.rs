@@ -0,0 +1,194 @@
+//! Properties of the conversions between motor powers, battery voltages and
+//! what goes on the wire.
+
+use proptest::prelude::*;
+
+use vrum::borg::{byte_to_motor_power, clamp_motor_power, motor_power_to_byte};
+use vrum::simulator::{BoardState, SimulatedBoard};
+use vrum::thunder_borg::{ControllerBuilder, VoltageCalibration};
+use vrum::Error;
+
+proptest! {
+    #[test]
+    fn clamped_powers_are_in_range(power in any::<f32>().prop_filter("not NaN", |p| !p.is_nan())) {
+        let clamped = clamp_motor_power(power);
+        prop_assert!((-1.0..=1.0).contains(&clamped));
+        if (-1.0..=1.0).contains(&power) {
+            prop_assert_eq!(clamped, power);
+        } else {
+            prop_assert_eq!(clamped, power.signum());
+        }
+    }
+
+    #[test]
+    fn clamped_powers_convert_to_bytes(power in any::<f32>().prop_filter("not NaN", |p| !p.is_nan())) {
+        prop_assert!(motor_power_to_byte(clamp_motor_power(power)).is_ok());
+    }
+
+    #[test]
+    fn powers_out_of_range_are_rejected(power in prop_oneof![1.0001f32..1e30, -1e30f32..-1.0001]) {
+        let is_invalid = matches!(
+            motor_power_to_byte(power),
+            Err(Error::InvalidMotorPower { .. })
+        );
+        prop_assert!(is_invalid);
+    }
+
+    #[test]
+    fn byte_ignores_direction(power in -1.0f32..=1.0) {
+        prop_assert_eq!(motor_power_to_byte(power)?, motor_power_to_byte(-power)?);
+    }
+
+    #[test]
+    fn byte_grows_with_power(a in 0.0f32..=1.0, b in 0.0f32..=1.0) {
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        prop_assert!(motor_power_to_byte(low)? <= motor_power_to_byte(high)?);
+    }
+
+    #[test]
+    fn bytes_round_trip(byte in any::<u8>()) {
+        prop_assert_eq!(motor_power_to_byte(byte_to_motor_power(byte))?, byte);
+        prop_assert_eq!(motor_power_to_byte(-byte_to_motor_power(byte))?, byte);
+    }
+
+    #[test]
+    fn bytes_are_the_nearest_step(power in -1.0f32..=1.0) {
+        let byte = motor_power_to_byte(power)?;
+        prop_assert!((byte_to_motor_power(byte) - power.abs()).abs() <= 0.5 / 255.0 + 1e-6);
+    }
+
+    #[test]
+    fn powers_round_trip_within_a_step(power in 0.0f32..=1.0) {
+        let power_back = byte_to_motor_power(motor_power_to_byte(power)?);
+        prop_assert_eq!(motor_power_to_byte(power_back)?, motor_power_to_byte(power)?);
+    }
+
+    #[test]
+    fn voltage_decode_is_monotonic(a in 0u16..=0x3FF, b in 0u16..=0x3FF) {
+        let calibration = VoltageCalibration::default();
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        prop_assert!(calibration.to_volts(low) <= calibration.to_volts(high));
+    }
+
+    #[test]
+    fn voltage_decode_spans_the_pin_range(pin_max in 1.0f32..50.0, correction in -1.0f32..1.0) {
+        let calibration = VoltageCalibration { pin_max, correction };
+        prop_assert_eq!(calibration.to_volts(0), correction);
+        prop_assert!((calibration.to_volts(0x3FF) - (pin_max + correction)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn battery_voltage_reads_back(battery_voltage in 0.0f32..36.0) {
+        let board = SimulatedBoard::with_state(BoardState {
+            battery_voltage,
+            ..BoardState::default()
+        });
+        let mut controller = ControllerBuilder::new().build_with_bus(board.bus())?;
+        let step = VoltageCalibration::default().pin_max / 0x3FF as f32;
+        let read = controller.get_battery_voltage()?;
+        prop_assert!((read - battery_voltage).abs() <= step, "read {} V", read);
+    }
+}
+
+#[test]
+fn nan_power_is_an_error() {
+    assert!(clamp_motor_power(f32::NAN).is_nan());
+    assert!(matches!(
+        motor_power_to_byte(f32::NAN),
+        Err(Error::InvalidMotorPower { .. })
+    ));
+}
+
+#[test]
+fn non_finite_powers_leave_the_motors_alone() {
+    let board = SimulatedBoard::new();
+    let mut controller = ControllerBuilder::new()
+        .build_with_bus(board.bus())
+        .unwrap();
+    controller.set_motors(0.5).unwrap();
+    for &power in &[f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+        assert!(matches!(
+            controller.set_motors(power),
+            Err(Error::InvalidMotorPower { .. })
+        ));
+        assert!(matches!(
+            controller.set_motor_b(power),
+            Err(Error::InvalidMotorPower { .. })
+        ));
+    }
+    assert_eq!(controller.motor_powers(), (0.5, 0.5));
+    assert_eq!(board.state().motor_a, byte_to_motor_power(128));
+    controller.set_motors(0.25).unwrap();
+    assert_eq!(controller.motor_powers(), (0.25, 0.25));
+}
+
+#[test]
+fn full_scale_powers() {
+    assert_eq!(motor_power_to_byte(0.0).unwrap(), 0);
+    assert_eq!(motor_power_to_byte(1.0).unwrap(), 255);
+    assert_eq!(motor_power_to_byte(-1.0).unwrap(), 255);
+}
+
+/// Duty cycles sent for a few powers, a snapshot of the rounding of
+/// `motor_power_to_byte` guarding against regressions. Not the reference
+/// implementation, see `REFERENCE_DUTY_CYCLES`.
+const DUTY_CYCLE_SNAPSHOT: &[(f32, u8)] = &[
+    (0.0, 0),
+    (0.001, 0),
+    (0.002, 1),
+    (0.1, 26),
+    (0.25, 64),
+    (0.3, 77),
+    (0.5, 128),
+    (0.75, 191),
+    (0.9, 230),
+    (0.99, 252),
+    (0.998, 254),
+    (0.999, 255),
+    (1.0, 255),
+];
+
+/// Duty cycles of the official Python library for the same powers, which
+/// truncates with `int(255 * power)`.
+const REFERENCE_DUTY_CYCLES: &[(f32, u8)] = &[
+    (0.0, 0),
+    (0.001, 0),
+    (0.002, 0),
+    (0.1, 25),
+    (0.25, 63),
+    (0.3, 76),
+    (0.5, 127),
+    (0.75, 191),
+    (0.9, 229),
+    (0.99, 252),
+    (0.998, 254),
+    (0.999, 254),
+    (1.0, 255),
+];
+
+#[test]
+fn duty_cycles_match_the_snapshot() {
+    for &(power, duty_cycle) in DUTY_CYCLE_SNAPSHOT {
+        assert_eq!(motor_power_to_byte(power).unwrap(), duty_cycle, "{}", power);
+        assert_eq!(
+            motor_power_to_byte(-power).unwrap(),
+            duty_cycle,
+            "{}",
+            -power
+        );
+    }
+}
+
+#[test]
+fn duty_cycles_round_what_the_reference_implementation_truncates() {
+    for &(power, reference) in REFERENCE_DUTY_CYCLES {
+        let duty_cycle = motor_power_to_byte(power).unwrap();
+        assert!(
+            duty_cycle == reference || duty_cycle == reference + 1,
+            "{} is {}, the reference sends {}",
+            power,
+            duty_cycle,
+            reference
+        );
+    }
+}
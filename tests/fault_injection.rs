@@ -0,0 +1,207 @@
+//! How controllers behave on a flaky bus, driving a `SimulatedBoard`
+//! through a `FaultyBus`.
+
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vrum::borg::RecoveryConfig;
+use vrum::fault_injection::{FaultyBus, InjectedFaults};
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::{Controller, ControllerBuilder};
+use vrum::watchdog::Watchdog;
+use vrum::Error;
+
+const SEED: u64 = 0x5eed;
+
+fn controller(faults: &FaultyBus) -> Controller {
+    ControllerBuilder::new()
+        .build_with_bus(faults.bus())
+        .expect("the simulated board answers")
+}
+
+fn controller_without_recovery(faults: &FaultyBus) -> Controller {
+    ControllerBuilder::new()
+        .recovery(RecoveryConfig {
+            enabled: false,
+            ..RecoveryConfig::default()
+        })
+        .build_with_bus(faults.bus())
+        .expect("the simulated board answers")
+}
+
+fn is_io_error(error: &Error, kind: io::ErrorKind) -> bool {
+    matches!(error, Error::Io(error) if error.kind() == kind)
+}
+
+#[test]
+fn wrong_echoes_are_retried() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller(&faults);
+    controller.reset_comm_stats();
+    faults.wrong_echo_rate(0.2);
+
+    for _ in 0..100 {
+        let voltage = controller.get_battery_voltage().unwrap();
+        assert!((voltage - 12.0).abs() < 0.1, "read {} V", voltage);
+    }
+    let stats = controller.comm_stats().total();
+    let injected = faults.injected().wrong_echoes;
+    assert!(injected > 0);
+    assert_eq!(stats.completed, 100);
+    assert_eq!(stats.mismatched_headers, injected);
+    assert_eq!(stats.retries, injected);
+    assert_eq!(stats.failures, 0);
+}
+
+#[test]
+fn persistent_wrong_echoes_fail_after_every_attempt() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller_without_recovery(&faults);
+    controller.reset_comm_stats();
+    faults.wrong_echo_rate(1.0);
+
+    match controller.get_battery_voltage() {
+        Err(Error::CommandFailed { attempts, raw, .. }) => {
+            assert_eq!(attempts, 3);
+            assert!(!raw.is_empty());
+        }
+        other => panic!("expected CommandFailed, got {:?}", other),
+    }
+    let stats = controller.comm_stats().total();
+    assert_eq!(stats.mismatched_headers, 3);
+    assert_eq!(stats.retries, 2);
+    assert_eq!(stats.failures, 1);
+}
+
+#[test]
+fn dropped_reads_are_bus_errors_and_not_retried() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller_without_recovery(&faults);
+    controller.reset_comm_stats();
+    faults.drop_every_nth_read(2);
+
+    let results: Vec<_> = (0..4).map(|_| controller.get_drive_fault_a()).collect();
+    assert!(matches!(results[0], Ok(false)));
+    assert!(matches!(results[1], Err(Error::Io(_))));
+    assert!(matches!(results[2], Ok(false)));
+    assert!(matches!(results[3], Err(Error::Io(_))));
+    let stats = controller.comm_stats().total();
+    assert_eq!(faults.injected().dropped_reads, 2);
+    assert_eq!(stats.retries, 0);
+    assert_eq!(stats.failures, 2);
+}
+
+#[test]
+fn timeouts_are_reported_as_such() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller_without_recovery(&faults);
+    faults.timeout_rate(1.0);
+    faults.timeout_delay(Duration::from_millis(20));
+
+    let started = Instant::now();
+    let error = controller.set_motors(0.5).unwrap_err();
+    assert!(is_io_error(&error, io::ErrorKind::TimedOut), "{:?}", error);
+    assert!(started.elapsed() >= Duration::from_millis(20));
+    assert_eq!(board.state().motor_a, 0.0);
+    assert_eq!(controller.motor_powers(), (0.0, 0.0));
+
+    faults.heal();
+    controller.set_motors(0.5).unwrap();
+    assert!(board.state().motor_a > 0.0);
+}
+
+#[test]
+fn flipped_bytes_go_unnoticed() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller(&faults);
+    controller.reset_comm_stats();
+    let expected = controller.get_battery_voltage().unwrap();
+    faults.flip_rate(1.0);
+
+    let voltages: Vec<f32> = (0..50)
+        .map(|_| controller.get_battery_voltage().unwrap())
+        .collect();
+    assert_eq!(faults.injected().flipped_bytes, 50);
+    assert!(voltages.iter().any(|&voltage| voltage != expected));
+    assert_eq!(controller.comm_stats().total().retries, 0);
+}
+
+#[test]
+fn bus_is_recovered_after_consecutive_failures() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller(&faults);
+    board.fail_writes(2);
+
+    // The first failure is returned, the second one in a row recovers the
+    // bus and sends the command again.
+    assert!(controller.set_motors(0.5).is_err());
+    controller.set_motors(0.5).unwrap();
+    assert_eq!(controller.comm_stats().recoveries, 1);
+    assert!(board.state().motor_a > 0.0);
+}
+
+#[test]
+fn failed_recovery_is_an_error() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller(&faults);
+    faults.wrong_echo_rate(1.0);
+
+    assert!(matches!(
+        controller.get_battery_voltage(),
+        Err(Error::CommandFailed { .. })
+    ));
+    assert!(matches!(
+        controller.get_battery_voltage(),
+        Err(Error::RecoveryFailed { .. })
+    ));
+    assert_eq!(controller.comm_stats().recoveries, 1);
+}
+
+#[test]
+fn watchdog_keeps_trying_to_stop_the_motors() {
+    let board = SimulatedBoard::new();
+    let faults = FaultyBus::new(board.bus(), SEED);
+    let mut controller = controller_without_recovery(&faults);
+    controller.set_motors(0.5).unwrap();
+    let watchdog = Watchdog::with_controller(controller, Duration::from_millis(20)).unwrap();
+    faults.timeout_rate(1.0);
+
+    thread::sleep(Duration::from_millis(100));
+    assert!(!watchdog.is_tripped());
+    assert!(board.state().motor_a > 0.0);
+
+    faults.heal();
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !watchdog.is_tripped() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(watchdog.is_tripped());
+    assert_eq!(board.state().motor_a, 0.0);
+    assert_eq!(board.state().motor_b, 0.0);
+}
+
+#[test]
+fn same_seed_injects_the_same_faults() {
+    let run = |seed| -> InjectedFaults {
+        let board = SimulatedBoard::new();
+        let faults = FaultyBus::new(board.bus(), seed);
+        let mut controller = controller_without_recovery(&faults);
+        faults.wrong_echo_rate(0.3);
+        faults.flip_rate(0.3);
+        faults.timeout_rate(0.1);
+        for _ in 0..50 {
+            let _ = controller.get_battery_voltage();
+        }
+        faults.injected()
+    };
+    assert_eq!(run(SEED), run(SEED));
+    assert_ne!(run(SEED), run(SEED + 1));
+}
@@ -0,0 +1,45 @@
+//! Fault policies of a `FaultMonitor` on a `SimulatedBoard`.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vrum::faults::{FaultConfig, FaultLatch, FaultMonitor, FaultPolicy};
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::{Controller, ControllerBuilder};
+
+fn build(board: &SimulatedBoard) -> Controller {
+    ControllerBuilder::new()
+        .refresh_interval(Duration::default())
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers")
+}
+
+#[test]
+fn reduce_power_keeps_driving_after_a_fault() {
+    let board = SimulatedBoard::new();
+    let mut controller = build(&board);
+    let latch = FaultLatch::new();
+    controller.set_fault_latch(latch.clone());
+    let monitor = FaultMonitor::spawn(
+        FaultConfig {
+            policy: FaultPolicy::ReducePower,
+            reduced_power: 0.3,
+            poll_interval_ms: 10,
+        },
+        build(&board),
+        Some(latch.clone()),
+    )
+    .unwrap();
+    controller.set_fault_guard(monitor.guard());
+
+    board.update(|state| state.fault_a = true);
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !monitor.guard().is_faulted() {
+        assert!(Instant::now() < deadline, "the fault was never seen");
+        thread::sleep(Duration::from_millis(5));
+    }
+    controller.set_motors(1.0).unwrap();
+
+    assert!(!latch.is_latched());
+    assert!((board.state().motor_a - 0.3).abs() < 0.01);
+}
@@ -0,0 +1,59 @@
+//! Gyro-assisted straight driving of a `SharedController` on a
+//! `SimulatedBoard`.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vrum::gyro::{GyroConfig, GyroMonitor, YawRateSensor};
+use vrum::motor_driver::MotorDriver;
+use vrum::shared::{SharedController, SharedControllerConfig};
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::ControllerBuilder;
+use vrum::Error;
+
+/// A robot curving counter-clockwise at a steady rate.
+struct ConstantYawRate(f32);
+
+impl YawRateSensor for ConstantYawRate {
+    fn yaw_rate(&mut self) -> Result<f32, Error> {
+        Ok(self.0)
+    }
+}
+
+#[test]
+fn straight_sides_keep_the_correction() {
+    let board = SimulatedBoard::new();
+    let controller = ControllerBuilder::new()
+        .refresh_interval(Duration::default())
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers");
+    let mut shared =
+        SharedController::spawn(SharedControllerConfig::default(), controller).unwrap();
+    let monitor = GyroMonitor::spawn(
+        &GyroConfig {
+            rate_hz: 100.0,
+            bias_samples: 0,
+            ..GyroConfig::default()
+        },
+        ConstantYawRate(10.0),
+        Some(shared.clone()),
+    )
+    .unwrap();
+
+    shared.set_sides(0.5, 0.5).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while board.state().motor_a <= board.state().motor_b {
+        assert!(
+            Instant::now() < deadline,
+            "the correction was never applied"
+        );
+        thread::sleep(Duration::from_millis(5));
+    }
+    for _ in 0..10 {
+        shared.set_sides(0.5, 0.5).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        assert!(monitor.guard().is_engaged());
+        let state = board.state();
+        assert!(state.motor_a > state.motor_b, "the correction was dropped");
+    }
+}
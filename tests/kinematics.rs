@@ -0,0 +1,32 @@
+//! Wheel speeds and body velocities of the drive kinematics.
+
+use proptest::prelude::*;
+
+use vrum::kinematics::{DiffDrive, MecanumDrive};
+
+proptest! {
+    #[test]
+    fn arcs_keep_their_radius(
+        speed in prop_oneof![-5.0f32..-0.1, 0.1f32..5.0],
+        radius in prop_oneof![-10.0f32..-0.05, 0.05f32..10.0],
+    ) {
+        let drive = DiffDrive::new(0.2, 1.0);
+        let (left, right) = drive.arc_powers(speed, radius);
+        prop_assert!(left.abs() <= 1.0 && right.abs() <= 1.0);
+        let (linear, angular) = drive.body_velocity(left, right);
+        prop_assert!((linear / angular - radius).abs() < 1e-3 * radius.abs().max(1.0));
+        prop_assert_eq!(linear.signum(), speed.signum());
+    }
+
+    #[test]
+    fn mecanum_velocities_round_trip(
+        vx in -2.0f32..2.0,
+        vy in -2.0f32..2.0,
+        omega in -5.0f32..5.0,
+    ) {
+        let drive = MecanumDrive::new(0.2, 0.15, 1.0);
+        let (x, y, turn) = drive.body_velocity(drive.wheel_speeds(vx, vy, omega));
+        prop_assert!((x - vx).abs() < 1e-4 && (y - vy).abs() < 1e-4 && (turn - omega).abs() < 1e-4);
+        prop_assert!(drive.motor_powers(vx, vy, omega).iter().all(|power| power.abs() <= 1.0));
+    }
+}
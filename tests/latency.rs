@@ -0,0 +1,26 @@
+//! Quantiles of the latency histograms.
+
+use std::time::Duration;
+
+use proptest::prelude::*;
+
+use vrum::latency::LatencyHistogram;
+
+proptest! {
+    #[test]
+    fn latency_quantiles_are_within_a_bucket(
+        mut latencies_us in prop::collection::vec(0u64..10_000_000, 1..200),
+        quantile in 0.0f64..=1.0,
+    ) {
+        let mut histogram = LatencyHistogram::new();
+        for &latency_us in &latencies_us {
+            histogram.record(Duration::from_micros(latency_us));
+        }
+        latencies_us.sort_unstable();
+        let rank = ((quantile * latencies_us.len() as f64).ceil() as usize).max(1);
+        let exact = latencies_us[rank - 1];
+        let bound = histogram.quantile(quantile).as_micros() as u64;
+        prop_assert!(bound >= exact && bound <= (2 * exact).max(1), "{} for {}", bound, exact);
+        prop_assert_eq!(histogram.quantile(1.0), Duration::from_micros(latencies_us[latencies_us.len() - 1]));
+    }
+}
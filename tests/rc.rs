@@ -0,0 +1,26 @@
+//! Encoding and decoding of the SBUS frames of an RC receiver.
+
+use proptest::prelude::*;
+
+use vrum::rc::{self, RcFrame, SbusDecoder};
+
+proptest! {
+    #[test]
+    fn sbus_frames_round_trip_within_a_step(
+        channels in prop::collection::vec(-1.0f32..=1.0, 16),
+        failsafe in any::<bool>(),
+        noise in prop::collection::vec(any::<u8>().prop_filter("not a header", |&byte| byte != 0x0f), 0..30),
+    ) {
+        let frame = RcFrame { channels, failsafe };
+        let mut decoder = SbusDecoder::new();
+        // The first frame only brings the decoder in sync.
+        let encoded = rc::encode_sbus(&frame);
+        let stream = noise.iter().chain(&encoded).chain(&encoded).copied().collect::<Vec<_>>();
+        let decoded = stream.into_iter().filter_map(|byte| decoder.push(byte)).next();
+        let decoded = decoded.ok_or_else(|| TestCaseError::fail("no frame decoded"))?;
+        prop_assert_eq!(decoded.failsafe, failsafe);
+        for (decoded, sent) in decoded.channels.iter().zip(&frame.channels) {
+            prop_assert!((decoded - sent).abs() <= 2.0 / 1639.0);
+        }
+    }
+}
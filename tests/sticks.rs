@@ -0,0 +1,22 @@
+//! Shaping of the teleop sticks.
+
+use proptest::prelude::*;
+
+use vrum::sticks::AxisCurve;
+
+proptest! {
+    #[test]
+    fn stick_curves_keep_the_range_and_order(
+        a in -1.0f32..=1.0,
+        b in -1.0f32..=1.0,
+        dead_zone in 0.0f32..0.5,
+        expo in 0.0f32..=1.0,
+    ) {
+        let curve = AxisCurve { dead_zone, expo };
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        prop_assert!((-1.0..=1.0).contains(&curve.apply(a)));
+        prop_assert!(curve.apply(low) <= curve.apply(high));
+        prop_assert_eq!(curve.apply(-a), -curve.apply(a));
+        prop_assert!((curve.apply(1.0) - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,22 @@
+//! Parsing of the readings of the temperature sensors.
+
+use proptest::prelude::*;
+
+use vrum::sensors::temperature;
+
+proptest! {
+    #[test]
+    fn ds18b20_readings_parse(millis in -55_000i32..=125_000, crc_ok in any::<bool>()) {
+        let contents = format!(
+            "72 01 4b 46 7f ff 0e 10 57 : crc=57 {}\n72 01 4b 46 7f ff 0e 10 57 t={}\n",
+            if crc_ok { "YES" } else { "NO" },
+            millis
+        );
+        let parsed = temperature::parse_w1_slave(&contents);
+        if crc_ok {
+            prop_assert!((parsed.unwrap() - millis as f32 / 1000.0).abs() < 1e-3);
+        } else {
+            prop_assert_eq!(parsed, None);
+        }
+    }
+}
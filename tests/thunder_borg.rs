@@ -0,0 +1,89 @@
+//! The ThunderBorg protocol, against a `SimulatedBoard`.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vrum::borg::Bus;
+use vrum::color::Color;
+use vrum::led::Effect;
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::ControllerBuilder;
+use vrum::Error;
+
+/// Passes transfers through, noting the length of every read.
+struct ReadLengths {
+    bus: Box<dyn Bus>,
+    lengths: Arc<Mutex<Vec<usize>>>,
+}
+
+impl Bus for ReadLengths {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.bus.write(bytes)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        self.lengths.lock().unwrap().push(buffer.len());
+        self.bus.read(buffer)
+    }
+}
+
+#[test]
+fn queries_read_only_their_response() {
+    let board = SimulatedBoard::new();
+    let lengths = Arc::new(Mutex::new(Vec::new()));
+    let mut controller = ControllerBuilder::new()
+        .build_with_bus(Box::new(ReadLengths {
+            bus: board.bus(),
+            lengths: lengths.clone(),
+        }))
+        .expect("the simulated board answers");
+    lengths.lock().unwrap().clear();
+
+    controller.get_battery_voltage().unwrap();
+    controller.get_drive_fault_a().unwrap();
+    controller.read_motor_powers().unwrap();
+    assert_eq!(*lengths.lock().unwrap(), [3, 2, 3, 3]);
+}
+
+#[test]
+fn fields_beyond_a_short_read_are_errors() {
+    let board = SimulatedBoard::new();
+    let mut controller = ControllerBuilder::new()
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers");
+
+    // The battery voltage is two bytes after the command byte.
+    let response = controller.query_raw(21, 2).unwrap();
+    match response.u16_be(1) {
+        Err(Error::ResponseTooShort { offset, width, raw }) => {
+            assert_eq!((offset, width), (1, 2));
+            assert_eq!(raw.len(), 2);
+        }
+        other => panic!("expected ResponseTooShort, got {:?}", other),
+    }
+}
+
+#[test]
+fn led_effects_play_on_the_bus_of_the_controller() {
+    let board = SimulatedBoard::new();
+    let mut controller = ControllerBuilder::new()
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers");
+
+    controller
+        .led_effect(Effect::Blink {
+            color: Color::RED,
+            period: Duration::from_millis(200),
+        })
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while board.state().led != Color::RED {
+        assert!(Instant::now() < deadline, "the effect never lit the LED");
+        thread::sleep(Duration::from_millis(5));
+    }
+    controller.get_battery_voltage().unwrap();
+    controller.stop_led_effect();
+
+    assert_eq!(board.state().led, Color::OFF);
+}
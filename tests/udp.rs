@@ -0,0 +1,52 @@
+//! Encoding and decoding of the UDP teleop packets, and the receiver.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use proptest::prelude::*;
+
+use vrum::estop::EStopLatch;
+use vrum::simulator::SimulatedBoard;
+use vrum::thunder_borg::ControllerBuilder;
+use vrum::udp::{Packet, UdpConfig, UdpReceiver, UdpSender};
+proptest! {
+    #[test]
+    fn udp_packets_round_trip_within_a_step(seq in any::<u32>(), left in -1.0f32..=1.0, right in -1.0f32..=1.0) {
+        let packet = Packet::decode(&Packet::drive(seq, left, right).encode())?;
+        prop_assert_eq!(packet.seq, seq);
+        prop_assert!((packet.left - left).abs() <= 1.0 / 32767.0);
+        prop_assert!((packet.right - right).abs() <= 1.0 / 32767.0);
+        prop_assert!(!packet.is_stop());
+    }
+}
+
+#[test]
+fn udp_receiver_outlives_the_estop() {
+    let board = SimulatedBoard::new();
+    let mut controller = ControllerBuilder::new()
+        .refresh_interval(Duration::default())
+        .build_with_bus(board.bus())
+        .expect("the simulated board answers");
+    let estop = EStopLatch::new();
+    controller.set_estop(estop.clone());
+    estop.trigger();
+    let config = UdpConfig {
+        listen: SocketAddr::from(([127, 0, 0, 1], 0)),
+        ..UdpConfig::default()
+    };
+    let mut receiver = UdpReceiver::bind(&mut controller, config).unwrap();
+    let mut sender = UdpSender::connect(receiver.local_addr().unwrap()).unwrap();
+
+    sender.drive(0.5, 0.5).unwrap();
+    receiver
+        .poll()
+        .expect("a refused packet keeps the receiver running");
+    assert_eq!(receiver.stats().ignored, 1);
+
+    estop.reset().unwrap();
+    sender.drive(0.5, 0.5).unwrap();
+    receiver.poll().unwrap();
+    assert_eq!(receiver.stats().received, 2);
+    assert_eq!(receiver.stats().ignored, 1);
+    assert!(board.state().motor_a > 0.0);
+}